@@ -1,14 +1,19 @@
 mod startup;
 
-use startup::{scanner, manager, settings, monitor, StartupItem};
-use winreg::enums::*;
-use winreg::RegKey;
+use startup::{scanner, manager, settings, monitor, sources, history, snapshot, baseline, watcher, policy, orphans, NewStartupItem, StartupItem};
+use startup::registry::{system_registry, RegistryHive, RegistryValue, RegValueType};
 use tauri::{
     tray::{TrayIconBuilder, MouseButton, MouseButtonState, TrayIconEvent},
     menu::{Menu, MenuItem},
-    Manager,
+    Emitter, Manager,
 };
 
+const TRAY_ID: &str = "main";
+const TRAY_TOOLTIP: &str = "UM Startup Manager";
+
+/// Tray menu entry that shows how many startup changes are waiting
+struct ChangesMenuItem(MenuItem<tauri::Wry>);
+
 #[tauri::command]
 fn get_startup_items() -> Vec<StartupItem> {
     let items = scanner::get_all_startup_items();
@@ -18,9 +23,22 @@ fn get_startup_items() -> Vec<StartupItem> {
         monitor::add_monitored_item(&item.id, &item.path);
     }
 
+    // Keep what configured items look like, to find their settings again after a rename
+    let _ = settings::remember_items(&items);
+
     items
 }
 
+#[tauri::command]
+fn get_offline_startup_items(target: scanner::OfflineTarget) -> Result<Vec<StartupItem>, String> {
+    scanner::get_offline_startup_items(&target)
+}
+
+#[tauri::command]
+fn get_startup_sources() -> Vec<sources::SourceInfo> {
+    sources::describe_sources()
+}
+
 #[tauri::command]
 fn toggle_startup_item(item: StartupItem, enable: bool) -> Result<(), String> {
     manager::toggle_startup_item(&item, enable)
@@ -33,6 +51,179 @@ fn delete_startup_item(item: StartupItem) -> Result<(), String> {
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn create_startup_item(new_item: NewStartupItem) -> Result<StartupItem, String> {
+    manager::create_startup_item(&new_item)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_startup_item(item: StartupItem, target: NewStartupItem) -> Result<StartupItem, String> {
+    manager::update_startup_item(&item, &target)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_history() -> Result<Vec<history::HistoryEntry>, String> {
+    manager::list_history()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn undo_operation(id: String) -> Result<history::HistoryEntry, String> {
+    manager::undo_operation(&id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_history_retention() -> u32 {
+    settings::get_history_retention_days()
+}
+
+#[tauri::command]
+fn set_history_retention(days: u32) -> Result<(), String> {
+    settings::set_history_retention_days(days)
+}
+
+#[tauri::command]
+fn export_snapshot() -> Result<String, String> {
+    snapshot::export_snapshot()
+}
+
+#[tauri::command]
+fn import_snapshot(content: String, options: snapshot::ImportOptions) -> Result<snapshot::ImportReport, String> {
+    let snapshot = snapshot::parse_snapshot(&content)?;
+    Ok(snapshot::import_snapshot(&snapshot, options))
+}
+
+/// Show the number of unaccepted startup changes on the tray icon and menu
+fn update_tray_changes(app: &tauri::AppHandle, count: usize) {
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let tooltip = if count == 0 {
+            TRAY_TOOLTIP.to_string()
+        } else {
+            format!("{} - 检测到 {} 项启动项变更", TRAY_TOOLTIP, count)
+        };
+        let _ = tray.set_tooltip(Some(tooltip));
+    }
+    if let Some(menu_item) = app.try_state::<ChangesMenuItem>() {
+        let text = if count == 0 {
+            "启动项无变更".to_string()
+        } else {
+            format!("查看 {} 项启动项变更", count)
+        };
+        let _ = menu_item.0.set_text(text);
+        let _ = menu_item.0.set_enabled(count > 0);
+    }
+}
+
+/// Compare the startup items with the baseline and announce any changes through the tray
+fn check_startup_changes(app: &tauri::AppHandle) -> baseline::BaselineDiff {
+    let diff = baseline::check_changes();
+    update_tray_changes(app, diff.changes.len());
+    if !diff.changes.is_empty() {
+        let _ = app.emit("startup-changes", &diff);
+    }
+    diff
+}
+
+#[tauri::command]
+fn get_startup_changes(app: tauri::AppHandle) -> baseline::BaselineDiff {
+    check_startup_changes(&app)
+}
+
+#[tauri::command]
+fn accept_startup_changes(app: tauri::AppHandle) -> Result<(), String> {
+    baseline::accept_changes()?;
+    update_tray_changes(&app, 0);
+    Ok(())
+}
+
+/// Watch the startup locations, passing every change on to the window and the tray
+fn start_startup_watcher(app: &tauri::AppHandle) {
+    let handle = app.clone();
+    watcher::start_watcher(move |events| {
+        let _ = handle.emit("startup-items-changed", &events);
+        check_startup_changes(&handle);
+    });
+}
+
+#[tauri::command]
+fn get_watch_enabled() -> bool {
+    watcher::is_watcher_running()
+}
+
+#[tauri::command]
+fn set_watch_enabled(app: tauri::AppHandle, enabled: bool) -> Result<(), String> {
+    settings::set_watch_enabled(enabled)?;
+    if enabled {
+        start_startup_watcher(&app);
+    } else {
+        watcher::stop_watcher();
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn get_watch_policy() -> settings::WatchPolicy {
+    settings::get_watch_policy()
+}
+
+#[tauri::command]
+fn set_watch_policy(policy: settings::WatchPolicy) -> Result<(), String> {
+    settings::set_watch_policy(policy)
+}
+
+#[tauri::command]
+fn get_policy() -> Result<policy::StartupPolicy, String> {
+    policy::load_policy()
+}
+
+#[tauri::command]
+fn save_policy(policy: policy::StartupPolicy) -> Result<(), String> {
+    policy::save_policy(&policy)
+}
+
+#[tauri::command]
+fn preview_policy(policy: policy::StartupPolicy) -> Vec<policy::PolicyDecision> {
+    policy::preview(&policy)
+}
+
+#[tauri::command]
+fn enforce_policy() -> Result<Vec<policy::PolicyDecision>, String> {
+    policy::enforce()
+}
+
+#[tauri::command]
+fn get_policy_log(limit: usize) -> Vec<policy::PolicyDecision> {
+    policy::read_log(limit)
+}
+
+#[tauri::command]
+fn list_quarantine() -> Vec<policy::QuarantinedItem> {
+    policy::list_quarantine()
+}
+
+#[tauri::command]
+fn restore_quarantined(id: String, allow: bool) -> Result<(), String> {
+    policy::restore_quarantined(&id, allow)
+}
+
+#[tauri::command]
+fn find_orphaned_settings() -> Vec<orphans::OrphanedSettings> {
+    orphans::find_orphaned_settings()
+}
+
+#[tauri::command]
+fn relink_orphaned_settings(orphan_id: String, item_id: String) -> Result<(), String> {
+    orphans::relink_settings(&orphan_id, &item_id)
+}
+
+#[tauri::command]
+fn purge_orphaned_settings(orphan_ids: Vec<String>) -> Result<(), String> {
+    orphans::purge_settings(&orphan_ids)
+}
+
 #[tauri::command]
 fn get_auto_minimize_settings() -> std::collections::HashSet<String> {
     settings::get_settings().auto_minimize_items
@@ -135,48 +326,40 @@ fn stop_process_monitor() {
 const APP_NAME: &str = "UMStartupManager";
 const RUN_KEY_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
 
+fn has_auto_start_value(hive: RegistryHive) -> bool {
+    system_registry().get_raw_value(hive, RUN_KEY_PATH, APP_NAME).is_ok()
+}
+
+fn remove_auto_start_value(hive: RegistryHive) -> std::io::Result<()> {
+    system_registry().delete_value(hive, RUN_KEY_PATH, APP_NAME)
+}
+
 #[tauri::command]
 fn get_auto_start_enabled() -> bool {
-    // Check HKLM first
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    if let Ok(key) = hklm.open_subkey(RUN_KEY_PATH) {
-        if key.get_value::<String, _>(APP_NAME).is_ok() {
-            return true;
-        }
-    }
-    // Then check HKCU
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    if let Ok(key) = hkcu.open_subkey(RUN_KEY_PATH) {
-        return key.get_value::<String, _>(APP_NAME).is_ok();
-    }
-    false
+    // Check HKLM first, then HKCU
+    has_auto_start_value(RegistryHive::LocalMachine) || has_auto_start_value(RegistryHive::CurrentUser)
 }
 
 #[tauri::command]
 fn get_auto_start_priority() -> bool {
     // Check if using HKLM (priority mode)
-    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-    if let Ok(key) = hklm.open_subkey(RUN_KEY_PATH) {
-        return key.get_value::<String, _>(APP_NAME).is_ok();
-    }
-    false
+    has_auto_start_value(RegistryHive::LocalMachine)
 }
 
 #[tauri::command]
 fn set_auto_start_enabled(enabled: bool, priority: bool) -> Result<(), String> {
+    let registry = system_registry();
     let exe_path = std::env::current_exe()
         .map_err(|e| e.to_string())?
         .to_string_lossy()
         .to_string();
     let startup_cmd = format!("\"{}\" --autostart", exe_path);
+    let startup_value = RegistryValue::from_string(&startup_cmd, RegValueType::Sz);
 
     if enabled {
         if priority {
             // Try HKLM first
-            let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-            let result = hklm
-                .open_subkey_with_flags(RUN_KEY_PATH, winreg::enums::KEY_SET_VALUE)
-                .and_then(|key| key.set_value(APP_NAME, &startup_cmd));
+            let result = registry.set_raw_value(RegistryHive::LocalMachine, RUN_KEY_PATH, APP_NAME, &startup_value);
 
             if result.is_err() {
                 // No permission, request elevation
@@ -197,52 +380,32 @@ fn set_auto_start_enabled(enabled: bool, priority: bool) -> Result<(), String> {
             }
 
             // Remove from HKCU if exists
-            let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-            if let Ok(key) = hkcu.open_subkey_with_flags(RUN_KEY_PATH, winreg::enums::KEY_SET_VALUE) {
-                let _ = key.delete_value(APP_NAME);
-            }
+            let _ = remove_auto_start_value(RegistryHive::CurrentUser);
         } else {
             // Use HKCU
-            let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-            let key = hkcu
-                .open_subkey_with_flags(RUN_KEY_PATH, winreg::enums::KEY_SET_VALUE)
-                .map_err(|e| e.to_string())?;
-            key.set_value(APP_NAME, &startup_cmd)
+            registry
+                .set_raw_value(RegistryHive::CurrentUser, RUN_KEY_PATH, APP_NAME, &startup_value)
                 .map_err(|e| e.to_string())?;
 
             // Remove from HKLM if exists (may fail without admin)
-            let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-            if let Ok(key) = hklm.open_subkey_with_flags(RUN_KEY_PATH, winreg::enums::KEY_SET_VALUE) {
-                let _ = key.delete_value(APP_NAME);
-            }
+            let _ = remove_auto_start_value(RegistryHive::LocalMachine);
         }
     } else {
         // Delete from both locations
-        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-        if let Ok(key) = hkcu.open_subkey_with_flags(RUN_KEY_PATH, winreg::enums::KEY_SET_VALUE) {
-            let _ = key.delete_value(APP_NAME);
-        }
+        let _ = remove_auto_start_value(RegistryHive::CurrentUser);
 
         // Try to delete from HKLM
-        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
-        let hklm_result = hklm
-            .open_subkey_with_flags(RUN_KEY_PATH, winreg::enums::KEY_SET_VALUE)
-            .and_then(|key| key.delete_value(APP_NAME));
-
-        if hklm_result.is_err() {
-            // Check if value exists in HKLM
-            if let Ok(key) = hklm.open_subkey(RUN_KEY_PATH) {
-                if key.get_value::<String, _>(APP_NAME).is_ok() {
-                    // Need elevation to delete
-                    let args = format!("delete \"HKLM\\{}\" /v {} /f", RUN_KEY_PATH, APP_NAME);
-                    let _ = std::process::Command::new("powershell")
-                        .args(["-Command", &format!(
-                            "Start-Process reg -ArgumentList '{}' -Verb RunAs -Wait",
-                            args.replace("'", "''")
-                        )])
-                        .status();
-                }
-            }
+        let hklm_result = remove_auto_start_value(RegistryHive::LocalMachine);
+
+        if hklm_result.is_err() && has_auto_start_value(RegistryHive::LocalMachine) {
+            // Need elevation to delete
+            let args = format!("delete \"HKLM\\{}\" /v {} /f", RUN_KEY_PATH, APP_NAME);
+            let _ = std::process::Command::new("powershell")
+                .args(["-Command", &format!(
+                    "Start-Process reg -ArgumentList '{}' -Verb RunAs -Wait",
+                    args.replace("'", "''")
+                )])
+                .status();
         }
     }
 
@@ -272,12 +435,14 @@ fn open_registry_location(path: String) -> Result<(), String> {
     let _ = taskkill_result;
 
     // Set the last key in registry so regedit opens to it
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let key = hkcu
-        .create_subkey(r"Software\Microsoft\Windows\CurrentVersion\Applets\Regedit")
-        .map_err(|e| e.to_string())?
-        .0;
-    key.set_value("LastKey", &path).map_err(|e| e.to_string())?;
+    let registry = system_registry();
+    let regedit_key = r"Software\Microsoft\Windows\CurrentVersion\Applets\Regedit";
+    registry
+        .create_subkey(RegistryHive::CurrentUser, regedit_key)
+        .map_err(|e| e.to_string())?;
+    registry
+        .set_raw_value(RegistryHive::CurrentUser, regedit_key, "LastKey", &RegistryValue::from_string(&path, RegValueType::Sz))
+        .map_err(|e| e.to_string())?;
 
     // Open regedit - regedit是GUI程序，不需要隐藏窗口
     std::process::Command::new("regedit")
@@ -288,7 +453,8 @@ fn open_registry_location(path: String) -> Result<(), String> {
 
 #[tauri::command]
 fn open_startup_folder(path: String) -> Result<(), String> {
-    std::process::Command::new("explorer")
+    let file_manager = if cfg!(windows) { "explorer" } else { "xdg-open" };
+    std::process::Command::new(file_manager)
         .arg(&path)
         .spawn()
         .map_err(|e| e.to_string())?;
@@ -306,9 +472,7 @@ fn open_file_location(path: String) -> Result<(), String> {
 
 #[tauri::command]
 fn open_config_folder() -> Result<(), String> {
-    let app_data = std::env::var("APPDATA").unwrap_or_else(|_| ".".to_string());
-    let config_path = std::path::PathBuf::from(app_data)
-        .join("UMStartupManager");
+    let config_path = settings::get_config_dir();
 
     // Ensure the folder exists
     std::fs::create_dir_all(&config_path).map_err(|e| e.to_string())?;
@@ -363,13 +527,16 @@ pub fn run() {
             // Create tray menu
             let quit = MenuItem::with_id(app, "quit", "退出", true, None::<&str>)?;
             let show = MenuItem::with_id(app, "show", "显示窗口", true, None::<&str>)?;
-            let menu = Menu::with_items(app, &[&show, &quit])?;
+            let changes = MenuItem::with_id(app, "changes", "启动项无变更", false, None::<&str>)?;
+            let menu = Menu::with_items(app, &[&show, &changes, &quit])?;
+            app.manage(ChangesMenuItem(changes.clone()));
 
             // Create tray icon
             // 使用更清晰的图标，直接使用default_window_icon()获取配置的图标
-            let _tray = TrayIconBuilder::new()
+            let _tray = TrayIconBuilder::with_id(TRAY_ID)
                 // default_window_icon()会自动使用tauri.conf.json中配置的最高质量图标
                 .icon(app.default_window_icon().unwrap().clone())
+                .tooltip(TRAY_TOOLTIP)
                 .menu(&menu)
                 .show_menu_on_left_click(false)
                 .on_menu_event(|app, event| {
@@ -383,6 +550,13 @@ pub fn run() {
                                 let _ = window.set_focus();
                             }
                         }
+                        "changes" => {
+                            if let Some(window) = app.get_webview_window("main") {
+                                let _ = window.show();
+                                let _ = window.set_focus();
+                            }
+                            let _ = app.emit("show-startup-changes", ());
+                        }
                         _ => {}
                     }
                 })
@@ -396,6 +570,22 @@ pub fn run() {
                 })
                 .build(app)?;
 
+            // Rekey old settings, enforce the allowlist and look for startup changes since the baseline
+            // without holding up the window
+            let handle = app.handle().clone();
+            std::thread::spawn(move || {
+                // Settings saved by versions whose item IDs were not stable are rekeyed once
+                if settings::needs_id_migration() {
+                    let _ = settings::migrate_item_ids(&scanner::get_all_startup_items());
+                }
+                let _ = policy::enforce();
+                check_startup_changes(&handle);
+            });
+
+            if settings::is_watch_enabled() {
+                start_startup_watcher(app.handle());
+            }
+
             // If not autostart, show the window
             if !autostart {
                 if let Some(window) = app.get_webview_window("main") {
@@ -407,8 +597,34 @@ pub fn run() {
         })
         .invoke_handler(tauri::generate_handler![
             get_startup_items,
+            get_offline_startup_items,
+            get_startup_sources,
             toggle_startup_item,
             delete_startup_item,
+            create_startup_item,
+            update_startup_item,
+            list_history,
+            undo_operation,
+            get_history_retention,
+            set_history_retention,
+            export_snapshot,
+            import_snapshot,
+            get_startup_changes,
+            accept_startup_changes,
+            get_watch_enabled,
+            set_watch_enabled,
+            get_watch_policy,
+            set_watch_policy,
+            get_policy,
+            save_policy,
+            preview_policy,
+            enforce_policy,
+            get_policy_log,
+            list_quarantine,
+            restore_quarantined,
+            find_orphaned_settings,
+            relink_orphaned_settings,
+            purge_orphaned_settings,
             get_auto_minimize_settings,
             set_auto_minimize,
             start_process_monitor,
@@ -0,0 +1,321 @@
+//! Explorer's `StartupApproved` records, the switch Task Manager flips when an
+//! entry is disabled on its Startup tab.
+//!
+//! Each record is a 12-byte REG_BINARY named after the entry: a DWORD of flags,
+//! where bit 0 means disabled, followed by the FILETIME of when it was disabled
+//! (zero while enabled).
+
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::registry::{RegistryBackend, RegistryHive, RegistryValue, RegValueType};
+
+const APPROVED_ROOT: &str = r"Software\Microsoft\Windows\CurrentVersion\Explorer\StartupApproved";
+
+/// Seconds between 1601-01-01 (FILETIME epoch) and 1970-01-01
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+
+/// The approval key that covers a kind of startup entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalKey {
+    /// `...\Run` values of the matching hive
+    Run,
+    /// `WOW6432Node\...\Run` values
+    Run32,
+    /// Files in the user (HKCU) or all-users (HKLM) startup folder
+    StartupFolder,
+}
+
+impl ApprovalKey {
+    pub fn path(&self) -> String {
+        let name = match self {
+            ApprovalKey::Run => "Run",
+            ApprovalKey::Run32 => "Run32",
+            ApprovalKey::StartupFolder => "StartupFolder",
+        };
+        format!("{}\\{}", APPROVED_ROOT, name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApprovalRecord {
+    pub flags: u32,
+    /// FILETIME (100ns ticks since 1601) of when the entry was disabled
+    pub disabled_at: Option<u64>,
+}
+
+impl ApprovalRecord {
+    pub fn parse(data: &[u8]) -> Option<ApprovalRecord> {
+        let flags = u32::from_le_bytes(data.get(0..4)?.try_into().ok()?);
+        let filetime = data
+            .get(4..12)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .filter(|&t| t != 0);
+
+        Some(ApprovalRecord {
+            flags,
+            disabled_at: filetime,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.flags & 1 == 0
+    }
+
+    /// A copy switched on or off. Other flag bits are kept; disabling stamps the current time.
+    pub fn with_enabled(&self, enabled: bool) -> ApprovalRecord {
+        if enabled {
+            ApprovalRecord {
+                flags: self.flags & !1,
+                disabled_at: None,
+            }
+        } else {
+            // Disabling again keeps the original time
+            let disabled_at = self.disabled_at.filter(|_| !self.is_enabled());
+            ApprovalRecord {
+                flags: self.flags | 1,
+                disabled_at: disabled_at.or_else(|| Some(filetime_now())),
+            }
+        }
+    }
+
+    /// When a disabled entry was switched off, in milliseconds since the Unix epoch
+    pub fn disabled_since(&self) -> Option<u64> {
+        self.disabled_at
+            .filter(|_| !self.is_enabled())
+            .map(filetime_to_unix_millis)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 12] {
+        let mut bytes = [0u8; 12];
+        bytes[0..4].copy_from_slice(&self.flags.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.disabled_at.unwrap_or(0).to_le_bytes());
+        bytes
+    }
+}
+
+impl Default for ApprovalRecord {
+    /// What Task Manager writes for an enabled entry: `02 00 00 00` and no timestamp
+    fn default() -> ApprovalRecord {
+        ApprovalRecord {
+            flags: 0x02,
+            disabled_at: None,
+        }
+    }
+}
+
+pub fn filetime_now() -> u64 {
+    let since_unix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO);
+    (since_unix.as_secs() + FILETIME_UNIX_OFFSET) * 10_000_000 + since_unix.subsec_nanos() as u64 / 100
+}
+
+/// Convert a FILETIME to milliseconds since the Unix epoch (0 for earlier times)
+pub fn filetime_to_unix_millis(filetime: u64) -> u64 {
+    (filetime / 10_000).saturating_sub(FILETIME_UNIX_OFFSET * 1000)
+}
+
+/// The record of an entry, if Explorer or we have written one
+pub fn read_approval(
+    registry: &dyn RegistryBackend,
+    hive: RegistryHive,
+    key: ApprovalKey,
+    name: &str,
+) -> Option<ApprovalRecord> {
+    registry
+        .get_raw_value(hive, &key.path(), name)
+        .ok()
+        .and_then(|value| ApprovalRecord::parse(&value.bytes))
+}
+
+/// Entries without a record are enabled
+pub fn is_approved(registry: &dyn RegistryBackend, hive: RegistryHive, key: ApprovalKey, name: &str) -> bool {
+    read_approval(registry, hive, key, name)
+        .map(|record| record.is_enabled())
+        .unwrap_or(true)
+}
+
+pub fn write_approval(
+    registry: &dyn RegistryBackend,
+    hive: RegistryHive,
+    key: ApprovalKey,
+    name: &str,
+    enabled: bool,
+) -> io::Result<()> {
+    let record = read_approval(registry, hive, key, name)
+        .unwrap_or_default()
+        .with_enabled(enabled);
+    write_record(registry, hive, key, name, &record)
+}
+
+/// Store a record as it is, e.g. one carried over from an entry that was moved
+pub fn write_record(
+    registry: &dyn RegistryBackend,
+    hive: RegistryHive,
+    key: ApprovalKey,
+    name: &str,
+    record: &ApprovalRecord,
+) -> io::Result<()> {
+    let path = key.path();
+    registry.create_subkey(hive, &path)?;
+    registry.set_raw_value(
+        hive,
+        &path,
+        name,
+        &RegistryValue {
+            bytes: record.to_bytes().to_vec(),
+            vtype: RegValueType::Binary,
+        },
+    )
+}
+
+/// Drop an entry's record, ignoring a missing one
+pub fn remove_approval(registry: &dyn RegistryBackend, hive: RegistryHive, key: ApprovalKey, name: &str) {
+    let _ = registry.delete_value(hive, &key.path(), name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::locations::{find_location, DisableStrategy};
+    use crate::startup::registry::MemoryRegistry;
+    use crate::startup::scanner::{scan_registry_items, scan_startup_folder_items, ScanContext};
+    use crate::startup::testdir::TestDir;
+
+    const DISABLED: [u8; 12] = [0x03, 0, 0, 0, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0xD0, 0x01];
+
+    fn record(bytes: &[u8]) -> ApprovalRecord {
+        ApprovalRecord::parse(bytes).unwrap()
+    }
+
+    #[test]
+    fn parses_records() {
+        let disabled = record(&DISABLED);
+        assert_eq!(disabled.flags, 3);
+        assert_eq!(disabled.disabled_at, Some(0x01D0_6050_4030_2010));
+        assert!(!disabled.is_enabled());
+        assert_eq!(disabled.to_bytes(), DISABLED);
+
+        let enabled = record(&[0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(enabled, ApprovalRecord::default());
+        assert!(enabled.is_enabled());
+    }
+
+    #[test]
+    fn parses_short_and_long_records() {
+        assert_eq!(ApprovalRecord::parse(&[]), None);
+        assert_eq!(ApprovalRecord::parse(&[0x03, 0, 0]), None);
+        // Flags without a whole timestamp
+        assert_eq!(record(&[0x03, 0, 0, 0]), ApprovalRecord { flags: 3, disabled_at: None });
+        assert_eq!(record(&DISABLED[..11]), ApprovalRecord { flags: 3, disabled_at: None });
+        // Anything past the timestamp is ignored
+        let mut long = DISABLED.to_vec();
+        long.extend_from_slice(&[0xFF; 5]);
+        assert_eq!(record(&long), record(&DISABLED));
+    }
+
+    #[test]
+    fn switching_keeps_the_other_flag_bits() {
+        let disabled = record(&[0x07, 0, 0, 0, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0xD0, 0x01]);
+        let enabled = disabled.with_enabled(true);
+        assert_eq!(enabled, ApprovalRecord { flags: 0x06, disabled_at: None });
+
+        let disabled_again = enabled.with_enabled(false);
+        assert_eq!(disabled_again.flags, 0x07);
+        assert!(disabled_again.disabled_at.is_some());
+        // Disabling a disabled record keeps it as it is
+        assert_eq!(disabled.with_enabled(false), disabled);
+        assert_eq!(ApprovalRecord::default().with_enabled(false).flags, 0x03);
+    }
+
+    #[test]
+    fn key_paths() {
+        let root = r"Software\Microsoft\Windows\CurrentVersion\Explorer\StartupApproved";
+        assert_eq!(ApprovalKey::Run.path(), format!(r"{}\Run", root));
+        assert_eq!(ApprovalKey::Run32.path(), format!(r"{}\Run32", root));
+        assert_eq!(ApprovalKey::StartupFolder.path(), format!(r"{}\StartupFolder", root));
+    }
+
+    #[test]
+    fn wow64_run_entries_use_run32() {
+        let run32 = r"Software\WOW6432Node\Microsoft\Windows\CurrentVersion\Run";
+        let location = find_location(RegistryHive::LocalMachine, run32, "Tool").unwrap();
+        assert_eq!(location.disable, DisableStrategy::StartupApproved { key: ApprovalKey::Run32 });
+
+        let registry = MemoryRegistry::new();
+        let hive = RegistryHive::LocalMachine;
+        registry.create_subkey(hive, run32).unwrap();
+        let command = RegistryValue::from_string(r"C:\Tools\tool.exe", RegValueType::Sz);
+        registry.set_raw_value(hive, run32, "Tool", &command).unwrap();
+        let enabled = || {
+            scan_registry_items(&ScanContext::with_registry(&registry))
+                .into_iter()
+                .find(|item| item.entry_name == "Tool")
+                .unwrap()
+                .enabled
+        };
+
+        // A record under Run is for the 64-bit entry of the same name
+        write_record(&registry, hive, ApprovalKey::Run, "Tool", &record(&DISABLED)).unwrap();
+        assert!(enabled());
+        write_record(&registry, hive, ApprovalKey::Run32, "Tool", &record(&DISABLED)).unwrap();
+        assert!(!enabled());
+    }
+
+    #[test]
+    fn startup_folder_records_follow_the_folder_hive() {
+        let dir = TestDir::new("approved");
+        let user = dir.join("user");
+        let common = dir.join("common");
+        dir.write("user/Mine.lnk", "");
+        dir.write("common/Shared.lnk", "");
+
+        let registry = MemoryRegistry::new();
+        let scan = || {
+            let mut ctx = ScanContext::with_registry(&registry);
+            ctx.user_startup_folder = Some(user.clone());
+            ctx.common_startup_folder = Some(common.clone());
+            let items = scan_startup_folder_items(&ctx);
+            let enabled = |name: &str| items.iter().find(|item| item.entry_name == name).unwrap().enabled;
+            (enabled("Mine.lnk"), enabled("Shared.lnk"))
+        };
+        assert_eq!(scan(), (true, true));
+
+        // Records of the other hive do not count
+        write_record(&registry, RegistryHive::LocalMachine, ApprovalKey::StartupFolder, "Mine.lnk", &record(&DISABLED)).unwrap();
+        write_record(&registry, RegistryHive::CurrentUser, ApprovalKey::StartupFolder, "Shared.lnk", &record(&DISABLED)).unwrap();
+        assert_eq!(scan(), (true, true));
+
+        write_record(&registry, RegistryHive::CurrentUser, ApprovalKey::StartupFolder, "Mine.lnk", &record(&DISABLED)).unwrap();
+        write_record(&registry, RegistryHive::LocalMachine, ApprovalKey::StartupFolder, "Shared.lnk", &record(&DISABLED)).unwrap();
+        assert_eq!(scan(), (false, false));
+    }
+
+    #[test]
+    fn converts_filetimes_to_unix_millis() {
+        // 1970-01-01 and 2020-01-01 00:00:00.123 UTC
+        assert_eq!(filetime_to_unix_millis(116_444_736_000_000_000), 0);
+        assert_eq!(filetime_to_unix_millis(132_223_104_001_230_000), 1_577_836_800_123);
+        // Sub-millisecond ticks are dropped and times before 1970 clamp to 0
+        assert_eq!(filetime_to_unix_millis(116_444_736_000_009_999), 0);
+        assert_eq!(filetime_to_unix_millis(0), 0);
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as u64;
+        assert!(filetime_to_unix_millis(filetime_now()).abs_diff(now) < 1000);
+    }
+
+    #[test]
+    fn only_disabled_records_have_a_disable_time() {
+        let filetime = 132_223_104_001_230_000u64;
+        let mut bytes = [0u8; 12];
+        bytes[0] = 0x03;
+        bytes[4..12].copy_from_slice(&filetime.to_le_bytes());
+        assert_eq!(record(&bytes).disabled_since(), Some(1_577_836_800_123));
+
+        // A leftover timestamp on an enabled record means nothing
+        bytes[0] = 0x02;
+        assert_eq!(record(&bytes).disabled_since(), None);
+        assert_eq!(record(&[0x03, 0, 0, 0]).disabled_since(), None);
+    }
+}
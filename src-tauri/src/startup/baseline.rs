@@ -0,0 +1,318 @@
+//! Baseline of the scanned startup items, to notice what changed since.
+//!
+//! The items from a scan are kept in `baseline.json` in the config directory.
+//! A later scan is compared with it: items that appeared or disappeared, and
+//! items whose command or enabled state differs. The baseline stays until the
+//! changes are accepted, so nothing is missed while the app is closed. Changes
+//! made through this app are folded into the baseline as they happen.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::{scanner, settings, SourceType, StartupItem};
+
+const BASELINE_FILE: &str = "baseline.json";
+
+lazy_static::lazy_static! {
+    /// Serializes reads and writes of the baseline file
+    static ref BASELINE_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineItem {
+    pub source_type: SourceType,
+    pub name: String,
+    pub entry_name: String,
+    pub source: String,
+    pub source_location: String,
+    pub command: String,
+    pub enabled: bool,
+}
+
+type ItemKey = (SourceType, String, String);
+
+impl BaselineItem {
+    fn from_item(item: &StartupItem) -> BaselineItem {
+        BaselineItem {
+            source_type: item.source_type,
+            name: item.name.clone(),
+            entry_name: item.entry_name.clone(),
+            source: item.source.clone(),
+            source_location: item.source_location.clone(),
+            command: item.command.clone(),
+            enabled: item.enabled,
+        }
+    }
+
+    /// Identifies an entry across scans; a startup folder file keeps its key when renamed to `.disabled`
+    fn key(&self) -> ItemKey {
+        let entry_name = if self.source_type == SourceType::Folder {
+            self.entry_name.strip_suffix(".disabled").unwrap_or(&self.entry_name)
+        } else {
+            &self.entry_name
+        };
+        (self.source_type, self.source_location.to_lowercase(), entry_name.to_lowercase())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Baseline {
+    /// When the baseline was taken or last accepted, in milliseconds since the Unix epoch
+    pub created_at: u64,
+    pub items: Vec<BaselineItem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    CommandChanged,
+    EnabledChanged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemChange {
+    pub kind: ChangeKind,
+    pub source_type: SourceType,
+    pub name: String,
+    pub source: String,
+    pub source_location: String,
+    pub entry_name: String,
+    /// Command in the baseline, `None` for an added item
+    pub previous_command: Option<String>,
+    /// Command now, `None` for a removed item
+    pub command: Option<String>,
+    pub previous_enabled: Option<bool>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineDiff {
+    /// When the compared baseline was taken
+    pub baseline_created_at: u64,
+    pub changes: Vec<ItemChange>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn change(kind: ChangeKind, previous: Option<&BaselineItem>, current: Option<&BaselineItem>) -> ItemChange {
+    let item = current.or(previous).expect("a change has an item on at least one side");
+    ItemChange {
+        kind,
+        source_type: item.source_type,
+        name: item.name.clone(),
+        source: item.source.clone(),
+        source_location: item.source_location.clone(),
+        entry_name: item.entry_name.clone(),
+        previous_command: previous.map(|p| p.command.clone()),
+        command: current.map(|c| c.command.clone()),
+        previous_enabled: previous.map(|p| p.enabled),
+        enabled: current.map(|c| c.enabled),
+    }
+}
+
+/// Classify how `current` differs from `baseline`; an item can both run a new command and be switched
+pub fn diff(baseline: &[BaselineItem], current: &[BaselineItem]) -> Vec<ItemChange> {
+    let previous_by_key: HashMap<ItemKey, &BaselineItem> = baseline.iter().map(|item| (item.key(), item)).collect();
+    let current_by_key: HashMap<ItemKey, &BaselineItem> = current.iter().map(|item| (item.key(), item)).collect();
+
+    let mut changes = Vec::new();
+    for item in current {
+        let Some(previous) = previous_by_key.get(&item.key()) else {
+            changes.push(change(ChangeKind::Added, None, Some(item)));
+            continue;
+        };
+        if previous.command != item.command {
+            changes.push(change(ChangeKind::CommandChanged, Some(previous), Some(item)));
+        }
+        if previous.enabled != item.enabled {
+            changes.push(change(ChangeKind::EnabledChanged, Some(previous), Some(item)));
+        }
+    }
+    for item in baseline {
+        if !current_by_key.contains_key(&item.key()) {
+            changes.push(change(ChangeKind::Removed, Some(item), None));
+        }
+    }
+    changes
+}
+
+/// Changes between two scans
+pub fn diff_items(previous: &[StartupItem], current: &[StartupItem]) -> Vec<ItemChange> {
+    let previous: Vec<BaselineItem> = previous.iter().map(BaselineItem::from_item).collect();
+    let current: Vec<BaselineItem> = current.iter().map(BaselineItem::from_item).collect();
+    diff(&previous, &current)
+}
+
+fn baseline_path() -> PathBuf {
+    settings::get_config_dir().join(BASELINE_FILE)
+}
+
+fn load_baseline() -> Option<Baseline> {
+    std::fs::read_to_string(baseline_path())
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+}
+
+fn save_baseline(baseline: &Baseline) -> Result<(), String> {
+    let path = baseline_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let content = serde_json::to_string_pretty(baseline).map_err(|e| e.to_string())?;
+    std::fs::write(path, content).map_err(|e| e.to_string())
+}
+
+fn scan() -> Vec<BaselineItem> {
+    scanner::get_all_startup_items()
+        .iter()
+        .map(BaselineItem::from_item)
+        .collect()
+}
+
+/// Compare a scan with the baseline. The first time there is none, the scan becomes the baseline.
+pub fn check_changes() -> BaselineDiff {
+    let _guard = BASELINE_LOCK.lock().unwrap();
+    let current = scan();
+    let Some(baseline) = load_baseline() else {
+        let baseline = Baseline { created_at: now_millis(), items: current };
+        let _ = save_baseline(&baseline);
+        return BaselineDiff { baseline_created_at: baseline.created_at, changes: Vec::new() };
+    };
+
+    BaselineDiff {
+        baseline_created_at: baseline.created_at,
+        changes: diff(&baseline.items, &current),
+    }
+}
+
+/// Whether an item is in the baseline; everything counts as known until a baseline is taken
+pub fn is_known(item: &StartupItem) -> bool {
+    let _guard = BASELINE_LOCK.lock().unwrap();
+    let key = BaselineItem::from_item(item).key();
+    load_baseline().is_none_or(|baseline| baseline.items.iter().any(|known| known.key() == key))
+}
+
+/// Take the current scan as the new baseline
+pub fn accept_changes() -> Result<(), String> {
+    let _guard = BASELINE_LOCK.lock().unwrap();
+    save_baseline(&Baseline { created_at: now_millis(), items: scan() })
+}
+
+/// Fold a change made through this app into the baseline, so it is not reported later.
+/// `previous` is the item before the change and `current` the item after it.
+pub fn acknowledge(previous: Option<&StartupItem>, current: Option<&StartupItem>) {
+    let _guard = BASELINE_LOCK.lock().unwrap();
+    let Some(mut baseline) = load_baseline() else {
+        return;
+    };
+
+    let current = current.map(BaselineItem::from_item);
+    let stale: Vec<ItemKey> = previous
+        .map(BaselineItem::from_item)
+        .iter()
+        .chain(current.iter())
+        .map(BaselineItem::key)
+        .collect();
+    baseline.items.retain(|item| !stale.contains(&item.key()));
+    baseline.items.extend(current);
+    let _ = save_baseline(&baseline);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RUN: &str = r"HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Run";
+    const STARTUP: &str = r"C:\Users\me\AppData\Roaming\Microsoft\Windows\Start Menu\Programs\Startup";
+
+    fn run_item(entry_name: &str, command: &str, enabled: bool) -> StartupItem {
+        StartupItem::for_test(SourceType::Registry, RUN, entry_name, command, enabled)
+    }
+
+    fn kinds(changes: &[ItemChange]) -> Vec<(ChangeKind, &str)> {
+        changes.iter().map(|change| (change.kind, change.entry_name.as_str())).collect()
+    }
+
+    #[test]
+    fn reports_added_and_removed_items() {
+        let previous = [run_item("Old", "old.exe", true), run_item("Kept", "kept.exe", true)];
+        let current = [run_item("Kept", "kept.exe", true), run_item("New", "new.exe", false)];
+
+        let changes = diff_items(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::Added, "New"), (ChangeKind::Removed, "Old")]);
+
+        let added = &changes[0];
+        assert_eq!((added.previous_command.as_deref(), added.command.as_deref()), (None, Some("new.exe")));
+        assert_eq!((added.previous_enabled, added.enabled), (None, Some(false)));
+        let removed = &changes[1];
+        assert_eq!((removed.previous_command.as_deref(), removed.command.as_deref()), (Some("old.exe"), None));
+        assert_eq!((removed.previous_enabled, removed.enabled), (Some(true), None));
+    }
+
+    #[test]
+    fn reports_command_and_enabled_changes() {
+        let previous = [
+            run_item("Command", "v1.exe", true),
+            run_item("Switched", "app.exe", true),
+            run_item("Both", "v1.exe", true),
+        ];
+        let current = [
+            run_item("Command", "v2.exe", true),
+            run_item("Switched", "app.exe", false),
+            run_item("Both", "v2.exe", false),
+        ];
+
+        let changes = diff_items(&previous, &current);
+        assert_eq!(
+            kinds(&changes),
+            [
+                (ChangeKind::CommandChanged, "Command"),
+                (ChangeKind::EnabledChanged, "Switched"),
+                (ChangeKind::CommandChanged, "Both"),
+                (ChangeKind::EnabledChanged, "Both"),
+            ]
+        );
+        assert_eq!(changes[0].previous_command.as_deref(), Some("v1.exe"));
+        assert_eq!(changes[0].command.as_deref(), Some("v2.exe"));
+        assert_eq!((changes[1].previous_enabled, changes[1].enabled), (Some(true), Some(false)));
+    }
+
+    #[test]
+    fn matches_items_without_case() {
+        let previous = [StartupItem::for_test(SourceType::Registry, &RUN.to_uppercase(), "APP", "app.exe", true)];
+        let current = [run_item("app", "app.exe", true)];
+        assert!(diff_items(&previous, &current).is_empty());
+
+        // The same name in another source is another item
+        let other = [StartupItem::for_test(SourceType::ScheduledTask, RUN, "app", "app.exe", true)];
+        assert_eq!(kinds(&diff_items(&previous, &other)), [(ChangeKind::Added, "app"), (ChangeKind::Removed, "APP")]);
+    }
+
+    #[test]
+    fn renaming_a_folder_file_to_disabled_keeps_its_key() {
+        let previous = [StartupItem::for_test(SourceType::Folder, STARTUP, "App.lnk", "app.exe", true)];
+        let current = [StartupItem::for_test(SourceType::Folder, STARTUP, "App.lnk.disabled", "app.exe", false)];
+
+        let changes = diff_items(&previous, &current);
+        assert_eq!(kinds(&changes), [(ChangeKind::EnabledChanged, "App.lnk.disabled")]);
+
+        // Only folder files are matched that way
+        let previous = [run_item("App", "app.exe", true)];
+        let current = [run_item("App.disabled", "app.exe", false)];
+        assert_eq!(
+            kinds(&diff_items(&previous, &current)),
+            [(ChangeKind::Added, "App.disabled"), (ChangeKind::Removed, "App")]
+        );
+    }
+}
@@ -0,0 +1,505 @@
+//! Command lines as Windows reads them.
+//!
+//! Startup entries store a whole command line, and which part of it is the
+//! program is not always obvious: unquoted paths may contain spaces or dots,
+//! the program may be a bare name found through `App Paths` or `PATH`, and
+//! `REG_EXPAND_SZ` values hold `%VAR%` references. Splitting follows the
+//! rules of `CommandLineToArgvW`; an unquoted program is found the way
+//! `CreateProcess` does, trying each run of words up to a space in turn.
+//!
+//! The system is only reached through [`CommandEnvironment`], so parsing can be
+//! checked on any platform.
+
+use serde::{Deserialize, Serialize};
+
+/// `PATHEXT` when the variable is not set
+const DEFAULT_PATHEXT: &str = ".COM;.EXE;.BAT;.CMD";
+/// Extensions that end the program in an unquoted command line whose file cannot be found
+const PROGRAM_EXTENSIONS: &[&str] = &["exe", "com", "bat", "cmd", "scr", "pif"];
+const APP_PATHS_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\App Paths";
+
+/// A command line split into the program it starts and the arguments passed to it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandLine {
+    /// Full path of the program when it could be found, otherwise as written
+    pub executable: String,
+    /// Everything after the program, with `%VAR%` references expanded
+    pub arguments: String,
+}
+
+impl CommandLine {
+    /// The arguments split the way the program itself would see them
+    pub fn argument_list(&self) -> Vec<String> {
+        split_arguments(&self.arguments)
+    }
+}
+
+/// What resolving a command line needs to know about the system it runs on
+pub trait CommandEnvironment {
+    fn var(&self, name: &str) -> Option<String>;
+    fn is_file(&self, path: &str) -> bool;
+    /// The program registered under `App Paths` for a name such as `chrome.exe`
+    fn app_path(&self, name: &str) -> Option<String>;
+}
+
+/// The `App Paths` key of a program name
+pub fn app_paths_key(name: &str) -> String {
+    format!("{}\\{}", APP_PATHS_KEY, name)
+}
+
+/// Replace `%NAME%` references using `lookup`, leaving unknown ones as they are.
+/// Like `ExpandEnvironmentStrings`, the `%` closing an unknown name may open the next one.
+pub fn expand_with(value: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find('%') {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('%') else {
+            result.push_str(&rest[start..]);
+            return result;
+        };
+        let name = &after[..end];
+        match lookup(name).filter(|_| !name.is_empty()) {
+            Some(expanded) => {
+                result.push_str(&expanded);
+                rest = &after[end + 1..];
+            }
+            None => {
+                result.push('%');
+                rest = after;
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// Replace `%NAME%` references with environment variables, leaving unknown ones as they are
+pub fn expand_environment_strings(value: &str) -> String {
+    expand_with(value, |name| std::env::var(name).ok())
+}
+
+/// Split arguments by the `CommandLineToArgvW` rules: whitespace separates them
+/// outside quotes, `2n` backslashes before a quote become `n` and the quote is
+/// a delimiter, `2n+1` become `n` and a literal quote, and `""` inside quotes
+/// is a literal quote that also closes them.
+pub fn split_arguments(arguments: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    // 1 while inside quotes; counts the quotes of a run, where the third is a literal one
+    let mut quotes = 0;
+    let mut backslashes: usize = 0;
+    let mut chars = arguments.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' if quotes == 0 => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+                backslashes = 0;
+            }
+            '\\' => {
+                current.push('\\');
+                backslashes += 1;
+                in_arg = true;
+            }
+            '"' => {
+                in_arg = true;
+                current.truncate(current.len() - backslashes.div_ceil(2));
+                if backslashes.is_multiple_of(2) {
+                    quotes += 1;
+                } else {
+                    current.push('"');
+                }
+                backslashes = 0;
+
+                while chars.next_if_eq(&'"').is_some() {
+                    quotes += 1;
+                    if quotes == 3 {
+                        current.push('"');
+                        quotes = 0;
+                    }
+                }
+                if quotes == 2 {
+                    quotes = 0;
+                }
+            }
+            _ => {
+                current.push(c);
+                backslashes = 0;
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+
+    args
+}
+
+/// Split a whole command line as `CommandLineToArgvW` does. The program name
+/// is taken up to the closing quote or the first whitespace, with no escapes.
+pub fn split_command_line(command: &str) -> Vec<String> {
+    let (program, rest) = split_program(command.trim_start());
+    if program.is_empty() && rest.is_empty() {
+        return Vec::new();
+    }
+    let mut args = vec![program.to_string()];
+    args.extend(split_arguments(rest));
+    args
+}
+
+/// The program token and the text after it, by the `CommandLineToArgvW` rule for the first argument
+pub fn split_program(command: &str) -> (&str, &str) {
+    match command.strip_prefix('"') {
+        Some(rest) => match rest.find('"') {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, ""),
+        },
+        None => {
+            let end = command.find([' ', '\t']).unwrap_or(command.len());
+            (&command[..end], &command[end..])
+        }
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit(['\\', '/']).next().unwrap_or(path)
+}
+
+fn extension(path: &str) -> Option<String> {
+    let name = file_name(path);
+    name.rfind('.')
+        .filter(|&dot| dot > 0 && dot + 1 < name.len())
+        .map(|dot| name[dot + 1..].to_lowercase())
+}
+
+/// A name without a directory, which Windows searches for
+fn is_bare_name(program: &str) -> bool {
+    !program.contains(['\\', '/', ':'])
+}
+
+/// The file itself, or with `.exe` added when it has no extension, as `CreateProcess` tries
+fn find_file(path: &str, env: &dyn CommandEnvironment) -> Option<String> {
+    if env.is_file(path) {
+        return Some(path.to_string());
+    }
+    if extension(path).is_none() {
+        let with_exe = format!("{}.exe", path);
+        if env.is_file(&with_exe) {
+            return Some(with_exe);
+        }
+    }
+    None
+}
+
+/// Find a bare program name through `App Paths`, the Windows directories and `PATH`
+fn search(name: &str, env: &dyn CommandEnvironment) -> Option<String> {
+    let has_extension = extension(name).is_some();
+    let registered_name = if has_extension { name.to_string() } else { format!("{}.exe", name) };
+    if let Some(registered) = env.app_path(&registered_name) {
+        let registered = expand_with(registered.trim(), |var| env.var(var));
+        let registered = registered.trim_matches('"');
+        if !registered.is_empty() {
+            return Some(registered.to_string());
+        }
+    }
+
+    let mut dirs = Vec::new();
+    if let Some(root) = env.var("SystemRoot") {
+        dirs.push(format!("{}\\System32", root));
+        dirs.push(format!("{}\\System", root));
+        dirs.push(root);
+    }
+    if let Some(path) = env.var("PATH") {
+        dirs.extend(
+            path.split(';')
+                .map(|dir| expand_with(dir.trim().trim_matches('"'), |var| env.var(var)))
+                .filter(|dir| !dir.is_empty()),
+        );
+    }
+
+    let extensions: Vec<String> = if has_extension {
+        vec![String::new()]
+    } else {
+        env.var("PATHEXT")
+            .unwrap_or_else(|| DEFAULT_PATHEXT.to_string())
+            .split(';')
+            .filter(|ext| !ext.is_empty())
+            .map(str::to_lowercase)
+            .collect()
+    };
+
+    dirs.iter().find_map(|dir| {
+        let dir = dir.trim_end_matches(['\\', '/']);
+        extensions
+            .iter()
+            .map(|ext| format!("{}\\{}{}", dir, name, ext))
+            .find(|candidate| env.is_file(candidate))
+    })
+}
+
+fn locate(program: &str, env: &dyn CommandEnvironment) -> Option<String> {
+    if is_bare_name(program) {
+        search(program, env)
+    } else {
+        find_file(program, env)
+    }
+}
+
+/// Full path of a program given alone, such as the program of a task action; `%VAR%` must already be expanded
+pub fn resolve_program(program: &str, env: &dyn CommandEnvironment) -> String {
+    let program = program.trim().trim_matches('"');
+    locate(program, env).unwrap_or_else(|| program.to_string())
+}
+
+/// Split a command line into its program and arguments, expanding `%VAR%` first
+pub fn parse_command_line(command: &str, env: &dyn CommandEnvironment) -> CommandLine {
+    let command = expand_with(command.trim(), |name| env.var(name));
+    let command = command.trim();
+    if command.is_empty() {
+        return CommandLine::default();
+    }
+
+    if command.starts_with('"') {
+        let (program, arguments) = split_program(command);
+        return CommandLine {
+            executable: resolve_program(program, env),
+            arguments: arguments.trim_start().to_string(),
+        };
+    }
+
+    // Each run of words up to a space could be the program, shortest first
+    let ends: Vec<usize> = command
+        .char_indices()
+        .filter(|&(_, c)| c == ' ' || c == '\t')
+        .map(|(i, _)| i)
+        .chain(std::iter::once(command.len()))
+        .collect();
+    let split_at = |end: usize| CommandLine {
+        executable: command[..end].to_string(),
+        arguments: command[end..].trim_start().to_string(),
+    };
+
+    for &end in &ends {
+        if let Some(found) = locate(&command[..end], env) {
+            return CommandLine { executable: found, ..split_at(end) };
+        }
+    }
+
+    // Nothing exists here; guess the end of the program from its extension
+    ends.iter()
+        .find(|&&end| extension(&command[..end]).is_some_and(|ext| PROGRAM_EXTENSIONS.contains(&ext.as_str())))
+        .or(ends.first())
+        .map(|&end| split_at(end))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeEnvironment {
+        vars: &'static [(&'static str, &'static str)],
+        files: &'static [&'static str],
+        app_paths: &'static [(&'static str, &'static str)],
+    }
+
+    impl CommandEnvironment for FakeEnvironment {
+        fn var(&self, name: &str) -> Option<String> {
+            self.vars
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.to_string())
+        }
+
+        fn is_file(&self, path: &str) -> bool {
+            self.files.iter().any(|file| file.eq_ignore_ascii_case(path))
+        }
+
+        fn app_path(&self, name: &str) -> Option<String> {
+            self.app_paths
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, path)| path.to_string())
+        }
+    }
+
+    const ENV: FakeEnvironment = FakeEnvironment {
+        vars: &[
+            ("SystemRoot", r"C:\Windows"),
+            ("ProgramFiles", r"C:\Program Files"),
+            ("LOCALAPPDATA", r"C:\Users\me\AppData\Local"),
+            ("PATH", r"C:\Windows\system32;C:\Tools;;%LOCALAPPDATA%\bin"),
+            ("PATHEXT", ".COM;.EXE;.BAT;.CMD"),
+        ],
+        files: &[
+            r"C:\Windows\System32\notepad.exe",
+            r"C:\Windows\System32\rundll32.exe",
+            r"C:\Windows\regedit.exe",
+            r"C:\Tools\tool.cmd",
+            r"C:\Users\me\AppData\Local\bin\sync.exe",
+            r"C:\Program Files\App\app.exe",
+            r"C:\Program Files\Vendor Suite\agent.exe",
+            r"C:\Program Files\Launcher",
+        ],
+        app_paths: &[("chrome.exe", r#""%ProgramFiles%\Google\Chrome\chrome.exe""#)],
+    };
+
+    const EMPTY: FakeEnvironment = FakeEnvironment { vars: &[], files: &[], app_paths: &[] };
+
+    #[test]
+    fn splits_arguments_like_command_line_to_argv() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("   ", &[]),
+            ("a b c", &["a", "b", "c"]),
+            ("  a \t b  ", &["a", "b"]),
+            (r#""a b" c"#, &["a b", "c"]),
+            (r#"a"b c"d"#, &["ab cd"]),
+            (r#""""#, &[""]),
+            (r#"a "" b"#, &["a", "", "b"]),
+            (r"a\\b c\", &[r"a\\b", r"c\"]),
+            (r#"a\"b"#, &[r#"a"b"#]),
+            (r#"a\\"b c""#, &[r"a\b c"]),
+            (r#"a\\\"b"#, &[r#"a\"b"#]),
+            (r#"a\\\\"b c""#, &[r"a\\b c"]),
+            (r#""a""b""#, &[r#"a"b"#]),
+            (r#""a""b c""#, &[r#"a"b"#, "c"]),
+            (r#""a"""b c""#, &[r#"a"b c"#]),
+            (r#"a""""b"#, &[r#"a"b"#]),
+            (r#""C:\Path\""#, &[r#"C:\Path""#]),
+            (r#""C:\Path\\" next"#, &[r"C:\Path\", "next"]),
+            ("--flag=\"x y\" z", &["--flag=x y", "z"]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(split_arguments(input), *expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn splits_the_program_name_without_escapes() {
+        let cases: &[(&str, &[&str])] = &[
+            ("", &[]),
+            ("app.exe", &["app.exe"]),
+            (r#""C:\Program Files\a.exe" -x "y z""#, &[r"C:\Program Files\a.exe", "-x", "y z"]),
+            (r#""C:\dir\" arg"#, &[r"C:\dir\", "arg"]),
+            (r#"C:\dir\"quoted"" x"#, &[r#"C:\dir\"quoted"""#, "x"]),
+            (r#""C:\a"b c"#, &[r"C:\a", "b", "c"]),
+            (r#""C:\unterminated arg"#, &[r"C:\unterminated arg"]),
+            ("  lead.exe  tail", &["lead.exe", "tail"]),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(split_command_line(input), *expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn expands_environment_references() {
+        let lookup = |name: &str| ENV.var(name);
+        let cases: &[(&str, &str)] = &[
+            ("", ""),
+            ("plain", "plain"),
+            (r"%SystemRoot%\x.exe", r"C:\Windows\x.exe"),
+            (r"%systemroot%\x.exe", r"C:\Windows\x.exe"),
+            (r"%ProgramFiles%\%SystemRoot%", r"C:\Program Files\C:\Windows"),
+            (r"%MISSING%\x", r"%MISSING%\x"),
+            ("100%", "100%"),
+            ("100% done", "100% done"),
+            ("%%", "%%"),
+            ("%%SystemRoot%", r"%C:\Windows"),
+            ("%MISSING%SystemRoot%", r"%MISSINGC:\Windows"),
+            ("a%b", "a%b"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(expand_with(input, lookup), *expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn parses_startup_commands() {
+        let cases: &[(&str, &str, &str)] = &[
+            ("", "", ""),
+            (r#""C:\Program Files\App\app.exe" /min"#, r"C:\Program Files\App\app.exe", "/min"),
+            (r#""C:\Program Files\App\app.exe""#, r"C:\Program Files\App\app.exe", ""),
+            (r#"  "C:\Program Files\App\app.exe"   -a  -b "#, r"C:\Program Files\App\app.exe", "-a  -b"),
+            (r#""C:\Program Files\Missing\m.exe" -x"#, r"C:\Program Files\Missing\m.exe", "-x"),
+            (r#""C:\Program Files\App\app""#, r"C:\Program Files\App\app.exe", ""),
+            (r#""C:\Unterminated\u.exe -x"#, r"C:\Unterminated\u.exe -x", ""),
+            // Unquoted paths with spaces, found on disk
+            (r"C:\Program Files\App\app.exe /min", r"C:\Program Files\App\app.exe", "/min"),
+            (r"C:\Program Files\App\app /min", r"C:\Program Files\App\app.exe", "/min"),
+            (r"C:\Program Files\Vendor Suite\agent.exe --bg", r"C:\Program Files\Vendor Suite\agent.exe", "--bg"),
+            (r"C:\Program Files\Launcher --go", r"C:\Program Files\Launcher", "--go"),
+            // Missing files: the first run of words with a program extension
+            (r"C:\my.exe.tools\app.exe -x", r"C:\my.exe.tools\app.exe", "-x"),
+            (r"C:\Some Dir\tool.exe arg.exe", r"C:\Some Dir\tool.exe", "arg.exe"),
+            (r"C:\Scripts\start.bat arg", r"C:\Scripts\start.bat", "arg"),
+            (r"C:\Scripts\my script.cmd /q", r"C:\Scripts\my script.cmd", "/q"),
+            (r"D:\Legacy\run.com", r"D:\Legacy\run.com", ""),
+            (r"C:\No Extension\thing arg", r"C:\No", r"Extension\thing arg"),
+            // Environment references, as stored in REG_EXPAND_SZ values
+            (r"%ProgramFiles%\App\app.exe --tray", r"C:\Program Files\App\app.exe", "--tray"),
+            (r#""%ProgramFiles%\Missing\m.exe" --tray"#, r"C:\Program Files\Missing\m.exe", "--tray"),
+            (r"%SystemRoot%\System32\notepad.exe %MISSING%", r"C:\Windows\System32\notepad.exe", "%MISSING%"),
+            (r"%UNKNOWN%\x.exe -y", r"%UNKNOWN%\x.exe", "-y"),
+            // Bare names through App Paths, the Windows directories and PATH
+            ("notepad.exe file.txt", r"C:\Windows\System32\notepad.exe", "file.txt"),
+            ("notepad", r"C:\Windows\System32\notepad.exe", ""),
+            ("regedit /s x.reg", r"C:\Windows\regedit.exe", "/s x.reg"),
+            ("rundll32.exe shell32.dll,Control_RunDLL", r"C:\Windows\System32\rundll32.exe", "shell32.dll,Control_RunDLL"),
+            ("chrome --no-startup-window", r"C:\Program Files\Google\Chrome\chrome.exe", "--no-startup-window"),
+            ("chrome.exe", r"C:\Program Files\Google\Chrome\chrome.exe", ""),
+            ("tool -x", r"C:\Tools\tool.cmd", "-x"),
+            ("sync", r"C:\Users\me\AppData\Local\bin\sync.exe", ""),
+            ("unknown.exe -z", "unknown.exe", "-z"),
+            ("unknown arg", "unknown", "arg"),
+        ];
+        for (input, executable, arguments) in cases {
+            let parsed = parse_command_line(input, &ENV);
+            assert_eq!(parsed.executable, *executable, "input: {}", input);
+            assert_eq!(parsed.arguments, *arguments, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn parses_without_a_system_to_look_at() {
+        let cases: &[(&str, &str, &str)] = &[
+            (r"C:\Program Files\App\app.exe /min", r"C:\Program Files\App\app.exe", "/min"),
+            (r"%SystemRoot%\x.exe", r"%SystemRoot%\x.exe", ""),
+            ("notepad file.txt", "notepad", "file.txt"),
+            (r"C:\Tools\x.cmd", r"C:\Tools\x.cmd", ""),
+        ];
+        for (input, executable, arguments) in cases {
+            let parsed = parse_command_line(input, &EMPTY);
+            assert_eq!(parsed.executable, *executable, "input: {}", input);
+            assert_eq!(parsed.arguments, *arguments, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn resolves_programs_given_alone() {
+        let cases: &[(&str, &str)] = &[
+            (r#""C:\Program Files\App\app.exe""#, r"C:\Program Files\App\app.exe"),
+            (r"C:\Program Files\App\app", r"C:\Program Files\App\app.exe"),
+            ("notepad.exe", r"C:\Windows\System32\notepad.exe"),
+            ("missing.exe", "missing.exe"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(resolve_program(input, &ENV), *expected, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn lists_arguments() {
+        let parsed = parse_command_line(r#""C:\Program Files\App\app.exe" --open "a b" c\"d"#, &ENV);
+        assert_eq!(parsed.argument_list(), ["--open", "a b", r#"c"d"#]);
+    }
+}
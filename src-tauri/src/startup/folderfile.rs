@@ -0,0 +1,381 @@
+//! Files in startup folders that are not shortcuts or programs.
+//!
+//! Explorer opens every file in a startup folder with its default verb, so
+//! batch files, Windows Script Host scripts, HTML applications, Internet
+//! shortcuts, ClickOnce references and PIF files all run at logon. Each is read
+//! for what it points at and for the program its file association opens it
+//! with; without an association in the scanned registry the Windows default is
+//! assumed. A `.ps1` is opened in Notepad by default, not run.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::commandline::{self, CommandEnvironment, CommandLine};
+use super::registry::{RegistryBackend, RegistryHive};
+
+const CLASSES_PATH: &str = r"Software\Classes";
+const FILE_EXTS_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\Explorer\FileExts";
+const UNINSTALL_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\Uninstall";
+
+/// Offset and length of the program and of its parameters in a PIF file
+const PIF_PROGRAM: (usize, usize) = (0x24, 63);
+const PIF_PARAMETERS: (usize, usize) = (0xA5, 64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderFileType {
+    /// `.exe`, `.com` or `.scr`
+    Program,
+    /// `.bat` or `.cmd`
+    Batch,
+    /// `.vbs`, `.vbe`, `.js`, `.jse`, `.wsf` or `.wsh`
+    WindowsScript,
+    /// `.ps1`
+    PowerShell,
+    /// `.hta`
+    HtmlApplication,
+    /// `.url`
+    InternetShortcut,
+    /// `.appref-ms`
+    ClickOnce,
+    /// `.pif`
+    Pif,
+}
+
+impl FolderFileType {
+    /// The type of a startup folder file by extension, `None` for files Explorer does not run
+    pub fn from_extension(extension: &str) -> Option<FolderFileType> {
+        match extension.to_lowercase().as_str() {
+            "exe" | "com" | "scr" => Some(FolderFileType::Program),
+            "bat" | "cmd" => Some(FolderFileType::Batch),
+            "vbs" | "vbe" | "js" | "jse" | "wsf" | "wsh" => Some(FolderFileType::WindowsScript),
+            "ps1" => Some(FolderFileType::PowerShell),
+            "hta" => Some(FolderFileType::HtmlApplication),
+            "url" => Some(FolderFileType::InternetShortcut),
+            "appref-ms" => Some(FolderFileType::ClickOnce),
+            "pif" => Some(FolderFileType::Pif),
+            _ => None,
+        }
+    }
+
+    /// The open command Windows registers for the type, with `%1` for the file
+    fn default_command(&self) -> Option<&'static str> {
+        match self {
+            FolderFileType::Batch => Some(r#"%SystemRoot%\System32\cmd.exe /c "%1" %*"#),
+            FolderFileType::WindowsScript => Some(r#"%SystemRoot%\System32\WScript.exe "%1" %*"#),
+            FolderFileType::PowerShell => Some(r#"%SystemRoot%\System32\notepad.exe "%1""#),
+            FolderFileType::HtmlApplication => Some(r#"%SystemRoot%\System32\mshta.exe "%1" %*"#),
+            FolderFileType::ClickOnce => Some(r#"rundll32.exe dfshim.dll,ShOpenVerbShortcut %1"#),
+            FolderFileType::Program | FolderFileType::InternetShortcut | FolderFileType::Pif => None,
+        }
+    }
+}
+
+/// What a `.appref-ms` file refers to: an application deployed from a URL
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClickOnceReference {
+    /// The deployment manifest, as in `https://example.com/App.application`
+    pub deployment_url: String,
+    /// Identity of the application, as in `App.application`
+    pub name: String,
+    pub culture: Option<String>,
+    pub public_key_token: Option<String>,
+    pub processor_architecture: Option<String>,
+    /// Name and publisher of the installed application, from its uninstall entry
+    pub display_name: Option<String>,
+    pub publisher: Option<String>,
+}
+
+/// A startup folder file as Explorer runs it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FolderFile {
+    pub file_type: FolderFileType,
+    /// Program the file is opened with, when it is not run itself
+    pub interpreter: Option<String>,
+    /// Where an Internet shortcut points, the deployment of a ClickOnce reference,
+    /// or the program a PIF file starts
+    pub target: Option<String>,
+    pub click_once: Option<ClickOnceReference>,
+    /// Icon named by an Internet shortcut
+    pub icon: Option<(String, i32)>,
+}
+
+impl FolderFile {
+    /// The file of what runs: the program a PIF or a `file:` Internet shortcut names,
+    /// otherwise the file itself
+    pub fn payload(&self, file_path: &str) -> String {
+        match self.file_type {
+            FolderFileType::Pif => self.target.clone().unwrap_or_else(|| file_path.to_string()),
+            FolderFileType::InternetShortcut => self
+                .target
+                .as_deref()
+                .and_then(file_url_path)
+                .unwrap_or_else(|| file_path.to_string()),
+            _ => file_path.to_string(),
+        }
+    }
+}
+
+/// Read a startup folder file and find what opens it. `file_path` is where the file
+/// is on the scanned system, `local_path` where it can be read from here.
+pub fn read_folder_file(
+    file_type: FolderFileType,
+    file_path: &str,
+    local_path: &Path,
+    registry: &dyn RegistryBackend,
+    env: &dyn CommandEnvironment,
+) -> (FolderFile, CommandLine) {
+    let mut file = FolderFile { file_type, interpreter: None, target: None, click_once: None, icon: None };
+    let data = std::fs::read(local_path).unwrap_or_default();
+
+    match file_type {
+        FolderFileType::InternetShortcut => {
+            let text = decode_text(&data);
+            file.target = ini_value(&text, "InternetShortcut", "URL");
+            file.icon = ini_value(&text, "InternetShortcut", "IconFile").map(|icon| {
+                let index = ini_value(&text, "InternetShortcut", "IconIndex")
+                    .and_then(|index| index.trim().parse().ok())
+                    .unwrap_or(0);
+                (commandline::expand_with(&icon, |name| env.var(name)), index)
+            });
+        }
+        FolderFileType::ClickOnce => {
+            file.click_once = parse_appref(&decode_text(&data)).map(|reference| resolve_appref(reference, registry));
+            file.target = file.click_once.as_ref().map(|reference| reference.deployment_url.clone());
+        }
+        FolderFileType::Pif if !data.starts_with(b"MZ") => {
+            // A PE image named `.pif` is run as a program; a real PIF names one
+            file.target = pif_field(&data, PIF_PROGRAM).map(|program| commandline::resolve_program(&program, env));
+        }
+        _ => {}
+    }
+
+    let command_line = match file_type {
+        FolderFileType::Program => CommandLine { executable: file_path.to_string(), arguments: String::new() },
+        FolderFileType::Pif => CommandLine {
+            executable: file.payload(file_path),
+            arguments: file.target.as_ref().and_then(|_| pif_field(&data, PIF_PARAMETERS)).unwrap_or_default(),
+        },
+        // The browser or other handler of the URL's scheme opens it
+        FolderFileType::InternetShortcut => CommandLine { executable: file.payload(file_path), arguments: String::new() },
+        _ => {
+            let template = association_command(registry, file_path)
+                .or_else(|| file_type.default_command().map(str::to_string))
+                .unwrap_or_else(|| "\"%1\"".to_string());
+            let mut command_line = commandline::parse_command_line(&fill_template(&template, file_path), env);
+            // Batch files registered as `"%1" %*` are run by the command interpreter
+            if file_type == FolderFileType::Batch && command_line.executable.eq_ignore_ascii_case(file_path) {
+                let comspec = file_type.default_command().map(|template| fill_template(template, file_path));
+                command_line = commandline::parse_command_line(&comspec.unwrap_or_default(), env);
+            }
+            if !command_line.executable.eq_ignore_ascii_case(file_path) {
+                file.interpreter = Some(command_line.executable.clone());
+            }
+            command_line
+        }
+    };
+
+    (file, command_line)
+}
+
+/// Text of an INI-style or reference file, which may be UTF-16 with a byte order mark
+fn decode_text(data: &[u8]) -> String {
+    match data {
+        [0xFF, 0xFE, rest @ ..] => {
+            let units: Vec<u16> = rest.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+            String::from_utf16_lossy(&units)
+        }
+        [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8_lossy(rest).into_owned(),
+        _ => String::from_utf8_lossy(data).into_owned(),
+    }
+}
+
+/// A value of an INI file, matching section and key without regard to case
+fn ini_value(text: &str, section: &str, key: &str) -> Option<String> {
+    let mut in_section = false;
+    for line in text.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = name.trim().eq_ignore_ascii_case(section);
+        } else if in_section
+            && let Some((name, value)) = line.split_once('=')
+            && name.trim().eq_ignore_ascii_case(key)
+        {
+            return Some(value.trim().to_string());
+        }
+    }
+    None
+}
+
+/// The local path of a `file:` URL
+fn file_url_path(url: &str) -> Option<String> {
+    let rest = url.get(..5).filter(|scheme| scheme.eq_ignore_ascii_case("file:")).map(|_| &url[5..])?;
+    let path = rest.trim_start_matches('/');
+    let path = percent_decode(path).replace('/', "\\");
+    // `file:///C:/x` is a drive path, `file://server/share` a UNC one
+    if path.as_bytes().get(1) == Some(&b':') {
+        Some(path)
+    } else {
+        Some(format!("\\\\{}", path))
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = value.get(index + 1..index + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// `<deployment url>#<name>, Culture=..., PublicKeyToken=..., processorArchitecture=...`
+fn parse_appref(text: &str) -> Option<ClickOnceReference> {
+    let text = text.trim().trim_end_matches('\0').trim();
+    let (url, identity) = text.split_once('#')?;
+    let mut parts = identity.split(',').map(str::trim);
+    let mut reference = ClickOnceReference {
+        deployment_url: url.trim().to_string(),
+        name: parts.next()?.to_string(),
+        ..ClickOnceReference::default()
+    };
+    for part in parts {
+        let Some((key, value)) = part.split_once('=') else { continue };
+        let value = Some(value.trim().to_string());
+        match key.trim().to_lowercase().as_str() {
+            "culture" => reference.culture = value,
+            "publickeytoken" => reference.public_key_token = value,
+            "processorarchitecture" => reference.processor_architecture = value,
+            _ => {}
+        }
+    }
+    Some(reference)
+}
+
+/// Fill in the installed application, whose uninstall entry names the same reference
+fn resolve_appref(mut reference: ClickOnceReference, registry: &dyn RegistryBackend) -> ClickOnceReference {
+    let value = |path: &str, name: &str| {
+        registry
+            .get_raw_value(RegistryHive::CurrentUser, path, name)
+            .ok()
+            .and_then(|value| value.as_string())
+    };
+    let identity = format!("{}#{}", reference.deployment_url, reference.name).to_lowercase();
+    for subkey in registry.enum_keys(RegistryHive::CurrentUser, UNINSTALL_PATH).unwrap_or_default() {
+        let path = format!("{}\\{}", UNINSTALL_PATH, subkey);
+        let matches = value(&path, "ShortcutAppId").is_some_and(|app_id| {
+            let app_id = app_id.to_lowercase();
+            app_id.starts_with(&identity)
+                && app_id[identity.len()..].chars().next().is_none_or(|c| c == ',')
+        });
+        if matches {
+            reference.display_name = value(&path, "DisplayName");
+            reference.publisher = value(&path, "Publisher");
+            break;
+        }
+    }
+    reference
+}
+
+/// A NUL-terminated OEM string of a PIF file
+fn pif_field(data: &[u8], (offset, len): (usize, usize)) -> Option<String> {
+    let field = data.get(offset..offset + len)?;
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    let value = String::from_utf8_lossy(&field[..end]).trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+/// The open command of the file's type, following the user's choice of program first
+fn association_command(registry: &dyn RegistryBackend, file_path: &str) -> Option<String> {
+    let extension = Path::new(file_path).extension()?.to_string_lossy().to_lowercase();
+    let default_value = |path: &str| {
+        [RegistryHive::CurrentUser, RegistryHive::LocalMachine]
+            .into_iter()
+            .find_map(|hive| registry.get_raw_value(hive, path, "").ok()?.as_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let user_choice = registry
+        .get_raw_value(RegistryHive::CurrentUser, &format!("{}\\.{}\\UserChoice", FILE_EXTS_PATH, extension), "ProgId")
+        .ok()
+        .and_then(|value| value.as_string());
+    let classes = format!("{}\\.{}", CLASSES_PATH, extension);
+    [user_choice, default_value(&classes)].into_iter().flatten().find_map(|prog_id| {
+        let shell = format!("{}\\{}\\shell", CLASSES_PATH, prog_id);
+        let verb = default_value(&shell).unwrap_or_else(|| "open".to_string());
+        default_value(&format!("{}\\{}\\command", shell, verb))
+    })
+}
+
+/// Put a file into an association command: `%1` and `%L` name it, `%*` passes no arguments
+fn fill_template(template: &str, file_path: &str) -> String {
+    let quoted = format!("\"{}\"", file_path);
+    let mut command = template.to_string();
+    for placeholder in ["%1", "%l", "%L"] {
+        command = command.replace(&format!("\"{}\"", placeholder), &quoted).replace(placeholder, &quoted);
+    }
+    command.replace("%*", "").trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_internet_shortcuts() {
+        let text = "[{000214A0-0000-0000-C000-000000000046}]\r\nProp3=19,11\r\n[InternetShortcut]\r\nIDList=\r\nurl=https://example.com/start\r\nIconFile=C:\\Icons\\site.ico\r\nIconIndex=2\r\n";
+        assert_eq!(ini_value(text, "internetshortcut", "URL").as_deref(), Some("https://example.com/start"));
+        assert_eq!(ini_value(text, "InternetShortcut", "IconIndex").as_deref(), Some("2"));
+        assert_eq!(ini_value(text, "InternetShortcut", "Prop3"), None);
+
+        assert_eq!(file_url_path("file:///C:/Program%20Files/App/app.exe").as_deref(), Some(r"C:\Program Files\App\app.exe"));
+        assert_eq!(file_url_path("file://server/share/x.exe").as_deref(), Some(r"\\server\share\x.exe"));
+        assert_eq!(file_url_path("https://example.com"), None);
+    }
+
+    #[test]
+    fn reads_click_once_references() {
+        let text = "http://deploy.example.com/Tool/Tool.application#Tool.application, Culture=neutral, PublicKeyToken=0123456789abcdef, processorArchitecture=msil\0";
+        let mut data = vec![0xFF, 0xFE];
+        data.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        let reference = parse_appref(&decode_text(&data)).unwrap();
+        assert_eq!(reference.deployment_url, "http://deploy.example.com/Tool/Tool.application");
+        assert_eq!(reference.name, "Tool.application");
+        assert_eq!(reference.culture.as_deref(), Some("neutral"));
+        assert_eq!(reference.public_key_token.as_deref(), Some("0123456789abcdef"));
+        assert_eq!(reference.processor_architecture.as_deref(), Some("msil"));
+        assert_eq!(parse_appref("not a reference"), None);
+    }
+
+    #[test]
+    fn reads_pif_programs() {
+        let mut data = vec![0u8; 0x171];
+        data[0x24..0x24 + 12].copy_from_slice(b"C:\\DOS\\X.EXE");
+        data[0xA5..0xA5 + 2].copy_from_slice(b"/q");
+        assert_eq!(pif_field(&data, PIF_PROGRAM).as_deref(), Some(r"C:\DOS\X.EXE"));
+        assert_eq!(pif_field(&data, PIF_PARAMETERS).as_deref(), Some("/q"));
+        assert_eq!(pif_field(&[0u8; 16], PIF_PROGRAM), None);
+    }
+
+    #[test]
+    fn fills_association_commands() {
+        let file = r"C:\Startup\run me.vbs";
+        assert_eq!(
+            fill_template(r#""C:\Windows\System32\WScript.exe" "%1" %*"#, file),
+            r#""C:\Windows\System32\WScript.exe" "C:\Startup\run me.vbs""#
+        );
+        assert_eq!(fill_template(r"notepad.exe %1", file), r#"notepad.exe "C:\Startup\run me.vbs""#);
+        assert_eq!(fill_template(r#""%L""#, file), r#""C:\Startup\run me.vbs""#);
+    }
+}
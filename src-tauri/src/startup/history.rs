@@ -0,0 +1,519 @@
+//! Journal of the changes made to startup entries, so they can be undone.
+//!
+//! Before an operation runs, the registry values and files it may touch are
+//! captured as they are. The captures are kept in `history.json` in the config
+//! directory and written back when the operation is undone. Entries older than
+//! the retention period from the settings are dropped.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::registry::{RegistryBackend, RegistryHive, RegistryValue};
+use super::{settings, systemd, SourceType};
+
+const HISTORY_FILE: &str = "history.json";
+
+lazy_static::lazy_static! {
+    /// Serializes reads and writes of the history file
+    static ref HISTORY_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Something an operation may change
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    RegistryValue { hive: RegistryHive, path: String, name: String },
+    /// A key with all of its values, for entries stored as a subkey
+    RegistryKey { hive: RegistryHive, path: String },
+    File(PathBuf),
+    /// A task definition, which has to go back through the Task Scheduler
+    ScheduledTask { task_path: String, file: PathBuf },
+}
+
+/// The state of a target before an operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Snapshot {
+    RegistryValue {
+        hive: RegistryHive,
+        path: String,
+        name: String,
+        previous: Option<RegistryValue>,
+    },
+    RegistryKey {
+        hive: RegistryHive,
+        path: String,
+        values: Option<Vec<(String, RegistryValue)>>,
+    },
+    File {
+        path: String,
+        previous: FileState,
+    },
+    ScheduledTask {
+        task_path: String,
+        file: String,
+        /// The task's XML file, base64 encoded
+        definition: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum FileState {
+    Missing,
+    /// Base64 encoded file contents
+    Contents { data: String },
+    Symlink { target: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Enable,
+    Disable,
+    Delete,
+    Create,
+    Update,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: String,
+    /// When the operation ran, in milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub operation: Operation,
+    pub item_name: String,
+    pub source: String,
+    pub source_type: SourceType,
+    #[serde(default)]
+    pub undone: bool,
+    pub snapshots: Vec<Snapshot>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+fn decode(data: &str) -> io::Result<Vec<u8>> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// `Ok(None)` for a missing value or key, so only real failures stop an operation
+fn missing_as_none<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    missing_as_none(result).map(|_| ())
+}
+
+fn capture_file(path: &Path) -> io::Result<FileState> {
+    let Some(metadata) = missing_as_none(path.symlink_metadata())? else {
+        return Ok(FileState::Missing);
+    };
+    if metadata.file_type().is_symlink() {
+        let target = std::fs::read_link(path)?;
+        return Ok(FileState::Symlink { target: target.to_string_lossy().to_string() });
+    }
+    Ok(FileState::Contents { data: encode(&std::fs::read(path)?) })
+}
+
+/// Record the current state of every target
+pub fn capture(registry: &dyn RegistryBackend, targets: &[Target]) -> io::Result<Vec<Snapshot>> {
+    targets
+        .iter()
+        .map(|target| {
+            Ok(match target {
+                Target::RegistryValue { hive, path, name } => Snapshot::RegistryValue {
+                    hive: *hive,
+                    path: path.clone(),
+                    name: name.clone(),
+                    previous: missing_as_none(registry.get_raw_value(*hive, path, name))?,
+                },
+                Target::RegistryKey { hive, path } => Snapshot::RegistryKey {
+                    hive: *hive,
+                    path: path.clone(),
+                    values: missing_as_none(registry.enum_values(*hive, path))?,
+                },
+                Target::File(path) => Snapshot::File {
+                    path: path.to_string_lossy().to_string(),
+                    previous: capture_file(path)?,
+                },
+                Target::ScheduledTask { task_path, file } => Snapshot::ScheduledTask {
+                    task_path: task_path.clone(),
+                    file: file.to_string_lossy().to_string(),
+                    definition: missing_as_none(std::fs::read(file))?.map(|data| encode(&data)),
+                },
+            })
+        })
+        .collect()
+}
+
+fn restore_file(path: &Path, state: &FileState) -> io::Result<()> {
+    // Whatever is there now is replaced, a link is never written through
+    if path.symlink_metadata().is_ok() {
+        std::fs::remove_file(path)?;
+    }
+    if *state == FileState::Missing {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    match state {
+        FileState::Contents { data } => std::fs::write(path, decode(data)?),
+        FileState::Symlink { target } => systemd::symlink(Path::new(target), path),
+        FileState::Missing => Ok(()),
+    }
+}
+
+/// Put every target back the way it was captured, latest capture first
+pub fn restore(registry: &dyn RegistryBackend, snapshots: &[Snapshot]) -> io::Result<()> {
+    for snapshot in snapshots.iter().rev() {
+        match snapshot {
+            Snapshot::RegistryValue { hive, path, name, previous } => match previous {
+                Some(value) => {
+                    registry.create_subkey(*hive, path)?;
+                    registry.set_raw_value(*hive, path, name, value)?;
+                }
+                None => ignore_missing(registry.delete_value(*hive, path, name))?,
+            },
+            Snapshot::RegistryKey { hive, path, values } => {
+                ignore_missing(registry.delete_subkey(*hive, path))?;
+                if let Some(values) = values {
+                    registry.create_subkey(*hive, path)?;
+                    for (name, value) in values {
+                        registry.set_raw_value(*hive, path, name, value)?;
+                    }
+                }
+            }
+            Snapshot::File { path, previous } => restore_file(Path::new(path), previous)?,
+            Snapshot::ScheduledTask { task_path, file, definition } => {
+                let definition = definition.as_deref().map(decode).transpose()?;
+                super::manager::restore_task(task_path, Path::new(file), definition.as_deref())
+                    .map_err(|e| io::Error::other(e.to_string()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn history_path() -> PathBuf {
+    settings::get_config_dir().join(HISTORY_FILE)
+}
+
+/// The entries of a history file, none if it does not exist yet. A file that cannot
+/// be parsed is an error, so it is never overwritten with an empty history.
+fn read_entries(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    let Some(content) = missing_as_none(std::fs::read_to_string(path))? else {
+        return Ok(Vec::new());
+    };
+    serde_json::from_str(&content).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("历史记录文件已损坏 ({}): {}", path.display(), e))
+    })
+}
+
+fn load_entries() -> io::Result<Vec<HistoryEntry>> {
+    read_entries(&history_path())
+}
+
+/// Write the entries back, dropping those past the retention period
+fn save_entries(entries: &mut Vec<HistoryEntry>) -> io::Result<()> {
+    let retention_ms = settings::get_history_retention_days() as u64 * 24 * 60 * 60 * 1000;
+    let cutoff = now_millis().saturating_sub(retention_ms);
+    entries.retain(|entry| entry.timestamp >= cutoff);
+
+    let path = history_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let content = serde_json::to_string_pretty(entries).map_err(io::Error::other)?;
+    std::fs::write(path, content)
+}
+
+/// Start an entry for an operation about to run; its id is assigned here
+pub fn new_entry(operation: Operation, item_name: &str, source: &str, source_type: SourceType, snapshots: Vec<Snapshot>) -> HistoryEntry {
+    let timestamp = now_millis();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or(0);
+
+    HistoryEntry {
+        id: format!("{}-{:08x}", timestamp, nanos),
+        timestamp,
+        operation,
+        item_name: item_name.to_string(),
+        source: source.to_string(),
+        source_type,
+        undone: false,
+        snapshots,
+    }
+}
+
+pub fn record(entry: HistoryEntry) -> io::Result<()> {
+    let _guard = HISTORY_LOCK.lock().unwrap();
+    let mut entries = load_entries()?;
+    entries.push(entry);
+    save_entries(&mut entries)
+}
+
+/// Drop an entry whose operation failed, so the history only lists what happened
+pub fn discard(id: &str) -> io::Result<()> {
+    let _guard = HISTORY_LOCK.lock().unwrap();
+    let mut entries = load_entries()?;
+    entries.retain(|entry| entry.id != id);
+    save_entries(&mut entries)
+}
+
+/// Entries within the retention period, newest first
+pub fn list_history() -> io::Result<Vec<HistoryEntry>> {
+    let _guard = HISTORY_LOCK.lock().unwrap();
+    let mut entries = load_entries()?;
+    let _ = save_entries(&mut entries);
+    entries.reverse();
+    Ok(entries)
+}
+
+/// Whether a registry path is `key` or lies below it
+fn within_key(path: &str, key: &str) -> bool {
+    let (path, key) = (path.to_ascii_lowercase(), key.to_ascii_lowercase());
+    path == key || path.starts_with(&format!("{}\\", key))
+}
+
+/// Whether two snapshots cover the same value, key or file
+fn overlaps(a: &Snapshot, b: &Snapshot) -> bool {
+    match (a, b) {
+        (
+            Snapshot::RegistryValue { hive, path, name, .. },
+            Snapshot::RegistryValue { hive: other_hive, path: other_path, name: other_name, .. },
+        ) => hive == other_hive && path.eq_ignore_ascii_case(other_path) && name.eq_ignore_ascii_case(other_name),
+        (Snapshot::RegistryKey { hive, path, .. }, Snapshot::RegistryValue { hive: other_hive, path: other_path, .. })
+        | (Snapshot::RegistryValue { hive: other_hive, path: other_path, .. }, Snapshot::RegistryKey { hive, path, .. }) => {
+            hive == other_hive && within_key(other_path, path)
+        }
+        (Snapshot::RegistryKey { hive, path, .. }, Snapshot::RegistryKey { hive: other_hive, path: other_path, .. }) => {
+            hive == other_hive && (within_key(path, other_path) || within_key(other_path, path))
+        }
+        (Snapshot::File { path, .. }, Snapshot::File { path: other_path, .. })
+        | (Snapshot::File { path, .. }, Snapshot::ScheduledTask { file: other_path, .. })
+        | (Snapshot::ScheduledTask { file: path, .. }, Snapshot::File { path: other_path, .. }) => path == other_path,
+        (
+            Snapshot::ScheduledTask { task_path, .. },
+            Snapshot::ScheduledTask { task_path: other_task_path, .. },
+        ) => task_path.eq_ignore_ascii_case(other_task_path),
+        _ => false,
+    }
+}
+
+/// Undo the entry `id` of a history in recording order. A later operation on the
+/// same target has to be undone first, or its changes would be silently overwritten.
+fn undo_entry(registry: &dyn RegistryBackend, entries: &mut [HistoryEntry], id: &str) -> io::Result<HistoryEntry> {
+    let index = entries
+        .iter()
+        .position(|entry| entry.id == id)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "history entry not found"))?;
+    let entry = &entries[index];
+    if entry.undone {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "operation already undone"));
+    }
+
+    let blocking = entries[index + 1..].iter().find(|later| {
+        !later.undone && later.snapshots.iter().any(|a| entry.snapshots.iter().any(|b| overlaps(a, b)))
+    });
+    if let Some(later) = blocking {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("之后对“{}”的操作修改了同一项，请先撤销该操作", later.item_name),
+        ));
+    }
+
+    restore(registry, &entry.snapshots)?;
+    entries[index].undone = true;
+    Ok(entries[index].clone())
+}
+
+/// Restore the state an operation started from and mark it as undone
+pub fn undo(registry: &dyn RegistryBackend, id: &str) -> io::Result<HistoryEntry> {
+    let _guard = HISTORY_LOCK.lock().unwrap();
+    let mut entries = load_entries()?;
+    let undone = undo_entry(registry, &mut entries, id)?;
+    save_entries(&mut entries)?;
+    Ok(undone)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::registry::{MemoryRegistry, RegValueType};
+    use crate::startup::testdir::TestDir;
+
+    const RUN_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+    const APP_PATH: &str = r"Software\Microsoft\Active Setup\Installed Components\{App}";
+
+    fn text(value: &str) -> RegistryValue {
+        RegistryValue::from_string(value, RegValueType::Sz)
+    }
+
+    #[test]
+    fn missing_history_is_empty() {
+        let dir = TestDir::new("history-missing");
+        assert!(read_entries(&dir.join(HISTORY_FILE)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_recorded_entries() {
+        let dir = TestDir::new("history-read");
+        let snapshot = Snapshot::File {
+            path: "/tmp/app.desktop".to_string(),
+            previous: FileState::Missing,
+        };
+        let entry = new_entry(Operation::Create, "App", "XDG", SourceType::XdgAutostart, vec![snapshot]);
+        let path = dir.write(HISTORY_FILE, &serde_json::to_string(&[&entry]).unwrap());
+
+        let entries = read_entries(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, entry.id);
+        assert_eq!(entries[0].operation, Operation::Create);
+    }
+
+    #[test]
+    fn unparsable_history_is_an_error() {
+        let dir = TestDir::new("history-corrupt");
+        let path = dir.write(HISTORY_FILE, "[{\"id\": ");
+        assert_eq!(read_entries(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn restores_captured_values_keys_and_files() {
+        let dir = TestDir::new("history-restore");
+        let registry = MemoryRegistry::new();
+        let hive = RegistryHive::CurrentUser;
+        registry.create_subkey(hive, RUN_PATH).unwrap();
+        registry.set_raw_value(hive, RUN_PATH, "Tool", &text(r"C:\Tools\tool.exe")).unwrap();
+        registry.create_subkey(hive, APP_PATH).unwrap();
+        registry.set_raw_value(hive, APP_PATH, "StubPath", &text("setup.exe /user")).unwrap();
+        registry.set_raw_value(hive, APP_PATH, "IsInstalled", &RegistryValue::from_dword(1)).unwrap();
+        let file = dir.write("app.desktop", "[Desktop Entry]\nExec=app\n");
+        let missing = dir.join("new.desktop");
+
+        let targets = [
+            Target::RegistryValue { hive, path: RUN_PATH.to_string(), name: "Tool".to_string() },
+            Target::RegistryValue { hive, path: RUN_PATH.to_string(), name: "Added".to_string() },
+            Target::RegistryKey { hive, path: APP_PATH.to_string() },
+            Target::File(file.clone()),
+            Target::File(missing.clone()),
+        ];
+        let snapshots = capture(&registry, &targets).unwrap();
+
+        registry.set_raw_value(hive, RUN_PATH, "Tool", &text(r"C:\Other\other.exe")).unwrap();
+        registry.set_raw_value(hive, RUN_PATH, "Added", &text("added.exe")).unwrap();
+        registry.delete_value(hive, APP_PATH, "StubPath").unwrap();
+        registry.set_raw_value(hive, APP_PATH, "IsInstalled", &RegistryValue::from_dword(0)).unwrap();
+        registry.set_raw_value(hive, APP_PATH, "Version", &text("2")).unwrap();
+        std::fs::write(&file, "[Desktop Entry]\nExec=other\n").unwrap();
+        std::fs::write(&missing, "[Desktop Entry]\n").unwrap();
+
+        restore(&registry, &snapshots).unwrap();
+        assert_eq!(registry.get_raw_value(hive, RUN_PATH, "Tool").unwrap().as_string().as_deref(), Some(r"C:\Tools\tool.exe"));
+        assert!(registry.get_raw_value(hive, RUN_PATH, "Added").is_err());
+        let mut values = registry.enum_values(hive, APP_PATH).unwrap();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].0, "IsInstalled");
+        assert_eq!(values[0].1.as_dword(), Some(1));
+        assert_eq!(values[1].0, "StubPath");
+        assert_eq!(values[1].1.as_string().as_deref(), Some("setup.exe /user"));
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "[Desktop Entry]\nExec=app\n");
+        assert!(!missing.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restores_symlinks_without_writing_through_them() {
+        let dir = TestDir::new("history-symlink");
+        let unit = dir.write("app.service", "[Service]\nExecStart=/usr/bin/app\n");
+        let link = dir.join("default.target.wants/app.service");
+        std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+        systemd::symlink(&unit, &link).unwrap();
+
+        let snapshots = capture(&MemoryRegistry::new(), &[Target::File(link.clone())]).unwrap();
+        std::fs::remove_file(&link).unwrap();
+        std::fs::write(&link, "replaced").unwrap();
+
+        restore(&MemoryRegistry::new(), &snapshots).unwrap();
+        assert_eq!(std::fs::read_link(&link).unwrap(), unit);
+        assert_eq!(std::fs::read_to_string(&unit).unwrap(), "[Service]\nExecStart=/usr/bin/app\n");
+    }
+
+    /// Set a value of the Run key, journaling the change like the manager does
+    fn journaled_set(registry: &MemoryRegistry, name: &str, command: &str) -> HistoryEntry {
+        let target = Target::RegistryValue { hive: RegistryHive::CurrentUser, path: RUN_PATH.to_string(), name: name.to_string() };
+        let snapshots = capture(registry, &[target]).unwrap();
+        registry.create_subkey(RegistryHive::CurrentUser, RUN_PATH).unwrap();
+        registry.set_raw_value(RegistryHive::CurrentUser, RUN_PATH, name, &text(command)).unwrap();
+        let mut entry = new_entry(Operation::Update, name, "HKCU Run", SourceType::Registry, snapshots);
+        entry.id = format!("{}-{}", name, command);
+        entry
+    }
+
+    fn command(registry: &MemoryRegistry, name: &str) -> Option<String> {
+        registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, name).ok().and_then(|value| value.as_string())
+    }
+
+    #[test]
+    fn later_changes_to_the_same_target_are_undone_first() {
+        let registry = MemoryRegistry::new();
+        let mut entries = vec![
+            journaled_set(&registry, "Tool", "first.exe"),
+            journaled_set(&registry, "Tool", "second.exe"),
+            journaled_set(&registry, "Other", "other.exe"),
+        ];
+
+        let error = undo_entry(&registry, &mut entries, "Tool-first.exe").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(command(&registry, "Tool").as_deref(), Some("second.exe"));
+        assert!(!entries[0].undone);
+
+        // An operation on another value is not in the way
+        undo_entry(&registry, &mut entries, "Tool-second.exe").unwrap();
+        assert_eq!(command(&registry, "Tool").as_deref(), Some("first.exe"));
+        undo_entry(&registry, &mut entries, "Tool-first.exe").unwrap();
+        assert_eq!(command(&registry, "Tool"), None);
+        assert_eq!(command(&registry, "Other").as_deref(), Some("other.exe"));
+        assert!(undo_entry(&registry, &mut entries, "Tool-first.exe").is_err());
+    }
+
+    #[test]
+    fn keys_overlap_the_values_below_them() {
+        let key = Snapshot::RegistryKey { hive: RegistryHive::LocalMachine, path: APP_PATH.to_string(), values: None };
+        let value = |hive, path: &str| Snapshot::RegistryValue {
+            hive,
+            path: path.to_string(),
+            name: "StubPath".to_string(),
+            previous: None,
+        };
+        assert!(overlaps(&key, &value(RegistryHive::LocalMachine, &APP_PATH.to_ascii_uppercase())));
+        assert!(overlaps(&value(RegistryHive::LocalMachine, &format!(r"{}\Sub", APP_PATH)), &key));
+        assert!(!overlaps(&key, &value(RegistryHive::CurrentUser, APP_PATH)));
+        assert!(!overlaps(&key, &value(RegistryHive::LocalMachine, &format!("{}2", APP_PATH))));
+    }
+}
@@ -1,157 +1,339 @@
 use base64::Engine;
 use std::path::Path;
 
-#[cfg(windows)]
-use windows::Win32::UI::WindowsAndMessaging::{
-    DestroyIcon, GetIconInfo, ICONINFO,
-};
-#[cfg(windows)]
-use windows::Win32::Graphics::Gdi::{
-    CreateCompatibleDC, DeleteDC, DeleteObject, GetDIBits, GetObjectW,
-    BITMAP, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS,
-};
-#[cfg(windows)]
-use windows::Win32::UI::Shell::ExtractIconExW;
-#[cfg(windows)]
-use std::os::windows::ffi::OsStrExt;
-
-#[cfg(windows)]
-pub fn extract_icon_base64(exe_path: &str) -> Option<String> {
-    use std::ffi::OsStr;
+use super::lnk::ShellLink;
+use super::pe::{PeResources, ResourceName, RT_GROUP_ICON, RT_ICON};
 
-    let path = Path::new(exe_path);
-    if !path.exists() {
-        return None;
+/// Smallest icon edge we are happy with; the list shows icons at 40px
+const PREFERRED_ICON_SIZE: u32 = 48;
+/// Larger images (e.g. 256px PNG entries) are scaled down to this size
+const MAX_OUTPUT_SIZE: u32 = 64;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// One image of an icon group, from an .ico file or RT_ICON resource
+struct IconEntry<'a> {
+    width: u32,
+    height: u32,
+    bit_count: u16,
+    data: &'a [u8],
+}
+
+impl IconEntry<'_> {
+    fn is_png(&self) -> bool {
+        self.data.starts_with(PNG_SIGNATURE)
     }
 
-    unsafe {
-        let wide_path: Vec<u16> = OsStr::new(exe_path)
-            .encode_wide()
-            .chain(std::iter::once(0))
-            .collect();
-
-        let mut large_icon = [windows::Win32::UI::WindowsAndMessaging::HICON::default()];
-        let mut small_icon = [windows::Win32::UI::WindowsAndMessaging::HICON::default()];
-
-        let count = ExtractIconExW(
-            windows::core::PCWSTR(wide_path.as_ptr()),
-            0,
-            Some(large_icon.as_mut_ptr()),
-            Some(small_icon.as_mut_ptr()),
-            1,
-        );
-
-        if count == 0 {
-            return None;
+    /// Real size, read from the PNG header when the directory entry is unreliable
+    fn size(&self) -> u32 {
+        if self.is_png() && self.data.len() >= 24 {
+            let width = u32::from_be_bytes([self.data[16], self.data[17], self.data[18], self.data[19]]);
+            if width > 0 {
+                return width;
+            }
         }
+        self.width.max(self.height)
+    }
+}
 
-        let icon = if !large_icon[0].is_invalid() {
-            large_icon[0]
-        } else if !small_icon[0].is_invalid() {
-            small_icon[0]
-        } else {
-            return None;
-        };
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
 
-        let result = icon_to_base64_png(icon);
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
 
-        // Cleanup
-        if !large_icon[0].is_invalid() {
-            let _ = DestroyIcon(large_icon[0]);
-        }
-        if !small_icon[0].is_invalid() {
-            let _ = DestroyIcon(small_icon[0]);
-        }
+/// In directory entries a width/height of 0 means 256
+fn entry_dimension(value: u8) -> u32 {
+    if value == 0 { 256 } else { value as u32 }
+}
 
-        result
+/// Entries of a standalone .ico file (ICONDIR with 16-byte entries)
+fn parse_ico_file(data: &[u8]) -> Option<Vec<IconEntry<'_>>> {
+    if read_u16(data, 0)? != 0 || read_u16(data, 2)? != 1 {
+        return None;
     }
+    let count = read_u16(data, 4)? as usize;
+
+    let entries = (0..count)
+        .filter_map(|i| {
+            let entry = data.get(6 + i * 16..6 + (i + 1) * 16)?;
+            let size = read_u32(entry, 8)? as usize;
+            let offset = read_u32(entry, 12)? as usize;
+            Some(IconEntry {
+                width: entry_dimension(entry[0]),
+                height: entry_dimension(entry[1]),
+                bit_count: read_u16(entry, 6)?,
+                data: data.get(offset..offset.checked_add(size)?)?,
+            })
+        })
+        .collect();
+    Some(entries)
 }
 
-#[cfg(windows)]
-unsafe fn icon_to_base64_png(icon: windows::Win32::UI::WindowsAndMessaging::HICON) -> Option<String> {
-    let mut icon_info = ICONINFO::default();
-    unsafe {
-        if GetIconInfo(icon, &mut icon_info).is_err() {
-            return None;
-        }
+/// Entries of an RT_GROUP_ICON resource (GRPICONDIR with 14-byte entries
+/// referencing RT_ICON resources by ID)
+fn parse_group_icon<'a>(resources: &'a PeResources, group: &[u8]) -> Option<Vec<IconEntry<'a>>> {
+    if read_u16(group, 2)? != 1 {
+        return None;
     }
+    let count = read_u16(group, 4)? as usize;
 
-    // Get actual bitmap size
-    let mut bmp = BITMAP::default();
-    let bmp_handle = if !icon_info.hbmColor.is_invalid() {
-        icon_info.hbmColor
+    let entries = (0..count)
+        .filter_map(|i| {
+            let entry = group.get(6 + i * 14..6 + (i + 1) * 14)?;
+            let id = read_u16(entry, 12)?;
+            let icon = resources.find_resource(RT_ICON, &ResourceName::Id(id))?;
+            Some(IconEntry {
+                width: entry_dimension(entry[0]),
+                height: entry_dimension(entry[1]),
+                bit_count: read_u16(entry, 6)?,
+                data: icon.data,
+            })
+        })
+        .collect();
+    Some(entries)
+}
+
+/// Pick the smallest entry that is at least `PREFERRED_ICON_SIZE`, otherwise the
+/// largest one; ties go to the higher color depth
+fn pick_best_entry<'a, 'b>(entries: &'b [IconEntry<'a>]) -> Option<&'b IconEntry<'a>> {
+    let large_enough = entries
+        .iter()
+        .filter(|e| e.size() >= PREFERRED_ICON_SIZE)
+        .min_by_key(|e| (e.size(), std::cmp::Reverse(e.bit_count)));
+
+    large_enough.or_else(|| entries.iter().max_by_key(|e| (e.size(), e.bit_count)))
+}
+
+/// Decode a single icon image, either PNG-compressed or a DIB with AND mask
+fn decode_entry(entry: &IconEntry) -> Option<image::RgbaImage> {
+    if entry.is_png() {
+        return image::load_from_memory_with_format(entry.data, image::ImageFormat::Png)
+            .ok()
+            .map(|img| img.to_rgba8());
+    }
+
+    // Wrap the DIB in a one-entry .ico so the ICO decoder handles masks and palettes;
+    // the entry size must match the BITMAPINFOHEADER (whose height covers both masks)
+    let width = read_u32(entry.data, 4)?;
+    let height = read_u32(entry.data, 8)? / 2;
+    if width == 0 || width > 256 || height == 0 || height > 256 {
+        return None;
+    }
+
+    let mut ico = Vec::with_capacity(22 + entry.data.len());
+    ico.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+    ico.push(if width == 256 { 0 } else { width as u8 });
+    ico.push(if height == 256 { 0 } else { height as u8 });
+    ico.extend_from_slice(&[0, 0]);
+    ico.extend_from_slice(&1u16.to_le_bytes());
+    ico.extend_from_slice(&entry.bit_count.to_le_bytes());
+    ico.extend_from_slice(&(entry.data.len() as u32).to_le_bytes());
+    ico.extend_from_slice(&22u32.to_le_bytes());
+    ico.extend_from_slice(entry.data);
+
+    image::load_from_memory_with_format(&ico, image::ImageFormat::Ico)
+        .ok()
+        .map(|img| img.to_rgba8())
+}
+
+/// Select the icon group like ExtractIconEx: a non-negative index counts groups
+/// in resource order, a negative one is the negated resource ID
+fn extract_pe_icon(resources: &PeResources, index: i32) -> Option<image::RgbaImage> {
+    let groups = resources.resources(RT_GROUP_ICON);
+    let group = if index >= 0 {
+        groups.get(index as usize).or_else(|| groups.first())?
     } else {
-        icon_info.hbmMask
+        let id = ResourceName::Id(index.unsigned_abs() as u16);
+        groups.iter().find(|g| g.name == id)?
     };
 
-    unsafe {
-        GetObjectW(bmp_handle, std::mem::size_of::<BITMAP>() as i32, Some(&mut bmp as *mut _ as *mut _));
-    }
-    let width = bmp.bmWidth as u32;
-    let height = bmp.bmHeight.unsigned_abs();
+    let entries = parse_group_icon(resources, group.data)?;
+    decode_entry(pick_best_entry(&entries)?)
+}
+
+fn extract_ico_file(data: &[u8]) -> Option<image::RgbaImage> {
+    let entries = parse_ico_file(data)?;
+    decode_entry(pick_best_entry(&entries)?)
+}
 
-    let hdc = unsafe {
-        CreateCompatibleDC(None)
+fn to_png_data_url(img: image::RgbaImage) -> Option<String> {
+    let img = if img.width() > MAX_OUTPUT_SIZE || img.height() > MAX_OUTPUT_SIZE {
+        image::imageops::resize(&img, MAX_OUTPUT_SIZE, MAX_OUTPUT_SIZE, image::imageops::FilterType::Lanczos3)
+    } else {
+        img
     };
-    if hdc.is_invalid() {
-        unsafe {
-            if !icon_info.hbmColor.is_invalid() { let _ = DeleteObject(icon_info.hbmColor); }
-            if !icon_info.hbmMask.is_invalid() { let _ = DeleteObject(icon_info.hbmMask); }
-        }
+
+    let mut png_data = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut png_data), image::ImageFormat::Png).ok()?;
+
+    Some(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png_data)))
+}
+
+/// Extract the first icon of an executable, DLL, .ico or .png file or shortcut as a PNG data URL
+pub fn extract_icon_base64(exe_path: &str) -> Option<String> {
+    extract_icon_base64_at(exe_path, 0)
+}
+
+/// Extract an icon by ExtractIconEx-style index, as found in icon locations like `shell32.dll,-16`
+pub fn extract_icon_base64_at(path: &str, index: i32) -> Option<String> {
+    let file_path = Path::new(path);
+    if !file_path.is_file() {
         return None;
     }
 
-    let mut bmp_info = BITMAPINFO {
-        bmiHeader: BITMAPINFOHEADER {
-            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
-            biWidth: width as i32,
-            biHeight: -(height as i32),
-            biPlanes: 1,
-            biBitCount: 32,
-            biCompression: BI_RGB.0,
-            ..Default::default()
-        },
-        bmiColors: [Default::default()],
+    let extension = file_path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    let img = match extension.as_str() {
+        "ico" => extract_ico_file(&std::fs::read(file_path).ok()?)?,
+        // Icons of XDG desktop entries
+        "png" => image::open(file_path).ok()?.to_rgba8(),
+        "lnk" => {
+            // Follow the shortcut's icon location, but never another shortcut
+            let link = ShellLink::from_file(file_path).ok()?;
+            let (icon_path, icon_index) = link.icon()?;
+            if icon_path.to_lowercase().ends_with(".lnk") {
+                return None;
+            }
+            return extract_icon_base64_at(&icon_path, icon_index);
+        }
+        _ => extract_pe_icon(&PeResources::from_file(file_path).ok()?, index)?,
     };
 
-    let mut pixels: Vec<u8> = vec![0u8; (width * height * 4) as usize];
+    to_png_data_url(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ICONS: &[u8] = include_bytes!("../../tests/fixtures/pe/icons.dll");
 
-    if !icon_info.hbmColor.is_invalid() {
-        unsafe {
-            GetDIBits(hdc, icon_info.hbmColor, 0, height, Some(pixels.as_mut_ptr() as *mut _), &mut bmp_info, DIB_RGB_COLORS);
+    fn png(size: u32, color: [u8; 4]) -> Vec<u8> {
+        let img = image::RgbaImage::from_pixel(size, size, image::Rgba(color));
+        let mut data = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Png).unwrap();
+        data
+    }
+
+    /// A 32-bit DIB with its AND mask, as stored in .ico files
+    fn dib(size: u32, color: [u8; 4]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [40, size, size * 2] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&[0; 24]);
+        let [r, g, b, a] = color;
+        for _ in 0..size * size {
+            data.extend_from_slice(&[b, g, r, a]);
         }
+        data.resize(data.len() + (size * size.div_ceil(32) * 4) as usize, 0);
+        data
     }
 
-    let has_alpha = pixels.chunks_exact(4).any(|c| c[3] != 0);
-    if !has_alpha && !icon_info.hbmMask.is_invalid() {
-        let mut mask_pixels: Vec<u8> = vec![0u8; (width * height * 4) as usize];
-        unsafe {
-            GetDIBits(hdc, icon_info.hbmMask, 0, height, Some(mask_pixels.as_mut_ptr() as *mut _), &mut bmp_info, DIB_RGB_COLORS);
+    /// An .ico file holding `images` as (directory width, bit count, data)
+    fn ico(images: &[(u8, u16, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0, 0, 1, 0];
+        data.extend_from_slice(&(images.len() as u16).to_le_bytes());
+        let mut offset = 6 + 16 * images.len();
+        for (width, bit_count, image) in images {
+            data.extend_from_slice(&[*width, *width, 0, 0]);
+            data.extend_from_slice(&1u16.to_le_bytes());
+            data.extend_from_slice(&bit_count.to_le_bytes());
+            data.extend_from_slice(&(image.len() as u32).to_le_bytes());
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += image.len();
         }
-        for (pixel, mask) in pixels.chunks_exact_mut(4).zip(mask_pixels.chunks_exact(4)) {
-            pixel[3] = if mask[0] == 0 { 255 } else { 0 };
+        for (_, _, image) in images {
+            data.extend_from_slice(image);
         }
+        data
     }
 
-    unsafe {
-        let _ = DeleteDC(hdc);
-        if !icon_info.hbmColor.is_invalid() { let _ = DeleteObject(icon_info.hbmColor); }
-        if !icon_info.hbmMask.is_invalid() { let _ = DeleteObject(icon_info.hbmMask); }
+    #[test]
+    fn reads_ico_entries() {
+        let small = dib(16, [0, 255, 0, 255]);
+        let large = png(256, [0, 0, 255, 255]);
+        let data = ico(&[(16, 32, &small), (0, 32, &large)]);
+
+        let entries = parse_ico_file(&data).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].width, entries[0].height, entries[0].bit_count), (16, 16, 32));
+        assert!(!entries[0].is_png());
+        // A width and height of 0 stand for 256
+        assert_eq!((entries[1].width, entries[1].height), (256, 256));
+        assert!(entries[1].is_png());
+        assert_eq!(entries[1].size(), 256);
+
+        assert_eq!(pick_best_entry(&entries).unwrap().size(), 256);
+        let img = extract_ico_file(&data).unwrap();
+        assert_eq!(img.dimensions(), (256, 256));
+        assert_eq!(img.get_pixel(0, 0).0, [0, 0, 255, 255]);
+
+        let img = extract_ico_file(&ico(&[(16, 32, &small)])).unwrap();
+        assert_eq!(img.dimensions(), (16, 16));
+        assert_eq!(img.get_pixel(3, 3).0, [0, 255, 0, 255]);
     }
 
-    // Convert BGRA to RGBA
-    for chunk in pixels.chunks_exact_mut(4) {
-        chunk.swap(0, 2);
+    #[test]
+    fn picks_the_smallest_entry_large_enough() {
+        let entry = |width: u32, bit_count: u16| IconEntry { width, height: width, bit_count, data: &[] };
+
+        let entries = [entry(16, 32), entry(256, 32), entry(48, 8), entry(48, 32), entry(32, 32)];
+        let best = pick_best_entry(&entries).unwrap();
+        assert_eq!((best.size(), best.bit_count), (48, 32));
+
+        // Nothing large enough: the largest, deepest one
+        let entries = [entry(16, 32), entry(32, 8), entry(32, 32)];
+        let best = pick_best_entry(&entries).unwrap();
+        assert_eq!((best.size(), best.bit_count), (32, 32));
+
+        assert!(pick_best_entry(&[]).is_none());
     }
 
-    let img = image::RgbaImage::from_raw(width, height, pixels)?;
-    let mut png_data = Vec::new();
-    img.write_to(&mut std::io::Cursor::new(&mut png_data), image::ImageFormat::Png).ok()?;
+    #[test]
+    fn skips_entries_outside_the_file() {
+        let image = png(32, [255, 0, 0, 255]);
+        let mut data = ico(&[(32, 32, &image)]);
 
-    Some(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png_data)))
-}
+        // An offset past the end of the file, then a size running past it
+        data[18..22].copy_from_slice(&(u32::MAX - 4).to_le_bytes());
+        assert!(parse_ico_file(&data).unwrap().is_empty());
+        assert!(extract_ico_file(&data).is_none());
+        data[18..22].copy_from_slice(&22u32.to_le_bytes());
+        data[14..18].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_ico_file(&data).unwrap().is_empty());
 
-#[cfg(not(windows))]
-pub fn extract_icon_base64(_exe_path: &str) -> Option<String> {
-    None
+        // Truncated directories and files that are not icons
+        assert!(parse_ico_file(&data[..4]).is_none());
+        assert!(parse_ico_file(&ico(&[(32, 32, &image)])[..30]).unwrap().is_empty());
+        assert!(parse_ico_file(b"\x89PNG\r\n\x1a\n").is_none());
+    }
+
+    #[test]
+    fn selects_pe_icon_groups_by_index_and_id() {
+        let resources = PeResources::from_bytes(ICONS).unwrap();
+        let red = [255, 0, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let color = |index: i32| extract_pe_icon(&resources, index).map(|img| (img.width(), img.get_pixel(0, 0).0));
+
+        // Groups 100 and 200, counted in resource order
+        assert_eq!(color(0), Some((16, red)));
+        assert_eq!(color(1), Some((32, blue)));
+        // A negative index is the negated resource ID
+        assert_eq!(color(-200), Some((32, blue)));
+        assert_eq!(color(-100), Some((16, red)));
+        assert_eq!(color(-1), None);
+        // An index past the last group falls back to the first
+        assert_eq!(color(5), Some((16, red)));
+    }
 }
@@ -0,0 +1,103 @@
+//! IDs of startup items.
+//!
+//! Per-item settings are keyed by ID, so an ID must come out the same on
+//! every scan, in every build of the app. It is the first 8 bytes of the
+//! SHA-256 of `<location>\n<entry name>`, in lowercase hex, where the location
+//! is the item's `source_location` and the entry name its `entry_name`. Both are
+//! trimmed, and the location has its separators turned into backslashes with
+//! any trailing one removed. For the Windows sources both are also lowercased,
+//! since Windows does not tell `C:\Startup\` from `c:/startup`; XDG autostart
+//! files and systemd units keep their case, as `App.desktop` and `app.desktop`
+//! are different files there.
+//!
+//! IDs before [`ID_SCHEME`] 1 came from `DefaultHasher`, whose output may change
+//! between Rust releases; [`legacy_item_id`] recomputes them so settings saved
+//! under them can be carried over once.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use super::sha256::Sha256;
+use super::{SourceType, StartupItem};
+
+/// Version of the ID scheme described above, stored with the settings
+pub const ID_SCHEME: u32 = 1;
+
+/// Whether the names of a source's entries are compared without case
+fn ignores_case(source_type: SourceType) -> bool {
+    match source_type {
+        SourceType::Registry | SourceType::Folder | SourceType::ScheduledTask | SourceType::Service => true,
+        SourceType::XdgAutostart | SourceType::SystemdUser => false,
+    }
+}
+
+fn normalize_location(location: &str) -> String {
+    location.trim().replace('/', "\\").trim_end_matches('\\').to_string()
+}
+
+/// The ID of the entry `entry_name` of `source_type` found at `location`
+pub fn item_id(source_type: SourceType, location: &str, entry_name: &str) -> String {
+    let mut location = normalize_location(location);
+    let mut entry_name = entry_name.trim().to_string();
+    if ignores_case(source_type) {
+        location = location.to_lowercase();
+        entry_name = entry_name.to_lowercase();
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(location.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(entry_name.as_bytes());
+    hasher.finish()[..8].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The ID an item had before the scheme was made stable
+pub fn legacy_item_id(item: &StartupItem) -> String {
+    // Services were hashed with the Services key rather than their own subkey
+    let location = match item.source_type {
+        SourceType::Service => item
+            .source_location
+            .rsplit_once('\\')
+            .map_or(item.source_location.as_str(), |(parent, _)| parent),
+        _ => item.source_location.as_str(),
+    };
+    let mut hasher = DefaultHasher::new();
+    format!("{}:{}", location, item.entry_name).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_do_not_change() {
+        // Settings files hold these; changing them loses every item's settings
+        assert_eq!(
+            item_id(SourceType::Registry, r"HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Run", "OneDrive"),
+            "46f541f420ab81b9"
+        );
+        assert_eq!(item_id(SourceType::Registry, "", ""), "01ba4719c80b6fe9");
+        assert_eq!(item_id(SourceType::XdgAutostart, "", ""), "01ba4719c80b6fe9");
+    }
+
+    #[test]
+    fn windows_sources_ignore_case_and_separators() {
+        let id = item_id(SourceType::Folder, r"C:\Users\me\Startup", "App.lnk");
+        assert_eq!(item_id(SourceType::Folder, "c:/users/me/startup/", "app.lnk"), id);
+        assert_eq!(item_id(SourceType::Folder, r" C:\Users\me\Startup\ ", " App.lnk "), id);
+        assert_ne!(item_id(SourceType::Folder, r"C:\Users\me\Startup", "Other.lnk"), id);
+        assert_ne!(item_id(SourceType::Folder, r"C:\Users\me", r"Startup\App.lnk"), id);
+        assert_eq!(item_id(SourceType::Registry, "HKEY_CURRENT_USER", "APP"), item_id(SourceType::Registry, "hkey_current_user", "app"));
+    }
+
+    #[test]
+    fn xdg_and_systemd_keep_case() {
+        for source_type in [SourceType::XdgAutostart, SourceType::SystemdUser] {
+            let id = item_id(source_type, "/home/me/.config/autostart", "App.desktop");
+            assert_eq!(item_id(source_type, " /home/me/.config/autostart/ ", "App.desktop "), id);
+            assert_ne!(item_id(source_type, "/home/me/.config/autostart", "app.desktop"), id);
+            assert_ne!(item_id(source_type, "/home/Me/.config/autostart", "App.desktop"), id);
+        }
+    }
+}
@@ -0,0 +1,591 @@
+//! Host programs that run something else on behalf of a startup entry.
+//!
+//! Many entries start `rundll32`, a script host, PowerShell, `cmd` or
+//! `msiexec`, and the interesting part is what those are told to run: a DLL
+//! export, a script, an installer package or another program. The host's
+//! arguments are read the way the host reads them, and `cmd /c` is followed
+//! into the command it runs, which may be a launcher again.
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::commandline::{self, CommandEnvironment, CommandLine};
+
+/// How many launchers inside launchers are followed, as in `cmd /c powershell -File x.ps1`
+const MAX_DEPTH: usize = 4;
+
+/// PowerShell parameters followed by a value, with the shortest abbreviation accepted
+const POWERSHELL_VALUE_PARAMETERS: &[(&str, usize)] = &[
+    ("executionpolicy", 2),
+    ("windowstyle", 1),
+    ("version", 1),
+    ("configurationname", 4),
+    ("psconsolefile", 2),
+    ("outputformat", 1),
+    ("inputformat", 2),
+    ("workingdirectory", 2),
+    ("settingsfile", 2),
+    ("custompipename", 2),
+];
+const POWERSHELL_VALUE_ALIASES: &[&str] = &["ep", "ex", "wd"];
+
+/// `cmd` built-in commands, which run inside `cmd` itself
+const CMD_BUILTINS: &[&str] = &[
+    "echo", "set", "setlocal", "if", "for", "del", "erase", "copy", "move", "ren", "rename", "type", "cd", "chdir",
+    "pushd", "popd", "md", "mkdir", "rd", "rmdir", "goto", "exit", "assoc", "ftype", "path", "title", "ver", "vol",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LauncherKind {
+    Rundll32,
+    Regsvr32,
+    /// `wscript` or `cscript`
+    ScriptHost,
+    /// Windows PowerShell or `pwsh`
+    PowerShell,
+    Cmd,
+    Msiexec,
+    Mshta,
+}
+
+impl LauncherKind {
+    fn from_program(executable: &str) -> Option<LauncherKind> {
+        let name = executable.rsplit(['\\', '/']).next().unwrap_or(executable).to_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        match name {
+            "rundll32" => Some(LauncherKind::Rundll32),
+            "regsvr32" => Some(LauncherKind::Regsvr32),
+            "wscript" | "cscript" => Some(LauncherKind::ScriptHost),
+            "powershell" | "pwsh" => Some(LauncherKind::PowerShell),
+            "cmd" => Some(LauncherKind::Cmd),
+            "msiexec" => Some(LauncherKind::Msiexec),
+            "mshta" => Some(LauncherKind::Mshta),
+            _ => None,
+        }
+    }
+}
+
+/// What the target of a launcher is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
+    /// A DLL, script, package or program on disk
+    File,
+    /// Code given in the command line itself
+    Code,
+    Url,
+    /// The product code of an installed package
+    Product,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Launcher {
+    pub kind: LauncherKind,
+    /// The host program, as found for the command line
+    pub host: String,
+    /// What the host runs
+    pub target: String,
+    pub target_kind: TargetKind,
+    /// Function a DLL is entered at, for `rundll32`
+    pub entry_point: Option<String>,
+    /// Arguments passed on to the target
+    pub arguments: String,
+}
+
+impl Launcher {
+    /// The file the launcher runs, when it runs one
+    pub fn target_file(&self) -> Option<&str> {
+        (self.target_kind == TargetKind::File).then_some(self.target.as_str())
+    }
+
+    /// The program whose process runs: `cmd` starts its target as a process of its own,
+    /// the other hosts load or interpret theirs
+    pub fn process(&self) -> &str {
+        match self.target_file() {
+            Some(target) if self.kind == LauncherKind::Cmd => target,
+            _ => &self.host,
+        }
+    }
+}
+
+/// The launcher a command line starts and what it runs, following `cmd /c` into the command it runs
+pub fn unwrap_launcher(command_line: &CommandLine, env: &dyn CommandEnvironment) -> Option<Launcher> {
+    let mut launcher = unwrap_once(command_line, env)?;
+    for _ in 1..MAX_DEPTH {
+        if launcher.kind != LauncherKind::Cmd || launcher.target_kind != TargetKind::File {
+            break;
+        }
+        let inner = CommandLine { executable: launcher.target.clone(), arguments: launcher.arguments.clone() };
+        match unwrap_once(&inner, env) {
+            Some(inner) => launcher = inner,
+            None => break,
+        }
+    }
+    Some(launcher)
+}
+
+/// What a launcher runs, before the host is filled in
+struct Target {
+    target: String,
+    target_kind: TargetKind,
+    entry_point: Option<String>,
+    arguments: String,
+}
+
+impl Target {
+    fn file(path: &str, arguments: &[String], env: &dyn CommandEnvironment) -> Target {
+        Target {
+            target: commandline::resolve_program(path, env),
+            target_kind: TargetKind::File,
+            entry_point: None,
+            arguments: join_arguments(arguments),
+        }
+    }
+
+    fn code(code: &str) -> Target {
+        Target {
+            target: code.trim().to_string(),
+            target_kind: TargetKind::Code,
+            entry_point: None,
+            arguments: String::new(),
+        }
+    }
+}
+
+fn unwrap_once(command_line: &CommandLine, env: &dyn CommandEnvironment) -> Option<Launcher> {
+    let kind = LauncherKind::from_program(&command_line.executable)?;
+    let args = command_line.argument_list();
+    let target = match kind {
+        LauncherKind::Rundll32 => rundll32(&args, &command_line.arguments, env),
+        LauncherKind::Regsvr32 => regsvr32(&args, env),
+        LauncherKind::ScriptHost => script_host(&args, env),
+        LauncherKind::PowerShell => {
+            let pwsh = command_line.executable.to_lowercase().contains("pwsh");
+            powershell(&args, pwsh, env)
+        }
+        LauncherKind::Cmd => cmd(&command_line.arguments, env),
+        LauncherKind::Msiexec => msiexec(&args, env),
+        LauncherKind::Mshta => mshta(&args, &command_line.arguments, env),
+    }?;
+
+    Some(Launcher {
+        kind,
+        host: command_line.executable.clone(),
+        target: target.target,
+        target_kind: target.target_kind,
+        entry_point: target.entry_point,
+        arguments: target.arguments,
+    })
+}
+
+/// Arguments put back into a command line for display, quoting those with spaces
+fn join_arguments(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains([' ', '\t']) {
+                format!("\"{}\"", arg)
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_script_url(value: &str) -> bool {
+    let lower = value.trim_start().to_lowercase();
+    ["javascript:", "vbscript:", "about:"].iter().any(|scheme| lower.starts_with(scheme))
+}
+
+/// `rundll32 <dll>,<entry> [arguments]`; a space also separates the DLL from the entry point
+fn rundll32(args: &[String], raw: &str, env: &dyn CommandEnvironment) -> Option<Target> {
+    let (first, rest) = args.split_first()?;
+    if is_script_url(first) {
+        return Some(Target::code(raw));
+    }
+
+    let (dll, entry_point, rest) = match first.split_once(',') {
+        Some((dll, entry)) => (dll, Some(entry.to_string()), rest),
+        None => match rest.split_first() {
+            Some((entry, rest)) => (first.as_str(), Some(entry.clone()), rest),
+            None => (first.as_str(), None, rest),
+        },
+    };
+    Some(Target {
+        entry_point: entry_point.map(|e| e.trim().to_string()).filter(|e| !e.is_empty()),
+        ..Target::file(dll.trim(), rest, env)
+    })
+}
+
+/// `regsvr32 [/s] [/u] [/n] [/i[:cmdline]] <dll>`
+fn regsvr32(args: &[String], env: &dyn CommandEnvironment) -> Option<Target> {
+    let (options, rest): (Vec<String>, Vec<String>) = args.iter().cloned().partition(|arg| arg.starts_with(['/', '-']));
+    let dll = rest.first()?;
+    Some(Target::file(dll, &options, env))
+}
+
+/// `wscript [//options] <script> [arguments]`
+fn script_host(args: &[String], env: &dyn CommandEnvironment) -> Option<Target> {
+    let index = args.iter().position(|arg| !arg.starts_with("//"))?;
+    Some(Target::file(&args[index], &args[index + 1..], env))
+}
+
+/// The name of a PowerShell parameter such as `-NoProfile`, lowercased
+fn powershell_parameter(arg: &str) -> Option<String> {
+    let name = arg.strip_prefix(['-', '/'])?;
+    let name = name.strip_prefix('-').unwrap_or(name);
+    (!name.is_empty()).then(|| name.to_lowercase())
+}
+
+fn is_abbreviation(name: &str, full: &str, min_len: usize) -> bool {
+    name.len() >= min_len && full.starts_with(name)
+}
+
+/// A script file invoked by a PowerShell command, as in `& 'C:\x.ps1' -Arg`
+fn script_in_command(command: &str) -> Option<(String, String)> {
+    let command = command.trim();
+    // `& script` calls it and `. script` dot-sources it; `.\script.ps1` is a path
+    let command = command
+        .strip_prefix('&')
+        .or_else(|| command.strip_prefix(". "))
+        .map(str::trim_start)
+        .unwrap_or(command);
+    let (script, rest) = match command.chars().next()? {
+        quote @ ('\'' | '"') => {
+            let end = command[1..].find(quote)? + 1;
+            (&command[1..end], &command[end + 1..])
+        }
+        _ => {
+            let end = command.find(char::is_whitespace).unwrap_or(command.len());
+            (&command[..end], &command[end..])
+        }
+    };
+    script
+        .to_lowercase()
+        .ends_with(".ps1")
+        .then(|| (script.to_string(), rest.trim().to_string()))
+}
+
+fn powershell_command(command: &str, env: &dyn CommandEnvironment) -> Target {
+    match script_in_command(command) {
+        Some((script, arguments)) => Target {
+            arguments,
+            ..Target::file(&script, &[], env)
+        },
+        None => Target::code(command),
+    }
+}
+
+/// Base64 of UTF-16LE text, as `-EncodedCommand` takes it
+fn decode_encoded_command(encoded: &str) -> Option<String> {
+    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// `powershell [-options] -File <script> [arguments]`, `-Command <text>` or `-EncodedCommand <base64>`.
+/// A bare first argument is a command for Windows PowerShell and a script for `pwsh`.
+fn powershell(args: &[String], pwsh: bool, env: &dyn CommandEnvironment) -> Option<Target> {
+    let mut index = 0;
+    while index < args.len() {
+        let Some(name) = powershell_parameter(&args[index]) else {
+            return Some(if pwsh {
+                Target::file(&args[index], &args[index + 1..], env)
+            } else {
+                powershell_command(&args[index..].join(" "), env)
+            });
+        };
+
+        if is_abbreviation(&name, "file", 1) {
+            let script = args.get(index + 1)?;
+            return Some(Target::file(script, &args[index + 2..], env));
+        }
+        if is_abbreviation(&name, "command", 1) {
+            return Some(powershell_command(&args[index + 1..].join(" "), env));
+        }
+        if name == "ec" || is_abbreviation(&name, "encodedcommand", 1) {
+            let encoded = args.get(index + 1)?;
+            return Some(match decode_encoded_command(encoded) {
+                Some(command) => powershell_command(&command, env),
+                None => Target::code(encoded),
+            });
+        }
+
+        let takes_value = POWERSHELL_VALUE_ALIASES.contains(&name.as_str())
+            || POWERSHELL_VALUE_PARAMETERS
+                .iter()
+                .any(|(full, min_len)| is_abbreviation(&name, full, *min_len));
+        index += if takes_value { 2 } else { 1 };
+    }
+    None
+}
+
+/// Strip the quotes `cmd /c` removes around the whole command, when there is more than one pair
+fn strip_outer_quotes(command: &str) -> &str {
+    if command.len() >= 2 && command.starts_with('"') && command.ends_with('"') && command.matches('"').count() > 2 {
+        &command[1..command.len() - 1]
+    } else {
+        command
+    }
+}
+
+/// The first command of a line chained with `&`, `&&`, `|` or `||`
+fn first_command(command: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (index, c) in command.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '^' if !quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '&' | '|' if !quoted => return command[..index].trim_end(),
+            _ => {}
+        }
+    }
+    command
+}
+
+/// The first word of a command and the text after it
+fn split_word(command: &str) -> (&str, &str) {
+    let end = command.find(char::is_whitespace).unwrap_or(command.len());
+    (&command[..end], command[end..].trim_start())
+}
+
+/// The program `start` is given, after its options and window title
+fn start_target(mut rest: &str) -> &str {
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with('/') {
+            let (option, after) = split_word(rest);
+            // `/D path` names the working directory; `/Dpath` is one word
+            rest = if option.eq_ignore_ascii_case("/d") { split_word(after).1 } else { after };
+        } else if let Some(quoted) = rest.strip_prefix('"') {
+            // The first quoted argument is the window title
+            return match quoted.find('"') {
+                Some(end) => quoted[end + 1..].trim_start(),
+                None => "",
+            };
+        } else {
+            return rest;
+        }
+    }
+}
+
+/// `cmd [/options] /c <command>` or `/k`; a plain `cmd` runs nothing but itself
+fn cmd(raw: &str, env: &dyn CommandEnvironment) -> Option<Target> {
+    let mut rest = raw.trim_start();
+    let command = loop {
+        if !rest.starts_with('/') {
+            return None;
+        }
+        let lower = rest.get(..2).unwrap_or(rest).to_lowercase();
+        if lower == "/c" || lower == "/k" {
+            break &rest[2..];
+        }
+        rest = split_word(rest).1;
+    };
+
+    let command = strip_outer_quotes(command.trim());
+    let command = first_command(command).trim_start_matches('@').trim();
+    let (word, after) = split_word(command);
+    let command = match word.to_lowercase().as_str() {
+        "start" => start_target(after),
+        "call" => after,
+        _ => command,
+    };
+    if command.is_empty() {
+        return None;
+    }
+
+    let (word, _) = split_word(command);
+    if CMD_BUILTINS.contains(&word.to_lowercase().as_str()) {
+        return Some(Target::code(command));
+    }
+    let parsed = commandline::parse_command_line(command, env);
+    Some(Target {
+        target: parsed.executable,
+        target_kind: TargetKind::File,
+        entry_point: None,
+        arguments: parsed.arguments,
+    })
+}
+
+/// Options of `msiexec` followed by the package or product they act on
+fn msiexec_takes_package(option: &str) -> bool {
+    matches!(option, "i" | "package" | "x" | "uninstall" | "a" | "p" | "update" | "j" | "ju" | "jm")
+        || option
+            .strip_prefix('f')
+            .is_some_and(|flags| !flags.is_empty() && flags.chars().all(|c| "pocedmsuav".contains(c)))
+}
+
+/// `msiexec /i <package> [PROPERTY=value]`, `/x <package|product code>` and the other package options
+fn msiexec(args: &[String], env: &dyn CommandEnvironment) -> Option<Target> {
+    let mut package: Option<String> = None;
+    let mut others = Vec::new();
+    let mut index = 0;
+    while index < args.len() {
+        let arg = &args[index];
+        index += 1;
+        let Some(option) = arg.strip_prefix(['/', '-']).map(str::to_lowercase) else {
+            others.push(arg.clone());
+            continue;
+        };
+        if package.is_some() {
+            others.push(arg.clone());
+        } else if msiexec_takes_package(&option) {
+            package = args.get(index).cloned();
+            index += 1;
+        } else if option.starts_with(['i', 'x']) && arg[2..].starts_with('{') {
+            // `/x{product code}`
+            package = Some(arg[2..].to_string());
+        } else {
+            others.push(arg.clone());
+        }
+    }
+
+    let package = package?;
+    if package.starts_with('{') {
+        return Some(Target {
+            target: package,
+            target_kind: TargetKind::Product,
+            entry_point: None,
+            arguments: join_arguments(&others),
+        });
+    }
+    Some(Target::file(&package, &others, env))
+}
+
+/// `mshta <file.hta|url>` or inline `vbscript:`/`javascript:` code
+fn mshta(args: &[String], raw: &str, env: &dyn CommandEnvironment) -> Option<Target> {
+    let (first, rest) = args.split_first()?;
+    if is_script_url(first) {
+        return Some(Target::code(raw));
+    }
+    let lower = first.to_lowercase();
+    if lower.starts_with("http://") || lower.starts_with("https://") {
+        return Some(Target {
+            target: first.clone(),
+            target_kind: TargetKind::Url,
+            entry_point: None,
+            arguments: join_arguments(rest),
+        });
+    }
+    Some(Target::file(first, rest, env))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeEnvironment;
+
+    const FILES: &[&str] = &[
+        r"C:\Windows\System32\rundll32.exe",
+        r"C:\Windows\System32\shell32.dll",
+        r"C:\Windows\System32\wscript.exe",
+        r"C:\Windows\System32\cmd.exe",
+        r"C:\Windows\System32\msiexec.exe",
+        r"C:\Windows\System32\WindowsPowerShell\v1.0\powershell.exe",
+        r"C:\Program Files\App\app.exe",
+    ];
+
+    impl CommandEnvironment for FakeEnvironment {
+        fn var(&self, name: &str) -> Option<String> {
+            match name.to_lowercase().as_str() {
+                "systemroot" => Some(r"C:\Windows".to_string()),
+                "path" => Some(r"C:\Windows\System32\WindowsPowerShell\v1.0".to_string()),
+                _ => None,
+            }
+        }
+
+        fn is_file(&self, path: &str) -> bool {
+            FILES.iter().any(|file| file.eq_ignore_ascii_case(path))
+        }
+
+        fn app_path(&self, _name: &str) -> Option<String> {
+            None
+        }
+    }
+
+    /// Command, launcher, target, target kind, entry point and arguments
+    type Case = (&'static str, LauncherKind, &'static str, TargetKind, Option<&'static str>, &'static str);
+
+    fn unwrap(command: &str) -> Option<Launcher> {
+        let command_line = commandline::parse_command_line(command, &FakeEnvironment);
+        unwrap_launcher(&command_line, &FakeEnvironment)
+    }
+
+    #[test]
+    fn finds_what_launchers_run() {
+        use LauncherKind::*;
+        let file = TargetKind::File;
+        let cases: &[Case] = &[
+            ("rundll32.exe shell32.dll,Control_RunDLL desk.cpl", Rundll32, r"C:\Windows\System32\shell32.dll", file, Some("Control_RunDLL"), "desk.cpl"),
+            (r#"rundll32 "C:\Users\me\App Data\x.dll",#1"#, Rundll32, r"C:\Users\me\App Data\x.dll", file, Some("#1"), ""),
+            (r"rundll32 C:\x\y.dll Start", Rundll32, r"C:\x\y.dll", file, Some("Start"), ""),
+            (r#"rundll32.exe javascript:"\..\mshtml,RunHTMLApplication ";alert(1)"#, Rundll32, r#"javascript:"\..\mshtml,RunHTMLApplication ";alert(1)"#, TargetKind::Code, None, ""),
+            (r"regsvr32 /s /n /i:http://x/y.sct scrobj.dll", Regsvr32, "scrobj.dll", file, None, "/s /n /i:http://x/y.sct"),
+            (r#"wscript.exe //B //NoLogo "C:\Scripts\up date.vbs" -q"#, ScriptHost, r"C:\Scripts\up date.vbs", file, None, "-q"),
+            (r"cscript C:\s.js", ScriptHost, r"C:\s.js", file, None, ""),
+            (r#"powershell.exe -NoProfile -ExecutionPolicy Bypass -WindowStyle Hidden -File "C:\Scripts\s.ps1" -Mode quiet"#, PowerShell, r"C:\Scripts\s.ps1", file, None, "-Mode quiet"),
+            (r"powershell -nop -ep bypass -f C:\s.ps1", PowerShell, r"C:\s.ps1", file, None, ""),
+            (r#"powershell -Command "& 'C:\My Scripts\s.ps1' -X 1""#, PowerShell, r"C:\My Scripts\s.ps1", file, None, "-X 1"),
+            (r#"powershell -c "Start-Process notepad""#, PowerShell, "Start-Process notepad", TargetKind::Code, None, ""),
+            ("powershell Get-Date", PowerShell, "Get-Date", TargetKind::Code, None, ""),
+            // "Write-Host hi" as UTF-16LE
+            ("powershell -enc VwByAGkAdABlAC0ASABvAHMAdAAgAGgAaQA=", PowerShell, "Write-Host hi", TargetKind::Code, None, ""),
+            (r"powershell -ExecutionPolicy Bypass .\s.ps1", PowerShell, r".\s.ps1", file, None, ""),
+            (r"pwsh C:\s.ps1 a", PowerShell, r"C:\s.ps1", file, None, "a"),
+            (r#"cmd /c start "" "C:\Program Files\App\app.exe" /tray"#, Cmd, r"C:\Program Files\App\app.exe", file, None, "/tray"),
+            (r"cmd.exe /q /c start /min /d C:\Work C:\Tools\t.exe", Cmd, r"C:\Tools\t.exe", file, None, ""),
+            (r#"cmd /C ""C:\Program Files\App\app.exe" -a & echo done""#, Cmd, r"C:\Program Files\App\app.exe", file, None, "-a"),
+            (r"cmd /c echo hi > C:\log.txt", Cmd, r"echo hi > C:\log.txt", TargetKind::Code, None, ""),
+            (r"cmd /k call C:\s.bat x", Cmd, r"C:\s.bat", file, None, "x"),
+            (r"cmd /c powershell -File C:\s.ps1", PowerShell, r"C:\s.ps1", file, None, ""),
+            (r"cmd /c wscript C:\s.vbs", ScriptHost, r"C:\s.vbs", file, None, ""),
+            (r"msiexec /i C:\Setup\app.msi /qn REBOOT=0", Msiexec, r"C:\Setup\app.msi", file, None, "/qn REBOOT=0"),
+            ("msiexec.exe /x {12345678-1234-1234-1234-123456789012} /quiet", Msiexec, "{12345678-1234-1234-1234-123456789012}", TargetKind::Product, None, "/quiet"),
+            ("msiexec /x{ABC} /qn", Msiexec, "{ABC}", TargetKind::Product, None, "/qn"),
+            (r"msiexec /fvomus C:\a.msi", Msiexec, r"C:\a.msi", file, None, ""),
+            (r"mshta C:\x.hta", Mshta, r"C:\x.hta", file, None, ""),
+            ("mshta https://example.com/a.hta", Mshta, "https://example.com/a.hta", TargetKind::Url, None, ""),
+            (r#"mshta vbscript:Execute("x")"#, Mshta, r#"vbscript:Execute("x")"#, TargetKind::Code, None, ""),
+        ];
+        for (command, kind, target, target_kind, entry_point, arguments) in cases {
+            let launcher = unwrap(command).unwrap_or_else(|| panic!("no launcher in {}", command));
+            assert_eq!(launcher.kind, *kind, "command: {}", command);
+            assert_eq!(launcher.target, *target, "command: {}", command);
+            assert_eq!(launcher.target_kind, *target_kind, "command: {}", command);
+            assert_eq!(launcher.entry_point.as_deref(), *entry_point, "command: {}", command);
+            assert_eq!(launcher.arguments, *arguments, "command: {}", command);
+        }
+    }
+
+    #[test]
+    fn leaves_other_commands_alone() {
+        let cases = [
+            r"C:\Program Files\App\app.exe /min",
+            "cmd",
+            r"cmd /q",
+            "rundll32",
+            "powershell -NoProfile",
+            "msiexec /qn",
+            r"C:\Tools\rundll32helper.exe x.dll,Run",
+        ];
+        for command in cases {
+            assert_eq!(unwrap(command), None, "command: {}", command);
+        }
+    }
+
+    #[test]
+    fn names_the_running_process() {
+        let cmd = unwrap(r#"cmd /c start "" "C:\Program Files\App\app.exe""#).unwrap();
+        assert_eq!(cmd.process(), r"C:\Program Files\App\app.exe");
+        let rundll32 = unwrap("rundll32.exe shell32.dll,Control_RunDLL").unwrap();
+        assert_eq!(rundll32.process(), r"C:\Windows\System32\rundll32.exe");
+    }
+}
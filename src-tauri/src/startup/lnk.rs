@@ -0,0 +1,809 @@
+//! Shell Link (.lnk) parser following the MS-SHLLINK specification.
+//!
+//! The Unicode variants of strings are preferred whenever the link provides
+//! them; ANSI strings are decoded with the system code page, which is only
+//! right if the link was created under the same one. `build_shortcut` writes
+//! simple links to a local file.
+
+use std::path::Path;
+
+use super::commandline::expand_environment_strings;
+
+const HEADER_SIZE: usize = 0x4C;
+const LINK_CLSID: [u8; 16] = [
+    0x01, 0x14, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46,
+];
+
+// LinkFlags
+const HAS_LINK_TARGET_ID_LIST: u32 = 0x0000_0001;
+const HAS_LINK_INFO: u32 = 0x0000_0002;
+const HAS_NAME: u32 = 0x0000_0004;
+const HAS_RELATIVE_PATH: u32 = 0x0000_0008;
+const HAS_WORKING_DIR: u32 = 0x0000_0010;
+const HAS_ARGUMENTS: u32 = 0x0000_0020;
+const HAS_ICON_LOCATION: u32 = 0x0000_0040;
+const IS_UNICODE: u32 = 0x0000_0080;
+const FORCE_NO_LINK_INFO: u32 = 0x0000_0100;
+const PREFER_ENVIRONMENT_PATH: u32 = 0x0200_0000;
+
+// LinkInfoFlags
+const VOLUME_ID_AND_LOCAL_BASE_PATH: u32 = 0x1;
+const COMMON_NETWORK_RELATIVE_LINK_AND_PATH_SUFFIX: u32 = 0x2;
+
+// ExtraData block signatures
+const ENVIRONMENT_VARIABLE_DATA_BLOCK: u32 = 0xA000_0001;
+const TRACKER_DATA_BLOCK: u32 = 0xA000_0003;
+const SPECIAL_FOLDER_DATA_BLOCK: u32 = 0xA000_0005;
+const DARWIN_DATA_BLOCK: u32 = 0xA000_0006;
+const ICON_ENVIRONMENT_DATA_BLOCK: u32 = 0xA000_0007;
+const KNOWN_FOLDER_DATA_BLOCK: u32 = 0xA000_000B;
+
+/// "My Computer" shell folder, the usual root of a file system ID list
+const CLSID_MY_COMPUTER: &str = "20D04FE0-3AEA-1069-A2D8-08002B30309D";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LnkError {
+    Io(String),
+    InvalidHeader,
+    Truncated,
+}
+
+impl std::fmt::Display for LnkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LnkError::Io(msg) => write!(f, "读取快捷方式失败: {}", msg),
+            LnkError::InvalidHeader => write!(f, "不是有效的快捷方式文件"),
+            LnkError::Truncated => write!(f, "快捷方式文件已损坏"),
+        }
+    }
+}
+
+/// Window state requested for the launched program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShowCommand {
+    Normal,
+    Maximized,
+    MinNoActive,
+}
+
+impl ShowCommand {
+    fn from_raw(raw: u32) -> ShowCommand {
+        // Any value other than SW_SHOWMAXIMIZED / SW_SHOWMINNOACTIVE means SW_SHOWNORMAL
+        match raw {
+            3 => ShowCommand::Maximized,
+            7 => ShowCommand::MinNoActive,
+            _ => ShowCommand::Normal,
+        }
+    }
+}
+
+/// Location information resolved when the link was created
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LinkInfo {
+    pub drive_type: Option<u32>,
+    pub drive_serial_number: Option<u32>,
+    pub volume_label: Option<String>,
+    pub local_base_path: Option<String>,
+    pub net_name: Option<String>,
+    pub device_name: Option<String>,
+    pub common_path_suffix: Option<String>,
+    /// Whether the base path or share name came from its Unicode copy
+    pub unicode: bool,
+}
+
+impl LinkInfo {
+    /// Full target path: local base path or network share, plus the common suffix
+    pub fn path(&self) -> Option<String> {
+        let suffix = self.common_path_suffix.as_deref().unwrap_or("");
+        if let Some(base) = &self.local_base_path {
+            return Some(format!("{}{}", base, suffix));
+        }
+        self.net_name.as_ref().map(|net| {
+            if suffix.is_empty() {
+                net.clone()
+            } else {
+                format!("{}\\{}", net.trim_end_matches('\\'), suffix)
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShellLink {
+    pub link_flags: u32,
+    pub file_attributes: u32,
+    pub file_size: u32,
+    pub icon_index: i32,
+    pub show_command: ShowCommand,
+    pub hotkey: u16,
+    /// File system path decoded from the LinkTargetIDList, if it describes one
+    pub id_list_path: Option<String>,
+    pub link_info: Option<LinkInfo>,
+    /// Description ("Comment" in the shortcut properties)
+    pub name: Option<String>,
+    pub relative_path: Option<String>,
+    pub working_dir: Option<String>,
+    pub arguments: Option<String>,
+    pub icon_location: Option<String>,
+    /// Unexpanded target from the EnvironmentVariableDataBlock
+    pub environment_target: Option<String>,
+    /// Unexpanded icon path from the IconEnvironmentDataBlock
+    pub icon_environment: Option<String>,
+    /// Windows Installer descriptor of an advertised shortcut
+    pub darwin_data: Option<String>,
+    pub known_folder_id: Option<String>,
+    pub special_folder_id: Option<u32>,
+    /// NetBIOS name of the machine the link was created on
+    pub machine_id: Option<String>,
+}
+
+impl ShellLink {
+    pub fn from_file(path: &Path) -> Result<ShellLink, LnkError> {
+        let data = std::fs::read(path).map_err(|e| LnkError::Io(e.to_string()))?;
+        ShellLink::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<ShellLink, LnkError> {
+        if data.len() < HEADER_SIZE {
+            return Err(LnkError::Truncated);
+        }
+        if read_u32(data, 0)? as usize != HEADER_SIZE || data[4..20] != LINK_CLSID {
+            return Err(LnkError::InvalidHeader);
+        }
+
+        let link_flags = read_u32(data, 0x14)?;
+        let mut link = ShellLink {
+            link_flags,
+            file_attributes: read_u32(data, 0x18)?,
+            file_size: read_u32(data, 0x34)?,
+            icon_index: read_u32(data, 0x38)? as i32,
+            show_command: ShowCommand::from_raw(read_u32(data, 0x3C)?),
+            hotkey: read_u16(data, 0x40)?,
+            id_list_path: None,
+            link_info: None,
+            name: None,
+            relative_path: None,
+            working_dir: None,
+            arguments: None,
+            icon_location: None,
+            environment_target: None,
+            icon_environment: None,
+            darwin_data: None,
+            known_folder_id: None,
+            special_folder_id: None,
+            machine_id: None,
+        };
+
+        let mut offset = HEADER_SIZE;
+
+        if link_flags & HAS_LINK_TARGET_ID_LIST != 0 {
+            let id_list_size = read_u16(data, offset)? as usize;
+            let id_list = slice(data, offset + 2, id_list_size)?;
+            link.id_list_path = parse_id_list(id_list);
+            offset += 2 + id_list_size;
+        }
+
+        if link_flags & HAS_LINK_INFO != 0 {
+            let link_info_size = read_u32(data, offset)? as usize;
+            let link_info = slice(data, offset, link_info_size)?;
+            if link_flags & FORCE_NO_LINK_INFO == 0 {
+                link.link_info = Some(parse_link_info(link_info)?);
+            }
+            offset += link_info_size;
+        }
+
+        let unicode = link_flags & IS_UNICODE != 0;
+        let string_fields = [
+            (HAS_NAME, &mut link.name),
+            (HAS_RELATIVE_PATH, &mut link.relative_path),
+            (HAS_WORKING_DIR, &mut link.working_dir),
+            (HAS_ARGUMENTS, &mut link.arguments),
+            (HAS_ICON_LOCATION, &mut link.icon_location),
+        ];
+        for (flag, field) in string_fields {
+            if link_flags & flag != 0 {
+                let (value, size) = read_string_data(data, offset, unicode)?;
+                *field = Some(value).filter(|s| !s.is_empty());
+                offset += size;
+            }
+        }
+
+        parse_extra_data(data, offset, &mut link);
+
+        Ok(link)
+    }
+
+    /// Best-effort target path, in the order the shell itself tries them
+    pub fn target_path(&self) -> Option<String> {
+        let environment = self.environment_target.as_deref().map(expand_environment_strings);
+        if self.link_flags & PREFER_ENVIRONMENT_PATH != 0 && environment.is_some() {
+            return environment;
+        }
+
+        // An ANSI LinkInfo path depends on the code page, so the ID list comes first
+        let link_info_path = |unicode: bool| {
+            self.link_info
+                .as_ref()
+                .filter(|info| info.unicode == unicode)
+                .and_then(LinkInfo::path)
+        };
+        link_info_path(true)
+            .or_else(|| self.id_list_path.clone())
+            .or_else(|| link_info_path(false))
+            .or(environment)
+    }
+
+    /// Like `target_path`, falling back to the relative path from the link's own folder
+    pub fn resolve_target(&self, link_path: &Path) -> Option<String> {
+        self.target_path().or_else(|| {
+            let relative = self.relative_path.as_ref()?;
+            let folder = link_path.parent()?;
+            Some(folder.join(relative).to_string_lossy().to_string())
+        })
+    }
+
+    /// Icon file and index, falling back to the target itself
+    pub fn icon(&self) -> Option<(String, i32)> {
+        let icon_path = self.icon_environment
+            .as_deref()
+            .map(expand_environment_strings)
+            .or_else(|| self.icon_location.as_deref().map(expand_environment_strings));
+
+        match icon_path {
+            Some(path) if !path.is_empty() => Some((path, self.icon_index)),
+            _ => self.target_path().map(|path| (path, 0)),
+        }
+    }
+
+    /// Command line equivalent to launching the shortcut
+    pub fn command_line(&self, link_path: &Path) -> Option<String> {
+        let target = self.resolve_target(link_path)?;
+        match self.arguments.as_deref().map(str::trim) {
+            Some(args) if !args.is_empty() => {
+                if target.contains(' ') {
+                    Some(format!("\"{}\" {}", target, args))
+                } else {
+                    Some(format!("{} {}", target, args))
+                }
+            }
+            _ => Some(target),
+        }
+    }
+}
+
+/// SW_SHOWNORMAL
+const SHOW_NORMAL: u32 = 1;
+/// DRIVE_FIXED
+const DRIVE_FIXED: u32 = 3;
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_unicode_z(out: &mut Vec<u8>, value: &str) {
+    for unit in value.encode_utf16().chain(std::iter::once(0)) {
+        push_u16(out, unit);
+    }
+}
+
+/// LinkInfo with a VolumeID and the local path, in both ANSI and Unicode.
+/// Characters outside ASCII only appear in the Unicode copy, which the shell prefers.
+fn build_link_info(target: &str) -> Vec<u8> {
+    const HEADER: u32 = 0x24;
+    const VOLUME_ID_SIZE: u32 = 0x11;
+
+    let ansi: Vec<u8> = target.chars().map(|c| if c.is_ascii() { c as u8 } else { b'?' }).collect();
+    let volume_id_offset = HEADER;
+    let local_base_path_offset = volume_id_offset + VOLUME_ID_SIZE;
+    let common_path_suffix_offset = local_base_path_offset + ansi.len() as u32 + 1;
+    let local_base_path_unicode_offset = common_path_suffix_offset + 1;
+    let common_path_suffix_unicode_offset =
+        local_base_path_unicode_offset + (target.encode_utf16().count() as u32 + 1) * 2;
+    let size = common_path_suffix_unicode_offset + 2;
+
+    let mut info = Vec::with_capacity(size as usize);
+    push_u32(&mut info, size);
+    push_u32(&mut info, HEADER);
+    push_u32(&mut info, VOLUME_ID_AND_LOCAL_BASE_PATH);
+    push_u32(&mut info, volume_id_offset);
+    push_u32(&mut info, local_base_path_offset);
+    push_u32(&mut info, 0); // CommonNetworkRelativeLinkOffset
+    push_u32(&mut info, common_path_suffix_offset);
+    push_u32(&mut info, local_base_path_unicode_offset);
+    push_u32(&mut info, common_path_suffix_unicode_offset);
+
+    // VolumeID: fixed drive, unknown serial number, empty label
+    push_u32(&mut info, VOLUME_ID_SIZE);
+    push_u32(&mut info, DRIVE_FIXED);
+    push_u32(&mut info, 0);
+    push_u32(&mut info, 0x10);
+    info.push(0);
+
+    info.extend_from_slice(&ansi);
+    info.push(0);
+    info.push(0); // empty CommonPathSuffix
+    push_unicode_z(&mut info, target);
+    push_u16(&mut info, 0); // empty CommonPathSuffixUnicode
+
+    info
+}
+
+/// A shortcut to a local program, as Explorer would create it without an ID list
+pub fn build_shortcut(target: &str, arguments: Option<&str>, working_dir: Option<&str>) -> Vec<u8> {
+    let arguments = arguments.map(str::trim).filter(|s| !s.is_empty());
+    let working_dir = working_dir.map(str::trim).filter(|s| !s.is_empty());
+
+    let mut link_flags = HAS_LINK_INFO | IS_UNICODE;
+    if working_dir.is_some() {
+        link_flags |= HAS_WORKING_DIR;
+    }
+    if arguments.is_some() {
+        link_flags |= HAS_ARGUMENTS;
+    }
+
+    let mut data = Vec::new();
+    push_u32(&mut data, HEADER_SIZE as u32);
+    data.extend_from_slice(&LINK_CLSID);
+    push_u32(&mut data, link_flags);
+    push_u32(&mut data, 0); // FileAttributes
+    data.resize(0x34, 0); // creation, access and write times
+    push_u32(&mut data, 0); // FileSize
+    push_u32(&mut data, 0); // IconIndex
+    push_u32(&mut data, SHOW_NORMAL);
+    push_u16(&mut data, 0); // HotKey
+    data.resize(HEADER_SIZE, 0);
+
+    data.extend_from_slice(&build_link_info(target));
+
+    // StringData entries follow the order of their flags
+    for value in [working_dir, arguments].into_iter().flatten() {
+        let units: Vec<u16> = value.encode_utf16().collect();
+        push_u16(&mut data, units.len() as u16);
+        for unit in units {
+            push_u16(&mut data, unit);
+        }
+    }
+
+    push_u32(&mut data, 0); // TerminalBlock
+    data
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> Result<&[u8], LnkError> {
+    data.get(offset..offset.checked_add(len).ok_or(LnkError::Truncated)?)
+        .ok_or(LnkError::Truncated)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, LnkError> {
+    let bytes = slice(data, offset, 2)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, LnkError> {
+    let bytes = slice(data, offset, 4)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Decode a string in the system ANSI code page
+#[cfg(windows)]
+fn decode_ansi(bytes: &[u8]) -> String {
+    use windows::Win32::Globalization::{MultiByteToWideChar, CP_ACP, MULTI_BYTE_TO_WIDE_CHAR_FLAGS};
+
+    if bytes.is_ascii() {
+        return String::from_utf8_lossy(bytes).to_string();
+    }
+    let flags = MULTI_BYTE_TO_WIDE_CHAR_FLAGS(0);
+    let len = unsafe { MultiByteToWideChar(CP_ACP, flags, bytes, None) };
+    if len <= 0 {
+        return String::from_utf8_lossy(bytes).to_string();
+    }
+    let mut wide = vec![0u16; len as usize];
+    let len = unsafe { MultiByteToWideChar(CP_ACP, flags, bytes, Some(&mut wide)) };
+    String::from_utf16_lossy(&wide[..len.max(0) as usize])
+}
+
+/// Decode a string in the system ANSI code page.
+///
+/// There is none outside Windows: UTF-8 is taken as is and anything else is
+/// read as Latin-1.
+#[cfg(not(windows))]
+fn decode_ansi(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// NUL-terminated single-byte string starting at `offset`
+fn read_ansi_z(data: &[u8], offset: usize) -> Option<String> {
+    read_ansi_z_raw(data, offset).map(|(text, _)| text)
+}
+
+/// NUL-terminated single-byte string starting at `offset`, with its length in bytes before decoding
+fn read_ansi_z_raw(data: &[u8], offset: usize) -> Option<(String, usize)> {
+    let rest = data.get(offset..)?;
+    let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+    Some((decode_ansi(&rest[..end]), end))
+}
+
+/// NUL-terminated UTF-16LE string starting at `offset`
+fn read_unicode_z(data: &[u8], offset: usize) -> Option<String> {
+    let rest = data.get(offset..)?;
+    let units: Vec<u16> = rest
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    Some(String::from_utf16_lossy(&units))
+}
+
+/// Read one StringData entry, returning the string and the bytes consumed
+fn read_string_data(data: &[u8], offset: usize, unicode: bool) -> Result<(String, usize), LnkError> {
+    let count = read_u16(data, offset)? as usize;
+    if unicode {
+        let bytes = slice(data, offset + 2, count * 2)?;
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        Ok((String::from_utf16_lossy(&units), 2 + count * 2))
+    } else {
+        let bytes = slice(data, offset + 2, count)?;
+        Ok((decode_ansi(bytes), 2 + count))
+    }
+}
+
+fn format_guid(bytes: &[u8]) -> Option<String> {
+    if bytes.len() < 16 {
+        return None;
+    }
+    Some(format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+        u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        u16::from_le_bytes([bytes[4], bytes[5]]),
+        u16::from_le_bytes([bytes[6], bytes[7]]),
+        bytes[8], bytes[9], bytes[10], bytes[11], bytes[12], bytes[13], bytes[14], bytes[15],
+    ))
+}
+
+fn parse_link_info(info: &[u8]) -> Result<LinkInfo, LnkError> {
+    let header_size = read_u32(info, 4)? as usize;
+    let flags = read_u32(info, 8)?;
+    let volume_id_offset = read_u32(info, 12)? as usize;
+    let local_base_path_offset = read_u32(info, 16)? as usize;
+    let network_link_offset = read_u32(info, 20)? as usize;
+    let common_path_suffix_offset = read_u32(info, 24)? as usize;
+
+    // The optional Unicode offsets are present when the header is at least 0x24 bytes
+    let (local_base_path_unicode, common_path_suffix_unicode) = if header_size >= 0x24 {
+        (Some(read_u32(info, 28)? as usize), Some(read_u32(info, 32)? as usize))
+    } else {
+        (None, None)
+    };
+
+    let mut result = LinkInfo::default();
+
+    if flags & VOLUME_ID_AND_LOCAL_BASE_PATH != 0 {
+        if volume_id_offset != 0 {
+            let volume = info.get(volume_id_offset..).ok_or(LnkError::Truncated)?;
+            result.drive_type = read_u32(volume, 4).ok();
+            result.drive_serial_number = read_u32(volume, 8).ok();
+            if let Ok(label_offset) = read_u32(volume, 12) {
+                result.volume_label = if label_offset == 0x14 {
+                    read_u32(volume, 16)
+                        .ok()
+                        .and_then(|unicode_offset| read_unicode_z(volume, unicode_offset as usize))
+                } else {
+                    read_ansi_z(volume, label_offset as usize)
+                };
+            }
+        }
+
+        result.local_base_path = match local_base_path_unicode {
+            Some(o) if o != 0 => {
+                result.unicode = true;
+                read_unicode_z(info, o)
+            }
+            _ => read_ansi_z(info, local_base_path_offset),
+        };
+    }
+
+    if flags & COMMON_NETWORK_RELATIVE_LINK_AND_PATH_SUFFIX != 0 && network_link_offset != 0 {
+        let network = info.get(network_link_offset..).ok_or(LnkError::Truncated)?;
+        let net_name_offset = read_u32(network, 8)? as usize;
+        let device_name_offset = read_u32(network, 12)? as usize;
+        if net_name_offset > 0x14 {
+            result.unicode = true;
+            result.net_name = read_u32(network, 20)
+                .ok()
+                .and_then(|o| read_unicode_z(network, o as usize));
+            result.device_name = read_u32(network, 24)
+                .ok()
+                .filter(|&o| o != 0)
+                .and_then(|o| read_unicode_z(network, o as usize));
+        } else {
+            result.net_name = read_ansi_z(network, net_name_offset);
+            if device_name_offset != 0 {
+                result.device_name = read_ansi_z(network, device_name_offset);
+            }
+        }
+    }
+
+    result.common_path_suffix = match common_path_suffix_unicode {
+        Some(o) if o != 0 => read_unicode_z(info, o),
+        _ => read_ansi_z(info, common_path_suffix_offset),
+    }
+    .filter(|s| !s.is_empty());
+
+    Ok(result)
+}
+
+/// Decode the shell items of an ID list into a file system path.
+///
+/// Handles the common "My Computer → drive → folders → file" chain; any other
+/// shell namespace (control panel items, libraries, ...) yields `None`.
+fn parse_id_list(id_list: &[u8]) -> Option<String> {
+    let mut offset = 0;
+    let mut path = String::new();
+
+    while offset + 2 <= id_list.len() {
+        let size = u16::from_le_bytes([id_list[offset], id_list[offset + 1]]) as usize;
+        if size == 0 {
+            break;
+        }
+        let item = id_list.get(offset..offset + size)?;
+        offset += size;
+
+        let class_type = *item.get(2)?;
+        match class_type {
+            // Root folder: only "My Computer" leads to a file system path
+            0x1F => {
+                if format_guid(item.get(4..20)?)? != CLSID_MY_COMPUTER {
+                    return None;
+                }
+            }
+            // Volume item, e.g. "C:\"
+            0x20..=0x2F => {
+                let drive = read_ansi_z(item, 3)?;
+                if drive.is_empty() {
+                    return None;
+                }
+                path = drive;
+            }
+            // File entry item (folder or file)
+            0x30..=0x3F => {
+                let name = parse_file_entry_name(item, class_type)?;
+                if !path.is_empty() && !path.ends_with('\\') {
+                    path.push('\\');
+                }
+                path.push_str(&name);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(path).filter(|p| !p.is_empty())
+}
+
+/// Long name of a file entry shell item, falling back to its 8.3 name
+fn parse_file_entry_name(item: &[u8], class_type: u8) -> Option<String> {
+    // Layout: size(2) type(1) unknown(1) file size(4) modified(4) attributes(2) primary name
+    let unicode_primary = class_type & 0x04 != 0;
+    let primary_offset = 14;
+    let (primary_name, primary_len) = if unicode_primary {
+        let name = read_unicode_z(item, primary_offset)?;
+        (name.clone(), (name.encode_utf16().count() + 1) * 2)
+    } else {
+        let (name, len) = read_ansi_z_raw(item, primary_offset)?;
+        (name, len + 1)
+    };
+
+    // The extension block starts on the next 2-byte boundary
+    let mut ext_offset = primary_offset + primary_len;
+    ext_offset += ext_offset % 2;
+
+    parse_beef0004_long_name(item, ext_offset).or(Some(primary_name))
+}
+
+fn parse_beef0004_long_name(item: &[u8], ext_offset: usize) -> Option<String> {
+    let ext = item.get(ext_offset..)?;
+    let ext_size = u16::from_le_bytes([*ext.first()?, *ext.get(1)?]) as usize;
+    let version = u16::from_le_bytes([*ext.get(2)?, *ext.get(3)?]);
+    let signature = u32::from_le_bytes([*ext.get(4)?, *ext.get(5)?, *ext.get(6)?, *ext.get(7)?]);
+    if signature != 0xBEEF_0004 || version < 3 || ext_size > ext.len() {
+        return None;
+    }
+
+    // size(2) version(2) signature(4) created(4) accessed(4) identifier(2)
+    let mut name_offset = 18;
+    if version >= 7 {
+        // unknown(2) file reference(8) unknown(8)
+        name_offset += 18;
+    }
+    // long string size(2)
+    name_offset += 2;
+    if version >= 9 {
+        name_offset += 4;
+    }
+    if version >= 8 {
+        name_offset += 4;
+    }
+
+    read_unicode_z(&ext[..ext_size], name_offset).filter(|s| !s.is_empty())
+}
+
+fn parse_extra_data(data: &[u8], mut offset: usize, link: &mut ShellLink) {
+    // Each block starts with its size and signature; a size below 4 terminates the list
+    while let (Ok(size), Ok(signature)) = (read_u32(data, offset), read_u32(data, offset + 4)) {
+        let size = size as usize;
+        if size < 8 {
+            break;
+        }
+        let Ok(block) = slice(data, offset, size) else {
+            break;
+        };
+
+        match signature {
+            ENVIRONMENT_VARIABLE_DATA_BLOCK => {
+                link.environment_target = read_ansi_unicode_block(block);
+            }
+            ICON_ENVIRONMENT_DATA_BLOCK => {
+                link.icon_environment = read_ansi_unicode_block(block);
+            }
+            DARWIN_DATA_BLOCK => {
+                link.darwin_data = read_ansi_unicode_block(block);
+            }
+            KNOWN_FOLDER_DATA_BLOCK => {
+                link.known_folder_id = block.get(8..24).and_then(format_guid);
+            }
+            SPECIAL_FOLDER_DATA_BLOCK => {
+                link.special_folder_id = read_u32(block, 8).ok();
+            }
+            TRACKER_DATA_BLOCK => {
+                // size(4) signature(4) length(4) version(4) machine id(16)
+                link.machine_id = block.get(16..32)
+                    .and_then(|id| read_ansi_z(id, 0))
+                    .filter(|s| !s.is_empty());
+            }
+            _ => {}
+        }
+
+        offset += size;
+    }
+}
+
+/// Blocks holding a 260-byte ANSI string followed by a 520-byte Unicode one
+fn read_ansi_unicode_block(block: &[u8]) -> Option<String> {
+    let unicode = block.get(268..788).and_then(|b| read_unicode_z(b, 0));
+    unicode
+        .filter(|s| !s.is_empty())
+        .or_else(|| block.get(8..268).and_then(|b| read_ansi_z(b, 0)))
+        .filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNICODE: &[u8] = include_bytes!("../../tests/fixtures/lnk/unicode.lnk");
+    const ANSI_ID_LIST: &[u8] = include_bytes!("../../tests/fixtures/lnk/ansi_id_list.lnk");
+    const NETWORK_ENVIRONMENT: &[u8] = include_bytes!("../../tests/fixtures/lnk/network_environment.lnk");
+
+    /// A file entry shell item with an ANSI primary name and a version 3 BEEF0004 block
+    fn file_entry(primary: &[u8], long_name: &str) -> Vec<u8> {
+        let mut item = vec![0; 14];
+        item[2] = 0x32;
+        item.extend_from_slice(primary);
+        item.push(0);
+        if !item.len().is_multiple_of(2) {
+            item.push(0);
+        }
+
+        let mut ext = vec![0; 20];
+        ext[2..4].copy_from_slice(&3u16.to_le_bytes());
+        ext[4..8].copy_from_slice(&0xBEEF_0004u32.to_le_bytes());
+        ext.extend(long_name.encode_utf16().chain([0]).flat_map(u16::to_le_bytes));
+        ext.extend_from_slice(&[0, 0]);
+        let ext_size = ext.len() as u16;
+        ext[..2].copy_from_slice(&ext_size.to_le_bytes());
+        item.extend(ext);
+
+        let size = item.len() as u16;
+        item[..2].copy_from_slice(&size.to_le_bytes());
+        item
+    }
+
+    #[test]
+    fn finds_the_long_name_after_a_non_ascii_ansi_name() {
+        // "Été.txt" in Windows-1252: 7 bytes, but 9 once decoded to UTF-8
+        let item = file_entry(b"\xc9t\xe9.txt", "Été long name.txt");
+        assert_eq!(parse_file_entry_name(&item, 0x32).as_deref(), Some("Été long name.txt"));
+
+        let item = file_entry(b"PLAIN~1.TXT", "Plain long name.txt");
+        assert_eq!(parse_file_entry_name(&item, 0x32).as_deref(), Some("Plain long name.txt"));
+    }
+
+    #[test]
+    fn reads_link_info_and_string_data() {
+        let link = ShellLink::parse(UNICODE).unwrap();
+        assert_eq!(link.file_size, 4096);
+        assert_eq!(link.icon_index, 2);
+        assert_eq!(link.show_command, ShowCommand::MinNoActive);
+        assert_eq!(link.hotkey, 0x0641);
+
+        let info = link.link_info.as_ref().unwrap();
+        assert!(info.unicode);
+        assert_eq!(info.drive_type, Some(DRIVE_FIXED));
+        assert_eq!(info.drive_serial_number, Some(0x1234_ABCD));
+        assert_eq!(info.volume_label.as_deref(), Some("System"));
+        assert_eq!(info.local_base_path.as_deref(), Some(r"C:\Café\app.exe"));
+
+        assert_eq!(link.name.as_deref(), Some("Café sync"));
+        assert_eq!(link.working_dir.as_deref(), Some(r"C:\Café"));
+        assert_eq!(link.arguments.as_deref(), Some(r#"--tray --profile "Work""#));
+        assert_eq!(link.icon_location.as_deref(), Some(r"%ProgramFiles%\Café\app.ico"));
+        assert_eq!(link.environment_target.as_deref(), Some(r"%ProgramFiles%\Café\app.exe"));
+
+        assert_eq!(link.target_path().as_deref(), Some(r"C:\Café\app.exe"));
+        assert_eq!(
+            link.command_line(Path::new("app.lnk")).as_deref(),
+            Some(r#"C:\Café\app.exe --tray --profile "Work""#)
+        );
+    }
+
+    #[test]
+    fn prefers_the_id_list_over_an_ansi_link_info_path() {
+        let link = ShellLink::parse(ANSI_ID_LIST).unwrap();
+        assert_eq!(link.id_list_path.as_deref(), Some(r"C:\Программы\app.exe"));
+
+        let info = link.link_info.as_ref().unwrap();
+        assert!(!info.unicode);
+        assert!(info.local_base_path.as_deref().unwrap().ends_with(r"\app.exe"));
+
+        assert_eq!(link.relative_path.as_deref(), Some(r"..\..\Programs\app.exe"));
+        assert_eq!(link.arguments.as_deref(), Some("--minimized"));
+        assert_eq!(link.target_path().as_deref(), Some(r"C:\Программы\app.exe"));
+    }
+
+    #[test]
+    fn falls_back_to_the_ansi_path_without_an_id_list() {
+        let mut link = ShellLink::parse(ANSI_ID_LIST).unwrap();
+        link.id_list_path = None;
+        assert_eq!(link.target_path(), link.link_info.as_ref().and_then(LinkInfo::path));
+    }
+
+    #[test]
+    fn reads_network_paths_and_the_environment_block() {
+        let link = ShellLink::parse(NETWORK_ENVIRONMENT).unwrap();
+        let info = link.link_info.as_ref().unwrap();
+        assert_eq!(info.net_name.as_deref(), Some(r"\\server\share"));
+        assert_eq!(info.path().as_deref(), Some(r"\\server\share\tools\app.exe"));
+
+        let environment = r"%SystemRoot%\System32\notepad.exe";
+        assert_eq!(link.environment_target.as_deref(), Some(environment));
+        assert_eq!(link.target_path(), Some(expand_environment_strings(environment)));
+    }
+
+    #[test]
+    fn built_shortcuts_parse_back() {
+        let data = build_shortcut(r"C:\Tools\Café.exe", Some(" --hidden "), Some(r"C:\Tools"));
+        let link = ShellLink::parse(&data).unwrap();
+        assert_eq!(link.target_path().as_deref(), Some(r"C:\Tools\Café.exe"));
+        assert_eq!(link.arguments.as_deref(), Some("--hidden"));
+        assert_eq!(link.working_dir.as_deref(), Some(r"C:\Tools"));
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign_files() {
+        assert_eq!(ShellLink::parse(&UNICODE[..0x40]), Err(LnkError::Truncated));
+        assert_eq!(ShellLink::parse(&UNICODE[..0x80]), Err(LnkError::Truncated));
+
+        let mut data = UNICODE.to_vec();
+        data[4] ^= 0xFF;
+        assert_eq!(ShellLink::parse(&data), Err(LnkError::InvalidHeader));
+    }
+}
@@ -0,0 +1,355 @@
+//! Catalog of the registry locations Windows starts programs from.
+//!
+//! Each location describes how its entries are stored and how one of them is
+//! switched off without losing it. The scanner and the manager both work from
+//! this table, so adding a location here is enough to list and manage it.
+
+use super::approved::ApprovalKey;
+use super::commandline::{parse_command_line, CommandEnvironment};
+use super::registry::RegistryHive;
+
+/// Subkey that holds entries switched off by moving them out of the key Windows reads.
+/// Sysinternals Autoruns uses the same name, so entries disabled by either tool show up in both.
+pub const DISABLED_SUBKEY: &str = "AutorunsDisabled";
+
+/// How the entries of a location are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryLayout {
+    /// Every value of the key is one entry (`Run`, `RunOnce`, ...)
+    ValueList,
+    /// A single value with a comma-separated list of programs. Only a value that
+    /// differs from the Windows default is reported; an empty default means the
+    /// value is normally absent.
+    SingleValue {
+        value: &'static str,
+        default: &'static str,
+    },
+    /// Every subkey is one entry with its command in a named value (Active Setup)
+    SubkeyPerEntry { command_value: &'static str },
+    /// Every value of every subkey is one entry (`RunOnceEx`)
+    SubkeyValues,
+}
+
+/// How an entry of a location is disabled and enabled again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisableStrategy {
+    /// A flag under Explorer's `StartupApproved` key, as Task Manager does it
+    StartupApproved { key: ApprovalKey },
+    /// Move the value into the `AutorunsDisabled` subkey and back
+    MoveToDisabledSubkey,
+    /// Park the value in `AutorunsDisabled` and put the Windows default in its place
+    RestoreDefault,
+    /// A DWORD in the entry's subkey, 0 when disabled (Active Setup `IsInstalled`)
+    DwordFlag { value: &'static str },
+    /// Entries Windows consumes on its own, such as `RunOnceEx` batches
+    NotSupported,
+}
+
+pub struct RegistryLocation {
+    pub hive: RegistryHive,
+    pub path: &'static str,
+    /// Short form shown as the item's source
+    pub name: &'static str,
+    pub layout: EntryLayout,
+    pub disable: DisableStrategy,
+}
+
+impl RegistryLocation {
+    /// Path including the hive root, as stored in `StartupItem::source_location`
+    pub fn full_path(&self) -> String {
+        format!("{}\\{}", self.hive.root_name(), self.path)
+    }
+}
+
+pub const REGISTRY_LOCATIONS: &[RegistryLocation] = &[
+    RegistryLocation {
+        hive: RegistryHive::CurrentUser,
+        path: r"Software\Microsoft\Windows\CurrentVersion\Run",
+        name: "HKCU\\...\\Run",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::StartupApproved { key: ApprovalKey::Run },
+    },
+    RegistryLocation {
+        hive: RegistryHive::CurrentUser,
+        path: r"Software\Microsoft\Windows\CurrentVersion\RunOnce",
+        name: "HKCU\\...\\RunOnce",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::CurrentUser,
+        path: r"Software\Microsoft\Windows\CurrentVersion\Policies\Explorer\Run",
+        name: "HKCU\\...\\Policies\\Explorer\\Run",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::CurrentUser,
+        path: r"Software\Microsoft\Windows\CurrentVersion\RunServices",
+        name: "HKCU\\...\\RunServices",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::CurrentUser,
+        path: r"Software\Microsoft\Windows\CurrentVersion\RunServicesOnce",
+        name: "HKCU\\...\\RunServicesOnce",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::CurrentUser,
+        path: r"Software\Microsoft\Windows NT\CurrentVersion\Winlogon",
+        name: "HKCU\\...\\Winlogon\\Shell",
+        layout: EntryLayout::SingleValue { value: "Shell", default: "" },
+        disable: DisableStrategy::RestoreDefault,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows\CurrentVersion\Run",
+        name: "HKLM\\...\\Run",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::StartupApproved { key: ApprovalKey::Run },
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows\CurrentVersion\RunOnce",
+        name: "HKLM\\...\\RunOnce",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows\CurrentVersion\RunOnceEx",
+        name: "HKLM\\...\\RunOnceEx",
+        layout: EntryLayout::SubkeyValues,
+        disable: DisableStrategy::NotSupported,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\WOW6432Node\Microsoft\Windows\CurrentVersion\Run",
+        name: "HKLM\\...\\Run (32位)",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::StartupApproved { key: ApprovalKey::Run32 },
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\WOW6432Node\Microsoft\Windows\CurrentVersion\RunOnce",
+        name: "HKLM\\...\\RunOnce (32位)",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows\CurrentVersion\Policies\Explorer\Run",
+        name: "HKLM\\...\\Policies\\Explorer\\Run",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows\CurrentVersion\RunServices",
+        name: "HKLM\\...\\RunServices",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows\CurrentVersion\RunServicesOnce",
+        name: "HKLM\\...\\RunServicesOnce",
+        layout: EntryLayout::ValueList,
+        disable: DisableStrategy::MoveToDisabledSubkey,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows NT\CurrentVersion\Winlogon",
+        name: "HKLM\\...\\Winlogon\\Shell",
+        layout: EntryLayout::SingleValue { value: "Shell", default: "explorer.exe" },
+        disable: DisableStrategy::RestoreDefault,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Windows NT\CurrentVersion\Winlogon",
+        name: "HKLM\\...\\Winlogon\\Userinit",
+        layout: EntryLayout::SingleValue {
+            value: "Userinit",
+            default: r"%SystemRoot%\system32\userinit.exe,",
+        },
+        disable: DisableStrategy::RestoreDefault,
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\Microsoft\Active Setup\Installed Components",
+        name: "HKLM\\...\\Active Setup",
+        layout: EntryLayout::SubkeyPerEntry { command_value: "StubPath" },
+        disable: DisableStrategy::DwordFlag { value: "IsInstalled" },
+    },
+    RegistryLocation {
+        hive: RegistryHive::LocalMachine,
+        path: r"Software\WOW6432Node\Microsoft\Active Setup\Installed Components",
+        name: "HKLM\\...\\Active Setup (32位)",
+        layout: EntryLayout::SubkeyPerEntry { command_value: "StubPath" },
+        disable: DisableStrategy::DwordFlag { value: "IsInstalled" },
+    },
+];
+
+/// Find the location an item came from. Single-value locations share their key
+/// (Winlogon), so the entry name picks between them.
+pub fn find_location(hive: RegistryHive, path: &str, entry_name: &str) -> Option<&'static RegistryLocation> {
+    REGISTRY_LOCATIONS.iter().find(|location| {
+        location.hive == hive
+            && location.path.eq_ignore_ascii_case(path)
+            && match location.layout {
+                EntryLayout::SingleValue { value, .. } => value.eq_ignore_ascii_case(entry_name),
+                _ => true,
+            }
+    })
+}
+
+/// Programs in a single-value location that Windows does not put there itself,
+/// compared by file name (`C:\Windows\system32\userinit.exe` matches `userinit.exe`)
+pub fn extra_programs(value: &str, default: &str, env: &dyn CommandEnvironment) -> Vec<String> {
+    let file_name = |program: &str| {
+        let executable = parse_command_line(program, env).executable;
+        executable.rsplit(['\\', '/']).next().unwrap_or(&executable).to_lowercase()
+    };
+    let defaults: Vec<String> = default
+        .split(',')
+        .filter(|p| !p.trim().is_empty())
+        .map(file_name)
+        .collect();
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .filter(|p| !defaults.contains(&file_name(p)))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::manager::{toggle_registry_item, StartupError};
+    use crate::startup::registry::{MemoryRegistry, RegValueType, RegistryBackend, RegistryValue};
+    use crate::startup::scanner::{scan_registry_items, ScanContext};
+    use crate::startup::StartupItem;
+
+    const WINLOGON: &str = r"Software\Microsoft\Windows NT\CurrentVersion\Winlogon";
+    const ACTIVE_SETUP: &str = r"Software\Microsoft\Active Setup\Installed Components";
+    const RUN_ONCE_EX: &str = r"Software\Microsoft\Windows\CurrentVersion\RunOnceEx";
+    const HKLM: RegistryHive = RegistryHive::LocalMachine;
+
+    fn set_string(registry: &MemoryRegistry, path: &str, name: &str, value: &str) {
+        registry.create_subkey(HKLM, path).unwrap();
+        registry.set_raw_value(HKLM, path, name, &RegistryValue::from_string(value, RegValueType::Sz)).unwrap();
+    }
+
+    fn get_string(registry: &MemoryRegistry, path: &str, name: &str) -> Option<String> {
+        registry.get_raw_value(HKLM, path, name).ok()?.as_string()
+    }
+
+    fn scanned(registry: &MemoryRegistry, source_location: &str) -> Vec<StartupItem> {
+        let source_location = format!("{}\\{}", HKLM.root_name(), source_location);
+        scan_registry_items(&ScanContext::with_registry(registry))
+            .into_iter()
+            .filter(|item| item.source_location == source_location)
+            .collect()
+    }
+
+    #[test]
+    fn extra_programs_compare_whole_paths() {
+        let registry = MemoryRegistry::new();
+        let env = ScanContext::with_registry(&registry);
+        let extra = |value: &str, default: &str| extra_programs(value, default, &env);
+
+        assert!(extra("explorer.exe", "explorer.exe").is_empty());
+        assert!(extra(r"C:\Windows\explorer.exe /separate", "explorer.exe").is_empty());
+        // Unquoted paths with spaces are not cut at the first one
+        assert!(extra(r"C:\Program Files\Shell Tools\explorer.exe", "explorer.exe").is_empty());
+        assert_eq!(
+            extra(r"explorer.exe, C:\Program Files\Foo\foo.exe -x", "explorer.exe"),
+            [r"C:\Program Files\Foo\foo.exe -x"]
+        );
+        assert!(extra(r#""C:\Program Files\Foo\program.exe""#, "program.exe, other.exe").is_empty());
+        assert_eq!(
+            extra(r"C:\Windows\system32\userinit.exe,C:\Program Files\Foo\foo.exe,", r"%SystemRoot%\system32\userinit.exe,"),
+            [r"C:\Program Files\Foo\foo.exe"]
+        );
+    }
+
+    #[test]
+    fn single_values_are_parked_and_restored_to_the_default() {
+        let registry = MemoryRegistry::new();
+        let disabled_path = format!("{}\\{}", WINLOGON, DISABLED_SUBKEY);
+        set_string(&registry, WINLOGON, "Shell", "explorer.exe");
+        assert!(scanned(&registry, WINLOGON).is_empty());
+
+        let shell = r"explorer.exe, C:\Program Files\Foo\foo.exe";
+        set_string(&registry, WINLOGON, "Shell", shell);
+        let items = scanned(&registry, WINLOGON);
+        assert_eq!(items.len(), 1);
+        let item = &items[0];
+        assert_eq!((item.entry_name.as_str(), item.command.as_str()), ("Shell", shell));
+        assert_eq!(item.path, r"C:\Program Files\Foo\foo.exe");
+        assert!(item.enabled);
+
+        toggle_registry_item(&registry, item, false).unwrap();
+        assert_eq!(get_string(&registry, WINLOGON, "Shell").as_deref(), Some("explorer.exe"));
+        assert_eq!(get_string(&registry, &disabled_path, "Shell").as_deref(), Some(shell));
+        let item = &scanned(&registry, WINLOGON)[0];
+        assert_eq!(item.command, shell);
+        assert!(!item.enabled);
+
+        toggle_registry_item(&registry, item, true).unwrap();
+        assert_eq!(get_string(&registry, WINLOGON, "Shell").as_deref(), Some(shell));
+        assert!(get_string(&registry, &disabled_path, "Shell").is_none());
+        assert!(scanned(&registry, WINLOGON)[0].enabled);
+    }
+
+    #[test]
+    fn active_setup_components_are_switched_by_their_flag() {
+        let registry = MemoryRegistry::new();
+        let component = format!("{}\\{{CONTOSO}}", ACTIVE_SETUP);
+        set_string(&registry, &component, "", "Contoso Setup");
+        set_string(&registry, &component, "StubPath", r"C:\Contoso\setup.exe /user");
+        registry.set_raw_value(HKLM, &component, "IsInstalled", &RegistryValue::from_dword(1)).unwrap();
+        // A resource title falls back to the subkey name, and a component without a command is skipped
+        set_string(&registry, &format!("{}\\{{FABRIKAM}}", ACTIVE_SETUP), "", "@fabrikam.dll,-100");
+        set_string(&registry, &format!("{}\\{{FABRIKAM}}", ACTIVE_SETUP), "StubPath", r"C:\Fabrikam\stub.exe");
+        set_string(&registry, &format!("{}\\{{EMPTY}}", ACTIVE_SETUP), "", "No command");
+
+        let mut items = scanned(&registry, ACTIVE_SETUP);
+        items.sort_by(|a, b| a.entry_name.cmp(&b.entry_name));
+        let names: Vec<(&str, &str)> = items.iter().map(|item| (item.entry_name.as_str(), item.name.as_str())).collect();
+        assert_eq!(names, [("{CONTOSO}", "Contoso Setup"), ("{FABRIKAM}", "{FABRIKAM}")]);
+        assert!(items.iter().all(|item| item.enabled));
+
+        let flag = || registry.get_raw_value(HKLM, &component, "IsInstalled").unwrap().as_dword();
+        toggle_registry_item(&registry, &items[0], false).unwrap();
+        assert_eq!(flag(), Some(0));
+        assert!(!scanned(&registry, ACTIVE_SETUP).iter().find(|item| item.entry_name == "{CONTOSO}").unwrap().enabled);
+
+        toggle_registry_item(&registry, &items[0], true).unwrap();
+        assert_eq!(flag(), Some(1));
+    }
+
+    #[test]
+    fn run_once_ex_batches_list_every_command() {
+        let registry = MemoryRegistry::new();
+        let batch = format!("{}\\0001", RUN_ONCE_EX);
+        set_string(&registry, &batch, "", "Finishing setup");
+        set_string(&registry, &batch, "1", r"C:\Setup\step1.exe");
+        set_string(&registry, &batch, "2", r"C:\Setup\step2.exe");
+
+        let mut items = scanned(&registry, RUN_ONCE_EX);
+        items.sort_by(|a, b| a.entry_name.cmp(&b.entry_name));
+        let entries: Vec<&str> = items.iter().map(|item| item.entry_name.as_str()).collect();
+        assert_eq!(entries, [r"0001\1", r"0001\2"]);
+        assert_eq!(items[0].command, r"C:\Setup\step1.exe");
+
+        // Windows runs and removes these on its own
+        assert!(matches!(toggle_registry_item(&registry, &items[0], false), Err(StartupError::NotSupported)));
+    }
+}
@@ -1,8 +1,18 @@
-use std::path::PathBuf;
-use winreg::enums::*;
-use winreg::RegKey;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 
-use super::StartupItem;
+use super::{NewStartupItem, Scope, SourceType, StartupItem};
+use super::sources::source_for;
+use super::registry::{system_registry, RegistryBackend, RegistryHive, RegistryValue, RegValueType};
+use super::tasks;
+use super::approved::{self, ApprovalKey, ApprovalRecord};
+use super::scanner::{get_startup_folder_path, startup_folder_hive};
+use super::lnk::{self, ShellLink};
+use super::commandline::expand_environment_strings;
+use super::history::{self, Operation, Target};
+use super::snapshot::SnapshotItem;
+use super::{baseline, services, systemd, xdg};
+use super::locations::{self, DISABLED_SUBKEY, DisableStrategy, EntryLayout, RegistryLocation};
 
 #[derive(Debug)]
 pub enum StartupError {
@@ -10,6 +20,9 @@ pub enum StartupError {
     AccessDenied,
     IoError(String),
     RegistryError(String),
+    NotSupported,
+    AlreadyExists,
+    InvalidInput(String),
 }
 
 impl std::fmt::Display for StartupError {
@@ -19,175 +32,1316 @@ impl std::fmt::Display for StartupError {
             StartupError::AccessDenied => write!(f, "访问被拒绝，可能需要管理员权限"),
             StartupError::IoError(msg) => write!(f, "IO错误: {}", msg),
             StartupError::RegistryError(msg) => write!(f, "注册表错误: {}", msg),
+            StartupError::NotSupported => write!(f, "该启动项不支持此操作"),
+            StartupError::AlreadyExists => write!(f, "同名启动项已存在"),
+            StartupError::InvalidInput(msg) => write!(f, "参数无效: {}", msg),
         }
     }
 }
 
-fn parse_registry_location(location: &str) -> Option<(winreg::HKEY, String)> {
+fn parse_registry_location(location: &str) -> Option<(RegistryHive, String)> {
     if location.starts_with("HKEY_CURRENT_USER\\") {
-        Some((HKEY_CURRENT_USER, location.replace("HKEY_CURRENT_USER\\", "")))
+        Some((RegistryHive::CurrentUser, location.replace("HKEY_CURRENT_USER\\", "")))
     } else if location.starts_with("HKEY_LOCAL_MACHINE\\") {
-        Some((HKEY_LOCAL_MACHINE, location.replace("HKEY_LOCAL_MACHINE\\", "")))
+        Some((RegistryHive::LocalMachine, location.replace("HKEY_LOCAL_MACHINE\\", "")))
     } else {
         None
     }
 }
 
-pub fn toggle_registry_item(item: &StartupItem, enable: bool) -> Result<(), StartupError> {
-    let (hkey, reg_path) = parse_registry_location(&item.source_location)
+fn registry_error(e: std::io::Error) -> StartupError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => StartupError::NotFound,
+        std::io::ErrorKind::PermissionDenied => StartupError::AccessDenied,
+        _ => StartupError::RegistryError(e.to_string()),
+    }
+}
+
+fn io_error(e: std::io::Error) -> StartupError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => StartupError::NotFound,
+        std::io::ErrorKind::PermissionDenied => StartupError::AccessDenied,
+        _ => StartupError::IoError(e.to_string()),
+    }
+}
+
+/// Find the catalog location of a registry item, with the key path relative to its hive
+fn registry_location_for(item: &StartupItem) -> Result<(&'static RegistryLocation, String), StartupError> {
+    let (hive, reg_path) = parse_registry_location(&item.source_location)
+        .ok_or(StartupError::NotFound)?;
+    let location = locations::find_location(hive, &reg_path, &item.entry_name)
         .ok_or(StartupError::NotFound)?;
+    Ok((location, reg_path))
+}
 
-    let root = RegKey::predef(hkey);
+/// Move a value between two keys, creating the destination if needed
+fn move_value(
+    registry: &dyn RegistryBackend,
+    hive: RegistryHive,
+    from: &str,
+    to: &str,
+    name: &str,
+) -> Result<(), StartupError> {
+    let value = registry.get_raw_value(hive, from, name).map_err(registry_error)?;
+    registry.create_subkey(hive, to).map_err(registry_error)?;
+    registry.set_raw_value(hive, to, name, &value).map_err(registry_error)?;
+    registry.delete_value(hive, from, name).map_err(registry_error)
+}
 
-    // Use StartupApproved mechanism (Windows 8+)
-    let approval_path = if reg_path.contains("RunOnce") {
-        reg_path.replace("RunOnce", "Explorer\\StartupApproved\\RunOnce")
+/// Put the Windows default back into a single-value location, or remove the value
+/// if Windows does not set it at all
+fn write_default_value(
+    registry: &dyn RegistryBackend,
+    hive: RegistryHive,
+    path: &str,
+    name: &str,
+    default: &str,
+) -> Result<(), StartupError> {
+    if default.is_empty() {
+        match registry.delete_value(hive, path, name) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(registry_error(e)),
+            _ => Ok(()),
+        }
     } else {
-        reg_path.replace("Run", "Explorer\\StartupApproved\\Run")
-    };
+        // Winlogon reads these as plain REG_SZ; a wrong Userinit blocks every logon
+        let default = expand_environment_strings(default);
+        registry
+            .set_raw_value(hive, path, name, &RegistryValue::from_string(&default, RegValueType::Sz))
+            .map_err(registry_error)
+    }
+}
+
+pub fn toggle_registry_item(
+    registry: &dyn RegistryBackend,
+    item: &StartupItem,
+    enable: bool,
+) -> Result<(), StartupError> {
+    let (location, reg_path) = registry_location_for(item)?;
+    let hive = location.hive;
+    let disabled_path = format!("{}\\{}", reg_path, DISABLED_SUBKEY);
+
+    match location.disable {
+        DisableStrategy::StartupApproved { key } => {
+            approved::write_approval(registry, hive, key, &item.entry_name, enable).map_err(registry_error)
+        }
+        DisableStrategy::MoveToDisabledSubkey => {
+            let currently_enabled = registry.get_raw_value(hive, &reg_path, &item.entry_name).is_ok();
+            match (enable, currently_enabled) {
+                (true, false) => move_value(registry, hive, &disabled_path, &reg_path, &item.entry_name),
+                (false, true) => move_value(registry, hive, &reg_path, &disabled_path, &item.entry_name),
+                // Already in desired state
+                _ => Ok(()),
+            }
+        }
+        DisableStrategy::RestoreDefault => {
+            let EntryLayout::SingleValue { value, default } = location.layout else {
+                return Err(StartupError::NotSupported);
+            };
+            let parked = registry.get_raw_value(hive, &disabled_path, value).ok();
+
+            match (enable, parked) {
+                (true, Some(parked)) => {
+                    registry.set_raw_value(hive, &reg_path, value, &parked).map_err(registry_error)?;
+                    registry.delete_value(hive, &disabled_path, value).map_err(registry_error)
+                }
+                (false, None) => {
+                    let current = registry.get_raw_value(hive, &reg_path, value).map_err(registry_error)?;
+                    registry.create_subkey(hive, &disabled_path).map_err(registry_error)?;
+                    registry.set_raw_value(hive, &disabled_path, value, &current).map_err(registry_error)?;
+                    write_default_value(registry, hive, &reg_path, value, default)
+                }
+                _ => Ok(()),
+            }
+        }
+        DisableStrategy::DwordFlag { value } => {
+            let entry_path = format!("{}\\{}", reg_path, item.entry_name);
+            registry
+                .set_raw_value(hive, &entry_path, value, &RegistryValue::from_dword(enable as u32))
+                .map_err(registry_error)
+        }
+        DisableStrategy::NotSupported => Err(StartupError::NotSupported),
+    }
+}
+
+/// The file of a folder item and the name its approval record is kept under.
+/// Files renamed to `.disabled` by older versions are approved by their original name.
+fn folder_item_file(item: &StartupItem) -> Result<(PathBuf, String), StartupError> {
+    let file_path = PathBuf::from(&item.source_location).join(&item.entry_name);
+    if !file_path.exists() {
+        return Err(StartupError::NotFound);
+    }
+
+    let approval_name = item
+        .entry_name
+        .strip_suffix(".disabled")
+        .unwrap_or(&item.entry_name)
+        .to_string();
+    Ok((file_path, approval_name))
+}
+
+pub fn toggle_folder_item(
+    registry: &dyn RegistryBackend,
+    item: &StartupItem,
+    enable: bool,
+) -> Result<(), StartupError> {
+    let (file_path, approval_name) = folder_item_file(item)?;
+    let hive = startup_folder_hive(Path::new(&item.source_location));
+
+    // Give a legacy `.disabled` file its name back, then let the approval record decide
+    if enable && approval_name != item.entry_name {
+        let restored = file_path.with_file_name(&approval_name);
+        std::fs::rename(&file_path, &restored).map_err(io_error)?;
+    }
+
+    approved::write_approval(registry, hive, ApprovalKey::StartupFolder, &approval_name, enable)
+        .map_err(registry_error)
+}
+
+/// Key new registry entries are written to, below the hive of their scope
+const RUN_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+
+fn scope_hive(scope: Scope) -> RegistryHive {
+    match scope {
+        Scope::CurrentUser => RegistryHive::CurrentUser,
+        Scope::AllUsers => RegistryHive::LocalMachine,
+    }
+}
+
+fn validate_new_item(new_item: &NewStartupItem) -> Result<(), StartupError> {
+    let name = new_item.name.trim();
+    if name.is_empty() {
+        return Err(StartupError::InvalidInput("名称不能为空".to_string()));
+    }
+    if name.contains(['\\', '/', ':', '*', '?', '"', '<', '>', '|']) {
+        return Err(StartupError::InvalidInput("名称不能包含 \\ / : * ? \" < > |".to_string()));
+    }
+    if new_item.path.trim().is_empty() {
+        return Err(StartupError::InvalidInput("程序路径不能为空".to_string()));
+    }
+    Ok(())
+}
 
-    // Create or open the approval key
-    let approval_key = root
-        .create_subkey(&approval_path)
-        .map_err(|e| StartupError::RegistryError(e.to_string()))?
-        .0;
-
-    // Set approval status
-    // 02 00 00 00 ... = enabled
-    // 03 00 00 00 ... = disabled
-    let status_value: Vec<u8> = if enable {
-        vec![0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+/// The command line stored in a `Run` value, quoting the program when it has spaces
+fn new_item_command(new_item: &NewStartupItem) -> String {
+    let path = new_item.path.trim().trim_matches('"');
+    let program = if path.contains(' ') {
+        format!("\"{}\"", path)
     } else {
-        vec![0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        path.to_string()
     };
 
-    approval_key
-        .set_raw_value(
-            &item.name,
-            &winreg::RegValue {
-                bytes: status_value,
-                vtype: REG_BINARY,
+    match new_item.arguments.as_deref().map(str::trim) {
+        Some(args) if !args.is_empty() => format!("{} {}", program, args),
+        _ => program,
+    }
+}
+
+/// Add a value to the `Run` key of the item's scope, returning the key's full path and
+/// the value name. `Run` values have no working directory, so that field is not used.
+pub fn create_registry_item(
+    registry: &dyn RegistryBackend,
+    new_item: &NewStartupItem,
+) -> Result<(String, String), StartupError> {
+    validate_new_item(new_item)?;
+    let name = new_item.name.trim();
+    let hive = scope_hive(new_item.scope);
+    let location = locations::find_location(hive, RUN_PATH, name).ok_or(StartupError::NotSupported)?;
+
+    let parked_path = format!("{}\\{}", RUN_PATH, DISABLED_SUBKEY);
+    if registry.get_raw_value(hive, RUN_PATH, name).is_ok() || registry.get_raw_value(hive, &parked_path, name).is_ok() {
+        return Err(StartupError::AlreadyExists);
+    }
+
+    registry.create_subkey(hive, RUN_PATH).map_err(registry_error)?;
+    registry
+        .set_raw_value(hive, RUN_PATH, name, &RegistryValue::from_string(&new_item_command(new_item), RegValueType::Sz))
+        .map_err(registry_error)?;
+
+    // A record left behind by an earlier entry of the same name would start this one disabled
+    if let DisableStrategy::StartupApproved { key } = location.disable {
+        approved::remove_approval(registry, hive, key, name);
+    }
+
+    Ok((location.full_path(), name.to_string()))
+}
+
+/// Write `<name>.lnk` into the startup folder of the item's scope, returning the
+/// folder and the file name
+pub fn create_folder_item(
+    registry: &dyn RegistryBackend,
+    new_item: &NewStartupItem,
+) -> Result<(String, String), StartupError> {
+    let folder = get_startup_folder_path(new_item.scope == Scope::AllUsers).ok_or(StartupError::NotFound)?;
+    create_shortcut_in(registry, &folder, new_item)
+}
+
+fn create_shortcut_in(
+    registry: &dyn RegistryBackend,
+    folder: &Path,
+    new_item: &NewStartupItem,
+) -> Result<(String, String), StartupError> {
+    validate_new_item(new_item)?;
+    let file_name = format!("{}.lnk", new_item.name.trim());
+    let file_path = folder.join(&file_name);
+
+    if file_path.exists() || folder.join(format!("{}.disabled", file_name)).exists() {
+        return Err(StartupError::AlreadyExists);
+    }
+
+    let target = expand_environment_strings(new_item.path.trim().trim_matches('"'));
+    let shortcut = lnk::build_shortcut(
+        &target,
+        new_item.arguments.as_deref(),
+        new_item.working_directory.as_deref(),
+    );
+    std::fs::create_dir_all(folder).map_err(io_error)?;
+    std::fs::write(&file_path, shortcut).map_err(io_error)?;
+
+    approved::remove_approval(registry, startup_folder_hive(folder), ApprovalKey::StartupFolder, &file_name);
+
+    Ok((folder.to_string_lossy().to_string(), file_name))
+}
+
+/// An entry that can be edited in place: a value of a `Run` key or a file in a startup folder
+enum EditableEntry {
+    Value { hive: RegistryHive, path: String, name: String, key: ApprovalKey },
+    File { folder: PathBuf, file_name: String },
+}
+
+impl EditableEntry {
+    fn from_item(item: &StartupItem) -> Result<EditableEntry, StartupError> {
+        match item.source_type {
+            SourceType::Registry => {
+                let (location, reg_path) = registry_location_for(item)?;
+                // Only locations switched through StartupApproved keep their state beside the value
+                let DisableStrategy::StartupApproved { key } = location.disable else {
+                    return Err(StartupError::NotSupported);
+                };
+                Ok(EditableEntry::Value { hive: location.hive, path: reg_path, name: item.entry_name.clone(), key })
+            }
+            SourceType::Folder => {
+                let (_, approval_name) = folder_item_file(item)?;
+                // A program copied into the folder is the entry itself, there is no shortcut to rewrite
+                if !approval_name.to_ascii_lowercase().ends_with(".lnk") {
+                    return Err(StartupError::NotSupported);
+                }
+                Ok(EditableEntry::File { folder: PathBuf::from(&item.source_location), file_name: approval_name })
+            }
+            _ => Err(StartupError::NotSupported),
+        }
+    }
+
+    /// Where an entry of a snapshot goes on this machine. Startup folders are found
+    /// by scope, since their paths differ between users and machines.
+    fn from_snapshot(item: &SnapshotItem) -> Result<EditableEntry, StartupError> {
+        match item.source_type {
+            SourceType::Registry => {
+                let (hive, reg_path) = parse_registry_location(&item.source_location).ok_or(StartupError::NotFound)?;
+                let location = locations::find_location(hive, &reg_path, &item.entry_name).ok_or(StartupError::NotSupported)?;
+                let DisableStrategy::StartupApproved { key } = location.disable else {
+                    return Err(StartupError::NotSupported);
+                };
+                Ok(EditableEntry::Value { hive, path: location.path.to_string(), name: item.entry_name.clone(), key })
+            }
+            SourceType::Folder => {
+                let folder = get_startup_folder_path(item.scope == Some(Scope::AllUsers)).ok_or(StartupError::NotFound)?;
+                Ok(EditableEntry::File { folder, file_name: item.entry_name.clone() })
+            }
+            _ => Err(StartupError::NotSupported),
+        }
+    }
+
+    /// Where `target` puts an entry. A value stays in its own key when it keeps its hive,
+    /// so a `Run (32位)` entry is not moved just by renaming it.
+    fn for_target(current: Option<&EditableEntry>, target: &NewStartupItem) -> Result<EditableEntry, StartupError> {
+        let name = target.name.trim();
+        match target.source_type {
+            SourceType::Registry => {
+                let hive = scope_hive(target.scope);
+                let path = match current {
+                    Some(EditableEntry::Value { hive: current_hive, path, .. }) if *current_hive == hive => path.clone(),
+                    _ => RUN_PATH.to_string(),
+                };
+                let location = locations::find_location(hive, &path, name).ok_or(StartupError::NotSupported)?;
+                let DisableStrategy::StartupApproved { key } = location.disable else {
+                    return Err(StartupError::NotSupported);
+                };
+                Ok(EditableEntry::Value { hive, path, name: name.to_string(), key })
+            }
+            SourceType::Folder => {
+                let folder = get_startup_folder_path(target.scope == Scope::AllUsers).ok_or(StartupError::NotFound)?;
+                Ok(EditableEntry::File { folder, file_name: format!("{}.lnk", name) })
+            }
+            _ => Err(StartupError::NotSupported),
+        }
+    }
+
+    /// Hive, key and value name of the entry's StartupApproved record
+    fn approval(&self) -> (RegistryHive, ApprovalKey, &str) {
+        match self {
+            EditableEntry::Value { hive, name, key, .. } => (*hive, *key, name),
+            EditableEntry::File { folder, file_name } => (startup_folder_hive(folder), ApprovalKey::StartupFolder, file_name),
+        }
+    }
+
+    /// What writing or removing the entry changes, for the history
+    fn targets(&self) -> Vec<Target> {
+        let (hive, key, name) = self.approval();
+        let approval = Target::RegistryValue { hive, path: key.path(), name: name.to_string() };
+        match self {
+            EditableEntry::Value { hive, path, name, .. } => vec![
+                Target::RegistryValue { hive: *hive, path: path.clone(), name: name.clone() },
+                approval,
+            ],
+            EditableEntry::File { folder, file_name } => vec![
+                Target::File(folder.join(file_name)),
+                Target::File(folder.join(format!("{}.disabled", file_name))),
+                approval,
+            ],
+        }
+    }
+
+    /// The file on disk, which older versions may have renamed to `.disabled`
+    fn existing_file(&self) -> Option<PathBuf> {
+        let EditableEntry::File { folder, file_name } = self else {
+            return None;
+        };
+        [folder.join(file_name), folder.join(format!("{}.disabled", file_name))]
+            .into_iter()
+            .find(|path| path.exists())
+    }
+
+    fn exists(&self, registry: &dyn RegistryBackend) -> bool {
+        match self {
+            EditableEntry::Value { hive, path, name, .. } => registry.get_raw_value(*hive, path, name).is_ok(),
+            EditableEntry::File { .. } => self.existing_file().is_some(),
+        }
+    }
+
+    fn is_same(&self, other: &EditableEntry) -> bool {
+        match (self, other) {
+            (
+                EditableEntry::Value { hive, path, name, .. },
+                EditableEntry::Value { hive: other_hive, path: other_path, name: other_name, .. },
+            ) => hive == other_hive && path.eq_ignore_ascii_case(other_path) && name.eq_ignore_ascii_case(other_name),
+            (
+                EditableEntry::File { folder, file_name },
+                EditableEntry::File { folder: other_folder, file_name: other_file_name },
+            ) => folder == other_folder && file_name.eq_ignore_ascii_case(other_file_name),
+            _ => false,
+        }
+    }
+
+    /// The entry's full location and name, as the scanner reports them
+    fn identity(&self) -> (String, String) {
+        match self {
+            EditableEntry::Value { hive, path, name, .. } => (format!("{}\\{}", hive.root_name(), path), name.clone()),
+            EditableEntry::File { folder, file_name } => (folder.to_string_lossy().to_string(), file_name.clone()),
+        }
+    }
+
+    /// Remove the value or file, leaving its approval record alone
+    fn remove(&self, registry: &dyn RegistryBackend) -> Result<(), StartupError> {
+        match self {
+            EditableEntry::Value { hive, path, name, .. } => registry.delete_value(*hive, path, name).map_err(registry_error),
+            EditableEntry::File { .. } => match self.existing_file() {
+                Some(file) => std::fs::remove_file(file).map_err(io_error),
+                None => Err(StartupError::NotFound),
             },
-        )
-        .map_err(|e| StartupError::RegistryError(e.to_string()))?;
+        }
+    }
+}
 
-    Ok(())
+/// What an entry holds: the value of a `Run` key or the bytes of a shortcut
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryContent {
+    Value(RegistryValue),
+    File(Vec<u8>),
 }
 
-pub fn toggle_folder_item(item: &StartupItem, enable: bool) -> Result<(), StartupError> {
-    let folder_path = PathBuf::from(&item.source_location);
+/// The content `target` describes. Whatever is unchanged is carried over from the
+/// current entry: the value keeps its type, an unedited shortcut keeps its icon and
+/// flags, and a shortcut's working directory is kept when none is given.
+fn target_content(
+    registry: &dyn RegistryBackend,
+    current: &EditableEntry,
+    target: &NewStartupItem,
+    destination: &EditableEntry,
+) -> Result<EntryContent, StartupError> {
+    match (current, destination) {
+        (EditableEntry::Value { hive, path, name, .. }, EditableEntry::Value { .. }) => {
+            let value = registry.get_raw_value(*hive, path, name).map_err(registry_error)?;
+            let command = new_item_command(target);
+            if value.as_string().as_deref() == Some(command.as_str()) {
+                return Ok(EntryContent::Value(value));
+            }
+            let vtype = match value.vtype {
+                RegValueType::ExpandSz => RegValueType::ExpandSz,
+                _ => RegValueType::Sz,
+            };
+            Ok(EntryContent::Value(RegistryValue::from_string(&command, vtype)))
+        }
+        (_, EditableEntry::Value { .. }) => Ok(EntryContent::Value(RegistryValue::from_string(
+            &new_item_command(target),
+            RegValueType::Sz,
+        ))),
+        (_, EditableEntry::File { .. }) => {
+            let target_path = expand_environment_strings(target.path.trim().trim_matches('"'));
+            let arguments = target.arguments.as_deref().map(str::trim).filter(|a| !a.is_empty());
+            let mut working_dir = target.working_directory.as_deref().map(str::trim).filter(|d| !d.is_empty());
 
-    // Find the actual file
-    let entries = std::fs::read_dir(&folder_path)
-        .map_err(|e| StartupError::IoError(e.to_string()))?;
+            let current_file = current.existing_file();
+            let current_bytes = current_file.as_ref().map(std::fs::read).transpose().map_err(io_error)?;
+            let current_link = current_bytes.as_deref().and_then(|bytes| ShellLink::parse(bytes).ok());
 
-    for entry in entries.flatten() {
-        let file_name = entry.file_name().to_string_lossy().to_string();
-        let file_path = entry.path();
-
-        // Check if this is our item (with or without .disabled suffix)
-        let base_name = file_name.trim_end_matches(".disabled");
-        let is_our_item = base_name.starts_with(&item.name)
-            || base_name.trim_end_matches(".lnk") == item.name
-            || base_name.trim_end_matches(".exe") == item.name;
-
-        if is_our_item {
-            let is_currently_disabled = file_name.ends_with(".disabled");
-
-            if enable && is_currently_disabled {
-                // Remove .disabled suffix
-                let new_name = file_name.trim_end_matches(".disabled");
-                let new_path = folder_path.join(new_name);
-                std::fs::rename(&file_path, &new_path)
-                    .map_err(|e| StartupError::IoError(e.to_string()))?;
-                return Ok(());
-            } else if !enable && !is_currently_disabled {
-                // Add .disabled suffix
-                let new_name = format!("{}.disabled", file_name);
-                let new_path = folder_path.join(new_name);
-                std::fs::rename(&file_path, &new_path)
-                    .map_err(|e| StartupError::IoError(e.to_string()))?;
-                return Ok(());
-            } else {
-                // Already in desired state
-                return Ok(());
+            if let (Some(bytes), Some(link)) = (&current_bytes, &current_link) {
+                if working_dir.is_none() {
+                    working_dir = link.working_dir.as_deref();
+                }
+                let unchanged = link.target_path().is_some_and(|p| p.eq_ignore_ascii_case(&target_path))
+                    && link.arguments.as_deref().filter(|a| !a.is_empty()) == arguments
+                    && link.working_dir.as_deref() == working_dir;
+                if unchanged {
+                    return Ok(EntryContent::File(bytes.clone()));
+                }
             }
+
+            Ok(EntryContent::File(lnk::build_shortcut(&target_path, arguments, working_dir)))
         }
     }
+}
 
-    Err(StartupError::NotFound)
+fn write_content(
+    registry: &dyn RegistryBackend,
+    destination: &EditableEntry,
+    content: &EntryContent,
+) -> Result<(), StartupError> {
+    match (destination, content) {
+        (EditableEntry::Value { hive, path, name, .. }, EntryContent::Value(value)) => {
+            registry.create_subkey(*hive, path).map_err(registry_error)?;
+            registry.set_raw_value(*hive, path, name, value).map_err(registry_error)
+        }
+        (EditableEntry::File { folder, file_name }, EntryContent::File(bytes)) => {
+            std::fs::create_dir_all(folder).map_err(io_error)?;
+            std::fs::write(folder.join(file_name), bytes).map_err(io_error)
+        }
+        _ => Err(StartupError::NotSupported),
+    }
 }
 
-pub fn toggle_startup_item(item: &StartupItem, enable: bool) -> Result<(), StartupError> {
-    if item.source_type == "registry" {
-        toggle_registry_item(item, enable)
+/// What a `Run` value or startup folder file holds, with the raw bytes of its
+/// StartupApproved record, so it can be written back exactly
+pub fn read_entry_state(
+    registry: &dyn RegistryBackend,
+    item: &StartupItem,
+) -> Result<(EntryContent, Option<Vec<u8>>), StartupError> {
+    let entry = EditableEntry::from_item(item)?;
+    let content = match &entry {
+        EditableEntry::Value { hive, path, name, .. } => {
+            EntryContent::Value(registry.get_raw_value(*hive, path, name).map_err(registry_error)?)
+        }
+        EditableEntry::File { .. } => {
+            let file = entry.existing_file().ok_or(StartupError::NotFound)?;
+            EntryContent::File(std::fs::read(file).map_err(io_error)?)
+        }
+    };
+
+    let (hive, key, name) = entry.approval();
+    let approval = registry.get_raw_value(hive, &key.path(), name).ok().map(|value| value.bytes);
+    Ok((content, approval))
+}
+
+/// Write an entry from a snapshot as it was stored, replacing what is there,
+/// together with its StartupApproved record. The change goes into the history.
+pub fn import_entry(item: &SnapshotItem, content: &EntryContent, approval: Option<&[u8]>) -> Result<(), StartupError> {
+    let _operation = OperationGuard::start();
+    let registry = system_registry();
+    let entry = EditableEntry::from_snapshot(item)?;
+    let operation = if entry.exists(registry) { Operation::Update } else { Operation::Create };
+    let (location, _) = entry.identity();
+
+    journaled(operation, &item.name, &location, item.source_type, &entry.targets(), || {
+        write_imported(registry, &entry, content, approval)
+    })
+}
+
+/// Write an entry's value or file, then its approval record or the lack of one
+fn write_imported(
+    registry: &dyn RegistryBackend,
+    entry: &EditableEntry,
+    content: &EntryContent,
+    approval: Option<&[u8]>,
+) -> Result<(), StartupError> {
+    write_content(registry, entry, content)?;
+    let (hive, key, name) = entry.approval();
+    match approval {
+        Some(bytes) => {
+            let path = key.path();
+            registry.create_subkey(hive, &path).map_err(registry_error)?;
+            registry
+                .set_raw_value(hive, &path, name, &RegistryValue { bytes: bytes.to_vec(), vtype: RegValueType::Binary })
+                .map_err(registry_error)
+        }
+        None => {
+            approved::remove_approval(registry, hive, key, name);
+            Ok(())
+        }
+    }
+}
+
+/// `import_entry` into a registry of the test's own, without journaling
+#[cfg(test)]
+pub fn import_entry_into(
+    registry: &dyn RegistryBackend,
+    item: &SnapshotItem,
+    content: &EntryContent,
+    approval: Option<&[u8]>,
+) -> Result<(), StartupError> {
+    write_imported(registry, &EditableEntry::from_snapshot(item)?, content, approval)
+}
+
+/// Rename an entry, change its command line, or move it to another `Run` key or
+/// startup folder, keeping its enabled state and the time it was disabled.
+/// The new entry is written before the old one is removed, and removed again if
+/// the old one cannot be, so a failure leaves exactly one of them behind.
+/// Returns the new entry's location and name.
+pub fn update_entry(
+    registry: &dyn RegistryBackend,
+    item: &StartupItem,
+    target: &NewStartupItem,
+) -> Result<(String, String), StartupError> {
+    validate_new_item(target)?;
+    let current = EditableEntry::from_item(item)?;
+    let destination = EditableEntry::for_target(Some(&current), target)?;
+    let same_entry = current.is_same(&destination);
+
+    if !same_entry && destination.exists(registry) {
+        return Err(StartupError::AlreadyExists);
+    }
+
+    let (hive, key, name) = current.approval();
+    let record = approved::read_approval(registry, hive, key, name)
+        .or_else(|| (!item.enabled).then(|| ApprovalRecord::default().with_enabled(false)));
+    let legacy_file = current.existing_file().filter(|file| file.extension().is_some_and(|e| e == "disabled"));
+
+    let content = target_content(registry, &current, target, &destination)?;
+    write_content(registry, &destination, &content)?;
+
+    let (hive, key, name) = destination.approval();
+    let approval_result = match &record {
+        Some(record) => approved::write_record(registry, hive, key, name, record),
+        None => {
+            approved::remove_approval(registry, hive, key, name);
+            Ok(())
+        }
+    };
+
+    let cleanup = approval_result.map_err(registry_error).and_then(|_| {
+        if same_entry {
+            // Only a legacy `.disabled` file is left over when an entry is rewritten in place
+            legacy_file.map_or(Ok(()), |file| std::fs::remove_file(file).map_err(io_error))
+        } else {
+            current.remove(registry)
+        }
+    });
+
+    if let Err(e) = cleanup {
+        if !same_entry {
+            let _ = destination.remove(registry);
+            let (hive, key, name) = destination.approval();
+            approved::remove_approval(registry, hive, key, name);
+        }
+        return Err(e);
+    }
+
+    if !same_entry {
+        let (hive, key, name) = current.approval();
+        approved::remove_approval(registry, hive, key, name);
+    }
+
+    Ok(destination.identity())
+}
+
+/// Run schtasks.exe without flashing a console window
+#[cfg(windows)]
+fn run_schtasks(args: &[&str]) -> Result<(), StartupError> {
+    use std::os::windows::process::CommandExt;
+    use windows::Win32::System::Threading::CREATE_NO_WINDOW;
+
+    let output = std::process::Command::new("schtasks")
+        .args(args)
+        .creation_flags(CREATE_NO_WINDOW.0)
+        .output()
+        .map_err(|e| StartupError::IoError(e.to_string()))?;
+
+    if output.status.success() {
+        Ok(())
     } else {
-        toggle_folder_item(item, enable)
+        let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if message.contains("Access is denied") || message.contains("拒绝访问") {
+            Err(StartupError::AccessDenied)
+        } else {
+            Err(StartupError::IoError(message))
+        }
     }
 }
 
-pub fn delete_registry_item(item: &StartupItem) -> Result<(), StartupError> {
-    let (hkey, reg_path) = parse_registry_location(&item.source_location)
-        .ok_or(StartupError::NotFound)?;
+fn task_file_for(item: &StartupItem) -> Result<PathBuf, StartupError> {
+    let tasks_folder = tasks::system_tasks_folder().ok_or(StartupError::NotFound)?;
+    let task_path = tasks::join_task_path(&item.source_location, &item.entry_name);
+    Ok(tasks::task_file_path(&tasks_folder, &task_path))
+}
 
-    let root = RegKey::predef(hkey);
+/// Enable or disable a scheduled task.
+///
+/// On Windows the scheduler service keeps its own copy of each definition, so
+/// the change goes through schtasks, which updates both. Elsewhere the task
+/// file is all there is and `Settings/Enabled` is rewritten in place.
+pub fn toggle_task_item(item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+    #[cfg(windows)]
+    {
+        let task_path = tasks::join_task_path(&item.source_location, &item.entry_name);
+        run_schtasks(&["/Change", "/TN", &task_path, if enable { "/ENABLE" } else { "/DISABLE" }])
+    }
 
-    let key = root
-        .open_subkey_with_flags(&reg_path, KEY_SET_VALUE)
-        .map_err(|e| {
-            if e.to_string().contains("Access") {
-                StartupError::AccessDenied
-            } else {
-                StartupError::RegistryError(e.to_string())
-            }
-        })?;
+    #[cfg(not(windows))]
+    {
+        let file_path = task_file_for(item)?;
+        let data = std::fs::read(&file_path).map_err(io_error)?;
+        let data = tasks::set_task_enabled(&data, enable).map_err(|e| StartupError::IoError(e.to_string()))?;
+        std::fs::write(&file_path, data).map_err(io_error)
+    }
+}
+
+/// Switch a service between Automatic and Manual start
+pub fn toggle_service_item(
+    registry: &dyn RegistryBackend,
+    item: &StartupItem,
+    enable: bool,
+) -> Result<(), StartupError> {
+    services::set_auto_start(registry, &item.entry_name, enable).map_err(registry_error)
+}
+
+/// Switch an XDG autostart entry through its override in the user directory
+pub fn toggle_xdg_item(item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+    let user_dir = xdg::user_autostart_dir().ok_or(StartupError::NotFound)?;
+    let source = Path::new(&item.source_location).join(&item.entry_name);
+    xdg::set_autostart_enabled(&user_dir, &source, enable).map_err(io_error)
+}
 
-    key.delete_value(&item.name)
-        .map_err(|e| StartupError::RegistryError(e.to_string()))?;
+/// Link a systemd user unit into its targets or remove the links
+pub fn toggle_systemd_item(paths: &[PathBuf], item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+    let unit_file = systemd::find_unit_file(paths, &item.entry_name).ok_or(StartupError::NotFound)?;
 
-    // Also try to remove from StartupApproved
-    let approval_path = if reg_path.contains("RunOnce") {
-        reg_path.replace("RunOnce", "Explorer\\StartupApproved\\RunOnce")
+    if enable {
+        let config_dir = paths.first().ok_or(StartupError::NotFound)?;
+        systemd::enable_unit(config_dir, &item.entry_name, &unit_file).map_err(io_error)
     } else {
-        reg_path.replace("Run", "Explorer\\StartupApproved\\Run")
-    };
+        systemd::disable_unit(paths, &item.entry_name, &unit_file).map_err(io_error)
+    }
+}
+
+
+pub fn delete_registry_item(registry: &dyn RegistryBackend, item: &StartupItem) -> Result<(), StartupError> {
+    let (location, reg_path) = registry_location_for(item)?;
+    let hive = location.hive;
+    let disabled_path = format!("{}\\{}", reg_path, DISABLED_SUBKEY);
 
-    if let Ok(approval_key) = root.open_subkey_with_flags(&approval_path, KEY_SET_VALUE) {
-        let _ = approval_key.delete_value(&item.name);
+    match location.layout {
+        EntryLayout::ValueList => {
+            // The value is either active or parked in the disabled subkey
+            let active = registry.delete_value(hive, &reg_path, &item.entry_name);
+            let parked = registry.delete_value(hive, &disabled_path, &item.entry_name);
+            if let (Err(e), Err(_)) = (active, parked) {
+                return Err(registry_error(e));
+            }
+
+            // Also try to remove from StartupApproved
+            if let DisableStrategy::StartupApproved { key } = location.disable {
+                approved::remove_approval(registry, hive, key, &item.entry_name);
+            }
+            Ok(())
+        }
+        EntryLayout::SingleValue { value, default } => {
+            let _ = registry.delete_value(hive, &disabled_path, value);
+            write_default_value(registry, hive, &reg_path, value, default)
+        }
+        EntryLayout::SubkeyPerEntry { .. } => registry
+            .delete_subkey(hive, &format!("{}\\{}", reg_path, item.entry_name))
+            .map_err(registry_error),
+        EntryLayout::SubkeyValues => {
+            let (subkey, name) = item.entry_name
+                .split_once('\\')
+                .ok_or(StartupError::NotFound)?;
+            registry
+                .delete_value(hive, &format!("{}\\{}", reg_path, subkey), name)
+                .map_err(registry_error)
+        }
     }
+}
+
+pub fn delete_folder_item(registry: &dyn RegistryBackend, item: &StartupItem) -> Result<(), StartupError> {
+    let (file_path, approval_name) = folder_item_file(item)?;
+    std::fs::remove_file(&file_path).map_err(io_error)?;
 
+    let hive = startup_folder_hive(Path::new(&item.source_location));
+    approved::remove_approval(registry, hive, ApprovalKey::StartupFolder, &approval_name);
     Ok(())
 }
 
-pub fn delete_folder_item(item: &StartupItem) -> Result<(), StartupError> {
-    let folder_path = PathBuf::from(&item.source_location);
+pub fn delete_task_item(item: &StartupItem) -> Result<(), StartupError> {
+    // Unregistering through the service also removes the file
+    #[cfg(windows)]
+    let result = {
+        let task_path = tasks::join_task_path(&item.source_location, &item.entry_name);
+        run_schtasks(&["/Delete", "/TN", &task_path, "/F"])
+    };
 
-    let entries = std::fs::read_dir(&folder_path)
-        .map_err(|e| StartupError::IoError(e.to_string()))?;
+    #[cfg(not(windows))]
+    let result = task_file_for(item).and_then(|path| std::fs::remove_file(path).map_err(io_error));
+
+    result
+}
+
+/// Register a task again from its saved definition, or remove it when there was none
+pub fn restore_task(task_path: &str, file: &Path, definition: Option<&[u8]>) -> Result<(), StartupError> {
+    #[cfg(windows)]
+    let result = {
+        let _ = file;
+        match definition {
+            Some(definition) => {
+                let xml_path = std::env::temp_dir().join(format!("UMStartupManager-{}.xml", std::process::id()));
+                std::fs::write(&xml_path, definition).map_err(io_error)?;
+                let xml_arg = xml_path.to_string_lossy().to_string();
+                let created = run_schtasks(&["/Create", "/TN", task_path, "/XML", &xml_arg, "/F"]);
+                let _ = std::fs::remove_file(&xml_path);
+                created
+            }
+            // The task may never have been registered, or be gone already
+            None => {
+                let _ = run_schtasks(&["/Delete", "/TN", task_path, "/F"]);
+                Ok(())
+            }
+        }
+    };
+
+    #[cfg(not(windows))]
+    let result = {
+        let _ = task_path;
+        match definition {
+            Some(definition) => file
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .and_then(|_| std::fs::write(file, definition))
+                .map_err(io_error),
+            None => match std::fs::remove_file(file) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(io_error(e)),
+                _ => Ok(()),
+            },
+        }
+    };
+
+    result
+}
+
+/// Remove an XDG autostart file. Removing a user override brings back the
+/// system entry it replaced; system entries need root to be removed.
+pub fn delete_xdg_item(item: &StartupItem) -> Result<(), StartupError> {
+    let file_path = Path::new(&item.source_location).join(&item.entry_name);
+    std::fs::remove_file(file_path).map_err(io_error)
+}
 
-    for entry in entries.flatten() {
-        let file_name = entry.file_name().to_string_lossy().to_string();
-        let file_path = entry.path();
+/// Disable a systemd user unit and remove its file. Units installed by packages need root.
+pub fn delete_systemd_item(paths: &[PathBuf], item: &StartupItem) -> Result<(), StartupError> {
+    let unit_file = systemd::find_unit_file(paths, &item.entry_name).ok_or(StartupError::NotFound)?;
+    systemd::disable_unit(paths, &item.entry_name, &unit_file).map_err(io_error)?;
+    std::fs::remove_file(&unit_file).map_err(io_error)
+}
 
-        let base_name = file_name.trim_end_matches(".disabled");
-        let is_our_item = base_name.starts_with(&item.name)
-            || base_name.trim_end_matches(".lnk") == item.name
-            || base_name.trim_end_matches(".exe") == item.name;
+/// What an operation on an existing item may change, captured for the history
+/// before the operation runs
+fn item_targets(item: &StartupItem) -> Result<Vec<Target>, StartupError> {
+    let targets = match item.source_type {
+        SourceType::Registry => {
+            let (location, reg_path) = registry_location_for(item)?;
+            let hive = location.hive;
+            let disabled_path = format!("{}\\{}", reg_path, DISABLED_SUBKEY);
+            let value = |path: &str, name: &str| Target::RegistryValue { hive, path: path.to_string(), name: name.to_string() };
 
-        if is_our_item {
-            std::fs::remove_file(&file_path)
-                .map_err(|e| StartupError::IoError(e.to_string()))?;
-            return Ok(());
+            let mut targets = match location.layout {
+                EntryLayout::ValueList => vec![value(&reg_path, &item.entry_name), value(&disabled_path, &item.entry_name)],
+                EntryLayout::SingleValue { value: name, .. } => vec![value(&reg_path, name), value(&disabled_path, name)],
+                EntryLayout::SubkeyPerEntry { .. } => vec![Target::RegistryKey {
+                    hive,
+                    path: format!("{}\\{}", reg_path, item.entry_name),
+                }],
+                EntryLayout::SubkeyValues => {
+                    let (subkey, name) = item.entry_name.split_once('\\').ok_or(StartupError::NotFound)?;
+                    vec![value(&format!("{}\\{}", reg_path, subkey), name)]
+                }
+            };
+            if let DisableStrategy::StartupApproved { key } = location.disable {
+                targets.push(value(&key.path(), &item.entry_name));
+            }
+            targets
+        }
+        SourceType::Folder => {
+            let (_, approval_name) = folder_item_file(item)?;
+            EditableEntry::File { folder: PathBuf::from(&item.source_location), file_name: approval_name }.targets()
+        }
+        SourceType::ScheduledTask => vec![Target::ScheduledTask {
+            task_path: tasks::join_task_path(&item.source_location, &item.entry_name),
+            file: task_file_for(item)?,
+        }],
+        SourceType::Service => vec![Target::RegistryValue {
+            hive: RegistryHive::LocalMachine,
+            path: services::service_key(&item.entry_name),
+            name: "Start".to_string(),
+        }],
+        SourceType::XdgAutostart => {
+            let mut targets = vec![Target::File(Path::new(&item.source_location).join(&item.entry_name))];
+            if let Some(user_dir) = xdg::user_autostart_dir() {
+                targets.push(Target::File(user_dir.join(&item.entry_name)));
+            }
+            targets
+        }
+        SourceType::SystemdUser => {
+            let paths = systemd::user_unit_paths();
+            let unit_file = systemd::find_unit_file(&paths, &item.entry_name).ok_or(StartupError::NotFound)?;
+            let mut targets = vec![Target::File(unit_file.clone())];
+            targets.extend(systemd::unit_link_paths(&paths, &item.entry_name, &unit_file).into_iter().map(Target::File));
+            targets
         }
+    };
+    Ok(targets)
+}
+
+/// Operations of this app that are running, and how many have finished
+static OPERATIONS_RUNNING: AtomicUsize = AtomicUsize::new(0);
+static OPERATIONS_FINISHED: AtomicU64 = AtomicU64::new(0);
+
+/// Held while an operation changes startup entries, so the watcher can tell its changes from others
+struct OperationGuard;
+
+impl OperationGuard {
+    fn start() -> OperationGuard {
+        OPERATIONS_RUNNING.fetch_add(1, Ordering::SeqCst);
+        OperationGuard
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        OPERATIONS_FINISHED.fetch_add(1, Ordering::SeqCst);
+        OPERATIONS_RUNNING.fetch_sub(1, Ordering::SeqCst);
     }
+}
+
+pub fn operations_running() -> bool {
+    OPERATIONS_RUNNING.load(Ordering::SeqCst) > 0
+}
 
-    Err(StartupError::NotFound)
+pub fn operations_finished() -> u64 {
+    OPERATIONS_FINISHED.load(Ordering::SeqCst)
+}
+
+/// Run an operation with its targets saved to the history first. The entry is
+/// dropped again if the operation fails.
+fn journaled<T>(
+    operation: Operation,
+    item_name: &str,
+    source: &str,
+    source_type: SourceType,
+    targets: &[Target],
+    run: impl FnOnce() -> Result<T, StartupError>,
+) -> Result<T, StartupError> {
+    let snapshots = history::capture(system_registry(), targets).map_err(io_error)?;
+    let entry = history::new_entry(operation, item_name, source, source_type, snapshots);
+    let id = entry.id.clone();
+    history::record(entry).map_err(io_error)?;
+
+    run().inspect_err(|_| {
+        let _ = history::discard(&id);
+    })
+}
+
+pub fn toggle_startup_item(item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+    let _operation = OperationGuard::start();
+    let source = source_for(item.source_type).ok_or(StartupError::NotSupported)?;
+    let operation = if enable { Operation::Enable } else { Operation::Disable };
+    journaled(operation, &item.name, &item.source, item.source_type, &item_targets(item)?, || {
+        source.toggle(item, enable)
+    })?;
+
+    let toggled = StartupItem { enabled: enable, ..item.clone() };
+    baseline::acknowledge(Some(item), Some(&toggled));
+    Ok(())
 }
 
 pub fn delete_startup_item(item: &StartupItem) -> Result<(), StartupError> {
-    if item.source_type == "registry" {
-        delete_registry_item(item)
-    } else {
-        delete_folder_item(item)
+    let _operation = OperationGuard::start();
+    let source = source_for(item.source_type).ok_or(StartupError::NotSupported)?;
+    journaled(Operation::Delete, &item.name, &item.source, item.source_type, &item_targets(item)?, || {
+        source.delete(item)
+    })?;
+
+    baseline::acknowledge(Some(item), None);
+    Ok(())
+}
+
+pub fn create_startup_item(new_item: &NewStartupItem) -> Result<StartupItem, StartupError> {
+    let _operation = OperationGuard::start();
+    let source = source_for(new_item.source_type).ok_or(StartupError::NotSupported)?;
+    let destination = EditableEntry::for_target(None, new_item)?;
+    let (location, _) = destination.identity();
+    journaled(Operation::Create, new_item.name.trim(), &location, new_item.source_type, &destination.targets(), || {
+        source.create(new_item)
+    })
+    .inspect(|created| baseline::acknowledge(None, Some(created)))
+}
+
+pub fn update_startup_item(item: &StartupItem, target: &NewStartupItem) -> Result<StartupItem, StartupError> {
+    let _operation = OperationGuard::start();
+    let source = source_for(item.source_type).ok_or(StartupError::NotSupported)?;
+    let current = EditableEntry::from_item(item)?;
+    let mut targets = current.targets();
+    targets.extend(EditableEntry::for_target(Some(&current), target)?.targets());
+    journaled(Operation::Update, &item.name, &item.source, item.source_type, &targets, || {
+        source.update(item, target)
+    })
+    .inspect(|updated| baseline::acknowledge(Some(item), Some(updated)))
+}
+
+pub fn list_history() -> Result<Vec<history::HistoryEntry>, StartupError> {
+    history::list_history().map_err(io_error)
+}
+
+/// Put back what an operation from the history changed
+pub fn undo_operation(id: &str) -> Result<history::HistoryEntry, StartupError> {
+    let _operation = OperationGuard::start();
+    history::undo(system_registry(), id).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => StartupError::InvalidInput("历史记录不存在或已过期".to_string()),
+        std::io::ErrorKind::InvalidInput => StartupError::InvalidInput("该操作已撤销".to_string()),
+        _ => registry_error(e),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::registry::MemoryRegistry;
+    use crate::startup::scanner::{self, ScanContext};
+    use crate::startup::testdir::TestDir;
+
+    const RUN_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+    const RUN_ONCE_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\RunOnce";
+
+    fn registry_with(path: &str, name: &str, command: &str) -> MemoryRegistry {
+        let registry = MemoryRegistry::new();
+        registry.create_subkey(RegistryHive::CurrentUser, path).unwrap();
+        registry
+            .set_raw_value(RegistryHive::CurrentUser, path, name, &RegistryValue::from_string(command, RegValueType::Sz))
+            .unwrap();
+        registry
+    }
+
+    fn scanned(registry: &MemoryRegistry, name: &str) -> StartupItem {
+        scanner::scan_registry_items(&ScanContext::with_registry(registry))
+            .into_iter()
+            .find(|item| item.entry_name == name)
+            .unwrap_or_else(|| panic!("{} not scanned", name))
+    }
+
+    fn approval_bytes(registry: &MemoryRegistry, name: &str) -> Vec<u8> {
+        registry
+            .get_raw_value(RegistryHive::CurrentUser, &ApprovalKey::Run.path(), name)
+            .unwrap()
+            .bytes
+    }
+
+    #[test]
+    fn toggles_run_entries_through_startup_approved() {
+        let registry = registry_with(RUN_PATH, "Tool", r"C:\Tools\tool.exe /min");
+        let item = scanned(&registry, "Tool");
+        assert!(item.enabled);
+
+        toggle_registry_item(&registry, &item, false).unwrap();
+        let bytes = approval_bytes(&registry, "Tool");
+        assert_eq!(bytes.len(), 12);
+        // Task Manager's default flags with the disabled bit set, and a timestamp
+        assert_eq!(bytes[0..4], [0x03, 0, 0, 0]);
+        assert_ne!(bytes[4..12], [0; 8]);
+        // The command itself is left alone
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool").is_ok());
+
+        let item = scanned(&registry, "Tool");
+        assert!(!item.enabled);
+        assert!(item.disabled_at.is_some());
+
+        toggle_registry_item(&registry, &item, true).unwrap();
+        assert_eq!(approval_bytes(&registry, "Tool"), [0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(scanned(&registry, "Tool").enabled);
+    }
+
+    #[test]
+    fn disabling_again_keeps_the_first_timestamp_and_other_flags() {
+        let registry = registry_with(RUN_PATH, "Tool", r"C:\Tools\tool.exe");
+        let record = [0x07, 0, 0, 0, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0xD0, 0x01];
+        approved::write_record(
+            &registry,
+            RegistryHive::CurrentUser,
+            ApprovalKey::Run,
+            "Tool",
+            &ApprovalRecord::parse(&record).unwrap(),
+        )
+        .unwrap();
+
+        let item = scanned(&registry, "Tool");
+        assert!(!item.enabled);
+        toggle_registry_item(&registry, &item, false).unwrap();
+        assert_eq!(approval_bytes(&registry, "Tool"), record);
+
+        toggle_registry_item(&registry, &item, true).unwrap();
+        assert_eq!(approval_bytes(&registry, "Tool"), [0x06, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn toggles_run_once_entries_by_moving_them() {
+        let registry = registry_with(RUN_ONCE_PATH, "Setup", r"C:\Setup\finish.exe");
+        let disabled_path = format!("{}\\{}", RUN_ONCE_PATH, DISABLED_SUBKEY);
+
+        toggle_registry_item(&registry, &scanned(&registry, "Setup"), false).unwrap();
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, RUN_ONCE_PATH, "Setup").is_err());
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, &disabled_path, "Setup").is_ok());
+        assert!(!scanned(&registry, "Setup").enabled);
+
+        toggle_registry_item(&registry, &scanned(&registry, "Setup"), true).unwrap();
+        let value = registry.get_raw_value(RegistryHive::CurrentUser, RUN_ONCE_PATH, "Setup").unwrap();
+        assert_eq!(value.as_string().as_deref(), Some(r"C:\Setup\finish.exe"));
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, &disabled_path, "Setup").is_err());
+    }
+
+    fn new_item(source_type: SourceType, name: &str, path: &str, arguments: Option<&str>) -> NewStartupItem {
+        NewStartupItem {
+            source_type,
+            scope: Scope::CurrentUser,
+            name: name.to_string(),
+            path: path.to_string(),
+            arguments: arguments.map(str::to_string),
+            working_directory: None,
+        }
+    }
+
+    #[test]
+    fn creates_run_values_and_drops_stale_records() {
+        let registry = MemoryRegistry::new();
+        let stale = [0x03, 0, 0, 0, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0xD0, 0x01];
+        approved::write_record(&registry, RegistryHive::CurrentUser, ApprovalKey::Run, "Tool", &ApprovalRecord::parse(&stale).unwrap())
+            .unwrap();
+
+        let created = create_registry_item(&registry, &new_item(SourceType::Registry, " Tool ", r"C:\Program Files\Tool\tool.exe", Some("--tray")))
+            .unwrap();
+        assert_eq!(created, (format!(r"HKEY_CURRENT_USER\{}", RUN_PATH), "Tool".to_string()));
+        let value = registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool").unwrap();
+        assert_eq!(value.as_string().as_deref(), Some(r#""C:\Program Files\Tool\tool.exe" --tray"#));
+        // The record of an earlier entry of that name would start this one disabled
+        assert!(approved::read_approval(&registry, RegistryHive::CurrentUser, ApprovalKey::Run, "Tool").is_none());
+        assert!(scanned(&registry, "Tool").enabled);
+    }
+
+    #[test]
+    fn refuses_to_create_over_an_existing_value() {
+        let registry = registry_with(RUN_PATH, "Tool", r"C:\Tools\tool.exe");
+        let result = create_registry_item(&registry, &new_item(SourceType::Registry, "tool", r"C:\Other\other.exe", None));
+        assert!(matches!(result, Err(StartupError::AlreadyExists)));
+        let value = registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool").unwrap();
+        assert_eq!(value.as_string().as_deref(), Some(r"C:\Tools\tool.exe"));
+
+        // A value parked in AutorunsDisabled counts too
+        let parked = format!("{}\\{}", RUN_PATH, DISABLED_SUBKEY);
+        let registry = registry_with(&parked, "Parked", r"C:\Tools\parked.exe");
+        let result = create_registry_item(&registry, &new_item(SourceType::Registry, "Parked", r"C:\Tools\parked.exe", None));
+        assert!(matches!(result, Err(StartupError::AlreadyExists)));
+
+        let result = create_registry_item(&registry, &new_item(SourceType::Registry, r"a\b", "app.exe", None));
+        assert!(matches!(result, Err(StartupError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn creates_shortcuts_that_parse_back() {
+        let dir = TestDir::new("create-shortcut");
+        let registry = MemoryRegistry::new();
+        let stale = ApprovalRecord::default().with_enabled(false);
+        approved::write_record(&registry, RegistryHive::CurrentUser, ApprovalKey::StartupFolder, "Tool.lnk", &stale).unwrap();
+
+        let mut item = new_item(SourceType::Folder, "Tool", r#""C:\Program Files\Tool\tool.exe""#, Some(" --tray "));
+        item.working_directory = Some(r"C:\Program Files\Tool".to_string());
+        let created = create_shortcut_in(&registry, dir.path(), &item).unwrap();
+        assert_eq!(created, (dir.path().to_string_lossy().to_string(), "Tool.lnk".to_string()));
+
+        let link = ShellLink::parse(&std::fs::read(dir.join("Tool.lnk")).unwrap()).unwrap();
+        assert_eq!(link.target_path().as_deref(), Some(r"C:\Program Files\Tool\tool.exe"));
+        assert_eq!(link.arguments.as_deref(), Some("--tray"));
+        assert_eq!(link.working_dir.as_deref(), Some(r"C:\Program Files\Tool"));
+        assert!(approved::read_approval(&registry, RegistryHive::CurrentUser, ApprovalKey::StartupFolder, "Tool.lnk").is_none());
+
+        // An existing shortcut, or one renamed to `.disabled` by older versions, is not overwritten
+        assert!(matches!(create_shortcut_in(&registry, dir.path(), &item), Err(StartupError::AlreadyExists)));
+        dir.write("Old.lnk.disabled", "");
+        let old = new_item(SourceType::Folder, "Old", "old.exe", None);
+        assert!(matches!(create_shortcut_in(&registry, dir.path(), &old), Err(StartupError::AlreadyExists)));
+        assert_eq!(std::fs::read(dir.join("Old.lnk.disabled")).unwrap(), b"");
+    }
+
+    /// A registry that refuses writes or deletions under one key
+    struct FailingRegistry {
+        inner: MemoryRegistry,
+        fail_writes: Option<(RegistryHive, &'static str)>,
+        fail_deletes: Option<(RegistryHive, &'static str)>,
+    }
+
+    impl FailingRegistry {
+        fn check(rule: Option<(RegistryHive, &'static str)>, hive: RegistryHive, path: &str) -> std::io::Result<()> {
+            match rule {
+                Some((fail_hive, fail_path)) if fail_hive == hive && fail_path.eq_ignore_ascii_case(path) => {
+                    Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "refused"))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    impl RegistryBackend for FailingRegistry {
+        fn open_key(&self, hive: RegistryHive, path: &str) -> std::io::Result<()> {
+            self.inner.open_key(hive, path)
+        }
+
+        fn enum_keys(&self, hive: RegistryHive, path: &str) -> std::io::Result<Vec<String>> {
+            self.inner.enum_keys(hive, path)
+        }
+
+        fn enum_values(&self, hive: RegistryHive, path: &str) -> std::io::Result<Vec<(String, RegistryValue)>> {
+            self.inner.enum_values(hive, path)
+        }
+
+        fn get_raw_value(&self, hive: RegistryHive, path: &str, name: &str) -> std::io::Result<RegistryValue> {
+            self.inner.get_raw_value(hive, path, name)
+        }
+
+        fn set_raw_value(&self, hive: RegistryHive, path: &str, name: &str, value: &RegistryValue) -> std::io::Result<()> {
+            Self::check(self.fail_writes, hive, path)?;
+            self.inner.set_raw_value(hive, path, name, value)
+        }
+
+        fn delete_value(&self, hive: RegistryHive, path: &str, name: &str) -> std::io::Result<()> {
+            Self::check(self.fail_deletes, hive, path)?;
+            self.inner.delete_value(hive, path, name)
+        }
+
+        fn create_subkey(&self, hive: RegistryHive, path: &str) -> std::io::Result<()> {
+            self.inner.create_subkey(hive, path)
+        }
+
+        fn delete_subkey(&self, hive: RegistryHive, path: &str) -> std::io::Result<()> {
+            self.inner.delete_subkey(hive, path)
+        }
+    }
+
+    const DISABLED_RECORD: [u8; 12] = [0x03, 0, 0, 0, 0x10, 0x20, 0x30, 0x40, 0x50, 0x60, 0xD0, 0x01];
+
+    /// A disabled `Tool` entry in the machine-wide Run key
+    fn machine_entry(registry: &dyn RegistryBackend) {
+        registry.create_subkey(RegistryHive::LocalMachine, RUN_PATH).unwrap();
+        registry
+            .set_raw_value(
+                RegistryHive::LocalMachine,
+                RUN_PATH,
+                "Tool",
+                &RegistryValue::from_string(r"C:\Tools\tool.exe", RegValueType::Sz),
+            )
+            .unwrap();
+        approved::write_record(
+            registry,
+            RegistryHive::LocalMachine,
+            ApprovalKey::Run,
+            "Tool",
+            &ApprovalRecord::parse(&DISABLED_RECORD).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn raw(registry: &dyn RegistryBackend, hive: RegistryHive, path: &str, name: &str) -> Option<Vec<u8>> {
+        registry.get_raw_value(hive, path, name).ok().map(|value| value.bytes)
+    }
+
+    #[test]
+    fn renames_entries_within_their_key() {
+        let registry = registry_with(RUN_PATH, "Tool", r"C:\Tools\tool.exe /min");
+        let item = scanned(&registry, "Tool");
+
+        let target = new_item(SourceType::Registry, "Tool Tray", r"C:\Tools\tool.exe", Some("/tray"));
+        let updated = update_entry(&registry, &item, &target).unwrap();
+        assert_eq!(updated, (format!(r"HKEY_CURRENT_USER\{}", RUN_PATH), "Tool Tray".to_string()));
+
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool").is_err());
+        let value = registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool Tray").unwrap();
+        assert_eq!(value.as_string().as_deref(), Some(r"C:\Tools\tool.exe /tray"));
+        // An enabled entry without a record does not gain one
+        assert!(approved::read_approval(&registry, RegistryHive::CurrentUser, ApprovalKey::Run, "Tool Tray").is_none());
+        assert!(scanned(&registry, "Tool Tray").enabled);
+    }
+
+    #[test]
+    fn moves_disabled_entries_between_hives_with_their_record() {
+        let registry = MemoryRegistry::new();
+        machine_entry(&registry);
+        let item = scanned(&registry, "Tool");
+        assert!(!item.enabled);
+
+        let target = new_item(SourceType::Registry, "Tool", r"C:\Tools\tool.exe", None);
+        update_entry(&registry, &item, &target).unwrap();
+
+        assert!(raw(&registry, RegistryHive::LocalMachine, RUN_PATH, "Tool").is_none());
+        assert!(raw(&registry, RegistryHive::LocalMachine, &ApprovalKey::Run.path(), "Tool").is_none());
+        assert!(raw(&registry, RegistryHive::CurrentUser, RUN_PATH, "Tool").is_some());
+        // The disabled flag and the time it was disabled come along
+        assert_eq!(approval_bytes(&registry, "Tool"), DISABLED_RECORD);
+
+        let moved = scanned(&registry, "Tool");
+        assert_eq!(moved.source_location, format!(r"HKEY_CURRENT_USER\{}", RUN_PATH));
+        assert!(!moved.enabled);
+        assert_eq!(moved.disabled_at, item.disabled_at);
+    }
+
+    #[test]
+    fn failed_moves_leave_the_original_entry() {
+        let target = new_item(SourceType::Registry, "Tool", r"C:\Tools\tool.exe", None);
+
+        // The new value cannot be written
+        let registry = FailingRegistry {
+            inner: MemoryRegistry::new(),
+            fail_writes: Some((RegistryHive::CurrentUser, RUN_PATH)),
+            fail_deletes: None,
+        };
+        machine_entry(&registry);
+        let item = scanned(&registry.inner, "Tool");
+        assert!(update_entry(&registry, &item, &target).is_err());
+        assert!(raw(&registry, RegistryHive::LocalMachine, RUN_PATH, "Tool").is_some());
+        assert_eq!(raw(&registry, RegistryHive::LocalMachine, &ApprovalKey::Run.path(), "Tool").unwrap(), DISABLED_RECORD);
+        assert!(raw(&registry, RegistryHive::CurrentUser, RUN_PATH, "Tool").is_none());
+
+        // The old value cannot be removed, so the new one and its record are taken back
+        let registry = FailingRegistry {
+            inner: MemoryRegistry::new(),
+            fail_writes: None,
+            fail_deletes: Some((RegistryHive::LocalMachine, RUN_PATH)),
+        };
+        machine_entry(&registry);
+        let item = scanned(&registry.inner, "Tool");
+        assert!(update_entry(&registry, &item, &target).is_err());
+        assert!(raw(&registry, RegistryHive::LocalMachine, RUN_PATH, "Tool").is_some());
+        assert_eq!(raw(&registry, RegistryHive::LocalMachine, &ApprovalKey::Run.path(), "Tool").unwrap(), DISABLED_RECORD);
+        assert!(raw(&registry, RegistryHive::CurrentUser, RUN_PATH, "Tool").is_none());
+        assert!(raw(&registry, RegistryHive::CurrentUser, &ApprovalKey::Run.path(), "Tool").is_none());
     }
 }
@@ -3,35 +3,132 @@ pub mod manager;
 pub mod icon;
 pub mod settings;
 pub mod monitor;
+pub mod registry;
+pub mod lnk;
+pub mod commandline;
+pub mod launcher;
+pub mod folderfile;
+pub mod item_id;
+pub mod pe;
+pub mod regf;
+pub mod tasks;
+pub mod services;
+pub mod locations;
+pub mod approved;
+pub mod xdg;
+pub mod systemd;
+pub mod sources;
+pub mod history;
+pub mod snapshot;
+pub mod baseline;
+pub mod watcher;
+pub mod sha256;
+pub mod policy;
+pub mod orphans;
+#[cfg(test)]
+mod testdir;
 
 use serde::{Deserialize, Serialize};
 
+use commandline::CommandLine;
+use folderfile::FolderFile;
+use launcher::Launcher;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StartupItem {
     pub id: String,
     pub name: String,
+    /// Name of the underlying entry: registry value or subkey, file name, task or service name
+    pub entry_name: String,
     pub description: Option<String>,
+    pub company_name: Option<String>,
+    pub product_name: Option<String>,
+    pub file_version: Option<String>,
+    pub original_filename: Option<String>,
+    pub legal_copyright: Option<String>,
     pub path: String,
     pub command: String,
+    /// `command` split into the program it starts and its arguments
+    #[serde(default)]
+    pub command_line: CommandLine,
+    /// The host and what it runs, when the command starts `rundll32`, a script host,
+    /// PowerShell, `cmd` or `msiexec`; `path` is then the file it runs
+    #[serde(default)]
+    pub launcher: Option<Launcher>,
+    /// Type, opening program and target of a file in a startup folder
+    #[serde(default)]
+    pub folder_file: Option<FolderFile>,
     pub icon: Option<String>,
     pub source: String,
-    pub source_type: String,
+    pub source_type: SourceType,
     pub source_location: String,
     pub enabled: bool,
+    /// When the entry was disabled (milliseconds since the Unix epoch), from its StartupApproved record
+    #[serde(default)]
+    pub disabled_at: Option<u64>,
     pub valid: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg(test)]
+impl StartupItem {
+    /// An item found at `source_location` under `entry_name`, with nothing else known about it
+    pub fn for_test(source_type: SourceType, source_location: &str, entry_name: &str, command: &str, enabled: bool) -> StartupItem {
+        StartupItem {
+            id: item_id::item_id(source_type, source_location, entry_name),
+            name: entry_name.to_string(),
+            entry_name: entry_name.to_string(),
+            description: None,
+            company_name: None,
+            product_name: None,
+            file_version: None,
+            original_filename: None,
+            legal_copyright: None,
+            path: command.to_string(),
+            command: command.to_string(),
+            command_line: CommandLine::default(),
+            launcher: None,
+            folder_file: None,
+            icon: None,
+            source: format!("{:?}", source_type),
+            source_type,
+            source_location: source_location.to_string(),
+            enabled,
+            disabled_at: None,
+            valid: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SourceType {
     Registry,
     Folder,
+    ScheduledTask,
+    Service,
+    XdgAutostart,
+    SystemdUser,
 }
 
-impl ToString for SourceType {
-    fn to_string(&self) -> String {
-        match self {
-            SourceType::Registry => "registry".to_string(),
-            SourceType::Folder => "folder".to_string(),
-        }
-    }
+/// Whose startup an item belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    CurrentUser,
+    AllUsers,
+}
+
+/// A program to add to startup, or what an existing entry should become.
+/// It is placed by the source it names.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewStartupItem {
+    pub source_type: SourceType,
+    pub scope: Scope,
+    /// Registry value or shortcut name
+    pub name: String,
+    pub path: String,
+    #[serde(default)]
+    pub arguments: Option<String>,
+    #[serde(default)]
+    pub working_directory: Option<String>,
 }
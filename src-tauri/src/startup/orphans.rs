@@ -0,0 +1,318 @@
+//! Settings left behind by startup items that no longer exist under their ID.
+//!
+//! Renaming or moving an entry gives it a new ID, and the auto-minimize,
+//! delay, behavior and process name settings kept under the old one stop
+//! applying. Those settings are found by comparing their IDs with a scan, and
+//! scanned items that look like the lost one are suggested: the same program,
+//! the same process, or the same name. A suggestion can be taken, moving the
+//! settings over, or the settings can be purged.
+
+use serde::{Deserialize, Serialize};
+
+use super::settings::{self, AppSettings, KnownItem};
+use super::{scanner, StartupItem};
+
+/// Why a scanned item is suggested for orphaned settings, strongest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchReason {
+    /// It starts the program the lost item started
+    Program,
+    /// It runs as the process the settings watch for
+    Process,
+    /// It has the lost item's name
+    Name,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelinkSuggestion {
+    pub item_id: String,
+    pub name: String,
+    pub source: String,
+    pub path: String,
+    pub reason: MatchReason,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrphanedSettings {
+    pub item_id: String,
+    /// The item when it was last scanned, unknown for settings older than that record
+    pub last_seen: Option<KnownItem>,
+    pub auto_minimize: bool,
+    pub process_name: Option<String>,
+    pub minimize_behavior: Option<String>,
+    pub minimize_delay: Option<u32>,
+    pub suggestions: Vec<RelinkSuggestion>,
+}
+
+/// Lowercase process name without `.exe`, as the monitor compares them
+fn process_name(program: &str) -> String {
+    let name = program.rsplit(['\\', '/']).next().unwrap_or(program).trim().to_lowercase();
+    name.strip_suffix(".exe").map(str::to_string).unwrap_or(name)
+}
+
+/// The program whose process an item starts
+fn item_process(item: &StartupItem) -> String {
+    process_name(item.launcher.as_ref().map_or(&item.path, |launcher| launcher.process()))
+}
+
+fn match_reason(item: &StartupItem, last_seen: Option<&KnownItem>, process: Option<&str>) -> Option<MatchReason> {
+    if let Some(known) = last_seen
+        && !known.path.is_empty()
+        && known.path.eq_ignore_ascii_case(&item.path)
+    {
+        return Some(MatchReason::Program);
+    }
+    // An explicit mapping names the process; otherwise the lost item's program does
+    let process = process
+        .map(process_name)
+        .or_else(|| last_seen.map(|known| process_name(&known.path)))
+        .filter(|process| !process.is_empty());
+    if process.is_some_and(|process| process == item_process(item)) {
+        return Some(MatchReason::Process);
+    }
+    if let Some(known) = last_seen
+        && known.name.eq_ignore_ascii_case(&item.name)
+    {
+        return Some(MatchReason::Name);
+    }
+    None
+}
+
+/// Settings whose IDs match none of `items`, with the items they might belong to
+pub fn find_orphans(settings: &AppSettings, items: &[StartupItem]) -> Vec<OrphanedSettings> {
+    let mut orphans: Vec<OrphanedSettings> = settings
+        .item_ids()
+        .into_iter()
+        .filter(|id| !items.iter().any(|item| item.id == *id))
+        .map(|id| {
+            let last_seen = settings.known_items.get(id);
+            let process = settings.process_name_mappings.get(id);
+
+            let mut suggestions: Vec<RelinkSuggestion> = items
+                .iter()
+                .filter_map(|item| {
+                    let reason = match_reason(item, last_seen, process.map(String::as_str))?;
+                    Some(RelinkSuggestion {
+                        item_id: item.id.clone(),
+                        name: item.name.clone(),
+                        source: item.source.clone(),
+                        path: item.path.clone(),
+                        reason,
+                    })
+                })
+                .collect();
+            suggestions.sort_by(|a, b| a.reason.cmp(&b.reason).then_with(|| a.name.cmp(&b.name)));
+
+            OrphanedSettings {
+                item_id: id.to_string(),
+                last_seen: last_seen.cloned(),
+                auto_minimize: settings.auto_minimize_items.contains(id),
+                process_name: process.cloned(),
+                minimize_behavior: settings.minimize_behaviors.get(id).cloned(),
+                minimize_delay: settings.minimize_delays.get(id).copied(),
+                suggestions,
+            }
+        })
+        .collect();
+
+    orphans.sort_by(|a, b| a.item_id.cmp(&b.item_id));
+    orphans
+}
+
+/// Orphaned settings among the current settings, against a scan of the running system
+pub fn find_orphaned_settings() -> Vec<OrphanedSettings> {
+    find_orphans(&settings::get_settings(), &scanner::get_all_startup_items())
+}
+
+/// Move the settings of an orphaned ID to an item of `items` that has none of its own
+fn relink(settings: &mut AppSettings, items: &[StartupItem], orphan_id: &str, item_id: &str) -> Result<(), String> {
+    if items.iter().any(|item| item.id == orphan_id) {
+        return Err(format!("设置 {} 仍属于现有启动项", orphan_id));
+    }
+    let item = items
+        .iter()
+        .find(|item| item.id == item_id)
+        .ok_or_else(|| format!("未找到启动项 {}", item_id))?;
+
+    let ids = settings.item_ids();
+    if !ids.contains(orphan_id) {
+        return Err(format!("没有为 {} 保存的设置", orphan_id));
+    }
+    if ids.contains(item_id) {
+        return Err(format!("启动项 {} 已有自己的设置", item.name));
+    }
+    settings.rename_item(orphan_id, item_id);
+    settings.known_items.insert(item.id.clone(), KnownItem::from_item(item));
+    Ok(())
+}
+
+/// Move the settings of an orphaned ID to a scanned item; an item with settings of its own is refused
+pub fn relink_settings(orphan_id: &str, item_id: &str) -> Result<(), String> {
+    let mut settings = settings::get_settings();
+    relink(&mut settings, &scanner::get_all_startup_items(), orphan_id, item_id)?;
+    settings::save_settings(&settings)
+}
+
+/// Drop the settings of orphaned IDs; nothing is dropped if one still belongs to an item of `items`
+fn purge(settings: &mut AppSettings, items: &[StartupItem], orphan_ids: &[String]) -> Result<(), String> {
+    if let Some(id) = orphan_ids.iter().find(|id| items.iter().any(|item| item.id == **id)) {
+        return Err(format!("设置 {} 仍属于现有启动项", id));
+    }
+    for id in orphan_ids {
+        settings.forget_item(id);
+    }
+    Ok(())
+}
+
+/// Drop the settings kept for the given orphaned IDs, refusing those of scanned items
+pub fn purge_settings(orphan_ids: &[String]) -> Result<(), String> {
+    let mut settings = settings::get_settings();
+    purge(&mut settings, &scanner::get_all_startup_items(), orphan_ids)?;
+    settings::save_settings(&settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::SourceType;
+
+    const RUN: &str = r"HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Run";
+
+    fn items() -> Vec<StartupItem> {
+        vec![
+            StartupItem::for_test(SourceType::Registry, RUN, "App", r"C:\App\app.exe", true),
+            StartupItem::for_test(SourceType::Registry, RUN, "Other", r"C:\Other\other.exe", true),
+        ]
+    }
+
+    #[test]
+    fn relinking_moves_orphaned_settings() {
+        let items = items();
+        let mut settings = AppSettings::default();
+        settings.auto_minimize_items.insert("lost".to_string());
+        settings.minimize_delays.insert("lost".to_string(), 4);
+
+        relink(&mut settings, &items, "lost", &items[0].id).unwrap();
+        assert!(settings.auto_minimize_items.contains(&items[0].id));
+        assert_eq!(settings.minimize_delays.get(&items[0].id), Some(&4));
+        assert!(!settings.item_ids().contains("lost"));
+        assert_eq!(settings.known_items[&items[0].id].name, "App");
+    }
+
+    #[test]
+    fn relinking_refuses_to_replace_settings() {
+        let items = items();
+        let mut settings = AppSettings::default();
+        settings.auto_minimize_items.insert("lost".to_string());
+        settings.minimize_delays.insert(items[0].id.clone(), 9);
+        settings.minimize_delays.insert(items[1].id.clone(), 2);
+
+        // The item already has settings
+        assert!(relink(&mut settings, &items, "lost", &items[0].id).is_err());
+        // The settings are not orphaned
+        assert!(relink(&mut settings, &items, &items[1].id, &items[0].id).is_err());
+        // Nothing is kept for the ID, or the item is gone
+        assert!(relink(&mut settings, &items, "unknown", &items[0].id).is_err());
+        assert!(relink(&mut settings, &items, "lost", "missing").is_err());
+
+        assert!(settings.auto_minimize_items.contains("lost"));
+        assert_eq!(settings.minimize_delays.get(&items[0].id), Some(&9));
+        assert_eq!(settings.minimize_delays.get(&items[1].id), Some(&2));
+    }
+
+    fn known(name: &str, path: &str) -> KnownItem {
+        KnownItem {
+            name: name.to_string(),
+            entry_name: name.to_string(),
+            source: "HKCU\\...\\Run".to_string(),
+            source_location: RUN.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    fn run_item(name: &str, path: &str) -> StartupItem {
+        StartupItem::for_test(SourceType::Registry, RUN, name, path, true)
+    }
+
+    #[test]
+    fn match_reasons_prefer_program_then_process_then_name() {
+        let lost = known("Tool", r"C:\Old\tool.exe");
+        let reason = |name: &str, path: &str, process: Option<&str>| match_reason(&run_item(name, path), Some(&lost), process);
+
+        assert_eq!(reason("Renamed", r"c:\old\TOOL.EXE", None), Some(MatchReason::Program));
+        assert_eq!(reason("Tool", r"C:\Old\tool.exe", None), Some(MatchReason::Program));
+        assert_eq!(reason("Renamed", r"C:\New\Tool.exe", None), Some(MatchReason::Process));
+        assert_eq!(reason("Tool", r"C:\New\tool.exe", None), Some(MatchReason::Process));
+        assert_eq!(reason("tool", r"C:\New\other.exe", None), Some(MatchReason::Name));
+        assert_eq!(reason("Other", r"C:\New\other.exe", None), None);
+
+        // A process mapping replaces the lost item's program
+        assert_eq!(reason("Other", r"C:\New\helper.exe", Some("Helper.exe")), Some(MatchReason::Process));
+        assert_eq!(reason("Other", r"C:\New\tool.exe", Some("helper")), None);
+
+        // Without a record of the item only a mapping can match
+        let item = run_item("Tool", r"C:\Old\tool.exe");
+        assert_eq!(match_reason(&item, None, None), None);
+        assert_eq!(match_reason(&item, None, Some("tool")), Some(MatchReason::Process));
+        assert_eq!(match_reason(&item, Some(&known("Tool", "")), None), Some(MatchReason::Name));
+    }
+
+    #[test]
+    fn finds_settings_without_an_item_and_ranks_suggestions() {
+        let items = vec![
+            run_item("Other", r"C:\Other\other.exe"),
+            run_item("Mirror", r"C:\Mirror\app.exe"),
+            run_item("App 2", r"C:\App\app.exe"),
+            run_item("Unrelated", r"C:\Unrelated\unrelated.exe"),
+        ];
+        let mut settings = AppSettings::default();
+        settings.auto_minimize_items.insert(items[0].id.clone());
+        settings.auto_minimize_items.insert("lost".to_string());
+        settings.known_items.insert("lost".to_string(), known("Other", r"C:\App\app.exe"));
+        settings.minimize_delays.insert("mapped".to_string(), 3);
+        settings.process_name_mappings.insert("mapped".to_string(), "unrelated.exe".to_string());
+
+        let orphans = find_orphans(&settings, &items);
+        let ids: Vec<&str> = orphans.iter().map(|orphan| orphan.item_id.as_str()).collect();
+        assert_eq!(ids, ["lost", "mapped"]);
+
+        let lost = &orphans[0];
+        assert!(lost.auto_minimize);
+        assert_eq!(lost.last_seen.as_ref().map(|known| known.name.as_str()), Some("Other"));
+        let suggestions: Vec<(&str, MatchReason)> = lost.suggestions.iter().map(|s| (s.name.as_str(), s.reason)).collect();
+        assert_eq!(
+            suggestions,
+            [("App 2", MatchReason::Program), ("Mirror", MatchReason::Process), ("Other", MatchReason::Name)]
+        );
+        assert_eq!(lost.suggestions[0].item_id, items[2].id);
+
+        let mapped = &orphans[1];
+        assert!(mapped.last_seen.is_none());
+        assert_eq!(mapped.minimize_delay, Some(3));
+        assert_eq!(mapped.process_name.as_deref(), Some("unrelated.exe"));
+        assert_eq!(mapped.suggestions.len(), 1);
+        assert_eq!(mapped.suggestions[0].item_id, items[3].id);
+
+        // Once every ID has its item there is nothing to report
+        let mut settings = AppSettings::default();
+        settings.auto_minimize_items.insert(items[0].id.clone());
+        assert!(find_orphans(&settings, &items).is_empty());
+    }
+
+    #[test]
+    fn purging_refuses_settings_of_existing_items() {
+        let items = items();
+        let mut settings = AppSettings::default();
+        settings.auto_minimize_items.insert("lost".to_string());
+        settings.minimize_delays.insert(items[0].id.clone(), 9);
+
+        assert!(purge(&mut settings, &items, &["lost".to_string(), items[0].id.clone()]).is_err());
+        assert!(settings.auto_minimize_items.contains("lost"));
+        assert_eq!(settings.minimize_delays.get(&items[0].id), Some(&9));
+
+        purge(&mut settings, &items, &["lost".to_string()]).unwrap();
+        assert!(!settings.item_ids().contains("lost"));
+        assert_eq!(settings.minimize_delays.get(&items[0].id), Some(&9));
+    }
+}
@@ -0,0 +1,547 @@
+//! Minimal PE (Portable Executable) reader for the resource section.
+//!
+//! Only the headers and the section holding the resource directory are read,
+//! so large executables are not loaded into memory as a whole.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const RT_ICON: u16 = 3;
+pub const RT_GROUP_ICON: u16 = 14;
+pub const RT_VERSION: u16 = 16;
+
+const IMAGE_DIRECTORY_ENTRY_RESOURCE: usize = 2;
+const VS_FIXEDFILEINFO_SIGNATURE: u32 = 0xFEEF_04BD;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PeError {
+    Io(String),
+    NotPe,
+    NoResources,
+    Truncated,
+}
+
+impl std::fmt::Display for PeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeError::Io(msg) => write!(f, "读取文件失败: {}", msg),
+            PeError::NotPe => write!(f, "不是有效的PE文件"),
+            PeError::NoResources => write!(f, "PE文件不包含资源"),
+            PeError::Truncated => write!(f, "PE文件已损坏"),
+        }
+    }
+}
+
+/// Resource name or ID at one level of the resource tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ResourceName {
+    Id(u16),
+    Name(String),
+}
+
+/// A leaf of the resource tree: type / name / language → data
+#[derive(Debug, Clone)]
+pub struct Resource<'a> {
+    pub name: ResourceName,
+    pub language: u16,
+    pub data: &'a [u8],
+}
+
+/// The resource section of a PE file
+#[derive(Debug, Clone)]
+pub struct PeResources {
+    /// RVA at which `section` is mapped
+    section_rva: u32,
+    section: Vec<u8>,
+    /// Offset of the root resource directory inside `section`
+    root: usize,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn io_error(e: std::io::Error) -> PeError {
+    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+        PeError::Truncated
+    } else {
+        PeError::Io(e.to_string())
+    }
+}
+
+impl PeResources {
+    pub fn from_file(path: &Path) -> Result<PeResources, PeError> {
+        let file = std::fs::File::open(path).map_err(io_error)?;
+        PeResources::from_reader(std::io::BufReader::new(file))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<PeResources, PeError> {
+        PeResources::from_reader(std::io::Cursor::new(data))
+    }
+
+    fn from_reader<R: Read + Seek>(mut reader: R) -> Result<PeResources, PeError> {
+        let mut dos_header = [0u8; 64];
+        reader.read_exact(&mut dos_header).map_err(|_| PeError::NotPe)?;
+        if &dos_header[0..2] != b"MZ" {
+            return Err(PeError::NotPe);
+        }
+        let pe_offset = read_u32(&dos_header, 0x3C).ok_or(PeError::NotPe)? as u64;
+
+        // PE signature + COFF file header
+        let mut nt_header = [0u8; 24];
+        reader.seek(SeekFrom::Start(pe_offset)).map_err(io_error)?;
+        reader.read_exact(&mut nt_header).map_err(|_| PeError::NotPe)?;
+        if &nt_header[0..4] != b"PE\0\0" {
+            return Err(PeError::NotPe);
+        }
+        let section_count = read_u16(&nt_header, 6).ok_or(PeError::NotPe)? as usize;
+        let optional_header_size = read_u16(&nt_header, 20).ok_or(PeError::NotPe)? as usize;
+
+        let mut optional_header = vec![0u8; optional_header_size];
+        reader.read_exact(&mut optional_header).map_err(io_error)?;
+
+        // Data directories follow the fixed part, which is longer for PE32+
+        let (count_offset, directories_offset) = match read_u16(&optional_header, 0) {
+            Some(0x10B) => (92, 96),
+            Some(0x20B) => (108, 112),
+            _ => return Err(PeError::NotPe),
+        };
+        let directory_count = read_u32(&optional_header, count_offset).ok_or(PeError::Truncated)? as usize;
+        if directory_count <= IMAGE_DIRECTORY_ENTRY_RESOURCE {
+            return Err(PeError::NoResources);
+        }
+        let resource_entry = directories_offset + IMAGE_DIRECTORY_ENTRY_RESOURCE * 8;
+        let resource_rva = read_u32(&optional_header, resource_entry).ok_or(PeError::Truncated)?;
+        if resource_rva == 0 {
+            return Err(PeError::NoResources);
+        }
+
+        let mut section_table = vec![0u8; section_count * 40];
+        reader.read_exact(&mut section_table).map_err(io_error)?;
+        let file_len = reader.seek(SeekFrom::End(0)).map_err(io_error)?;
+
+        for header in section_table.chunks_exact(40) {
+            let virtual_size = read_u32(header, 8).unwrap_or(0);
+            let virtual_address = read_u32(header, 12).unwrap_or(0);
+            let raw_size = read_u32(header, 16).unwrap_or(0);
+            let raw_offset = read_u32(header, 20).unwrap_or(0);
+
+            let mapped_size = virtual_size.max(raw_size);
+            if resource_rva < virtual_address || resource_rva >= virtual_address.saturating_add(mapped_size) {
+                continue;
+            }
+
+            // Checked before allocating, as the header can claim any size
+            if raw_offset as u64 + raw_size as u64 > file_len {
+                return Err(PeError::Truncated);
+            }
+            let mut section = vec![0u8; raw_size as usize];
+            reader.seek(SeekFrom::Start(raw_offset as u64)).map_err(io_error)?;
+            reader.read_exact(&mut section).map_err(io_error)?;
+
+            return Ok(PeResources {
+                section_rva: virtual_address,
+                section,
+                root: (resource_rva - virtual_address) as usize,
+            });
+        }
+
+        Err(PeError::NoResources)
+    }
+
+    /// Entries of the resource directory at `offset`: (name, is_directory, target offset)
+    fn directory_entries(&self, offset: usize) -> Vec<(ResourceName, bool, usize)> {
+        let dir = match self.section.get(offset..) {
+            Some(dir) => dir,
+            None => return Vec::new(),
+        };
+        let named = read_u16(dir, 12).unwrap_or(0) as usize;
+        let ids = read_u16(dir, 14).unwrap_or(0) as usize;
+
+        (0..named + ids)
+            .filter_map(|i| {
+                let entry = 16 + i * 8;
+                let name_field = read_u32(dir, entry)?;
+                let offset_field = read_u32(dir, entry + 4)?;
+
+                let name = if name_field & 0x8000_0000 != 0 {
+                    // Length-prefixed UTF-16 string relative to the resource root
+                    let string_offset = self.root + (name_field & 0x7FFF_FFFF) as usize;
+                    let len = read_u16(&self.section, string_offset)? as usize;
+                    let bytes = self.section.get(string_offset + 2..string_offset + 2 + len * 2)?;
+                    let units: Vec<u16> = bytes
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                        .collect();
+                    ResourceName::Name(String::from_utf16_lossy(&units))
+                } else {
+                    ResourceName::Id(name_field as u16)
+                };
+
+                let is_directory = offset_field & 0x8000_0000 != 0;
+                Some((name, is_directory, self.root + (offset_field & 0x7FFF_FFFF) as usize))
+            })
+            .collect()
+    }
+
+    fn data_entry(&self, offset: usize) -> Option<&[u8]> {
+        let data_rva = read_u32(&self.section, offset)?;
+        let size = read_u32(&self.section, offset + 4)? as usize;
+        let start = data_rva.checked_sub(self.section_rva)? as usize;
+        self.section.get(start..start + size)
+    }
+
+    /// All resources of one type, in directory order
+    pub fn resources(&self, type_id: u16) -> Vec<Resource<'_>> {
+        let mut result = Vec::new();
+
+        let type_dir = self.directory_entries(self.root)
+            .into_iter()
+            .find(|(name, is_dir, _)| *is_dir && *name == ResourceName::Id(type_id));
+        let Some((_, _, type_offset)) = type_dir else {
+            return result;
+        };
+
+        for (name, is_dir, name_offset) in self.directory_entries(type_offset) {
+            if !is_dir {
+                continue;
+            }
+            for (language, is_dir, lang_offset) in self.directory_entries(name_offset) {
+                if is_dir {
+                    continue;
+                }
+                if let Some(data) = self.data_entry(lang_offset) {
+                    let language = match language {
+                        ResourceName::Id(id) => id,
+                        ResourceName::Name(_) => 0,
+                    };
+                    result.push(Resource {
+                        name: name.clone(),
+                        language,
+                        data,
+                    });
+                }
+            }
+        }
+
+        result
+    }
+
+    /// First resource of the given type and name, in any language
+    pub fn find_resource(&self, type_id: u16, name: &ResourceName) -> Option<Resource<'_>> {
+        self.resources(type_id).into_iter().find(|r| &r.name == name)
+    }
+
+    pub fn version_info(&self) -> Option<VersionInfo> {
+        self.resources(RT_VERSION)
+            .first()
+            .and_then(|resource| VersionInfo::parse(resource.data))
+    }
+}
+
+/// Numeric version from VS_FIXEDFILEINFO
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedFileInfo {
+    pub file_version: [u16; 4],
+    pub product_version: [u16; 4],
+    pub file_flags: u32,
+    pub file_os: u32,
+    pub file_type: u32,
+}
+
+/// One StringFileInfo table, e.g. "080404B0"
+#[derive(Debug, Clone, PartialEq)]
+pub struct StringTable {
+    pub language: u16,
+    pub code_page: u16,
+    pub strings: Vec<(String, String)>,
+}
+
+impl StringTable {
+    /// Non-empty value of a key, compared case-insensitively
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.strings
+            .iter()
+            .find(|(k, v)| k.eq_ignore_ascii_case(key) && !v.is_empty())
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionInfo {
+    pub fixed: Option<FixedFileInfo>,
+    pub string_tables: Vec<StringTable>,
+    /// Language/code page pairs from VarFileInfo\Translation
+    pub translations: Vec<(u16, u16)>,
+}
+
+/// A version resource block: key, value bytes, children bytes
+struct VersionBlock<'a> {
+    key: String,
+    value_type: u16,
+    value_length: u16,
+    value: &'a [u8],
+    children: &'a [u8],
+}
+
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+fn parse_version_block(data: &[u8]) -> Option<(VersionBlock<'_>, usize)> {
+    let length = read_u16(data, 0)? as usize;
+    let value_length = read_u16(data, 2)?;
+    let value_type = read_u16(data, 4)?;
+    if length < 6 || length > data.len() {
+        return None;
+    }
+    let block = &data[..length];
+
+    let mut key_units = Vec::new();
+    let mut offset = 6;
+    loop {
+        let unit = read_u16(block, offset)?;
+        offset += 2;
+        if unit == 0 {
+            break;
+        }
+        key_units.push(unit);
+    }
+    let value_offset = align4(offset).min(length);
+
+    // Text values count UTF-16 characters, binary values count bytes
+    let value_bytes = if value_type == 1 { value_length as usize * 2 } else { value_length as usize };
+    let value_end = (value_offset + value_bytes).min(length);
+    let children_offset = align4(value_end).min(length);
+
+    Some((
+        VersionBlock {
+            key: String::from_utf16_lossy(&key_units),
+            value_type,
+            value_length,
+            value: &block[value_offset..value_end],
+            children: &block[children_offset..],
+        },
+        align4(length),
+    ))
+}
+
+fn version_children(data: &[u8]) -> Vec<VersionBlock<'_>> {
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        match parse_version_block(&data[offset..]) {
+            Some((block, size)) => {
+                blocks.push(block);
+                offset += size;
+            }
+            None => break,
+        }
+    }
+    blocks
+}
+
+fn utf16_value(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    String::from_utf16_lossy(&units).trim().to_string()
+}
+
+fn split_version(ms: u32, ls: u32) -> [u16; 4] {
+    [(ms >> 16) as u16, ms as u16, (ls >> 16) as u16, ls as u16]
+}
+
+impl VersionInfo {
+    /// Parse a raw VS_VERSIONINFO resource
+    pub fn parse(data: &[u8]) -> Option<VersionInfo> {
+        let (root, _) = parse_version_block(data)?;
+        if root.key != "VS_VERSION_INFO" {
+            return None;
+        }
+
+        let fixed = read_u32(root.value, 0)
+            .filter(|&signature| signature == VS_FIXEDFILEINFO_SIGNATURE && root.value_length >= 52)
+            .and_then(|_| {
+                let field = |index: usize| read_u32(root.value, index * 4);
+                Some(FixedFileInfo {
+                    file_version: split_version(field(2)?, field(3)?),
+                    product_version: split_version(field(4)?, field(5)?),
+                    file_flags: field(7)? & field(6)?,
+                    file_os: field(8)?,
+                    file_type: field(9)?,
+                })
+            });
+
+        let mut info = VersionInfo {
+            fixed,
+            string_tables: Vec::new(),
+            translations: Vec::new(),
+        };
+
+        for child in version_children(root.children) {
+            match child.key.as_str() {
+                "StringFileInfo" => {
+                    for table in version_children(child.children) {
+                        let id = u32::from_str_radix(&table.key, 16).unwrap_or(0);
+                        let strings = version_children(table.children)
+                            .into_iter()
+                            .map(|s| {
+                                let value = if s.value_type == 1 { utf16_value(s.value) } else { String::new() };
+                                (s.key, value)
+                            })
+                            .collect();
+                        info.string_tables.push(StringTable {
+                            language: (id >> 16) as u16,
+                            code_page: id as u16,
+                            strings,
+                        });
+                    }
+                }
+                "VarFileInfo" => {
+                    for var in version_children(child.children) {
+                        if var.key == "Translation" {
+                            info.translations.extend(
+                                var.value
+                                    .chunks_exact(4)
+                                    .map(|c| (u16::from_le_bytes([c[0], c[1]]), u16::from_le_bytes([c[2], c[3]]))),
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Some(info)
+    }
+
+    /// Look up a string, preferring the declared translations, then US English,
+    /// then whichever table has a non-empty value
+    pub fn get(&self, key: &str) -> Option<&str> {
+        let preferred = self.translations
+            .iter()
+            .chain(std::iter::once(&(0x0409, 0x04B0)))
+            .filter_map(|(lang, cp)| {
+                self.string_tables
+                    .iter()
+                    .find(|t| t.language == *lang && t.code_page == *cp)
+            })
+            .find_map(|table| table.get(key));
+
+        preferred.or_else(|| self.string_tables.iter().find_map(|table| table.get(key)))
+    }
+
+    pub fn file_description(&self) -> Option<&str> {
+        self.get("FileDescription")
+    }
+
+    pub fn company_name(&self) -> Option<&str> {
+        self.get("CompanyName")
+    }
+
+    pub fn product_name(&self) -> Option<&str> {
+        self.get("ProductName")
+    }
+
+    pub fn original_filename(&self) -> Option<&str> {
+        self.get("OriginalFilename")
+    }
+
+    pub fn legal_copyright(&self) -> Option<&str> {
+        self.get("LegalCopyright")
+    }
+
+    /// FileVersion string, or the numeric version when the string is missing
+    pub fn file_version(&self) -> Option<String> {
+        self.get("FileVersion")
+            .map(|v| v.to_string())
+            .or_else(|| {
+                self.fixed.map(|f| {
+                    let [a, b, c, d] = f.file_version;
+                    format!("{}.{}.{}.{}", a, b, c, d)
+                })
+            })
+    }
+}
+
+/// Read the version resource of an executable or DLL
+pub fn read_version_info(path: &str) -> Option<VersionInfo> {
+    PeResources::from_file(Path::new(path)).ok()?.version_info()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VERSION32: &[u8] = include_bytes!("../../tests/fixtures/pe/version32.exe");
+    const VERSION64: &[u8] = include_bytes!("../../tests/fixtures/pe/version64.dll");
+
+    /// File offset of the first section header's SizeOfRawData in the 32-bit fixture
+    const RAW_SIZE_OFFSET: usize = 0x40 + 24 + 0xE0 + 16;
+
+    #[test]
+    fn reads_version_info_from_pe32() {
+        let resources = PeResources::from_bytes(VERSION32).unwrap();
+        let version = resources.resources(RT_VERSION);
+        assert_eq!(version.len(), 1);
+        assert_eq!(version[0].name, ResourceName::Id(1));
+        assert_eq!(version[0].language, 0x0409);
+
+        let info = resources.version_info().unwrap();
+        let fixed = info.fixed.unwrap();
+        assert_eq!(fixed.file_version, [2, 5, 0, 17]);
+        assert_eq!(fixed.file_os, 0x0004_0004);
+        assert_eq!(fixed.file_type, 1);
+        assert_eq!(info.translations, vec![(0x0804, 0x04B0)]);
+        assert_eq!(info.string_tables.len(), 2);
+
+        // The declared translation wins, then US English, then any table
+        assert_eq!(info.file_description(), Some("Contoso 同步"));
+        assert_eq!(info.company_name(), Some("Contoso Ltd."));
+        assert_eq!(info.file_version().as_deref(), Some("2.5.0.17"));
+        assert_eq!(info.legal_copyright(), None);
+    }
+
+    #[test]
+    fn reads_named_resources_from_pe32_plus() {
+        let resources = PeResources::from_bytes(VERSION64).unwrap();
+        let name = ResourceName::Name("VERSION".to_string());
+        let resource = resources.find_resource(RT_VERSION, &name).unwrap();
+        assert_eq!(resource.language, 0x0804);
+        assert!(resources.resources(RT_GROUP_ICON).is_empty());
+
+        let info = resources.version_info().unwrap();
+        assert_eq!(info.company_name(), Some("示例公司"));
+        assert_eq!(info.product_name(), Some("示例"));
+        // No FileVersion string: the numeric version stands in
+        assert_eq!(info.file_version().as_deref(), Some("10.0.19041.1"));
+    }
+
+    #[test]
+    fn rejects_sections_past_the_end_of_the_file() {
+        let truncated = &VERSION32[..VERSION32.len() - 1];
+        assert_eq!(PeResources::from_bytes(truncated).unwrap_err(), PeError::Truncated);
+
+        let mut data = VERSION32.to_vec();
+        data[RAW_SIZE_OFFSET..RAW_SIZE_OFFSET + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(PeResources::from_bytes(&data).unwrap_err(), PeError::Truncated);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_pe() {
+        assert_eq!(PeResources::from_bytes(b"MZ").unwrap_err(), PeError::NotPe);
+
+        let mut data = VERSION32.to_vec();
+        data[0x40] = b'N';
+        assert_eq!(PeResources::from_bytes(&data).unwrap_err(), PeError::NotPe);
+    }
+}
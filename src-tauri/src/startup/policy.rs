@@ -0,0 +1,785 @@
+//! Allowlist policy for startup items.
+//!
+//! The policy lists the entries allowed to start, by name, path glob, file
+//! hash or publisher, and says what to do with every other item: only report
+//! it, disable it, or quarantine it. A quarantined entry is removed and kept
+//! in the `quarantine` directory so it can be put back. This app's own entry
+//! and the services, tasks and Active Setup components that come with Windows
+//! are only ever reported. Every decision made while enforcing is appended to
+//! `policy.log` in the config directory.
+//!
+//! A policy in `%ProgramData%\UMStartupManager` covers every user of the
+//! machine and takes precedence over the one in the user's config directory.
+
+use std::cell::OnceCell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::commandline::expand_environment_strings;
+use super::locations::{EntryLayout, REGISTRY_LOCATIONS};
+use super::manager;
+use super::scanner;
+use super::settings;
+use super::sha256::sha256_file;
+use super::snapshot::SnapshotItem;
+use super::sources::source_for;
+use super::{SourceType, StartupItem};
+
+const POLICY_FILE: &str = "policy.json";
+const LOG_FILE: &str = "policy.log";
+const QUARANTINE_DIR: &str = "quarantine";
+/// The log is rotated to `policy.log.1` past this size
+const MAX_LOG_SIZE: u64 = 1024 * 1024;
+
+lazy_static::lazy_static! {
+    /// Serializes appends to the decision log
+    static ref LOG_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// An allowed entry. Every criterion given must match; a rule without any matches nothing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AllowRule {
+    /// Glob (`*`, `?`) matched against the item's name or entry name
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Glob matched against the executable path, after expanding `%VAR%`
+    #[serde(default)]
+    pub path: Option<String>,
+    /// SHA-256 of the executable, in hex
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Glob matched against the company name in the executable's version information
+    #[serde(default)]
+    pub publisher: Option<String>,
+}
+
+/// What happens to an item no rule allows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockAction {
+    /// Only log and report it
+    #[default]
+    Notify,
+    /// Switch it off, through StartupApproved where the source uses it
+    Disable,
+    /// Remove it and keep a copy to restore; entries that cannot be copied are disabled
+    Quarantine,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StartupPolicy {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub action: BlockAction,
+    #[serde(default)]
+    pub rules: Vec<AllowRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PolicyDecision {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub item_name: String,
+    pub entry_name: String,
+    pub source: String,
+    pub source_type: SourceType,
+    pub path: String,
+    pub allowed: bool,
+    /// Index of the first rule that allows the item
+    pub rule: Option<usize>,
+    /// What was (or in a preview, would be) done with a blocked item; `None` when nothing has to change
+    pub action: Option<BlockAction>,
+    pub error: Option<String>,
+}
+
+/// An entry removed by the policy, with what is needed to write it back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantinedItem {
+    pub id: String,
+    pub quarantined_at: u64,
+    /// Executable the entry started, for allowing it on restore
+    pub path: String,
+    pub item: SnapshotItem,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Case-insensitive glob match where `*` spans any run of characters and `?` one.
+/// `/` and `\` are treated alike.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let normalize = |s: &str| -> Vec<char> {
+        s.chars()
+            .map(|c| if c == '/' { '\\' } else { c })
+            .flat_map(char::to_lowercase)
+            .collect()
+    };
+    let pattern = normalize(pattern.trim());
+    let text = normalize(text);
+
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it is tried against
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the `*` take one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// An item being evaluated; its hash is only computed when a rule asks for it
+struct Candidate<'a> {
+    item: &'a StartupItem,
+    hash: OnceCell<Option<String>>,
+}
+
+impl Candidate<'_> {
+    fn hash(&self) -> Option<&str> {
+        self.hash
+            .get_or_init(|| sha256_file(Path::new(&self.item.path)).ok())
+            .as_deref()
+    }
+}
+
+impl AllowRule {
+    fn is_empty(&self) -> bool {
+        [&self.name, &self.path, &self.sha256, &self.publisher]
+            .iter()
+            .all(|criterion| criterion.as_deref().is_none_or(|value| value.trim().is_empty()))
+    }
+
+    fn matches(&self, candidate: &Candidate) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let item = candidate.item;
+        let given = |criterion: &Option<String>| criterion.clone().filter(|value| !value.trim().is_empty());
+
+        given(&self.name).is_none_or(|name| glob_match(&name, &item.name) || glob_match(&name, &item.entry_name))
+            && given(&self.path).is_none_or(|path| glob_match(&expand_environment_strings(&path), &item.path))
+            && given(&self.publisher).is_none_or(|publisher| {
+                item.company_name.as_deref().is_some_and(|company| glob_match(&publisher, company))
+            })
+            && given(&self.sha256).is_none_or(|hash| {
+                candidate.hash().is_some_and(|actual| actual.eq_ignore_ascii_case(hash.trim()))
+            })
+    }
+}
+
+/// Index of the first rule that allows an item
+pub fn evaluate(policy: &StartupPolicy, item: &StartupItem) -> Option<usize> {
+    let candidate = Candidate { item, hash: OnceCell::new() };
+    policy.rules.iter().position(|rule| rule.matches(&candidate))
+}
+
+fn machine_policy_path() -> Option<PathBuf> {
+    std::env::var("ProgramData")
+        .ok()
+        .map(|dir| PathBuf::from(dir).join("UMStartupManager").join(POLICY_FILE))
+}
+
+/// The machine-wide policy when there is one, otherwise the user's
+pub fn policy_path() -> PathBuf {
+    machine_policy_path()
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| settings::get_config_dir().join(POLICY_FILE))
+}
+
+/// The policy in effect; without a policy file nothing is enforced
+pub fn load_policy() -> Result<StartupPolicy, String> {
+    let path = policy_path();
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content).map_err(|e| format!("策略文件格式无效: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(StartupPolicy::default()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+pub fn save_policy(policy: &StartupPolicy) -> Result<(), String> {
+    let path = policy_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let content = serde_json::to_string_pretty(policy).map_err(|e| e.to_string())?;
+    std::fs::write(&path, content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+pub fn is_enforced() -> bool {
+    load_policy().is_ok_and(|policy| policy.enabled)
+}
+
+/// Kept beside the settings file; resetting the settings leaves it alone
+fn quarantine_dir() -> PathBuf {
+    settings::get_config_dir().join(QUARANTINE_DIR)
+}
+
+/// How enforcement changes entries. On the system every change goes through the
+/// manager, so it is journaled and acknowledged in the baseline like a manual one.
+trait EntryActions {
+    fn snapshot(&self, item: &StartupItem) -> SnapshotItem;
+    fn disable(&self, item: &StartupItem) -> Result<(), String>;
+    fn delete(&self, item: &StartupItem) -> Result<(), String>;
+    fn restore(&self, item: &SnapshotItem) -> Result<(), String>;
+}
+
+struct SystemActions;
+
+impl EntryActions for SystemActions {
+    fn snapshot(&self, item: &StartupItem) -> SnapshotItem {
+        SnapshotItem::from_item(item)
+    }
+
+    fn disable(&self, item: &StartupItem) -> Result<(), String> {
+        manager::toggle_startup_item(item, false).map_err(|e| e.to_string())
+    }
+
+    fn delete(&self, item: &StartupItem) -> Result<(), String> {
+        manager::delete_startup_item(item).map_err(|e| e.to_string())
+    }
+
+    fn restore(&self, item: &SnapshotItem) -> Result<(), String> {
+        item.restore()
+    }
+}
+
+/// Entries the policy reports but never disables or removes: this app's own entry,
+/// and services, Active Setup components and scheduled tasks that come with Windows.
+/// Winlogon values need no exemption, as they are only listed when they start more
+/// than the Windows default, and disabling one puts the default back.
+struct Exemptions {
+    own_executable: Option<String>,
+    /// The Windows directory, which only administrators and installers write to
+    system_root: Option<String>,
+}
+
+impl Exemptions {
+    fn system() -> Exemptions {
+        Exemptions {
+            own_executable: std::env::current_exe().ok().map(|path| path.to_string_lossy().to_string()),
+            system_root: std::env::var("SystemRoot").ok(),
+        }
+    }
+
+    fn is_system_file(&self, path: &str) -> bool {
+        self.system_root.as_deref().is_some_and(|root| {
+            let root = format!("{}\\", root.trim_end_matches(['\\', '/'])).to_lowercase();
+            path.replace('/', "\\").to_lowercase().starts_with(&root)
+        })
+    }
+
+    fn covers(&self, item: &StartupItem) -> bool {
+        if self.own_executable.as_deref().is_some_and(|exe| exe.eq_ignore_ascii_case(&item.path)) {
+            return true;
+        }
+        match item.source_type {
+            SourceType::Service => self.is_system_file(&item.path),
+            SourceType::ScheduledTask => {
+                let folder = item.source_location.to_lowercase();
+                folder == "\\microsoft" || folder.starts_with("\\microsoft\\")
+            }
+            SourceType::Registry => {
+                let active_setup = REGISTRY_LOCATIONS.iter().any(|location| {
+                    matches!(location.layout, EntryLayout::SubkeyPerEntry { .. })
+                        && location.full_path().eq_ignore_ascii_case(&item.source_location)
+                });
+                active_setup && self.is_system_file(&item.path)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Blocks and quarantines entries, and puts quarantined ones back
+struct Enforcer<'a> {
+    actions: &'a dyn EntryActions,
+    quarantine_dir: PathBuf,
+    exemptions: Exemptions,
+}
+
+impl Enforcer<'static> {
+    fn system() -> Enforcer<'static> {
+        Enforcer { actions: &SystemActions, quarantine_dir: quarantine_dir(), exemptions: Exemptions::system() }
+    }
+}
+
+impl Enforcer<'_> {
+    /// Copy an entry into the quarantine and remove it
+    fn quarantine(&self, item: &StartupItem) -> Result<(), String> {
+        let timestamp = now_millis();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        let record = QuarantinedItem {
+            id: format!("{}-{:08x}", timestamp, nanos),
+            quarantined_at: timestamp,
+            path: item.path.clone(),
+            item: self.actions.snapshot(item),
+        };
+
+        std::fs::create_dir_all(&self.quarantine_dir).map_err(|e| e.to_string())?;
+        let file = self.quarantine_dir.join(format!("{}.json", record.id));
+        let content = serde_json::to_string_pretty(&record).map_err(|e| e.to_string())?;
+        std::fs::write(&file, content).map_err(|e| e.to_string())?;
+
+        self.actions.delete(item).inspect_err(|_| {
+            let _ = std::fs::remove_file(&file);
+        })
+    }
+
+    /// The action a blocked item gets, carrying it out unless this is a preview
+    fn block(&self, action: BlockAction, item: &StartupItem, apply: bool) -> Result<Option<BlockAction>, String> {
+        // Exempt entries are only reported
+        let action = if self.exemptions.covers(item) { BlockAction::Notify } else { action };
+        let capabilities = source_for(item.source_type).map(|source| source.capabilities());
+        let can_quarantine = action == BlockAction::Quarantine
+            && capabilities.is_some_and(|c| c.can_delete)
+            && self.actions.snapshot(item).data.is_some();
+        let can_disable = capabilities.is_some_and(|c| c.can_toggle);
+
+        let action = match action {
+            BlockAction::Quarantine if can_quarantine => BlockAction::Quarantine,
+            _ if !item.enabled => return Ok(None),
+            BlockAction::Notify => BlockAction::Notify,
+            BlockAction::Disable | BlockAction::Quarantine if can_disable => BlockAction::Disable,
+            BlockAction::Disable | BlockAction::Quarantine => return Err("该启动项不支持禁用".to_string()),
+        };
+        if apply {
+            match action {
+                BlockAction::Quarantine => self.quarantine(item)?,
+                BlockAction::Disable => self.actions.disable(item)?,
+                BlockAction::Notify => {}
+            }
+        }
+        Ok(Some(action))
+    }
+
+    fn read_quarantined(&self, id: &str) -> Result<QuarantinedItem, String> {
+        let file = self.quarantine_dir.join(format!("{}.json", id));
+        let content = std::fs::read_to_string(&file).map_err(|_| "隔离记录不存在".to_string())?;
+        serde_json::from_str(&content).map_err(|e| e.to_string())
+    }
+
+    /// Write a quarantined entry back and drop its record
+    fn restore(&self, record: &QuarantinedItem) -> Result<(), String> {
+        self.actions.restore(&record.item)?;
+        std::fs::remove_file(self.quarantine_dir.join(format!("{}.json", record.id))).map_err(|e| e.to_string())
+    }
+
+    /// Evaluate items against a policy; with `apply` the actions are carried out and logged
+    fn check_items(&self, policy: &StartupPolicy, items: &[StartupItem], apply: bool) -> Vec<PolicyDecision> {
+        let decisions: Vec<PolicyDecision> = items
+            .iter()
+            .map(|item| {
+                let rule = evaluate(policy, item);
+                let (action, error) = if rule.is_some() {
+                    (None, None)
+                } else {
+                    match self.block(policy.action, item, apply) {
+                        Ok(action) => (action, None),
+                        Err(e) => (None, Some(e)),
+                    }
+                };
+                PolicyDecision {
+                    timestamp: now_millis(),
+                    item_name: item.name.clone(),
+                    entry_name: item.entry_name.clone(),
+                    source: item.source.clone(),
+                    source_type: item.source_type,
+                    path: item.path.clone(),
+                    allowed: rule.is_some(),
+                    rule,
+                    action,
+                    error,
+                }
+            })
+            .collect();
+
+        if apply {
+            let _ = append_log(&decisions);
+        }
+        decisions
+    }
+}
+
+/// What a policy would do with the current items, without changing anything
+pub fn preview(policy: &StartupPolicy) -> Vec<PolicyDecision> {
+    Enforcer::system().check_items(policy, &scanner::get_all_startup_items(), false)
+}
+
+/// Apply the policy in effect to every current item
+pub fn enforce() -> Result<Vec<PolicyDecision>, String> {
+    let policy = load_policy()?;
+    if !policy.enabled {
+        return Ok(Vec::new());
+    }
+    Ok(Enforcer::system().check_items(&policy, &scanner::get_all_startup_items(), true))
+}
+
+/// Apply the policy in effect to some items, such as those the watcher saw change
+pub fn enforce_items(items: &[StartupItem]) -> Result<Vec<PolicyDecision>, String> {
+    let policy = load_policy()?;
+    if !policy.enabled {
+        return Ok(Vec::new());
+    }
+    Ok(Enforcer::system().check_items(&policy, items, true))
+}
+
+fn log_path() -> PathBuf {
+    settings::get_config_dir().join(LOG_FILE)
+}
+
+/// Append decisions as JSON lines
+fn append_log(decisions: &[PolicyDecision]) -> std::io::Result<()> {
+    let _guard = LOG_LOCK.lock().unwrap();
+    let path = log_path();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::metadata(&path).is_ok_and(|metadata| metadata.len() > MAX_LOG_SIZE) {
+        std::fs::rename(&path, path.with_extension("log.1"))?;
+    }
+
+    let mut file = std::fs::OpenOptions::new().create(true).append(true).open(&path)?;
+    for decision in decisions {
+        let line = serde_json::to_string(decision).map_err(std::io::Error::other)?;
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// The latest logged decisions, newest first
+pub fn read_log(limit: usize) -> Vec<PolicyDecision> {
+    let _guard = LOG_LOCK.lock().unwrap();
+    let content = std::fs::read_to_string(log_path()).unwrap_or_default();
+    content
+        .lines()
+        .rev()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .take(limit)
+        .collect()
+}
+
+/// Quarantined entries, newest first
+pub fn list_quarantine() -> Vec<QuarantinedItem> {
+    let Ok(entries) = std::fs::read_dir(quarantine_dir()) else {
+        return Vec::new();
+    };
+    let mut items: Vec<QuarantinedItem> = entries
+        .flatten()
+        .filter_map(|entry| std::fs::read_to_string(entry.path()).ok())
+        .filter_map(|content| serde_json::from_str(&content).ok())
+        .collect();
+    items.sort_by_key(|item| std::cmp::Reverse(item.quarantined_at));
+    items
+}
+
+/// A rule allowing a quarantined entry's executable, or its name when it has none
+fn allow_rule(record: &QuarantinedItem) -> AllowRule {
+    if record.path.trim().is_empty() {
+        AllowRule { name: Some(record.item.entry_name.clone()), ..AllowRule::default() }
+    } else {
+        AllowRule { path: Some(record.path.clone()), ..AllowRule::default() }
+    }
+}
+
+/// Write a quarantined entry back; with `allow` a rule for its executable is added so it stays
+pub fn restore_quarantined(id: &str, allow: bool) -> Result<(), String> {
+    let enforcer = Enforcer::system();
+    let record = enforcer.read_quarantined(id)?;
+
+    if allow {
+        let mut policy = load_policy()?;
+        policy.rules.push(allow_rule(&record));
+        save_policy(&policy)?;
+    }
+
+    enforcer.restore(&record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::approved::{self, ApprovalKey};
+    use crate::startup::registry::{MemoryRegistry, RegValueType, RegistryBackend, RegistryHive, RegistryValue};
+    use crate::startup::scanner::ScanContext;
+    use crate::startup::testdir::TestDir;
+
+    const RUN: &str = r"HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Run";
+
+    fn item(name: &str, path: &str, company_name: Option<&str>) -> StartupItem {
+        let mut item = StartupItem::for_test(SourceType::Registry, RUN, name, path, true);
+        item.company_name = company_name.map(str::to_string);
+        item
+    }
+
+    fn policy(rules: Vec<AllowRule>) -> StartupPolicy {
+        StartupPolicy { enabled: true, action: BlockAction::Notify, rules }
+    }
+
+    #[test]
+    fn glob_matching() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("OneDrive", "onedrive"));
+        assert!(glob_match("one*", "OneDrive"));
+        assert!(glob_match("*drive", "OneDrive"));
+        assert!(glob_match("o?e*e", "OneDrive"));
+        assert!(glob_match("*a*b*", "xxaxxbxx"));
+        assert!(glob_match("*ab", "aab"));
+        assert!(glob_match("  app  ", "app"));
+        assert!(glob_match(r"C:\Program Files\*\app.exe", "c:/program files/Contoso/app.exe"));
+
+        assert!(!glob_match("one", "OneDrive"));
+        assert!(!glob_match("?", ""));
+        assert!(!glob_match("o?e", "oe"));
+        assert!(!glob_match("*.exe", "app.exe.bak"));
+        assert!(!glob_match("", "app"));
+    }
+
+    #[test]
+    fn evaluate_returns_the_first_rule_that_allows_an_item() {
+        let rules = vec![
+            AllowRule { publisher: Some("Contoso*".to_string()), ..AllowRule::default() },
+            AllowRule { name: Some("Updater".to_string()), ..AllowRule::default() },
+            AllowRule { name: Some("*".to_string()), path: Some(r"C:\Tools\*".to_string()), ..AllowRule::default() },
+        ];
+        let policy = policy(rules);
+
+        assert_eq!(evaluate(&policy, &item("Updater", r"C:\Contoso\updater.exe", Some("Contoso Ltd"))), Some(0));
+        assert_eq!(evaluate(&policy, &item("updater", r"C:\Other\updater.exe", None)), Some(1));
+        assert_eq!(evaluate(&policy, &item("Tool", r"C:\Tools\tool.exe", Some("Fabrikam"))), Some(2));
+        assert_eq!(evaluate(&policy, &item("Tool", r"C:\Other\tool.exe", Some("Fabrikam"))), None);
+    }
+
+    #[test]
+    fn rules_without_criteria_allow_nothing() {
+        let rules = vec![
+            AllowRule::default(),
+            AllowRule { name: Some("  ".to_string()), path: Some(String::new()), ..AllowRule::default() },
+        ];
+        assert_eq!(evaluate(&policy(rules), &item("App", "app.exe", None)), None);
+
+        // A blank criterion beside a given one is ignored
+        let rules = vec![AllowRule { name: Some("App".to_string()), path: Some(" ".to_string()), ..AllowRule::default() }];
+        assert_eq!(evaluate(&policy(rules), &item("App", "app.exe", None)), Some(0));
+    }
+
+    #[test]
+    fn rules_match_the_file_hash() {
+        let dir = TestDir::new("policy");
+        let path = dir.write("app.exe", "abc");
+        let path = path.to_string_lossy();
+        let abc = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+
+        let allow_hash = |hash: &str| {
+            policy(vec![AllowRule { sha256: Some(format!(" {} ", hash)), ..AllowRule::default() }])
+        };
+        assert_eq!(evaluate(&allow_hash(abc), &item("App", &path, None)), Some(0));
+        assert_eq!(evaluate(&allow_hash(&abc.replace('B', "C")), &item("App", &path, None)), None);
+        // A file that cannot be read matches no hash
+        let missing = dir.join("missing.exe");
+        assert_eq!(evaluate(&allow_hash(abc), &item("App", &missing.to_string_lossy(), None)), None);
+    }
+
+    const RUN_PATH: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+
+    /// Changes entries of a registry of the test's own
+    struct RegistryActions<'a>(&'a MemoryRegistry);
+
+    impl EntryActions for RegistryActions<'_> {
+        fn snapshot(&self, item: &StartupItem) -> SnapshotItem {
+            SnapshotItem::read(self.0, item)
+        }
+
+        fn disable(&self, item: &StartupItem) -> Result<(), String> {
+            manager::toggle_registry_item(self.0, item, false).map_err(|e| e.to_string())
+        }
+
+        fn delete(&self, item: &StartupItem) -> Result<(), String> {
+            manager::delete_registry_item(self.0, item).map_err(|e| e.to_string())
+        }
+
+        fn restore(&self, item: &SnapshotItem) -> Result<(), String> {
+            let (content, approval) = item.entry()?;
+            manager::import_entry_into(self.0, item, &content, approval.as_deref()).map_err(|e| e.to_string())
+        }
+    }
+
+    fn enforcer<'a>(actions: &'a RegistryActions, dir: &TestDir) -> Enforcer<'a> {
+        Enforcer {
+            actions,
+            quarantine_dir: dir.join(QUARANTINE_DIR),
+            exemptions: Exemptions { own_executable: None, system_root: Some(r"C:\Windows".to_string()) },
+        }
+    }
+
+    fn run_entry(registry: &MemoryRegistry, name: &str, command: &str) -> StartupItem {
+        registry.create_subkey(RegistryHive::CurrentUser, RUN_PATH).unwrap();
+        registry
+            .set_raw_value(RegistryHive::CurrentUser, RUN_PATH, name, &RegistryValue::from_string(command, RegValueType::Sz))
+            .unwrap();
+        scanner::scan_registry_items(&ScanContext::with_registry(registry))
+            .into_iter()
+            .find(|item| item.entry_name == name)
+            .unwrap()
+    }
+
+    fn quarantined_files(dir: &TestDir) -> Vec<PathBuf> {
+        std::fs::read_dir(dir.join(QUARANTINE_DIR))
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn blocked_items_are_disabled_or_quarantined() {
+        let dir = TestDir::new("policy-block");
+        let registry = MemoryRegistry::new();
+        let actions = RegistryActions(&registry);
+        let enforcer = enforcer(&actions, &dir);
+        let item = run_entry(&registry, "Tool", r"C:\Tools\tool.exe");
+
+        assert_eq!(enforcer.block(BlockAction::Notify, &item, true), Ok(Some(BlockAction::Notify)));
+        // A preview changes nothing
+        assert_eq!(enforcer.block(BlockAction::Disable, &item, false), Ok(Some(BlockAction::Disable)));
+        assert!(approved::read_approval(&registry, RegistryHive::CurrentUser, ApprovalKey::Run, "Tool").is_none());
+
+        assert_eq!(enforcer.block(BlockAction::Disable, &item, true), Ok(Some(BlockAction::Disable)));
+        let record = approved::read_approval(&registry, RegistryHive::CurrentUser, ApprovalKey::Run, "Tool").unwrap();
+        assert!(!record.is_enabled());
+        let disabled = StartupItem { enabled: false, ..item.clone() };
+        assert_eq!(enforcer.block(BlockAction::Disable, &disabled, true), Ok(None));
+
+        // Quarantine takes disabled entries too
+        assert_eq!(enforcer.block(BlockAction::Quarantine, &disabled, true), Ok(Some(BlockAction::Quarantine)));
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool").is_err());
+        assert_eq!(quarantined_files(&dir).len(), 1);
+    }
+
+    #[test]
+    fn entries_that_cannot_be_kept_are_disabled_instead_of_quarantined() {
+        let dir = TestDir::new("policy-no-copy");
+        let registry = MemoryRegistry::new();
+        let actions = RegistryActions(&registry);
+        let enforcer = enforcer(&actions, &dir);
+        // A scheduled task can be deleted, but the snapshot keeps no copy of it
+        let task = StartupItem::for_test(SourceType::ScheduledTask, r"\Contoso", "Updater", r"C:\Contoso\updater.exe", true);
+        assert_eq!(enforcer.block(BlockAction::Quarantine, &task, false), Ok(Some(BlockAction::Disable)));
+        assert!(quarantined_files(&dir).is_empty());
+    }
+
+    #[test]
+    fn quarantined_entries_are_restored_with_their_record() {
+        let dir = TestDir::new("policy-quarantine");
+        let registry = MemoryRegistry::new();
+        let actions = RegistryActions(&registry);
+        let enforcer = enforcer(&actions, &dir);
+        run_entry(&registry, "Tool", r"C:\Tools\tool.exe --tray");
+        approved::write_approval(&registry, RegistryHive::CurrentUser, ApprovalKey::Run, "Tool", false).unwrap();
+        let record_bytes = registry.get_raw_value(RegistryHive::CurrentUser, &ApprovalKey::Run.path(), "Tool").unwrap().bytes;
+        let item = scanner::scan_registry_items(&ScanContext::with_registry(&registry)).pop().unwrap();
+
+        enforcer.quarantine(&item).unwrap();
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool").is_err());
+        assert!(registry.get_raw_value(RegistryHive::CurrentUser, &ApprovalKey::Run.path(), "Tool").is_err());
+
+        let files = quarantined_files(&dir);
+        assert_eq!(files.len(), 1);
+        let id = files[0].file_stem().unwrap().to_string_lossy().to_string();
+        let record = enforcer.read_quarantined(&id).unwrap();
+        assert_eq!(record.path, r"C:\Tools\tool.exe");
+        assert_eq!(allow_rule(&record).path.as_deref(), Some(r"C:\Tools\tool.exe"));
+
+        enforcer.restore(&record).unwrap();
+        let value = registry.get_raw_value(RegistryHive::CurrentUser, RUN_PATH, "Tool").unwrap();
+        assert_eq!(value.as_string().as_deref(), Some(r"C:\Tools\tool.exe --tray"));
+        assert_eq!(registry.get_raw_value(RegistryHive::CurrentUser, &ApprovalKey::Run.path(), "Tool").unwrap().bytes, record_bytes);
+        assert!(quarantined_files(&dir).is_empty());
+        assert!(enforcer.read_quarantined(&id).is_err());
+    }
+
+    #[test]
+    fn a_failed_removal_keeps_no_quarantine_record() {
+        let dir = TestDir::new("policy-quarantine-failed");
+        let registry = MemoryRegistry::new();
+        let actions = RegistryActions(&registry);
+        let enforcer = enforcer(&actions, &dir);
+        // The entry is gone by the time it is quarantined
+        let item = item("Gone", r"C:\Tools\gone.exe", None);
+        assert!(enforcer.quarantine(&item).is_err());
+        assert!(quarantined_files(&dir).is_empty());
+    }
+
+    #[test]
+    fn windows_entries_and_this_app_are_only_reported() {
+        let dir = TestDir::new("policy-exempt");
+        let registry = MemoryRegistry::new();
+        let actions = RegistryActions(&registry);
+        let mut enforcer = enforcer(&actions, &dir);
+        enforcer.exemptions.own_executable = Some(r"C:\Program Files\UMStartupManager\app.exe".to_string());
+        let block = |item: &StartupItem| enforcer.block(BlockAction::Disable, item, false);
+
+        let own = item("UMStartupManager", r"c:\program files\UMStartupManager\app.exe", None);
+        assert_eq!(block(&own), Ok(Some(BlockAction::Notify)));
+
+        let service = |path: &str| {
+            StartupItem::for_test(SourceType::Service, r"HKEY_LOCAL_MACHINE\SYSTEM\CurrentControlSet\Services\Svc", "Svc", path, true)
+        };
+        assert_eq!(block(&service(r"C:\WINDOWS\system32\svchost.exe")), Ok(Some(BlockAction::Notify)));
+        assert_eq!(block(&service(r"C:\Program Files\Contoso\service.exe")), Ok(Some(BlockAction::Disable)));
+        assert_eq!(block(&service(r"C:\WindowsApps\service.exe")), Ok(Some(BlockAction::Disable)));
+
+        let task = |folder: &str| StartupItem::for_test(SourceType::ScheduledTask, folder, "Task", r"C:\Tools\task.exe", true);
+        assert_eq!(block(&task(r"\Microsoft\Windows\Defrag")), Ok(Some(BlockAction::Notify)));
+        assert_eq!(block(&task(r"\Microsoft")), Ok(Some(BlockAction::Notify)));
+        assert_eq!(block(&task(r"\MicrosoftEdgeUpdate")), Ok(Some(BlockAction::Disable)));
+
+        let active_setup = |path: &str| {
+            StartupItem::for_test(
+                SourceType::Registry,
+                r"HKEY_LOCAL_MACHINE\Software\Microsoft\Active Setup\Installed Components",
+                "{89820200-ECBD-11cf-8B85-00AA005B4383}",
+                path,
+                true,
+            )
+        };
+        assert_eq!(block(&active_setup(r"C:\Windows\System32\ie4uinit.exe")), Ok(Some(BlockAction::Notify)));
+        assert_eq!(block(&active_setup(r"C:\Contoso\setup.exe")), Ok(Some(BlockAction::Disable)));
+
+        // The same path in a Run key is not exempt
+        assert_eq!(block(&item("Helper", r"C:\Windows\System32\helper.exe", None)), Ok(Some(BlockAction::Disable)));
+    }
+
+    #[test]
+    fn restored_entries_without_a_path_are_allowed_by_name() {
+        let record = QuarantinedItem {
+            id: "1".to_string(),
+            quarantined_at: 0,
+            path: " ".to_string(),
+            item: SnapshotItem::read(&MemoryRegistry::new(), &item("Tool", "", None)),
+        };
+        let rule = allow_rule(&record);
+        assert_eq!(rule.name.as_deref(), Some("Tool"));
+        assert_eq!(rule.path, None);
+    }
+}
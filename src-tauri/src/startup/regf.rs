@@ -0,0 +1,445 @@
+//! Read-only parser for registry hive files (the "regf" format used by
+//! NTUSER.DAT, SOFTWARE, SYSTEM, ...).
+//!
+//! Transaction logs (.LOG1/.LOG2) are not replayed, so a hive copied from a
+//! machine that was not shut down cleanly may miss its most recent writes.
+
+use std::io;
+use std::path::Path;
+
+use super::registry::{RegistryBackend, RegistryHive, RegistryValue, RegValueType};
+
+const BASE_BLOCK_SIZE: usize = 4096;
+const KEY_COMP_NAME: u16 = 0x0020;
+const VALUE_COMP_NAME: u16 = 0x0001;
+/// Values larger than this are split into "db" segments (hive format 1.4+)
+const BIG_DATA_THRESHOLD: usize = 16344;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegfError {
+    Io(String),
+    InvalidHeader,
+    Corrupt,
+}
+
+impl std::fmt::Display for RegfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegfError::Io(msg) => write!(f, "读取注册表配置单元失败: {}", msg),
+            RegfError::InvalidHeader => write!(f, "不是有效的注册表配置单元文件"),
+            RegfError::Corrupt => write!(f, "注册表配置单元文件已损坏"),
+        }
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Decode a key or value name: Latin-1 when compressed, UTF-16LE otherwise
+fn decode_name(bytes: &[u8], compressed: bool) -> String {
+    if compressed {
+        bytes.iter().map(|&b| b as char).collect()
+    } else {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    }
+}
+
+/// A parsed hive file held in memory
+#[derive(Debug, Clone)]
+pub struct RegfHive {
+    data: Vec<u8>,
+    root_cell: u32,
+}
+
+impl RegfHive {
+    pub fn open(path: &Path) -> Result<RegfHive, RegfError> {
+        let data = std::fs::read(path).map_err(|e| RegfError::Io(e.to_string()))?;
+        RegfHive::from_bytes(data)
+    }
+
+    pub fn from_bytes(data: Vec<u8>) -> Result<RegfHive, RegfError> {
+        if data.len() < BASE_BLOCK_SIZE || &data[0..4] != b"regf" {
+            return Err(RegfError::InvalidHeader);
+        }
+        let root_cell = read_u32(&data, 36).ok_or(RegfError::InvalidHeader)?;
+
+        let hive = RegfHive { data, root_cell };
+        match hive.cell(root_cell) {
+            Some(root) if root.starts_with(b"nk") => Ok(hive),
+            _ => Err(RegfError::Corrupt),
+        }
+    }
+
+    /// Data of the cell at a hive-bin relative offset, without its size field
+    fn cell(&self, offset: u32) -> Option<&[u8]> {
+        let start = BASE_BLOCK_SIZE.checked_add(offset as usize)?;
+        let size = read_u32(&self.data, start)? as i32;
+        // Allocated cells have a negative size
+        let len = size.checked_neg().filter(|&len| len >= 4)? as usize;
+        self.data.get(start + 4..start + len)
+    }
+
+    fn key_name(&self, nk: &[u8]) -> Option<String> {
+        let flags = read_u16(nk, 2)?;
+        let name_len = read_u16(nk, 72)? as usize;
+        let name = nk.get(76..76 + name_len)?;
+        Some(decode_name(name, flags & KEY_COMP_NAME != 0))
+    }
+
+    /// Offsets of the subkey nodes of a key, following lf/lh/li/ri lists
+    fn subkey_offsets(&self, nk: &[u8]) -> Vec<u32> {
+        let mut offsets = Vec::new();
+        let count = read_u32(nk, 20).unwrap_or(0);
+        if count > 0 && let Some(list) = read_u32(nk, 28) {
+            self.collect_subkey_list(list, &mut offsets, 0);
+        }
+        offsets
+    }
+
+    fn collect_subkey_list(&self, list_offset: u32, offsets: &mut Vec<u32>, depth: u32) {
+        // "ri" lists only nest one level in valid hives; guard against loops anyway
+        if depth > 8 {
+            return;
+        }
+        let Some(list) = self.cell(list_offset) else {
+            return;
+        };
+        let count = read_u16(list, 2).unwrap_or(0) as usize;
+
+        match list.get(0..2) {
+            Some(b"lf") | Some(b"lh") => {
+                // Each element: key node offset + name hint/hash
+                offsets.extend((0..count).filter_map(|i| read_u32(list, 4 + i * 8)));
+            }
+            Some(b"li") => {
+                offsets.extend((0..count).filter_map(|i| read_u32(list, 4 + i * 4)));
+            }
+            Some(b"ri") => {
+                for sub_list in (0..count).filter_map(|i| read_u32(list, 4 + i * 4)) {
+                    self.collect_subkey_list(sub_list, offsets, depth + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn find_subkey(&self, nk: &[u8], name: &str) -> Option<u32> {
+        self.subkey_offsets(nk).into_iter().find(|&offset| {
+            self.cell(offset)
+                .filter(|sub| sub.starts_with(b"nk"))
+                .and_then(|sub| self.key_name(sub))
+                .map(|sub_name| sub_name.eq_ignore_ascii_case(name))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Walk a backslash-separated path from the root key
+    fn find_key(&self, path: &str) -> Option<&[u8]> {
+        let mut offset = self.root_cell;
+        for segment in path.split('\\').filter(|s| !s.is_empty()) {
+            let nk = self.cell(offset)?;
+            offset = self.find_subkey(nk, segment)?;
+        }
+        self.cell(offset).filter(|nk| nk.starts_with(b"nk"))
+    }
+
+    pub fn key_exists(&self, path: &str) -> bool {
+        self.find_key(path).is_some()
+    }
+
+    pub fn subkey_names(&self, path: &str) -> Option<Vec<String>> {
+        let nk = self.find_key(path)?;
+        Some(
+            self.subkey_offsets(nk)
+                .into_iter()
+                .filter_map(|offset| self.cell(offset))
+                .filter(|sub| sub.starts_with(b"nk"))
+                .filter_map(|sub| self.key_name(sub))
+                .collect(),
+        )
+    }
+
+    fn value_data(&self, vk: &[u8]) -> Option<Vec<u8>> {
+        let raw_size = read_u32(vk, 4)?;
+        let data_offset = read_u32(vk, 8)?;
+
+        // High bit set: up to 4 bytes stored in the offset field itself
+        if raw_size & 0x8000_0000 != 0 {
+            let size = (raw_size & 0x7FFF_FFFF) as usize;
+            return Some(data_offset.to_le_bytes()[..size.min(4)].to_vec());
+        }
+
+        let size = raw_size as usize;
+        let cell = self.cell(data_offset)?;
+        if size > BIG_DATA_THRESHOLD && cell.starts_with(b"db") {
+            let segment_count = read_u16(cell, 2)? as usize;
+            let segment_list = self.cell(read_u32(cell, 4)?)?;
+            let mut data = Vec::with_capacity(size);
+            for i in 0..segment_count {
+                let segment = self.cell(read_u32(segment_list, i * 4)?)?;
+                let take = (size - data.len()).min(BIG_DATA_THRESHOLD).min(segment.len());
+                data.extend_from_slice(&segment[..take]);
+            }
+            // Too few or too short segments: the value is cut off
+            return Some(data).filter(|data| data.len() == size);
+        }
+
+        cell.get(..size).map(|d| d.to_vec())
+    }
+
+    pub fn values(&self, path: &str) -> Option<Vec<(String, RegistryValue)>> {
+        let nk = self.find_key(path)?;
+        let count = read_u32(nk, 36)? as usize;
+        if count == 0 {
+            return Some(Vec::new());
+        }
+        let list = self.cell(read_u32(nk, 40)?)?;
+
+        Some(
+            (0..count)
+                .filter_map(|i| read_u32(list, i * 4))
+                .filter_map(|offset| self.cell(offset))
+                .filter(|vk| vk.starts_with(b"vk"))
+                .filter_map(|vk| {
+                    let name_len = read_u16(vk, 2)? as usize;
+                    let flags = read_u16(vk, 16)?;
+                    let name = decode_name(vk.get(20..20 + name_len)?, flags & VALUE_COMP_NAME != 0);
+                    let value = RegistryValue {
+                        bytes: self.value_data(vk)?,
+                        vtype: RegValueType::from_raw(read_u32(vk, 12)?),
+                    };
+                    Some((name, value))
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Registry backend over hive files copied from another machine.
+///
+/// HKCU maps to the user's NTUSER.DAT; HKLM\SOFTWARE and HKLM\SYSTEM map to the
+/// SOFTWARE and SYSTEM hives, with `CurrentControlSet` resolved through
+/// `Select\Current`. All writes fail with `PermissionDenied`.
+#[derive(Debug, Default)]
+pub struct OfflineRegistry {
+    pub user: Option<RegfHive>,
+    pub software: Option<RegfHive>,
+    pub system: Option<RegfHive>,
+}
+
+impl OfflineRegistry {
+    /// Pick the hive holding a key and the path inside that hive
+    fn resolve(&self, hive: RegistryHive, path: &str) -> io::Result<(&RegfHive, String)> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "registry key not found in offline hives");
+        let path = path.trim_matches('\\');
+
+        match hive {
+            RegistryHive::CurrentUser => Ok((self.user.as_ref().ok_or_else(not_found)?, path.to_string())),
+            RegistryHive::LocalMachine => {
+                let (first, rest) = path.split_once('\\').unwrap_or((path, ""));
+                if first.eq_ignore_ascii_case("SOFTWARE") {
+                    Ok((self.software.as_ref().ok_or_else(not_found)?, rest.to_string()))
+                } else if first.eq_ignore_ascii_case("SYSTEM") {
+                    let system = self.system.as_ref().ok_or_else(not_found)?;
+                    Ok((system, Self::resolve_control_set(system, rest)))
+                } else {
+                    Err(not_found())
+                }
+            }
+        }
+    }
+
+    /// Replace `CurrentControlSet`, which only exists on a running system
+    fn resolve_control_set(system: &RegfHive, path: &str) -> String {
+        let (first, rest) = path.split_once('\\').unwrap_or((path, ""));
+        if !first.eq_ignore_ascii_case("CurrentControlSet") {
+            return path.to_string();
+        }
+
+        let current = system
+            .values("Select")
+            .and_then(|values| {
+                values
+                    .into_iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case("Current"))
+                    .and_then(|(_, value)| value.as_dword())
+            })
+            .unwrap_or(1);
+
+        let control_set = format!("ControlSet{:03}", current);
+        if rest.is_empty() {
+            control_set
+        } else {
+            format!("{}\\{}", control_set, rest)
+        }
+    }
+
+    fn read_only() -> io::Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, "offline hives are read-only")
+    }
+}
+
+impl RegistryBackend for OfflineRegistry {
+    fn open_key(&self, hive: RegistryHive, path: &str) -> io::Result<()> {
+        let (regf, inner) = self.resolve(hive, path)?;
+        if regf.key_exists(&inner) {
+            Ok(())
+        } else {
+            Err(io::Error::new(io::ErrorKind::NotFound, "registry key not found in offline hives"))
+        }
+    }
+
+    fn enum_keys(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<String>> {
+        let (regf, inner) = self.resolve(hive, path)?;
+        regf.subkey_names(&inner)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "registry key not found in offline hives"))
+    }
+
+    fn enum_values(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<(String, RegistryValue)>> {
+        let (regf, inner) = self.resolve(hive, path)?;
+        regf.values(&inner)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "registry key not found in offline hives"))
+    }
+
+    fn get_raw_value(&self, hive: RegistryHive, path: &str, name: &str) -> io::Result<RegistryValue> {
+        self.enum_values(hive, path)?
+            .into_iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "registry value not found in offline hives"))
+    }
+
+    fn set_raw_value(&self, _hive: RegistryHive, _path: &str, _name: &str, _value: &RegistryValue) -> io::Result<()> {
+        Err(Self::read_only())
+    }
+
+    fn delete_value(&self, _hive: RegistryHive, _path: &str, _name: &str) -> io::Result<()> {
+        Err(Self::read_only())
+    }
+
+    fn create_subkey(&self, _hive: RegistryHive, _path: &str) -> io::Result<()> {
+        Err(Self::read_only())
+    }
+
+    fn delete_subkey(&self, _hive: RegistryHive, _path: &str) -> io::Result<()> {
+        Err(Self::read_only())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run keys, a service and `Select` in one hive, with every kind of subkey
+    /// list, compressed and UTF-16 names, inline values and a big-data value
+    const NTUSER: &[u8] = include_bytes!("../../tests/fixtures/regf/ntuser.dat");
+    const RUN: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+
+    fn hive() -> RegfHive {
+        RegfHive::from_bytes(NTUSER.to_vec()).unwrap()
+    }
+
+    fn value(hive: &RegfHive, path: &str, name: &str) -> Option<RegistryValue> {
+        hive.values(path)?.into_iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    #[test]
+    fn walks_keys_through_every_subkey_list() {
+        let hive = hive();
+        assert_eq!(hive.subkey_names("").unwrap(), ["Select", "Software", "ControlSet001"]);
+        assert_eq!(
+            hive.subkey_names(r"SOFTWARE\microsoft\Windows\CurrentVersion").unwrap(),
+            ["Run", "RunOnce", "Ärger"]
+        );
+        assert!(hive.key_exists(r"\Software\Microsoft\Windows\CurrentVersion\Ärger\"));
+        assert!(!hive.key_exists(r"Software\Classes"));
+        assert_eq!(hive.values(r"Software\Microsoft\Windows\CurrentVersion\RunOnce").unwrap(), []);
+    }
+
+    #[test]
+    fn reads_values() {
+        let hive = hive();
+        let names: Vec<String> = hive.values(RUN).unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["OneDrive", "Tool", "Größe", "Flag", "Big"]);
+
+        let one_drive = value(&hive, RUN, "OneDrive").unwrap();
+        assert_eq!(one_drive.vtype, RegValueType::Sz);
+        assert_eq!(one_drive.as_string().as_deref(), Some(r#""C:\Users\me\OneDrive.exe" /background"#));
+        let tool = value(&hive, RUN, "Tool").unwrap();
+        assert_eq!(tool.vtype, RegValueType::ExpandSz);
+        assert_eq!(tool.as_string().as_deref(), Some(r"%ProgramFiles%\Tool\tool.exe"));
+        assert_eq!(value(&hive, RUN, "Flag").unwrap().as_dword(), Some(42));
+    }
+
+    #[test]
+    fn joins_big_data_segments() {
+        let big = value(&hive(), RUN, "Big").unwrap();
+        assert_eq!(big.vtype, RegValueType::Binary);
+        assert_eq!(big.bytes.len(), 20000);
+        assert!(big.bytes.iter().enumerate().all(|(i, &b)| b == (i % 251) as u8));
+    }
+
+    #[test]
+    fn drops_big_data_with_missing_segments() {
+        let mut data = NTUSER.to_vec();
+        let db = data.windows(4).position(|w| w == b"db\x02\x00").unwrap();
+        data[db + 2] = 1;
+        let hive = RegfHive::from_bytes(data).unwrap();
+        assert!(value(&hive, RUN, "Big").is_none());
+        assert!(value(&hive, RUN, "OneDrive").is_some());
+    }
+
+    #[test]
+    fn rejects_bad_headers_and_root_cells() {
+        assert_eq!(RegfHive::from_bytes(NTUSER[..100].to_vec()).unwrap_err(), RegfError::InvalidHeader);
+
+        let mut data = NTUSER.to_vec();
+        data[0] = b'x';
+        assert_eq!(RegfHive::from_bytes(data).unwrap_err(), RegfError::InvalidHeader);
+
+        // Root cell past the end of the file
+        let mut data = NTUSER.to_vec();
+        data[36..40].copy_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        assert_eq!(RegfHive::from_bytes(data).unwrap_err(), RegfError::Corrupt);
+
+        // Root cell marked free
+        let mut data = NTUSER.to_vec();
+        let root = BASE_BLOCK_SIZE + read_u32(&data, 36).unwrap() as usize;
+        data[root..root + 4].copy_from_slice(&0x80u32.to_le_bytes());
+        assert_eq!(RegfHive::from_bytes(data).unwrap_err(), RegfError::Corrupt);
+
+        // Cut off inside the hive bin
+        let hive = RegfHive::from_bytes(NTUSER[..BASE_BLOCK_SIZE + 0x200].to_vec()).unwrap();
+        assert!(hive.values(RUN).is_none());
+    }
+
+    #[test]
+    fn offline_registry_resolves_hives_and_the_current_control_set() {
+        let offline = OfflineRegistry {
+            user: Some(hive()),
+            software: None,
+            system: Some(hive()),
+        };
+        let start = offline
+            .get_raw_value(RegistryHive::LocalMachine, r"SYSTEM\CurrentControlSet\Services\Contoso", "start")
+            .unwrap();
+        assert_eq!(start.as_dword(), Some(2));
+        assert!(offline.enum_keys(RegistryHive::CurrentUser, RUN).unwrap().is_empty());
+
+        let not_found = offline.open_key(RegistryHive::LocalMachine, r"SOFTWARE\Microsoft").unwrap_err();
+        assert_eq!(not_found.kind(), io::ErrorKind::NotFound);
+        let read_only = offline.delete_value(RegistryHive::CurrentUser, RUN, "OneDrive").unwrap_err();
+        assert_eq!(read_only.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+/// Registry hives that hold startup entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum RegistryHive {
+    CurrentUser,
+    LocalMachine,
+}
+
+impl RegistryHive {
+    /// Full root name as shown by regedit, e.g. `HKEY_CURRENT_USER`
+    pub fn root_name(&self) -> &'static str {
+        match self {
+            RegistryHive::CurrentUser => "HKEY_CURRENT_USER",
+            RegistryHive::LocalMachine => "HKEY_LOCAL_MACHINE",
+        }
+    }
+
+    /// Abbreviated root name, e.g. `HKCU`
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            RegistryHive::CurrentUser => "HKCU",
+            RegistryHive::LocalMachine => "HKLM",
+        }
+    }
+}
+
+/// Registry value types, numbered like the `REG_*` constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RegValueType {
+    None,
+    Sz,
+    ExpandSz,
+    Binary,
+    Dword,
+    DwordBigEndian,
+    Link,
+    MultiSz,
+    ResourceList,
+    FullResourceDescriptor,
+    ResourceRequirementsList,
+    Qword,
+}
+
+impl RegValueType {
+    pub fn from_raw(raw: u32) -> RegValueType {
+        match raw {
+            1 => RegValueType::Sz,
+            2 => RegValueType::ExpandSz,
+            3 => RegValueType::Binary,
+            4 => RegValueType::Dword,
+            5 => RegValueType::DwordBigEndian,
+            6 => RegValueType::Link,
+            7 => RegValueType::MultiSz,
+            8 => RegValueType::ResourceList,
+            9 => RegValueType::FullResourceDescriptor,
+            10 => RegValueType::ResourceRequirementsList,
+            11 => RegValueType::Qword,
+            _ => RegValueType::None,
+        }
+    }
+
+    pub fn to_raw(&self) -> u32 {
+        match self {
+            RegValueType::None => 0,
+            RegValueType::Sz => 1,
+            RegValueType::ExpandSz => 2,
+            RegValueType::Binary => 3,
+            RegValueType::Dword => 4,
+            RegValueType::DwordBigEndian => 5,
+            RegValueType::Link => 6,
+            RegValueType::MultiSz => 7,
+            RegValueType::ResourceList => 8,
+            RegValueType::FullResourceDescriptor => 9,
+            RegValueType::ResourceRequirementsList => 10,
+            RegValueType::Qword => 11,
+        }
+    }
+}
+
+/// Raw registry value: the stored bytes plus their declared type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryValue {
+    pub bytes: Vec<u8>,
+    pub vtype: RegValueType,
+}
+
+impl RegistryValue {
+    /// Encode a string as a NUL-terminated UTF-16LE value
+    pub fn from_string(value: &str, vtype: RegValueType) -> RegistryValue {
+        let bytes = value
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        RegistryValue { bytes, vtype }
+    }
+
+    pub fn from_dword(value: u32) -> RegistryValue {
+        RegistryValue {
+            bytes: value.to_le_bytes().to_vec(),
+            vtype: RegValueType::Dword,
+        }
+    }
+
+    /// Decode a REG_SZ / REG_EXPAND_SZ value, `None` for other types
+    pub fn as_string(&self) -> Option<String> {
+        match self.vtype {
+            RegValueType::Sz | RegValueType::ExpandSz => {
+                // Registry strings are UTF-16LE encoded
+                let u16_slice: Vec<u16> = self.bytes
+                    .chunks_exact(2)
+                    .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+                    .collect();
+                Some(
+                    String::from_utf16_lossy(&u16_slice)
+                        .trim_matches('\0')
+                        .to_string(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_dword(&self) -> Option<u32> {
+        match self.vtype {
+            RegValueType::Dword if self.bytes.len() >= 4 => {
+                Some(u32::from_le_bytes([self.bytes[0], self.bytes[1], self.bytes[2], self.bytes[3]]))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Access to a registry, addressed by hive and a backslash-separated key path
+/// relative to the hive root (the same form `RegKey::open_subkey` takes).
+///
+/// Errors follow `std::io` conventions: a missing key or value is
+/// `ErrorKind::NotFound` and a permission problem is `ErrorKind::PermissionDenied`.
+pub trait RegistryBackend: Send + Sync {
+    /// Check that a key exists and can be opened for reading
+    fn open_key(&self, hive: RegistryHive, path: &str) -> io::Result<()>;
+
+    /// Names of the direct subkeys of a key
+    fn enum_keys(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<String>>;
+
+    /// All values of a key, in storage order
+    fn enum_values(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<(String, RegistryValue)>>;
+
+    fn get_raw_value(&self, hive: RegistryHive, path: &str, name: &str) -> io::Result<RegistryValue>;
+
+    /// Write a value into an existing key
+    fn set_raw_value(&self, hive: RegistryHive, path: &str, name: &str, value: &RegistryValue) -> io::Result<()>;
+
+    fn delete_value(&self, hive: RegistryHive, path: &str, name: &str) -> io::Result<()>;
+
+    /// Create a key (and any missing parents), succeeding if it already exists
+    fn create_subkey(&self, hive: RegistryHive, path: &str) -> io::Result<()>;
+
+    /// Delete a key together with all of its subkeys and values
+    fn delete_subkey(&self, hive: RegistryHive, path: &str) -> io::Result<()>;
+}
+
+/// The registry of the running system
+pub fn system_registry() -> &'static dyn RegistryBackend {
+    #[cfg(windows)]
+    {
+        static LIVE: LiveRegistry = LiveRegistry;
+        &LIVE
+    }
+
+    #[cfg(not(windows))]
+    {
+        // There is no registry off Windows, behave like an empty one
+        static EMPTY: MemoryRegistry = MemoryRegistry::new();
+        &EMPTY
+    }
+}
+
+/// Backend for the live Windows registry via winreg
+#[cfg(windows)]
+pub struct LiveRegistry;
+
+#[cfg(windows)]
+impl LiveRegistry {
+    fn root(hive: RegistryHive) -> winreg::RegKey {
+        use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+
+        match hive {
+            RegistryHive::CurrentUser => winreg::RegKey::predef(HKEY_CURRENT_USER),
+            RegistryHive::LocalMachine => winreg::RegKey::predef(HKEY_LOCAL_MACHINE),
+        }
+    }
+
+    fn to_winreg_type(vtype: RegValueType) -> winreg::enums::RegType {
+        use winreg::enums::*;
+
+        match vtype {
+            RegValueType::None => REG_NONE,
+            RegValueType::Sz => REG_SZ,
+            RegValueType::ExpandSz => REG_EXPAND_SZ,
+            RegValueType::Binary => REG_BINARY,
+            RegValueType::Dword => REG_DWORD,
+            RegValueType::DwordBigEndian => REG_DWORD_BIG_ENDIAN,
+            RegValueType::Link => REG_LINK,
+            RegValueType::MultiSz => REG_MULTI_SZ,
+            RegValueType::ResourceList => REG_RESOURCE_LIST,
+            RegValueType::FullResourceDescriptor => REG_FULL_RESOURCE_DESCRIPTOR,
+            RegValueType::ResourceRequirementsList => REG_RESOURCE_REQUIREMENTS_LIST,
+            RegValueType::Qword => REG_QWORD,
+        }
+    }
+
+    fn from_winreg_value(value: winreg::RegValue) -> RegistryValue {
+        RegistryValue {
+            vtype: RegValueType::from_raw(value.vtype as u32),
+            bytes: value.bytes,
+        }
+    }
+}
+
+#[cfg(windows)]
+impl RegistryBackend for LiveRegistry {
+    fn open_key(&self, hive: RegistryHive, path: &str) -> io::Result<()> {
+        Self::root(hive).open_subkey(path).map(|_| ())
+    }
+
+    fn enum_keys(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<String>> {
+        let key = Self::root(hive).open_subkey(path)?;
+        Ok(key.enum_keys().flatten().collect())
+    }
+
+    fn enum_values(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<(String, RegistryValue)>> {
+        let key = Self::root(hive).open_subkey(path)?;
+        Ok(key
+            .enum_values()
+            .flatten()
+            .map(|(name, value)| (name, Self::from_winreg_value(value)))
+            .collect())
+    }
+
+    fn get_raw_value(&self, hive: RegistryHive, path: &str, name: &str) -> io::Result<RegistryValue> {
+        let key = Self::root(hive).open_subkey(path)?;
+        key.get_raw_value(name).map(Self::from_winreg_value)
+    }
+
+    fn set_raw_value(&self, hive: RegistryHive, path: &str, name: &str, value: &RegistryValue) -> io::Result<()> {
+        use winreg::enums::KEY_SET_VALUE;
+
+        let key = Self::root(hive).open_subkey_with_flags(path, KEY_SET_VALUE)?;
+        key.set_raw_value(
+            name,
+            &winreg::RegValue {
+                bytes: value.bytes.clone(),
+                vtype: Self::to_winreg_type(value.vtype),
+            },
+        )
+    }
+
+    fn delete_value(&self, hive: RegistryHive, path: &str, name: &str) -> io::Result<()> {
+        use winreg::enums::KEY_SET_VALUE;
+
+        let key = Self::root(hive).open_subkey_with_flags(path, KEY_SET_VALUE)?;
+        key.delete_value(name)
+    }
+
+    fn create_subkey(&self, hive: RegistryHive, path: &str) -> io::Result<()> {
+        Self::root(hive).create_subkey(path).map(|_| ())
+    }
+
+    fn delete_subkey(&self, hive: RegistryHive, path: &str) -> io::Result<()> {
+        Self::root(hive).delete_subkey_all(path)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct MemoryKey {
+    /// Key path with its original casing
+    path: String,
+    values: Vec<(String, RegistryValue)>,
+}
+
+/// In-memory registry, used for tests and as the data model for offline sources.
+///
+/// Key and value names are matched case-insensitively like the real registry.
+#[derive(Debug, Default)]
+pub struct MemoryRegistry {
+    keys: Mutex<BTreeMap<(RegistryHive, String), MemoryKey>>,
+}
+
+impl MemoryRegistry {
+    pub const fn new() -> MemoryRegistry {
+        MemoryRegistry {
+            keys: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn normalize(path: &str) -> String {
+        path.trim_matches('\\').to_lowercase()
+    }
+
+    fn not_found() -> io::Error {
+        io::Error::new(io::ErrorKind::NotFound, "registry key or value not found")
+    }
+}
+
+impl RegistryBackend for MemoryRegistry {
+    fn open_key(&self, hive: RegistryHive, path: &str) -> io::Result<()> {
+        let keys = self.keys.lock().unwrap();
+        if keys.contains_key(&(hive, Self::normalize(path))) {
+            Ok(())
+        } else {
+            Err(Self::not_found())
+        }
+    }
+
+    fn enum_keys(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<String>> {
+        let keys = self.keys.lock().unwrap();
+        let parent = Self::normalize(path);
+        if !keys.contains_key(&(hive, parent.clone())) {
+            return Err(Self::not_found());
+        }
+
+        let prefix = if parent.is_empty() { String::new() } else { format!("{}\\", parent) };
+        let children = keys
+            .iter()
+            .filter(|((h, p), _)| *h == hive && p.starts_with(&prefix) && p.len() > prefix.len())
+            .filter(|((_, p), _)| !p[prefix.len()..].contains('\\'))
+            .map(|(_, key)| {
+                key.path
+                    .rsplit('\\')
+                    .next()
+                    .unwrap_or(&key.path)
+                    .to_string()
+            })
+            .collect();
+        Ok(children)
+    }
+
+    fn enum_values(&self, hive: RegistryHive, path: &str) -> io::Result<Vec<(String, RegistryValue)>> {
+        let keys = self.keys.lock().unwrap();
+        keys.get(&(hive, Self::normalize(path)))
+            .map(|key| key.values.clone())
+            .ok_or_else(Self::not_found)
+    }
+
+    fn get_raw_value(&self, hive: RegistryHive, path: &str, name: &str) -> io::Result<RegistryValue> {
+        let keys = self.keys.lock().unwrap();
+        let key = keys.get(&(hive, Self::normalize(path))).ok_or_else(Self::not_found)?;
+        key.values
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.clone())
+            .ok_or_else(Self::not_found)
+    }
+
+    fn set_raw_value(&self, hive: RegistryHive, path: &str, name: &str, value: &RegistryValue) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        let key = keys.get_mut(&(hive, Self::normalize(path))).ok_or_else(Self::not_found)?;
+        match key.values.iter_mut().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+            Some((_, existing)) => *existing = value.clone(),
+            None => key.values.push((name.to_string(), value.clone())),
+        }
+        Ok(())
+    }
+
+    fn delete_value(&self, hive: RegistryHive, path: &str, name: &str) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        let key = keys.get_mut(&(hive, Self::normalize(path))).ok_or_else(Self::not_found)?;
+        let before = key.values.len();
+        key.values.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        if key.values.len() == before {
+            return Err(Self::not_found());
+        }
+        Ok(())
+    }
+
+    fn create_subkey(&self, hive: RegistryHive, path: &str) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        let segments: Vec<&str> = path.split('\\').filter(|s| !s.is_empty()).collect();
+
+        // The hive root always exists, then every ancestor of the new key
+        keys.entry((hive, String::new())).or_default();
+        for depth in 1..=segments.len() {
+            let sub_path = segments[..depth].join("\\");
+            keys.entry((hive, sub_path.to_lowercase()))
+                .or_insert_with(|| MemoryKey {
+                    path: sub_path.clone(),
+                    values: Vec::new(),
+                });
+        }
+        Ok(())
+    }
+
+    fn delete_subkey(&self, hive: RegistryHive, path: &str) -> io::Result<()> {
+        let mut keys = self.keys.lock().unwrap();
+        let target = Self::normalize(path);
+        if target.is_empty() || !keys.contains_key(&(hive, target.clone())) {
+            return Err(Self::not_found());
+        }
+
+        let prefix = format!("{}\\", target);
+        keys.retain(|(h, p), _| *h != hive || (*p != target && !p.starts_with(&prefix)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_registry_matches_names_without_case() {
+        let registry = MemoryRegistry::new();
+        registry.create_subkey(RegistryHive::CurrentUser, r"Software\Vendor\App").unwrap();
+        registry
+            .set_raw_value(RegistryHive::CurrentUser, r"software\VENDOR\app", "Path", &RegistryValue::from_string("x", RegValueType::Sz))
+            .unwrap();
+
+        let value = registry.get_raw_value(RegistryHive::CurrentUser, r"Software\Vendor\App", "PATH").unwrap();
+        assert_eq!(value.as_string().as_deref(), Some("x"));
+        assert_eq!(registry.enum_keys(RegistryHive::CurrentUser, "software").unwrap(), ["Vendor"]);
+        assert!(registry.open_key(RegistryHive::LocalMachine, r"Software\Vendor").is_err());
+
+        registry.delete_subkey(RegistryHive::CurrentUser, r"Software\Vendor").unwrap();
+        assert!(registry.open_key(RegistryHive::CurrentUser, r"Software\Vendor\App").is_err());
+        assert!(registry.open_key(RegistryHive::CurrentUser, "Software").is_ok());
+    }
+
+    #[test]
+    fn reads_strings_and_dwords() {
+        let value = RegistryValue::from_string("C:\\a.exe", RegValueType::ExpandSz);
+        assert_eq!(value.bytes.len(), ("C:\\a.exe".len() + 1) * 2);
+        assert_eq!(value.as_string().as_deref(), Some("C:\\a.exe"));
+        assert_eq!(RegistryValue::from_dword(3).as_dword(), Some(3));
+    }
+}
@@ -1,261 +1,392 @@
-use std::path::PathBuf;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
-use winreg::enums::*;
-use winreg::RegKey;
+use std::path::{Path, PathBuf};
 
 use super::{StartupItem, SourceType};
-use super::icon::extract_icon_base64;
+use super::icon::{extract_icon_base64, extract_icon_base64_at};
+use super::lnk::ShellLink;
+use super::commandline::{self, CommandEnvironment, CommandLine};
+use super::launcher::{self, Launcher};
+use super::item_id::item_id;
+use super::folderfile::{self, FolderFileType};
+use super::pe::read_version_info;
+use serde::{Deserialize, Serialize};
+
+use super::regf::{OfflineRegistry, RegfHive};
+use super::registry::{RegistryBackend, RegistryHive, system_registry};
+use super::tasks::{self, TaskDefinition};
+use super::services::{self, SERVICES_PATH};
+use super::settings;
+use super::xdg::{self, DesktopEntry};
+use super::systemd::{self, UnitFile};
+use super::sources::SOURCES;
+use super::approved::{self, ApprovalKey};
+use super::locations::{DISABLED_SUBKEY, DisableStrategy, EntryLayout, REGISTRY_LOCATIONS, RegistryLocation, extra_programs};
+
+/// Version resource strings shown alongside an item
+#[derive(Debug, Clone, Default)]
+pub struct FileDetails {
+    pub description: Option<String>,
+    pub company_name: Option<String>,
+    pub product_name: Option<String>,
+    pub file_version: Option<String>,
+    pub original_filename: Option<String>,
+    pub legal_copyright: Option<String>,
+}
 
-#[cfg(windows)]
-use std::os::windows::ffi::OsStrExt;
+/// Read description, publisher and version from the EXE/DLL version resource
+pub fn get_file_details(path: &str) -> FileDetails {
+    let info = match read_version_info(path) {
+        Some(info) => info,
+        None => return FileDetails::default(),
+    };
 
-#[cfg(windows)]
-use windows::core::PCWSTR;
+    FileDetails {
+        description: info.file_description().map(str::to_string),
+        company_name: info.company_name().map(str::to_string),
+        product_name: info.product_name().map(str::to_string),
+        file_version: info.file_version(),
+        original_filename: info.original_filename().map(str::to_string),
+        legal_copyright: info.legal_copyright().map(str::to_string),
+    }
+}
 
-/// Get file description from EXE version info
-#[cfg(windows)]
-pub fn get_file_description(path: &str) -> Option<String> {
-    use windows::Win32::Storage::FileSystem::{
-        GetFileVersionInfoSizeW, GetFileVersionInfoW, VerQueryValueW,
-    };
+/// Where a scan reads from: the running system or an offline Windows installation
+pub struct ScanContext<'a> {
+    pub registry: &'a dyn RegistryBackend,
+    pub user_startup_folder: Option<PathBuf>,
+    pub common_startup_folder: Option<PathBuf>,
+    /// `System32\Tasks`, holding the Task Scheduler definitions
+    pub tasks_folder: Option<PathBuf>,
+    /// Manual-start services to list anyway, because they were switched off here
+    pub manual_services: Vec<String>,
+    /// Root of a mounted Windows volume; drive-letter paths are looked up below it
+    pub volume_root: Option<PathBuf>,
+    /// `~/.config/autostart`, whose files override the system ones
+    pub xdg_user_autostart: Option<PathBuf>,
+    /// `/etc/xdg/autostart` and the other `$XDG_CONFIG_DIRS`
+    pub xdg_system_autostart: Vec<PathBuf>,
+    /// systemd user unit directories, the user's own configuration first
+    pub systemd_user_paths: Vec<PathBuf>,
+}
 
-    unsafe {
-        let wide_path: Vec<u16> = path.encode_utf16().chain(std::iter::once(0)).collect();
+impl ScanContext<'static> {
+    pub fn system() -> ScanContext<'static> {
+        ScanContext {
+            registry: system_registry(),
+            user_startup_folder: get_startup_folder_path(false),
+            common_startup_folder: get_startup_folder_path(true),
+            tasks_folder: tasks::system_tasks_folder(),
+            manual_services: settings::get_settings().manual_services.into_iter().collect(),
+            volume_root: None,
+            xdg_user_autostart: if cfg!(windows) { None } else { xdg::user_autostart_dir() },
+            xdg_system_autostart: if cfg!(windows) { Vec::new() } else { xdg::system_autostart_dirs() },
+            systemd_user_paths: if cfg!(windows) { Vec::new() } else { systemd::user_unit_paths() },
+        }
+    }
+}
 
-        let size = GetFileVersionInfoSizeW(PCWSTR(wide_path.as_ptr()), None);
-        if size == 0 {
-            return None;
+#[cfg(test)]
+impl<'a> ScanContext<'a> {
+    /// A context reading only `registry`, with no folders to look in
+    pub fn with_registry(registry: &'a dyn RegistryBackend) -> ScanContext<'a> {
+        ScanContext {
+            registry,
+            user_startup_folder: None,
+            common_startup_folder: None,
+            tasks_folder: None,
+            manual_services: Vec::new(),
+            volume_root: None,
+            xdg_user_autostart: None,
+            xdg_system_autostart: Vec::new(),
+            systemd_user_paths: Vec::new(),
         }
+    }
+}
 
-        let mut buffer = vec![0u8; size as usize];
+impl ScanContext<'_> {
+    /// Map a path of the scanned system to one readable from this machine
+    pub fn local_path(&self, path: &str) -> String {
+        let Some(root) = &self.volume_root else {
+            return path.to_string();
+        };
 
-        if GetFileVersionInfoW(
-            PCWSTR(wide_path.as_ptr()),
-            0,
-            size,
-            buffer.as_mut_ptr() as *mut _,
-        ).is_err() {
-            return None;
+        // "C:\Program Files\app.exe" -> "<root>/Program Files/app.exe"
+        let bytes = path.as_bytes();
+        if bytes.len() >= 3 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes[2] == b'\\' || bytes[2] == b'/') {
+            join_windows_path(root, &path[3..]).to_string_lossy().to_string()
+        } else {
+            path.to_string()
         }
+    }
+}
 
-        // Try common language codes for FileDescription
-        let lang_codepages = [
-            "040904B0", // US English, Unicode
-            "040904E4", // US English, Windows Multilingual
-            "080404B0", // Chinese Simplified, Unicode
-            "000004B0", // Neutral, Unicode
-        ];
-
-        for lang_cp in &lang_codepages {
-            let sub_block = format!("\\StringFileInfo\\{}\\FileDescription\0", lang_cp);
-            let wide_sub_block: Vec<u16> = sub_block.encode_utf16().collect();
-
-            let mut data_ptr: *mut std::ffi::c_void = std::ptr::null_mut();
-            let mut data_len: u32 = 0;
-
-            if VerQueryValueW(
-                buffer.as_ptr() as *const _,
-                PCWSTR(wide_sub_block.as_ptr()),
-                &mut data_ptr,
-                &mut data_len,
-            ).as_bool() && data_len > 0 {
-                let slice = std::slice::from_raw_parts(data_ptr as *const u16, data_len as usize);
-                let description = String::from_utf16_lossy(slice)
-                    .trim_matches('\0')
-                    .to_string();
-                if !description.is_empty() {
-                    return Some(description);
-                }
-            }
-        }
+impl CommandEnvironment for ScanContext<'_> {
+    fn var(&self, name: &str) -> Option<String> {
+        std::env::var(name).ok()
+    }
 
-        // Try to auto-detect language from translation table
-        let trans_block = "\\VarFileInfo\\Translation\0";
-        let wide_trans: Vec<u16> = trans_block.encode_utf16().collect();
-
-        let mut trans_ptr: *mut std::ffi::c_void = std::ptr::null_mut();
-        let mut trans_len: u32 = 0;
-
-        if VerQueryValueW(
-            buffer.as_ptr() as *const _,
-            PCWSTR(wide_trans.as_ptr()),
-            &mut trans_ptr,
-            &mut trans_len,
-        ).as_bool() && trans_len >= 4 {
-            let trans_data = std::slice::from_raw_parts(trans_ptr as *const u16, 2);
-            let lang = trans_data[0];
-            let codepage = trans_data[1];
-
-            let sub_block = format!(
-                "\\StringFileInfo\\{:04X}{:04X}\\FileDescription\0",
-                lang, codepage
-            );
-            let wide_sub_block: Vec<u16> = sub_block.encode_utf16().collect();
-
-            let mut data_ptr: *mut std::ffi::c_void = std::ptr::null_mut();
-            let mut data_len: u32 = 0;
-
-            if VerQueryValueW(
-                buffer.as_ptr() as *const _,
-                PCWSTR(wide_sub_block.as_ptr()),
-                &mut data_ptr,
-                &mut data_len,
-            ).as_bool() && data_len > 0 {
-                let slice = std::slice::from_raw_parts(data_ptr as *const u16, data_len as usize);
-                let description = String::from_utf16_lossy(slice)
-                    .trim_matches('\0')
-                    .to_string();
-                if !description.is_empty() {
-                    return Some(description);
-                }
-            }
-        }
+    fn is_file(&self, path: &str) -> bool {
+        Path::new(&self.local_path(path)).is_file()
+    }
 
-        None
+    fn app_path(&self, name: &str) -> Option<String> {
+        let key = commandline::app_paths_key(name);
+        [RegistryHive::CurrentUser, RegistryHive::LocalMachine]
+            .into_iter()
+            .find_map(|hive| self.registry.get_raw_value(hive, &key, "").ok()?.as_string())
+            .filter(|path| !path.is_empty())
     }
 }
 
-#[cfg(not(windows))]
-pub fn get_file_description(_path: &str) -> Option<String> {
-    None
+/// Hive files and mounted volume of a Windows installation to audit offline
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OfflineTarget {
+    /// NTUSER.DAT of the user to inspect
+    pub user_hive: Option<String>,
+    /// Windows\System32\config\SOFTWARE
+    pub software_hive: Option<String>,
+    /// Windows\System32\config\SYSTEM
+    pub system_hive: Option<String>,
+    /// Mount point of the Windows volume, used for startup folders and target files
+    pub volume_root: Option<String>,
+    /// User profile folder, defaults to the folder holding `user_hive`
+    pub user_profile: Option<String>,
 }
 
-struct RegistrySource {
-    hkey: winreg::HKEY,
-    path: &'static str,
-    name: &'static str,
+/// Join a backslash-separated relative path onto a local path
+fn join_windows_path(base: &Path, relative: &str) -> PathBuf {
+    relative
+        .split(['\\', '/'])
+        .filter(|segment| !segment.is_empty())
+        .fold(base.to_path_buf(), |path, segment| path.join(segment))
 }
 
-const REGISTRY_SOURCES: &[RegistrySource] = &[
-    RegistrySource {
-        hkey: HKEY_CURRENT_USER,
-        path: r"Software\Microsoft\Windows\CurrentVersion\Run",
-        name: "HKCU\\...\\Run",
-    },
-    RegistrySource {
-        hkey: HKEY_CURRENT_USER,
-        path: r"Software\Microsoft\Windows\CurrentVersion\RunOnce",
-        name: "HKCU\\...\\RunOnce",
-    },
-    RegistrySource {
-        hkey: HKEY_LOCAL_MACHINE,
-        path: r"Software\Microsoft\Windows\CurrentVersion\Run",
-        name: "HKLM\\...\\Run",
-    },
-    RegistrySource {
-        hkey: HKEY_LOCAL_MACHINE,
-        path: r"Software\Microsoft\Windows\CurrentVersion\RunOnce",
-        name: "HKLM\\...\\RunOnce",
-    },
-];
-
-fn generate_id(source: &str, name: &str) -> String {
-    let mut hasher = DefaultHasher::new();
-    format!("{}:{}", source, name).hash(&mut hasher);
-    format!("{:016x}", hasher.finish())
+/// A command line whose program its source already names, with the words after the first as arguments
+fn command_line_with_program(program: String, command: &str) -> CommandLine {
+    let (_, arguments) = commandline::split_program(command.trim());
+    CommandLine { executable: program, arguments: arguments.trim_start().to_string() }
 }
 
-fn parse_command_path(command: &str) -> String {
-    let command = command.trim();
-
-    // Handle quoted paths
-    if command.starts_with('"') {
-        if let Some(end_quote) = command[1..].find('"') {
-            return command[1..end_quote + 1].to_string();
-        }
-    }
+/// The file a command runs, looking through a launcher to its target, and the launcher if there is one
+fn effective_program(command_line: &CommandLine, ctx: &ScanContext) -> (String, Option<Launcher>) {
+    let launcher = launcher::unwrap_launcher(command_line, ctx);
+    let path = launcher
+        .as_ref()
+        .and_then(Launcher::target_file)
+        .unwrap_or(&command_line.executable)
+        .to_string();
+    (path, launcher)
+}
 
-    // Handle unquoted paths - find first space that's likely separating path from args
-    if let Some(exe_pos) = command.to_lowercase().find(".exe") {
-        return command[..exe_pos + 4].to_string();
-    }
+fn string_values(registry: &dyn RegistryBackend, hive: RegistryHive, path: &str) -> Vec<(String, String)> {
+    registry
+        .enum_values(hive, path)
+        .map(|values| {
+            values
+                .into_iter()
+                .filter_map(|(name, value)| Some((name, value.as_string()?)))
+                .filter(|(_, command)| !command.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
 
-    // Return as-is if we can't parse it
-    command.split_whitespace().next().unwrap_or(command).to_string()
+/// One entry found in a registry location, before file details are looked up
+struct RegistryEntry {
+    entry_name: String,
+    display_name: String,
+    command: String,
+    /// Program to show, when it is not simply the start of `command`
+    path: Option<String>,
+    enabled: bool,
+    disabled_at: Option<u64>,
 }
 
-fn is_item_disabled(hkey: winreg::HKEY, name: &str) -> bool {
-    let approval_path = if hkey == HKEY_CURRENT_USER {
-        r"Software\Microsoft\Windows\CurrentVersion\Explorer\StartupApproved\Run"
-    } else {
-        r"Software\Microsoft\Windows\CurrentVersion\Explorer\StartupApproved\Run"
-    };
+fn registry_location_entries(ctx: &ScanContext, location: &RegistryLocation) -> Vec<RegistryEntry> {
+    let registry = ctx.registry;
+    let hive = location.hive;
+    let disabled_path = format!("{}\\{}", location.path, DISABLED_SUBKEY);
+    let mut entries = Vec::new();
+
+    match location.layout {
+        EntryLayout::ValueList => {
+            let active = string_values(registry, hive, location.path);
+            for (name, command) in &active {
+                let record = match location.disable {
+                    DisableStrategy::StartupApproved { key } => approved::read_approval(registry, hive, key, name),
+                    _ => None,
+                };
+                entries.push(RegistryEntry {
+                    entry_name: name.clone(),
+                    display_name: name.clone(),
+                    command: command.clone(),
+                    path: None,
+                    enabled: record.map(|r| r.is_enabled()).unwrap_or(true),
+                    disabled_at: record.and_then(|r| r.disabled_since()),
+                });
+            }
 
-    let root = RegKey::predef(hkey);
-    if let Ok(key) = root.open_subkey(approval_path) {
-        if let Ok(data) = key.get_raw_value(name) {
-            // First byte: 02 = enabled, 03 = disabled
-            if !data.bytes.is_empty() && data.bytes[0] == 0x03 {
-                return true;
+            if location.disable == DisableStrategy::MoveToDisabledSubkey {
+                for (name, command) in string_values(registry, hive, &disabled_path) {
+                    if active.iter().any(|(n, _)| n.eq_ignore_ascii_case(&name)) {
+                        continue;
+                    }
+                    entries.push(RegistryEntry {
+                        entry_name: name.clone(),
+                        display_name: name,
+                        command,
+                        path: None,
+                        enabled: false,
+                        disabled_at: None,
+                    });
+                }
             }
         }
-    }
-    false
-}
-
-pub fn scan_registry_items() -> Vec<StartupItem> {
-    let mut items = Vec::new();
-
-    for source in REGISTRY_SOURCES.iter() {
-        let root = RegKey::predef(source.hkey);
-
-        if let Ok(key) = root.open_subkey(source.path) {
-            for value_result in key.enum_values() {
-                if let Ok((name, value)) = value_result {
-                    let command = match value.vtype {
-                        REG_SZ | REG_EXPAND_SZ => {
-                            // Registry strings are UTF-16LE encoded
-                            let u16_slice: Vec<u16> = value.bytes
-                                .chunks_exact(2)
-                                .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
-                                .collect();
-                            String::from_utf16_lossy(&u16_slice)
-                                .trim_matches('\0')
-                                .to_string()
-                        }
-                        _ => continue,
-                    };
-
-                    if command.is_empty() {
+        EntryLayout::SingleValue { value, default } => {
+            let current = registry
+                .get_raw_value(hive, location.path, value)
+                .ok()
+                .and_then(|v| v.as_string())
+                .unwrap_or_default();
+            let parked = registry
+                .get_raw_value(hive, &disabled_path, value)
+                .ok()
+                .and_then(|v| v.as_string());
+
+            let (command, enabled) = match parked {
+                Some(parked) => (parked, false),
+                None => (current, true),
+            };
+            if let Some(first_extra) = extra_programs(&command, default, ctx).into_iter().next() {
+                entries.push(RegistryEntry {
+                    entry_name: value.to_string(),
+                    display_name: value.to_string(),
+                    command,
+                    path: Some(first_extra),
+                    enabled,
+                    disabled_at: None,
+                });
+            }
+        }
+        EntryLayout::SubkeyPerEntry { command_value } => {
+            for subkey in registry.enum_keys(hive, location.path).unwrap_or_default() {
+                let entry_path = format!("{}\\{}", location.path, subkey);
+                let values = string_values(registry, hive, &entry_path);
+                let command = match values.iter().find(|(n, _)| n.eq_ignore_ascii_case(command_value)) {
+                    Some((_, command)) => command.clone(),
+                    None => continue,
+                };
+                // The default value holds the component's display name
+                let display_name = values
+                    .iter()
+                    .find(|(n, _)| n.is_empty())
+                    .map(|(_, title)| title.clone())
+                    .filter(|title| !title.starts_with('@'))
+                    .unwrap_or_else(|| subkey.clone());
+                let enabled = match location.disable {
+                    DisableStrategy::DwordFlag { value } => registry
+                        .get_raw_value(hive, &entry_path, value)
+                        .ok()
+                        .and_then(|v| v.as_dword())
+                        .map(|flag| flag != 0)
+                        .unwrap_or(true),
+                    _ => true,
+                };
+                entries.push(RegistryEntry {
+                    entry_name: subkey,
+                    display_name,
+                    command,
+                    path: None,
+                    enabled,
+                    disabled_at: None,
+                });
+            }
+        }
+        EntryLayout::SubkeyValues => {
+            for subkey in registry.enum_keys(hive, location.path).unwrap_or_default() {
+                let entry_path = format!("{}\\{}", location.path, subkey);
+                for (name, command) in string_values(registry, hive, &entry_path) {
+                    // The default value is the batch title, not a command
+                    if name.is_empty() {
                         continue;
                     }
-
-                    let path = parse_command_path(&command);
-                    let enabled = !is_item_disabled(source.hkey, &name);
-
-                    let icon = extract_icon_base64(&path);
-                    let description = get_file_description(&path);
-                    let valid = std::path::Path::new(&path).exists();
-
-                    let full_source = format!(
-                        "{}\\{}",
-                        if source.hkey == HKEY_CURRENT_USER { "HKEY_CURRENT_USER" } else { "HKEY_LOCAL_MACHINE" },
-                        source.path
-                    );
-
-                    items.push(StartupItem {
-                        id: generate_id(&full_source, &name),
-                        name: name.clone(),
-                        description,
-                        path: path.clone(),
-                        command: command.clone(),
-                        icon,
-                        source: source.name.to_string(),
-                        source_type: SourceType::Registry.to_string(),
-                        source_location: full_source,
-                        enabled,
-                        valid,
+                    let entry_name = format!("{}\\{}", subkey, name);
+                    entries.push(RegistryEntry {
+                        display_name: entry_name.clone(),
+                        entry_name,
+                        command,
+                        path: None,
+                        enabled: true,
+                        disabled_at: None,
                     });
                 }
             }
         }
     }
 
-    items
+    entries
 }
 
-fn get_startup_folder_path(all_users: bool) -> Option<PathBuf> {
+fn registry_item(ctx: &ScanContext, location: &RegistryLocation, entry: RegistryEntry) -> StartupItem {
+    // An entry naming its program apart from the command is shown as that program
+    let command_line = commandline::parse_command_line(entry.path.as_deref().unwrap_or(&entry.command), ctx);
+    let (path, launcher) = effective_program(&command_line, ctx);
+
+    let local_path = ctx.local_path(&path);
+    let icon = extract_icon_base64(&local_path);
+    let details = get_file_details(&local_path);
+    let valid = std::path::Path::new(&local_path).exists();
+    let full_source = location.full_path();
+
+    StartupItem {
+        id: item_id(SourceType::Registry, &full_source, &entry.entry_name),
+        name: entry.display_name,
+        entry_name: entry.entry_name,
+        description: details.description,
+        company_name: details.company_name,
+        product_name: details.product_name,
+        file_version: details.file_version,
+        original_filename: details.original_filename,
+        legal_copyright: details.legal_copyright,
+        path,
+        command: entry.command,
+        command_line,
+        launcher,
+        folder_file: None,
+        icon,
+        source: location.name.to_string(),
+        source_type: SourceType::Registry,
+        source_location: full_source,
+        enabled: entry.enabled,
+        disabled_at: entry.disabled_at,
+        valid,
+    }
+}
+
+pub fn scan_registry_items(ctx: &ScanContext) -> Vec<StartupItem> {
+    REGISTRY_LOCATIONS
+        .iter()
+        .flat_map(|location| {
+            registry_location_entries(ctx, location)
+                .into_iter()
+                .map(move |entry| registry_item(ctx, location, entry))
+        })
+        .collect()
+}
+
+/// The item for one entry of a registry location, without looking up the other entries' files
+pub fn scan_registry_entry(ctx: &ScanContext, source_location: &str, entry_name: &str) -> Option<StartupItem> {
+    REGISTRY_LOCATIONS
+        .iter()
+        .filter(|location| location.full_path().eq_ignore_ascii_case(source_location))
+        .find_map(|location| {
+            let entry = registry_location_entries(ctx, location)
+                .into_iter()
+                .find(|entry| entry.entry_name.eq_ignore_ascii_case(entry_name))?;
+            Some(registry_item(ctx, location, entry))
+        })
+}
+
+pub fn get_startup_folder_path(all_users: bool) -> Option<PathBuf> {
     if all_users {
         std::env::var("ProgramData")
             .ok()
@@ -267,149 +398,452 @@ fn get_startup_folder_path(all_users: bool) -> Option<PathBuf> {
     }
 }
 
-#[cfg(windows)]
-fn resolve_shortcut(lnk_path: &PathBuf) -> Option<String> {
-    use windows::core::{PCWSTR, Interface};
-    use windows::Win32::System::Com::{
-        CoCreateInstance, CoInitializeEx, CoUninitialize,
-        CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED, IPersistFile, STGM_READ,
+/// Hive whose `StartupFolder` approvals cover a folder: HKLM for the all-users folder, HKCU otherwise
+pub fn startup_folder_hive(folder: &Path) -> RegistryHive {
+    let is_common = get_startup_folder_path(true)
+        .map(|common| common.to_string_lossy().eq_ignore_ascii_case(&folder.to_string_lossy()))
+        .unwrap_or(false);
+    if is_common {
+        RegistryHive::LocalMachine
+    } else {
+        RegistryHive::CurrentUser
+    }
+}
+
+/// The item for one file in a startup folder, `None` for files Explorer does not run
+fn startup_folder_item(
+    ctx: &ScanContext,
+    folder_path: &Path,
+    file_name: String,
+    source_name: &str,
+    hive: RegistryHive,
+) -> Option<StartupItem> {
+    let file_path = folder_path.join(&file_name);
+
+    // Files renamed to `.disabled` by older versions stay disabled until enabled again
+    let (actual_name, legacy_disabled) = match file_name.strip_suffix(".disabled") {
+        Some(name) => (name.to_string(), true),
+        None => (file_name.clone(), false),
     };
-    use windows::Win32::UI::Shell::{IShellLinkW, ShellLink};
-    use windows::Win32::Foundation::MAX_PATH;
+    let record = approved::read_approval(ctx.registry, hive, ApprovalKey::StartupFolder, &actual_name);
+    let enabled = !legacy_disabled && record.map(|r| r.is_enabled()).unwrap_or(true);
+    let disabled_at = record.and_then(|r| r.disabled_since());
 
-    unsafe {
-        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+    // Skip hidden and system files
+    if actual_name.starts_with('.') {
+        return None;
+    }
 
-        let shell_link: IShellLinkW = match CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER) {
-            Ok(link) => link,
-            Err(_) => {
-                CoUninitialize();
-                return None;
-            }
+    let extension = Path::new(&actual_name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase());
+
+    let (command_line, command, display_name, icon_source, folder_file) = if extension.as_deref() == Some("lnk") {
+        // Resolve .lnk shortcut
+        let link = ShellLink::from_file(&file_path).ok();
+        let target = link.as_ref()
+            .and_then(|l| l.resolve_target(&file_path))
+            .unwrap_or_else(|| file_path.to_string_lossy().to_string());
+        let command = link.as_ref()
+            .and_then(|l| l.command_line(&file_path))
+            .unwrap_or_else(|| target.clone());
+        let command_line = CommandLine {
+            executable: target,
+            ..commandline::parse_command_line(&command, ctx)
         };
+        let icon_source = link.as_ref().and_then(|l| l.icon());
+        let name = actual_name.trim_end_matches(".lnk").to_string();
+        (command_line, command, name, icon_source, None)
+    } else if let Some(file_type) = extension.as_deref().and_then(FolderFileType::from_extension) {
+        let target = file_path.to_string_lossy().to_string();
+        let (file, command_line) = folderfile::read_folder_file(file_type, &target, &file_path, ctx.registry, ctx);
+        // Scripts and references show the icon of the program opening them
+        let icon_source = file.icon.clone().or_else(|| file.interpreter.clone().map(|program| (program, 0)));
+        let name = Path::new(&actual_name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| actual_name.clone());
+        (command_line, target, name, icon_source, Some(file))
+    } else {
+        return None; // Skip files Explorer does not run
+    };
 
-        let persist_file: IPersistFile = match shell_link.cast() {
-            Ok(pf) => pf,
-            Err(_) => {
-                CoUninitialize();
-                return None;
-            }
-        };
+    let (target_path, launcher) = match &folder_file {
+        // A launcher is only shown when it runs the file itself, not for
+        // the loader of a ClickOnce reference
+        Some(file) => {
+            let target_path = file.payload(&command);
+            let launcher = launcher::unwrap_launcher(&command_line, ctx)
+                .filter(|l| l.target_file().is_some_and(|t| t.eq_ignore_ascii_case(&target_path)));
+            (target_path, launcher)
+        }
+        None => effective_program(&command_line, ctx),
+    };
+    let icon_source = icon_source.unwrap_or_else(|| (target_path.clone(), 0));
+    let local_target = ctx.local_path(&target_path);
+    let icon = extract_icon_base64_at(&ctx.local_path(&icon_source.0), icon_source.1);
+    let details = get_file_details(&local_target);
+    let source_location = folder_path.to_string_lossy().to_string();
+    let valid = std::path::Path::new(&local_target).exists();
+
+    // An installed ClickOnce application is described by its uninstall entry
+    let click_once = folder_file.as_ref().and_then(|file| file.click_once.as_ref());
+
+    Some(StartupItem {
+        id: item_id(SourceType::Folder, &source_location, &file_name),
+        name: display_name,
+        entry_name: file_name.clone(),
+        description: details.description.or_else(|| click_once.and_then(|c| c.display_name.clone())),
+        company_name: details.company_name.or_else(|| click_once.and_then(|c| c.publisher.clone())),
+        product_name: details.product_name,
+        file_version: details.file_version,
+        original_filename: details.original_filename,
+        legal_copyright: details.legal_copyright,
+        path: target_path,
+        command,
+        command_line,
+        launcher,
+        folder_file,
+        icon,
+        source: source_name.to_string(),
+        source_type: SourceType::Folder,
+        source_location,
+        enabled,
+        disabled_at,
+        valid,
+    })
+}
 
-        let wide_path: Vec<u16> = lnk_path
-            .as_os_str()
-            .encode_wide()
-            .chain(std::iter::once(0))
-            .collect();
+/// Startup folders of a scan context with the name shown as their source and the hive of their approval records
+fn startup_folders<'a>(ctx: &'a ScanContext) -> [(&'a Option<PathBuf>, &'static str, RegistryHive); 2] {
+    [
+        (&ctx.user_startup_folder, "用户启动文件夹", RegistryHive::CurrentUser),
+        (&ctx.common_startup_folder, "所有用户启动文件夹", RegistryHive::LocalMachine),
+    ]
+}
 
-        if persist_file.Load(PCWSTR(wide_path.as_ptr()), STGM_READ).is_err() {
-            CoUninitialize();
-            return None;
-        }
+pub fn scan_startup_folder_items(ctx: &ScanContext) -> Vec<StartupItem> {
+    let mut items = Vec::new();
 
-        let mut target_path = [0u16; MAX_PATH as usize];
+    for (folder, source_name, hive) in startup_folders(ctx) {
+        if let Some(folder_path) = folder {
+            if !folder_path.exists() {
+                continue;
+            }
 
-        if shell_link.GetPath(&mut target_path, std::ptr::null_mut(), 0).is_ok() {
-            CoUninitialize();
-            let path = String::from_utf16_lossy(&target_path);
-            let path = path.trim_matches('\0').to_string();
-            if !path.is_empty() {
-                return Some(path);
+            if let Ok(entries) = std::fs::read_dir(folder_path) {
+                for entry in entries.flatten() {
+                    let file_name = entry.file_name().to_string_lossy().to_string();
+                    items.extend(startup_folder_item(ctx, folder_path, file_name, source_name, hive));
+                }
             }
         }
-
-        CoUninitialize();
-        None
     }
+
+    items
 }
 
-#[cfg(not(windows))]
-fn resolve_shortcut(_lnk_path: &PathBuf) -> Option<String> {
-    None
+/// The item for one file of a startup folder of the scan context, without reading the rest of the folder
+pub fn scan_startup_folder_file(ctx: &ScanContext, folder: &Path, file_name: &str) -> Option<StartupItem> {
+    let (folder_path, source_name, hive) = startup_folders(ctx).into_iter().find_map(|(path, source_name, hive)| {
+        let path = path.as_ref().filter(|path| path.to_string_lossy().eq_ignore_ascii_case(&folder.to_string_lossy()))?;
+        Some((path, source_name, hive))
+    })?;
+    if !folder_path.join(file_name).is_file() {
+        return None;
+    }
+    startup_folder_item(ctx, folder_path, file_name.to_string(), source_name, hive)
 }
 
-pub fn scan_startup_folder_items() -> Vec<StartupItem> {
+/// Tasks started by a logon or boot trigger. Tasks that only run COM handlers
+/// have no program to show and are skipped.
+pub fn scan_scheduled_task_items(ctx: &ScanContext) -> Vec<StartupItem> {
     let mut items = Vec::new();
 
-    let folders = [
-        (false, "用户启动文件夹"),
-        (true, "所有用户启动文件夹"),
-    ];
+    let Some(tasks_folder) = &ctx.tasks_folder else {
+        return items;
+    };
 
-    for (all_users, source_name) in folders.iter() {
-        if let Some(folder_path) = get_startup_folder_path(*all_users) {
-            if !folder_path.exists() {
-                continue;
-            }
+    for (task_path, file_path) in tasks::find_task_files(tasks_folder) {
+        let task = match TaskDefinition::from_file(&file_path) {
+            Ok(task) => task,
+            Err(_) => continue,
+        };
 
-            if let Ok(entries) = std::fs::read_dir(&folder_path) {
-                for entry in entries.flatten() {
-                    let file_path = entry.path();
-                    let file_name = entry.file_name().to_string_lossy().to_string();
+        if !task.runs_at_startup() {
+            continue;
+        }
 
-                    // Skip disabled items (with .disabled extension)
-                    let (actual_name, enabled) = if file_name.ends_with(".disabled") {
-                        (file_name.trim_end_matches(".disabled").to_string(), false)
-                    } else {
-                        (file_name.clone(), true)
-                    };
+        let (program, arguments) = match task.exec_action() {
+            Some(action) => action,
+            None => continue,
+        };
 
-                    // Skip hidden and system files
-                    if actual_name.starts_with('.') {
-                        continue;
-                    }
+        let command = match arguments {
+            Some(args) => format!("{} {}", program, args),
+            None => program.to_string(),
+        };
+        let command_line = CommandLine {
+            executable: commandline::resolve_program(&commandline::expand_with(program, |name| ctx.var(name)), ctx),
+            arguments: commandline::expand_with(arguments.unwrap_or_default().trim(), |name| ctx.var(name)),
+        };
+        let (path, launcher) = effective_program(&command_line, ctx);
+
+        let local_path = ctx.local_path(&path);
+        let icon = extract_icon_base64(&local_path);
+        let details = get_file_details(&local_path);
+        let valid = std::path::Path::new(&local_path).exists();
+
+        let (folder, name) = tasks::split_task_path(&task_path);
+
+        items.push(StartupItem {
+            id: item_id(SourceType::ScheduledTask, &folder, &name),
+            entry_name: name.clone(),
+            name,
+            description: details.description,
+            company_name: details.company_name,
+            product_name: details.product_name,
+            file_version: details.file_version,
+            original_filename: details.original_filename,
+            legal_copyright: details.legal_copyright,
+            path,
+            command,
+            command_line,
+            launcher,
+            folder_file: None,
+            icon,
+            source: "计划任务".to_string(),
+            source_type: SourceType::ScheduledTask,
+            source_location: folder,
+            enabled: task.enabled,
+            disabled_at: None,
+            valid,
+        });
+    }
 
-                    let (target_path, display_name) = if file_path.extension()
-                        .map(|e| e.to_string_lossy().to_lowercase() == "lnk")
-                        .unwrap_or(false)
-                    {
-                        // Resolve .lnk shortcut
-                        let target = resolve_shortcut(&file_path).unwrap_or_else(|| file_path.to_string_lossy().to_string());
-                        let name = actual_name.trim_end_matches(".lnk").to_string();
-                        (target, name)
-                    } else if file_path.extension()
-                        .map(|e| e.to_string_lossy().to_lowercase() == "exe")
-                        .unwrap_or(false)
-                    {
-                        (file_path.to_string_lossy().to_string(), actual_name.trim_end_matches(".exe").to_string())
-                    } else {
-                        continue; // Skip non-executable files
-                    };
-
-                    let icon = extract_icon_base64(&target_path);
-                    let description = get_file_description(&target_path);
-                    let source_location = folder_path.to_string_lossy().to_string();
-                    let valid = std::path::Path::new(&target_path).exists();
-
-                    items.push(StartupItem {
-                        id: generate_id(&source_location, &file_name),
-                        name: display_name,
-                        description,
-                        path: target_path.clone(),
-                        command: target_path,
-                        icon,
-                        source: source_name.to_string(),
-                        source_type: SourceType::Folder.to_string(),
-                        source_location,
-                        enabled,
-                        valid,
-                    });
-                }
+    items
+}
+
+/// Win32 services set to Automatic or Automatic (Delayed Start)
+pub fn scan_service_items(ctx: &ScanContext) -> Vec<StartupItem> {
+    let services_key = format!("{}\\{}", RegistryHive::LocalMachine.root_name(), SERVICES_PATH);
+
+    services::read_startup_services(ctx.registry, &ctx.manual_services)
+        .into_iter()
+        .map(|service| {
+            let command_line = service.command_line(ctx);
+            let path = service.binary_path(ctx);
+            let local_path = ctx.local_path(&path);
+            let icon = extract_icon_base64(&local_path);
+            let details = get_file_details(&local_path);
+            let valid = std::path::Path::new(&local_path).exists();
+
+            let source = if service.delayed_auto_start {
+                "系统服务（延迟启动）"
+            } else {
+                "系统服务"
+            };
+
+            let source_location = format!("{}\\{}", services_key, service.name);
+
+            StartupItem {
+                id: item_id(SourceType::Service, &source_location, &service.name),
+                description: service.display_name.or(service.description).or(details.description),
+                company_name: details.company_name,
+                product_name: details.product_name,
+                file_version: details.file_version,
+                original_filename: details.original_filename,
+                legal_copyright: details.legal_copyright,
+                path,
+                command: service.image_path,
+                command_line,
+                launcher: None,
+                folder_file: None,
+                icon,
+                source: source.to_string(),
+                source_type: SourceType::Service,
+                source_location,
+                enabled: service.start == services::SERVICE_AUTO_START,
+                disabled_at: None,
+                valid,
+                entry_name: service.name.clone(),
+                name: service.name,
             }
+        })
+        .collect()
+}
+
+/// XDG autostart entries that apply to the current desktop. Entries switched off
+/// with `Hidden=true` are listed as disabled so they can be switched on again.
+pub fn scan_xdg_autostart_items(ctx: &ScanContext) -> Vec<StartupItem> {
+    let mut items = Vec::new();
+
+    let dirs: Vec<PathBuf> = ctx.xdg_user_autostart
+        .iter()
+        .chain(ctx.xdg_system_autostart.iter())
+        .cloned()
+        .collect();
+    let desktops = xdg::current_desktops();
+
+    for (file_name, file_path) in xdg::find_autostart_files(&dirs) {
+        let entry = match DesktopEntry::from_file(&file_path) {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+
+        if !entry.is_application() || !entry.shown_in(&desktops) {
+            continue;
         }
+
+        let path = entry.program().unwrap_or_default();
+        let command = entry.command_line().unwrap_or_default();
+        let command_line = command_line_with_program(path.clone(), &command);
+        let icon = entry.icon
+            .as_deref()
+            .and_then(xdg::find_icon_file)
+            .and_then(|icon_path| extract_icon_base64(&icon_path.to_string_lossy()));
+        let valid = !path.is_empty() && Path::new(&path).exists();
+
+        let folder = file_path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let is_user = ctx.xdg_user_autostart.as_ref() == Some(&folder);
+        let source_location = folder.to_string_lossy().to_string();
+
+        items.push(StartupItem {
+            id: item_id(SourceType::XdgAutostart, &source_location, &file_name),
+            name: file_name.trim_end_matches(".desktop").to_string(),
+            entry_name: file_name,
+            description: entry.name.clone().or_else(|| entry.comment.clone()),
+            company_name: None,
+            product_name: None,
+            file_version: None,
+            original_filename: None,
+            legal_copyright: None,
+            path,
+            command,
+            command_line,
+            launcher: None,
+            folder_file: None,
+            icon,
+            source: if is_user { "用户自启动" } else { "系统自启动" }.to_string(),
+            source_type: SourceType::XdgAutostart,
+            source_location,
+            enabled: entry.is_enabled(),
+            disabled_at: None,
+            valid,
+        });
     }
 
     items
 }
 
-pub fn get_all_startup_items() -> Vec<StartupItem> {
+/// `systemd --user` services linked into a login target, and those that could be
+pub fn scan_systemd_user_items(ctx: &ScanContext) -> Vec<StartupItem> {
     let mut items = Vec::new();
 
-    items.extend(scan_registry_items());
-    items.extend(scan_startup_folder_items());
+    for (unit_name, unit_path, enabled) in systemd::find_login_units(&ctx.systemd_user_paths) {
+        let unit = match UnitFile::from_file(&unit_path) {
+            Ok(unit) => unit,
+            Err(_) => continue,
+        };
+
+        let path = unit.program().unwrap_or_default();
+        let command = unit.command_line().unwrap_or_default();
+        let command_line = command_line_with_program(path.clone(), &command);
+        let icon = extract_icon_base64(&path);
+        let valid = !path.is_empty() && Path::new(&path).exists();
+        let source_location = unit_path
+            .parent()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        items.push(StartupItem {
+            id: item_id(SourceType::SystemdUser, &source_location, &unit_name),
+            name: unit_name.trim_end_matches(".service").to_string(),
+            entry_name: unit_name,
+            description: unit.description,
+            company_name: None,
+            product_name: None,
+            file_version: None,
+            original_filename: None,
+            legal_copyright: None,
+            path,
+            command,
+            command_line,
+            launcher: None,
+            folder_file: None,
+            icon,
+            source: "systemd用户服务".to_string(),
+            source_type: SourceType::SystemdUser,
+            source_location,
+            enabled,
+            disabled_at: None,
+            valid,
+        });
+    }
+
+    items
+}
+
+/// Collect items from every source described by a scan context
+pub fn collect_startup_items(ctx: &ScanContext) -> Vec<StartupItem> {
+    let mut items: Vec<StartupItem> = SOURCES.iter().flat_map(|source| source.scan(ctx)).collect();
 
     // Sort by name
     items.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
 
     items
 }
+
+pub fn get_all_startup_items() -> Vec<StartupItem> {
+    collect_startup_items(&ScanContext::system())
+}
+
+/// Scan a Windows installation copied from another machine.
+///
+/// The returned items describe the offline system and must not be passed to
+/// the manager, which always acts on the running system.
+pub fn get_offline_startup_items(target: &OfflineTarget) -> Result<Vec<StartupItem>, String> {
+    let open = |path: &Option<String>| -> Result<Option<RegfHive>, String> {
+        match path {
+            Some(p) if !p.trim().is_empty() => RegfHive::open(Path::new(p))
+                .map(Some)
+                .map_err(|e| format!("{}: {}", p, e)),
+            _ => Ok(None),
+        }
+    };
+
+    let registry = OfflineRegistry {
+        user: open(&target.user_hive)?,
+        software: open(&target.software_hive)?,
+        system: open(&target.system_hive)?,
+    };
+
+    let volume_root = target.volume_root.as_ref().map(PathBuf::from);
+    let user_profile = target.user_profile
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(|| {
+            target.user_hive
+                .as_ref()
+                .and_then(|hive| Path::new(hive).parent().map(Path::to_path_buf))
+        });
+
+    let ctx = ScanContext {
+        registry: &registry,
+        user_startup_folder: user_profile
+            .map(|profile| join_windows_path(&profile, r"AppData\Roaming\Microsoft\Windows\Start Menu\Programs\Startup")),
+        common_startup_folder: volume_root
+            .as_ref()
+            .map(|root| join_windows_path(root, r"ProgramData\Microsoft\Windows\Start Menu\Programs\Startup")),
+        tasks_folder: volume_root
+            .as_ref()
+            .map(|root| join_windows_path(root, r"Windows\System32\Tasks")),
+        manual_services: Vec::new(),
+        volume_root,
+        xdg_user_autostart: None,
+        xdg_system_autostart: Vec::new(),
+        systemd_user_paths: Vec::new(),
+    };
+
+    Ok(collect_startup_items(&ctx))
+}
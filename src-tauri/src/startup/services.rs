@@ -0,0 +1,300 @@
+//! Win32 services configured under `HKLM\SYSTEM\CurrentControlSet\Services`.
+
+use std::io;
+
+use super::commandline::{self, CommandEnvironment, CommandLine};
+use super::registry::{RegistryBackend, RegistryHive, RegistryValue};
+
+pub const SERVICES_PATH: &str = r"SYSTEM\CurrentControlSet\Services";
+
+/// `Start` values
+pub const SERVICE_AUTO_START: u32 = 2;
+pub const SERVICE_DEMAND_START: u32 = 3;
+
+/// `Type` bits of services that run as a user-mode process (drivers use 0x1/0x2)
+const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+const SERVICE_WIN32_SHARE_PROCESS: u32 = 0x20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceConfig {
+    /// Key name, which is also the service name used by `sc` and the SCM
+    pub name: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    /// Raw `ImagePath`, e.g. `"C:\Program Files\App\svc.exe" -k` or `\SystemRoot\...`
+    pub image_path: String,
+    /// `Parameters\ServiceDll` of services hosted by svchost.exe
+    pub service_dll: Option<String>,
+    pub start: u32,
+    pub delayed_auto_start: bool,
+    pub service_type: u32,
+}
+
+impl ServiceConfig {
+    pub fn is_auto_start(&self) -> bool {
+        self.start == SERVICE_AUTO_START
+    }
+
+    pub fn is_win32(&self) -> bool {
+        self.service_type & (SERVICE_WIN32_OWN_PROCESS | SERVICE_WIN32_SHARE_PROCESS) != 0
+    }
+
+    /// `ImagePath` split into the program and its arguments
+    pub fn command_line(&self, env: &dyn CommandEnvironment) -> CommandLine {
+        commandline::parse_command_line(&normalize_image_path(&self.image_path), env)
+    }
+
+    /// The file to show for the service: the hosted DLL for svchost services, otherwise the program
+    pub fn binary_path(&self, env: &dyn CommandEnvironment) -> String {
+        match &self.service_dll {
+            Some(dll) => commandline::expand_environment_strings(&normalize_image_path(dll)),
+            None => self.command_line(env).executable,
+        }
+    }
+}
+
+fn dword(values: &[(String, RegistryValue)], name: &str) -> Option<u32> {
+    values
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .and_then(|(_, v)| v.as_dword())
+}
+
+fn string(values: &[(String, RegistryValue)], name: &str) -> Option<String> {
+    values
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .and_then(|(_, v)| v.as_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// Resource references like `@%SystemRoot%\system32\svc.dll,-101` need the MUI
+/// string tables; they are treated as missing
+fn direct_string(value: Option<String>) -> Option<String> {
+    value.filter(|s| !s.starts_with('@'))
+}
+
+pub fn service_key(name: &str) -> String {
+    format!("{}\\{}", SERVICES_PATH, name)
+}
+
+pub fn read_service(registry: &dyn RegistryBackend, name: &str) -> io::Result<ServiceConfig> {
+    let values = registry.enum_values(RegistryHive::LocalMachine, &service_key(name))?;
+
+    let service_dll = registry
+        .enum_values(RegistryHive::LocalMachine, &format!("{}\\Parameters", service_key(name)))
+        .ok()
+        .and_then(|params| string(&params, "ServiceDll"));
+
+    Ok(ServiceConfig {
+        name: name.to_string(),
+        display_name: direct_string(string(&values, "DisplayName")),
+        description: direct_string(string(&values, "Description")),
+        image_path: string(&values, "ImagePath").unwrap_or_default(),
+        service_dll,
+        start: dword(&values, "Start").unwrap_or(SERVICE_DEMAND_START),
+        delayed_auto_start: dword(&values, "DelayedAutostart").unwrap_or(0) != 0,
+        service_type: dword(&values, "Type").unwrap_or(0),
+    })
+}
+
+/// Win32 services that start automatically, plus any listed in `also_include`
+/// (services we switched to manual, so they can be switched back)
+pub fn read_startup_services(registry: &dyn RegistryBackend, also_include: &[String]) -> Vec<ServiceConfig> {
+    let names = match registry.enum_keys(RegistryHive::LocalMachine, SERVICES_PATH) {
+        Ok(names) => names,
+        Err(_) => return Vec::new(),
+    };
+
+    names
+        .iter()
+        .filter_map(|name| read_service(registry, name).ok())
+        .filter(|service| service.is_win32() && !service.image_path.is_empty())
+        .filter(|service| {
+            service.is_auto_start()
+                || also_include.iter().any(|n| n.eq_ignore_ascii_case(&service.name))
+        })
+        .collect()
+}
+
+/// Switch a service between Automatic and Manual. `DelayedAutostart` is left as is,
+/// so a delayed service becomes delayed again when switched back.
+pub fn set_auto_start(registry: &dyn RegistryBackend, name: &str, automatic: bool) -> io::Result<()> {
+    let key = service_key(name);
+    registry.open_key(RegistryHive::LocalMachine, &key)?;
+
+    let start = if automatic { SERVICE_AUTO_START } else { SERVICE_DEMAND_START };
+    registry.set_raw_value(RegistryHive::LocalMachine, &key, "Start", &RegistryValue::from_dword(start))
+}
+
+/// Turn the NT-style paths the SCM accepts into regular Win32 paths; `%VAR%` is left for the caller
+fn normalize_image_path(path: &str) -> String {
+    let path = path.trim();
+    // A quoted path keeps its quotes around the converted path
+    if let Some(rest) = path.strip_prefix('"') {
+        return format!("\"{}", normalize_image_path(rest));
+    }
+
+    let lower = path.to_lowercase();
+
+    if let Some(rest) = path.strip_prefix(r"\??\") {
+        rest.to_string()
+    } else if lower.starts_with(r"\systemroot\") {
+        format!("%SystemRoot%{}", &path[r"\systemroot".len()..])
+    } else if lower.starts_with(r"system32\") || lower.starts_with(r"syswow64\") {
+        format!("%SystemRoot%\\{}", path)
+    } else {
+        path.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::registry::{MemoryRegistry, RegValueType};
+
+    struct FakeEnvironment;
+
+    impl CommandEnvironment for FakeEnvironment {
+        fn var(&self, name: &str) -> Option<String> {
+            name.eq_ignore_ascii_case("SystemRoot").then(|| r"C:\Windows".to_string())
+        }
+
+        fn is_file(&self, path: &str) -> bool {
+            [r"C:\Program Files\Contoso\svc.exe", r"C:\Windows\System32\svchost.exe"]
+                .iter()
+                .any(|file| file.eq_ignore_ascii_case(path))
+        }
+
+        fn app_path(&self, _name: &str) -> Option<String> {
+            None
+        }
+    }
+
+    fn add_service(registry: &MemoryRegistry, name: &str, values: &[(&str, RegistryValue)]) {
+        let key = service_key(name);
+        registry.create_subkey(RegistryHive::LocalMachine, &key).unwrap();
+        for (value_name, value) in values {
+            registry.set_raw_value(RegistryHive::LocalMachine, &key, value_name, value).unwrap();
+        }
+    }
+
+    fn sz(value: &str) -> RegistryValue {
+        RegistryValue::from_string(value, RegValueType::Sz)
+    }
+
+    fn expand_sz(value: &str) -> RegistryValue {
+        RegistryValue::from_string(value, RegValueType::ExpandSz)
+    }
+
+    fn dword(value: u32) -> RegistryValue {
+        RegistryValue::from_dword(value)
+    }
+
+    fn registry() -> MemoryRegistry {
+        let registry = MemoryRegistry::new();
+        add_service(&registry, "Contoso", &[
+            ("Type", dword(SERVICE_WIN32_OWN_PROCESS)),
+            ("Start", dword(SERVICE_AUTO_START)),
+            ("DelayedAutostart", dword(1)),
+            ("ImagePath", expand_sz(r#""C:\Program Files\Contoso\svc.exe" --service"#)),
+            ("DisplayName", sz("Contoso Service")),
+            ("Description", sz(r"@%SystemRoot%\system32\contoso.dll,-101")),
+        ]);
+        add_service(&registry, "Shared", &[
+            ("Type", dword(SERVICE_WIN32_SHARE_PROCESS)),
+            ("Start", dword(SERVICE_AUTO_START)),
+            ("ImagePath", expand_sz(r"%SystemRoot%\System32\svchost.exe -k netsvcs")),
+        ]);
+        add_service(&registry, r"Shared\Parameters", &[
+            ("ServiceDll", expand_sz(r"\SystemRoot\System32\shared.dll")),
+        ]);
+        add_service(&registry, "Driver", &[
+            ("Type", dword(1)),
+            ("Start", dword(SERVICE_AUTO_START)),
+            ("ImagePath", expand_sz(r"\SystemRoot\System32\drivers\driver.sys")),
+        ]);
+        add_service(&registry, "Manual", &[
+            ("Type", dword(SERVICE_WIN32_OWN_PROCESS)),
+            ("Start", dword(SERVICE_DEMAND_START)),
+            ("ImagePath", expand_sz(r"C:\Tools\manual.exe")),
+        ]);
+        add_service(&registry, "NoImage", &[
+            ("Type", dword(SERVICE_WIN32_OWN_PROCESS)),
+            ("Start", dword(SERVICE_AUTO_START)),
+        ]);
+        registry
+    }
+
+    fn names(services: &[ServiceConfig]) -> Vec<&str> {
+        services.iter().map(|service| service.name.as_str()).collect()
+    }
+
+    #[test]
+    fn lists_automatic_win32_services() {
+        let registry = registry();
+        assert_eq!(names(&read_startup_services(&registry, &[])), ["Contoso", "Shared"]);
+        assert_eq!(
+            names(&read_startup_services(&registry, &["manual".to_string(), "Driver".to_string()])),
+            ["Contoso", "Manual", "Shared"]
+        );
+        assert!(read_startup_services(&MemoryRegistry::new(), &[]).is_empty());
+    }
+
+    #[test]
+    fn reads_service_configuration() {
+        let registry = registry();
+        let contoso = read_service(&registry, "Contoso").unwrap();
+        assert_eq!(contoso.display_name.as_deref(), Some("Contoso Service"));
+        assert_eq!(contoso.description, None);
+        assert!(contoso.is_auto_start() && contoso.delayed_auto_start && contoso.is_win32());
+        assert_eq!(contoso.binary_path(&FakeEnvironment), r"C:\Program Files\Contoso\svc.exe");
+        assert_eq!(contoso.command_line(&FakeEnvironment).arguments, "--service");
+
+        let shared = read_service(&registry, "Shared").unwrap();
+        assert_eq!(shared.service_dll.as_deref(), Some(r"\SystemRoot\System32\shared.dll"));
+        assert_eq!(shared.command_line(&FakeEnvironment).executable, r"C:\Windows\System32\svchost.exe");
+
+        let driver = read_service(&registry, "Driver").unwrap();
+        assert!(!driver.is_win32());
+        assert_eq!(
+            driver.command_line(&FakeEnvironment).executable,
+            r"C:\Windows\System32\drivers\driver.sys"
+        );
+    }
+
+    #[test]
+    fn switches_start_between_automatic_and_manual() {
+        let registry = registry();
+        let start = || read_service(&registry, "Contoso").unwrap().start;
+
+        set_auto_start(&registry, "Contoso", false).unwrap();
+        assert_eq!(start(), SERVICE_DEMAND_START);
+        assert!(read_service(&registry, "Contoso").unwrap().delayed_auto_start);
+        assert_eq!(names(&read_startup_services(&registry, &[])), ["Shared"]);
+
+        set_auto_start(&registry, "Contoso", true).unwrap();
+        assert_eq!(start(), SERVICE_AUTO_START);
+        assert!(read_service(&registry, "Contoso").unwrap().delayed_auto_start);
+
+        set_auto_start(&registry, "Manual", true).unwrap();
+        assert!(read_service(&registry, "Manual").unwrap().is_auto_start());
+    }
+
+    #[test]
+    fn does_not_create_missing_services() {
+        let registry = registry();
+        let error = set_auto_start(&registry, "Missing", true).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(registry.open_key(RegistryHive::LocalMachine, &service_key("Missing")).is_err());
+    }
+
+    #[test]
+    fn normalizes_nt_image_paths() {
+        assert_eq!(normalize_image_path(r"\SystemRoot\System32\a.exe"), r"%SystemRoot%\System32\a.exe");
+        assert_eq!(normalize_image_path(r"system32\a.exe"), r"%SystemRoot%\system32\a.exe");
+        assert_eq!(normalize_image_path(r"\??\C:\Tools\a.exe"), r"C:\Tools\a.exe");
+        assert_eq!(normalize_image_path(r#""\??\C:\My Tools\a.exe" -x"#), r#""C:\My Tools\a.exe" -x"#);
+        assert_eq!(normalize_image_path(r"C:\Tools\a.exe"), r"C:\Tools\a.exe");
+    }
+}
@@ -4,7 +4,10 @@ use std::fs;
 use std::path::PathBuf;
 use std::sync::Mutex;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+use super::StartupItem;
+use super::item_id::{ID_SCHEME, legacy_item_id};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppSettings {
     /// Set of startup item IDs that should auto-minimize after launch
     pub auto_minimize_items: HashSet<String>,
@@ -20,6 +23,182 @@ pub struct AppSettings {
     /// Whether to auto-exit after all auto-minimize tasks are completed
     #[serde(default)]
     pub auto_exit_after_minimize: bool,
+    /// Services switched from Automatic to Manual here, kept in the list so they can be switched back
+    #[serde(default)]
+    pub manual_services: HashSet<String>,
+    /// Days an operation stays in the history and can be undone
+    #[serde(default = "default_history_retention_days")]
+    pub history_retention_days: u32,
+    /// Whether startup locations are watched for changes while the app runs
+    #[serde(default = "default_watch_enabled")]
+    pub watch_enabled: bool,
+    /// What the watcher does with startup items added outside this app
+    #[serde(default)]
+    pub watch_policy: WatchPolicy,
+    /// Scheme of the item IDs the settings above are keyed by; files without it use the legacy IDs
+    #[serde(default)]
+    pub id_scheme: u32,
+    /// Maps item_id to the item as last scanned, for items with settings, so they can be found again
+    #[serde(default)]
+    pub known_items: HashMap<String, KnownItem>,
+}
+
+/// What a startup item with settings looked like when last scanned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownItem {
+    pub name: String,
+    pub entry_name: String,
+    pub source: String,
+    pub source_location: String,
+    pub path: String,
+}
+
+impl KnownItem {
+    pub fn from_item(item: &StartupItem) -> KnownItem {
+        KnownItem {
+            name: item.name.clone(),
+            entry_name: item.entry_name.clone(),
+            source: item.source.clone(),
+            source_location: item.source_location.clone(),
+            path: item.path.clone(),
+        }
+    }
+}
+
+/// How the watcher handles an item that appears without being in the baseline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchPolicy {
+    /// Only report it
+    #[default]
+    Notify,
+    /// Switch it off, keeping the entry
+    Disable,
+    /// Delete it again
+    Revert,
+}
+
+fn default_history_retention_days() -> u32 {
+    30
+}
+
+fn default_watch_enabled() -> bool {
+    true
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        AppSettings {
+            auto_minimize_items: HashSet::new(),
+            process_name_mappings: HashMap::new(),
+            minimize_behaviors: HashMap::new(),
+            minimize_delays: HashMap::new(),
+            auto_exit_after_minimize: false,
+            manual_services: HashSet::new(),
+            history_retention_days: default_history_retention_days(),
+            watch_enabled: default_watch_enabled(),
+            watch_policy: WatchPolicy::default(),
+            id_scheme: ID_SCHEME,
+            known_items: HashMap::new(),
+        }
+    }
+}
+
+impl AppSettings {
+    /// Move every setting kept for item `from` to item `to`, replacing any `to` already has
+    pub fn rename_item(&mut self, from: &str, to: &str) {
+        if from == to {
+            return;
+        }
+        if self.auto_minimize_items.remove(from) {
+            self.auto_minimize_items.insert(to.to_string());
+        }
+        if let Some(name) = self.process_name_mappings.remove(from) {
+            self.process_name_mappings.insert(to.to_string(), name);
+        }
+        if let Some(behavior) = self.minimize_behaviors.remove(from) {
+            self.minimize_behaviors.insert(to.to_string(), behavior);
+        }
+        if let Some(delay) = self.minimize_delays.remove(from) {
+            self.minimize_delays.insert(to.to_string(), delay);
+        }
+        if let Some(known) = self.known_items.remove(from) {
+            self.known_items.insert(to.to_string(), known);
+        }
+    }
+
+    /// Rekey settings saved under legacy IDs to the current IDs of the scanned items.
+    /// Keys matching no item are left as they are. A scan that found nothing leaves
+    /// the settings on the old scheme, to be migrated by a later one. Returns whether
+    /// anything was done.
+    pub fn migrate_item_ids(&mut self, items: &[StartupItem]) -> bool {
+        if self.id_scheme >= ID_SCHEME || items.is_empty() {
+            return false;
+        }
+        for item in items {
+            self.rename_item(&legacy_item_id(item), &item.id);
+        }
+        self.id_scheme = ID_SCHEME;
+        true
+    }
+
+    /// Drop every setting kept for an item
+    pub fn forget_item(&mut self, item_id: &str) {
+        self.auto_minimize_items.remove(item_id);
+        self.process_name_mappings.remove(item_id);
+        self.minimize_behaviors.remove(item_id);
+        self.minimize_delays.remove(item_id);
+        self.known_items.remove(item_id);
+    }
+
+    /// These settings with the preferences from `other` applied: its global options,
+    /// and its per-item settings in place of ours for the same items. What only
+    /// describes this machine (`manual_services`, `id_scheme`, `known_items`) stays.
+    pub fn merge_preferences(&self, other: &AppSettings) -> AppSettings {
+        let mut merged = AppSettings {
+            auto_exit_after_minimize: other.auto_exit_after_minimize,
+            history_retention_days: other.history_retention_days,
+            watch_enabled: other.watch_enabled,
+            watch_policy: other.watch_policy,
+            ..self.clone()
+        };
+        // IDs of another scheme name other items, or none at all
+        if other.id_scheme != self.id_scheme {
+            return merged;
+        }
+
+        for id in other.item_ids() {
+            let known = merged.known_items.remove(id).or_else(|| other.known_items.get(id).cloned());
+            merged.forget_item(id);
+            if other.auto_minimize_items.contains(id) {
+                merged.auto_minimize_items.insert(id.to_string());
+            }
+            if let Some(name) = other.process_name_mappings.get(id) {
+                merged.process_name_mappings.insert(id.to_string(), name.clone());
+            }
+            if let Some(behavior) = other.minimize_behaviors.get(id) {
+                merged.minimize_behaviors.insert(id.to_string(), behavior.clone());
+            }
+            if let Some(delay) = other.minimize_delays.get(id) {
+                merged.minimize_delays.insert(id.to_string(), *delay);
+            }
+            if let Some(known) = known {
+                merged.known_items.insert(id.to_string(), known);
+            }
+        }
+        merged
+    }
+
+    /// IDs of all items that have settings
+    pub fn item_ids(&self) -> HashSet<&str> {
+        self.auto_minimize_items
+            .iter()
+            .chain(self.process_name_mappings.keys())
+            .chain(self.minimize_behaviors.keys())
+            .chain(self.minimize_delays.keys())
+            .map(String::as_str)
+            .collect()
+    }
 }
 
 lazy_static::lazy_static! {
@@ -30,11 +209,23 @@ lazy_static::lazy_static! {
     static ref MINIMIZE_EXEC_TIMES: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
 }
 
+/// Directory holding the settings and the operation history: `%APPDATA%\UMStartupManager`
+/// on Windows, `$XDG_CONFIG_HOME/UMStartupManager` (by default under `~/.config`) elsewhere
+pub fn get_config_dir() -> PathBuf {
+    #[cfg(windows)]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+
+    #[cfg(not(windows))]
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    base.unwrap_or_else(|| PathBuf::from(".")).join("UMStartupManager")
+}
+
 fn get_settings_path() -> PathBuf {
-    let app_data = std::env::var("APPDATA").unwrap_or_else(|_| ".".to_string());
-    PathBuf::from(app_data)
-        .join("UMStartupManager")
-        .join("settings.json")
+    get_config_dir().join("settings.json")
 }
 
 pub fn load_settings() -> AppSettings {
@@ -73,6 +264,43 @@ pub fn get_settings() -> AppSettings {
     SETTINGS.lock().unwrap().clone()
 }
 
+/// Whether the loaded settings are keyed by IDs from an older scheme
+pub fn needs_id_migration() -> bool {
+    get_settings().id_scheme < ID_SCHEME
+}
+
+/// Rekey the saved settings to the current IDs of the scanned items, saving them if that was done
+pub fn migrate_item_ids(items: &[StartupItem]) -> Result<(), String> {
+    let mut settings = get_settings();
+    if settings.migrate_item_ids(items) {
+        save_settings(&settings)
+    } else {
+        Ok(())
+    }
+}
+
+/// Remember the scanned items that have settings, saving only when one of them changed
+pub fn remember_items(items: &[StartupItem]) -> Result<(), String> {
+    let mut settings = get_settings();
+    let ids: HashSet<String> = settings.item_ids().into_iter().map(str::to_string).collect();
+    // Items whose settings were all cleared need not be remembered
+    let before = settings.known_items.len();
+    settings.known_items.retain(|id, _| ids.contains(id));
+    let mut changed = settings.known_items.len() != before;
+    for item in items.iter().filter(|item| ids.contains(&item.id)) {
+        let known = KnownItem::from_item(item);
+        if settings.known_items.get(&item.id) != Some(&known) {
+            settings.known_items.insert(item.id.clone(), known);
+            changed = true;
+        }
+    }
+    if changed {
+        save_settings(&settings)
+    } else {
+        Ok(())
+    }
+}
+
 pub fn set_auto_minimize(item_id: &str, enabled: bool) -> Result<(), String> {
     let mut settings = get_settings();
     if enabled {
@@ -155,13 +383,54 @@ pub fn set_auto_exit_enabled(enabled: bool) -> Result<(), String> {
     save_settings(&settings)
 }
 
+pub fn set_service_switched_to_manual(service_name: &str, manual: bool) -> Result<(), String> {
+    let mut settings = get_settings();
+    let key = service_name.to_lowercase();
+    if manual {
+        settings.manual_services.insert(key);
+    } else {
+        settings.manual_services.remove(&key);
+    }
+    save_settings(&settings)
+}
+
+pub fn get_history_retention_days() -> u32 {
+    get_settings().history_retention_days
+}
+
+pub fn set_history_retention_days(days: u32) -> Result<(), String> {
+    let mut settings = get_settings();
+    settings.history_retention_days = days.max(1);
+    save_settings(&settings)
+}
+
+pub fn is_watch_enabled() -> bool {
+    get_settings().watch_enabled
+}
+
+pub fn set_watch_enabled(enabled: bool) -> Result<(), String> {
+    let mut settings = get_settings();
+    settings.watch_enabled = enabled;
+    save_settings(&settings)
+}
+
+pub fn get_watch_policy() -> WatchPolicy {
+    get_settings().watch_policy
+}
+
+pub fn set_watch_policy(policy: WatchPolicy) -> Result<(), String> {
+    let mut settings = get_settings();
+    settings.watch_policy = policy;
+    save_settings(&settings)
+}
+
+/// Delete the settings file. The history, baseline, policy and quarantine kept
+/// beside it in the config directory stay.
 pub fn reset_settings() -> Result<(), String> {
-    let settings_path = get_settings_path();
-    if let Some(parent_dir) = settings_path.parent() {
-        if parent_dir.exists() {
-            fs::remove_dir_all(parent_dir)
-                .map_err(|e| e.to_string())?;
-        }
+    match fs::remove_file(get_settings_path()) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.to_string()),
     }
 
     // 重置全局SETTINGS变量
@@ -188,3 +457,91 @@ pub fn get_all_minimize_times() -> HashMap<String, u64> {
     let guard = MINIMIZE_EXEC_TIMES.lock().unwrap();
     guard.clone()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::SourceType;
+
+    fn known(name: &str) -> KnownItem {
+        KnownItem {
+            name: name.to_string(),
+            entry_name: name.to_string(),
+            source: "Registry".to_string(),
+            source_location: String::new(),
+            path: String::new(),
+        }
+    }
+
+    #[test]
+    fn merging_preferences_keeps_what_describes_this_machine() {
+        let mut ours = AppSettings::default();
+        ours.auto_minimize_items.insert("shared".to_string());
+        ours.minimize_delays.insert("shared".to_string(), 5);
+        ours.minimize_behaviors.insert("ours".to_string(), "close".to_string());
+        ours.manual_services.insert("Spooler".to_string());
+        ours.known_items.insert("shared".to_string(), known("Ours"));
+
+        let mut theirs = AppSettings {
+            auto_exit_after_minimize: true,
+            history_retention_days: 7,
+            watch_policy: WatchPolicy::Disable,
+            ..AppSettings::default()
+        };
+        theirs.process_name_mappings.insert("shared".to_string(), "app".to_string());
+        theirs.auto_minimize_items.insert("theirs".to_string());
+        theirs.manual_services.insert("Other".to_string());
+        theirs.known_items.insert("shared".to_string(), known("Theirs"));
+        theirs.known_items.insert("theirs".to_string(), known("Theirs only"));
+
+        let merged = ours.merge_preferences(&theirs);
+        assert!(merged.auto_exit_after_minimize);
+        assert_eq!(merged.history_retention_days, 7);
+        assert_eq!(merged.watch_policy, WatchPolicy::Disable);
+        assert_eq!(merged.manual_services, ours.manual_services);
+        assert_eq!(merged.id_scheme, ID_SCHEME);
+
+        // Their settings for an item replace ours as a whole
+        assert!(!merged.auto_minimize_items.contains("shared"));
+        assert!(!merged.minimize_delays.contains_key("shared"));
+        assert_eq!(merged.process_name_mappings.get("shared").map(String::as_str), Some("app"));
+        assert_eq!(merged.known_items["shared"], known("Ours"));
+        // Items only one side has settings for keep them
+        assert!(merged.auto_minimize_items.contains("theirs"));
+        assert_eq!(merged.known_items["theirs"], known("Theirs only"));
+        assert_eq!(merged.minimize_behaviors.get("ours").map(String::as_str), Some("close"));
+    }
+
+    #[test]
+    fn per_item_settings_of_another_id_scheme_are_not_merged() {
+        let ours = AppSettings::default();
+        let mut theirs = AppSettings { id_scheme: 0, auto_exit_after_minimize: true, ..AppSettings::default() };
+        theirs.auto_minimize_items.insert("legacy".to_string());
+
+        let merged = ours.merge_preferences(&theirs);
+        assert!(merged.auto_exit_after_minimize);
+        assert!(merged.auto_minimize_items.is_empty());
+    }
+
+    #[test]
+    fn item_ids_are_migrated_only_after_a_scan_that_found_items() {
+        let item = StartupItem::for_test(SourceType::Registry, "HKEY_CURRENT_USER\\Run", "App", "app.exe", true);
+        let legacy = legacy_item_id(&item);
+        let mut settings = AppSettings { id_scheme: 0, ..AppSettings::default() };
+        settings.auto_minimize_items.insert(legacy.clone());
+        settings.minimize_delays.insert("gone".to_string(), 3);
+
+        assert!(!settings.migrate_item_ids(&[]));
+        assert_eq!(settings.id_scheme, 0);
+        assert!(settings.auto_minimize_items.contains(&legacy));
+
+        assert!(settings.migrate_item_ids(std::slice::from_ref(&item)));
+        assert_eq!(settings.id_scheme, ID_SCHEME);
+        assert!(settings.auto_minimize_items.contains(&item.id));
+        assert!(!settings.auto_minimize_items.contains(&legacy));
+        assert_eq!(settings.minimize_delays.get("gone"), Some(&3));
+
+        // Settings on the current scheme are left alone
+        assert!(!settings.migrate_item_ids(std::slice::from_ref(&item)));
+    }
+}
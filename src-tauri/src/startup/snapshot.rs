@@ -0,0 +1,461 @@
+//! Snapshots of the whole startup configuration.
+//!
+//! A snapshot lists every item with its raw command, location and enabled
+//! state, plus the app settings. For `Run` values and startup folder files it
+//! also keeps the stored value or file and the StartupApproved record, so
+//! those entries can be recreated exactly on another machine. Importing
+//! compares a snapshot with the current state and applies the differences,
+//! or only reports them in a dry run.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use super::manager::{self, EntryContent};
+use super::registry::{system_registry, RegistryBackend, RegistryHive, RegistryValue};
+use super::scanner::{self, startup_folder_hive};
+use super::settings::{self, AppSettings};
+use super::sources::source_for;
+use super::{Scope, SourceType, StartupItem};
+
+/// Format version written into snapshots; newer versions are refused
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StartupSnapshot {
+    pub version: u32,
+    /// Milliseconds since the Unix epoch
+    pub created_at: u64,
+    #[serde(default)]
+    pub machine: Option<String>,
+    pub items: Vec<SnapshotItem>,
+    pub settings: AppSettings,
+}
+
+/// The stored form of a `Run` value or startup folder file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EntryData {
+    RegistryValue { value: RegistryValue },
+    /// Base64 encoded file contents
+    File { data: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotItem {
+    pub source_type: SourceType,
+    pub name: String,
+    /// Registry value, file, task, service or unit name; a startup folder file without a legacy `.disabled`
+    pub entry_name: String,
+    pub source: String,
+    pub source_location: String,
+    /// Which startup folder a folder item is in, as the folder path differs between users
+    #[serde(default)]
+    pub scope: Option<Scope>,
+    pub command: String,
+    pub enabled: bool,
+    /// Raw StartupApproved record, base64 encoded
+    #[serde(default)]
+    pub approval: Option<String>,
+    #[serde(default)]
+    pub data: Option<EntryData>,
+}
+
+impl SnapshotItem {
+    pub fn from_item(item: &StartupItem) -> SnapshotItem {
+        SnapshotItem::read(system_registry(), item)
+    }
+
+    /// An item with its value or file and approval record as `registry` holds them
+    pub fn read(registry: &dyn RegistryBackend, item: &StartupItem) -> SnapshotItem {
+        let (entry_name, scope) = if item.source_type == SourceType::Folder {
+            let all_users = startup_folder_hive(Path::new(&item.source_location)) == RegistryHive::LocalMachine;
+            (
+                item.entry_name.strip_suffix(".disabled").unwrap_or(&item.entry_name).to_string(),
+                Some(if all_users { Scope::AllUsers } else { Scope::CurrentUser }),
+            )
+        } else {
+            (item.entry_name.clone(), None)
+        };
+
+        let state = manager::read_entry_state(registry, item).ok();
+        let data = state.as_ref().map(|(content, _)| match content {
+            EntryContent::Value(value) => EntryData::RegistryValue { value: value.clone() },
+            EntryContent::File(bytes) => EntryData::File { data: encode(bytes) },
+        });
+        let approval = state.and_then(|(_, approval)| approval).map(|bytes| encode(&bytes));
+
+        SnapshotItem {
+            source_type: item.source_type,
+            name: item.name.clone(),
+            entry_name,
+            source: item.source.clone(),
+            source_location: item.source_location.clone(),
+            scope,
+            command: item.command.clone(),
+            enabled: item.enabled,
+            approval,
+            data,
+        }
+    }
+
+    /// Identifies the same entry on another machine
+    fn key(&self) -> (SourceType, String, String) {
+        let location = match self.scope {
+            Some(scope) => format!("{:?}", scope),
+            None => self.source_location.to_lowercase(),
+        };
+        (self.source_type, location, self.entry_name.to_lowercase())
+    }
+
+    fn content(&self) -> Result<Option<EntryContent>, String> {
+        match &self.data {
+            Some(EntryData::RegistryValue { value }) => Ok(Some(EntryContent::Value(value.clone()))),
+            Some(EntryData::File { data }) => decode(data).map(|bytes| Some(EntryContent::File(bytes))),
+            None => Ok(None),
+        }
+    }
+
+    /// The stored value or file with the raw approval record, for writing them back
+    pub fn entry(&self) -> Result<(EntryContent, Option<Vec<u8>>), String> {
+        let content = self.content()?.ok_or_else(|| "快照中没有该启动项的数据".to_string())?;
+        let approval = self.approval.as_deref().map(decode).transpose()?;
+        Ok((content, approval))
+    }
+
+    /// Write the stored value or file and the approval record back
+    pub fn restore(&self) -> Result<(), String> {
+        let (content, approval) = self.entry()?;
+        manager::import_entry(self, &content, approval.as_deref()).map_err(|e| e.to_string())
+    }
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::engine::general_purpose::STANDARD.encode(data)
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .map_err(|e| e.to_string())
+}
+
+/// The current startup configuration and settings
+pub fn create_snapshot() -> StartupSnapshot {
+    let items = scanner::get_all_startup_items()
+        .iter()
+        .map(SnapshotItem::from_item)
+        .collect();
+
+    StartupSnapshot {
+        version: SNAPSHOT_VERSION,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        machine: std::env::var("COMPUTERNAME").or_else(|_| std::env::var("HOSTNAME")).ok(),
+        items,
+        settings: settings::get_settings(),
+    }
+}
+
+/// Write a snapshot into `snapshots` in the config directory, returning the file
+pub fn export_snapshot() -> Result<String, String> {
+    let snapshot = create_snapshot();
+    let dir = settings::get_config_dir().join("snapshots");
+    std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+
+    let path = dir.join(format!("snapshot-{}.json", snapshot.created_at));
+    let content = serde_json::to_string_pretty(&snapshot).map_err(|e| e.to_string())?;
+    std::fs::write(&path, content).map_err(|e| e.to_string())?;
+    Ok(path.to_string_lossy().to_string())
+}
+
+pub fn parse_snapshot(content: &str) -> Result<StartupSnapshot, String> {
+    let snapshot: StartupSnapshot = serde_json::from_str(content).map_err(|e| format!("快照格式无效: {}", e))?;
+    if snapshot.version > SNAPSHOT_VERSION {
+        return Err(format!("快照版本 {} 高于支持的版本 {}", snapshot.version, SNAPSHOT_VERSION));
+    }
+    Ok(snapshot)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// In the snapshot but not on this machine
+    Add,
+    /// The stored value, file or command differs
+    Modify,
+    Enable,
+    Disable,
+    /// On this machine but not in the snapshot, removed when asked to
+    Remove,
+    /// On this machine but not in the snapshot, left alone
+    Extra,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotChange {
+    pub kind: ChangeKind,
+    pub source_type: SourceType,
+    pub name: String,
+    pub source: String,
+    pub entry_name: String,
+    /// Whether the change can be applied here; tasks, services and units can only be switched on and off
+    pub supported: bool,
+    pub applied: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub changes: Vec<SnapshotChange>,
+    pub settings_changed: bool,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ImportOptions {
+    /// Only report the differences
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Delete items that are not in the snapshot
+    #[serde(default)]
+    pub remove_extra: bool,
+}
+
+/// How a change is carried out
+enum Action<'a> {
+    /// Write the stored value or file and the approval record
+    Write(&'a SnapshotItem),
+    Toggle(&'a StartupItem, bool),
+    Delete(&'a StartupItem),
+    None,
+}
+
+fn can(item_type: SourceType, check: impl Fn(&super::sources::SourceCapabilities) -> bool) -> bool {
+    source_for(item_type).map(|source| check(&source.capabilities())).unwrap_or(false)
+}
+
+fn change(kind: ChangeKind, item: &SnapshotItem, supported: bool) -> SnapshotChange {
+    SnapshotChange {
+        kind,
+        source_type: item.source_type,
+        name: item.name.clone(),
+        source: item.source.clone(),
+        entry_name: item.entry_name.clone(),
+        supported,
+        applied: false,
+    }
+}
+
+fn apply(action: &Action) -> Result<(), String> {
+    match action {
+        Action::Write(item) => item.restore(),
+        Action::Toggle(item, enable) => manager::toggle_startup_item(item, *enable).map_err(|e| e.to_string()),
+        Action::Delete(item) => manager::delete_startup_item(item).map_err(|e| e.to_string()),
+        Action::None => Ok(()),
+    }
+}
+
+/// The changes that turn `current` into `wanted`, with how each is carried out
+fn plan_changes<'a>(
+    wanted_items: &'a [SnapshotItem],
+    current: &'a [(SnapshotItem, &'a StartupItem)],
+    remove_extra: bool,
+) -> Vec<(SnapshotChange, Action<'a>)> {
+    let current_by_key: HashMap<_, _> = current.iter().map(|(s, item)| (s.key(), (s, *item))).collect();
+    let wanted_keys: Vec<_> = wanted_items.iter().map(SnapshotItem::key).collect();
+
+    let mut planned: Vec<(SnapshotChange, Action)> = Vec::new();
+
+    for wanted in wanted_items {
+        let Some((existing, item)) = current_by_key.get(&wanted.key()) else {
+            let supported = wanted.data.is_some();
+            let action = if supported { Action::Write(wanted) } else { Action::None };
+            planned.push((change(ChangeKind::Add, wanted, supported), action));
+            continue;
+        };
+
+        let modified = match (&wanted.data, &existing.data) {
+            (Some(wanted_data), Some(existing_data)) => wanted_data != existing_data,
+            _ => wanted.command != existing.command,
+        };
+        if modified {
+            // Writing the entry also writes its approval record, so the enabled state follows
+            let supported = wanted.data.is_some() && existing.data.is_some();
+            let action = if supported { Action::Write(wanted) } else { Action::None };
+            planned.push((change(ChangeKind::Modify, wanted, supported), action));
+            if supported {
+                continue;
+            }
+        }
+
+        if wanted.enabled != existing.enabled {
+            let kind = if wanted.enabled { ChangeKind::Enable } else { ChangeKind::Disable };
+            // A stored record keeps the time the entry was disabled
+            let (supported, action) = if wanted.data.is_some() && existing.data.is_some() {
+                (true, Action::Write(wanted))
+            } else if can(wanted.source_type, |c| c.can_toggle) {
+                (true, Action::Toggle(item, wanted.enabled))
+            } else {
+                (false, Action::None)
+            };
+            planned.push((change(kind, wanted, supported), action));
+        }
+    }
+
+    for (existing, item) in current {
+        if wanted_keys.contains(&existing.key()) {
+            continue;
+        }
+        let planned_change = if remove_extra {
+            let supported = can(existing.source_type, |c| c.can_delete);
+            let action = if supported { Action::Delete(item) } else { Action::None };
+            (change(ChangeKind::Remove, existing, supported), action)
+        } else {
+            (change(ChangeKind::Extra, existing, false), Action::None)
+        };
+        planned.push(planned_change);
+    }
+
+    planned
+}
+
+/// Compare a snapshot with this machine and, unless it is a dry run, apply the differences.
+///
+/// Of the snapshot's settings only the user's preferences are taken, merged
+/// into the current ones; see [`AppSettings::merge_preferences`].
+pub fn import_snapshot(snapshot: &StartupSnapshot, options: ImportOptions) -> ImportReport {
+    let current_items = scanner::get_all_startup_items();
+    let current: Vec<(SnapshotItem, &StartupItem)> = current_items
+        .iter()
+        .map(|item| (SnapshotItem::from_item(item), item))
+        .collect();
+
+    let mut planned = plan_changes(&snapshot.items, &current, options.remove_extra);
+
+    let current_settings = settings::get_settings();
+    let imported_settings = current_settings.merge_preferences(&snapshot.settings);
+    let settings_changed = serde_json::to_value(&imported_settings).ok() != serde_json::to_value(&current_settings).ok();
+
+    let mut errors = Vec::new();
+    if !options.dry_run {
+        for (change, action) in planned.iter_mut() {
+            if !change.supported {
+                continue;
+            }
+            match apply(action) {
+                Ok(()) => change.applied = true,
+                Err(e) => errors.push(format!("{}: {}", change.name, e)),
+            }
+        }
+        if settings_changed
+            && let Err(e) = settings::save_settings(&imported_settings)
+        {
+            errors.push(format!("设置: {}", e));
+        }
+    }
+
+    ImportReport {
+        dry_run: options.dry_run,
+        changes: planned.into_iter().map(|(change, _)| change).collect(),
+        settings_changed,
+        errors,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::registry::RegValueType;
+
+    const RUN: &str = r"HKEY_CURRENT_USER\Software\Microsoft\Windows\CurrentVersion\Run";
+
+    fn value(command: &str) -> Option<EntryData> {
+        Some(EntryData::RegistryValue { value: RegistryValue::from_string(command, RegValueType::Sz) })
+    }
+
+    fn snapshot_item(source_type: SourceType, entry_name: &str, command: &str, enabled: bool, data: Option<EntryData>) -> SnapshotItem {
+        SnapshotItem {
+            source_type,
+            name: entry_name.to_string(),
+            entry_name: entry_name.to_string(),
+            source: format!("{:?}", source_type),
+            source_location: RUN.to_string(),
+            scope: None,
+            command: command.to_string(),
+            enabled,
+            approval: None,
+            data,
+        }
+    }
+
+    fn summary(planned: &[(SnapshotChange, Action)]) -> Vec<(ChangeKind, String, bool)> {
+        planned
+            .iter()
+            .map(|(change, _)| (change.kind, change.entry_name.clone(), change.supported))
+            .collect()
+    }
+
+    #[test]
+    fn refuses_snapshots_from_newer_versions() {
+        let snapshot = StartupSnapshot {
+            version: SNAPSHOT_VERSION,
+            created_at: 0,
+            machine: None,
+            items: vec![snapshot_item(SourceType::Registry, "App", "app.exe", true, value("app.exe"))],
+            settings: AppSettings::default(),
+        };
+        let content = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(parse_snapshot(&content).unwrap().items.len(), 1);
+
+        let newer = content.replacen(&format!("\"version\":{}", SNAPSHOT_VERSION), "\"version\":99", 1);
+        assert!(parse_snapshot(&newer).unwrap_err().contains("99"));
+        assert!(parse_snapshot("{}").is_err());
+    }
+
+    #[test]
+    fn plans_the_differences() {
+        let current_items = [
+            StartupItem::for_test(SourceType::Registry, RUN, "Changed", "old.exe", true),
+            StartupItem::for_test(SourceType::Registry, RUN, "Same", "same.exe", true),
+            StartupItem::for_test(SourceType::Service, RUN, "Svc", "svc.exe", true),
+            StartupItem::for_test(SourceType::Registry, RUN, "Local", "local.exe", true),
+        ];
+        let current: Vec<(SnapshotItem, &StartupItem)> = current_items
+            .iter()
+            .map(|item| {
+                let data = (item.source_type == SourceType::Registry).then(|| value(&item.command)).flatten();
+                (snapshot_item(item.source_type, &item.entry_name, &item.command, item.enabled, data), item)
+            })
+            .collect();
+        let wanted = [
+            snapshot_item(SourceType::Registry, "changed", "new.exe", true, value("new.exe")),
+            snapshot_item(SourceType::Registry, "Same", "same.exe", true, value("same.exe")),
+            snapshot_item(SourceType::Service, "Svc", "svc.exe", false, None),
+            snapshot_item(SourceType::Registry, "New", "new.exe", true, value("new.exe")),
+            snapshot_item(SourceType::ScheduledTask, "Task", "task.exe", true, None),
+        ];
+
+        let planned = plan_changes(&wanted, &current, false);
+        assert_eq!(
+            summary(&planned),
+            [
+                (ChangeKind::Modify, "changed".to_string(), true),
+                (ChangeKind::Disable, "Svc".to_string(), true),
+                (ChangeKind::Add, "New".to_string(), true),
+                (ChangeKind::Add, "Task".to_string(), false),
+                (ChangeKind::Extra, "Local".to_string(), false),
+            ]
+        );
+        assert!(matches!(planned[1].1, Action::Toggle(item, false) if item.entry_name == "Svc"));
+        assert!(planned.iter().all(|(change, _)| !change.applied));
+
+        let planned = plan_changes(&wanted, &current, true);
+        assert_eq!(summary(&planned).last(), Some(&(ChangeKind::Remove, "Local".to_string(), true)));
+    }
+}
@@ -0,0 +1,372 @@
+//! The places startup items come from, behind one interface.
+//!
+//! Each source scans its own items and carries out operations on them. The
+//! scanner and the manager only walk `SOURCES`, so a new source is added by
+//! implementing `StartupSource` and listing it there.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::item_id::item_id;
+use super::manager::{self, StartupError};
+use super::registry::system_registry;
+use super::scanner::{self, ScanContext};
+use super::{settings, systemd, NewStartupItem, SourceType, StartupItem};
+
+/// Operations a source supports, so the UI can offer only those
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceCapabilities {
+    pub can_toggle: bool,
+    pub can_delete: bool,
+    pub can_create: bool,
+    pub can_edit: bool,
+}
+
+pub trait StartupSource: Sync {
+    fn source_type(&self) -> SourceType;
+
+    fn capabilities(&self) -> SourceCapabilities;
+
+    fn scan(&self, ctx: &ScanContext) -> Vec<StartupItem>;
+
+    /// The item this source lists for the entry `entry_name` at `source_location`
+    fn find(&self, ctx: &ScanContext, source_location: &str, entry_name: &str) -> Option<StartupItem> {
+        let id = item_id(self.source_type(), source_location, entry_name);
+        self.scan(ctx).into_iter().find(|item| item.id == id)
+    }
+
+    fn toggle(&self, item: &StartupItem, enable: bool) -> Result<(), StartupError>;
+
+    fn delete(&self, item: &StartupItem) -> Result<(), StartupError>;
+
+    fn create(&self, _new_item: &NewStartupItem) -> Result<StartupItem, StartupError> {
+        Err(StartupError::NotSupported)
+    }
+
+    /// Rewrite an item as `target` describes, possibly moving it to another source
+    fn update(&self, _item: &StartupItem, _target: &NewStartupItem) -> Result<StartupItem, StartupError> {
+        Err(StartupError::NotSupported)
+    }
+}
+
+/// The item a source lists for an entry it has just written
+fn find_created(source: &dyn StartupSource, source_location: &str, entry_name: &str) -> Result<StartupItem, StartupError> {
+    source
+        .find(&ScanContext::system(), source_location, entry_name)
+        .ok_or(StartupError::NotFound)
+}
+
+/// Update a `Run` value or startup folder file, which may move between the two
+fn update_entry(item: &StartupItem, target: &NewStartupItem) -> Result<StartupItem, StartupError> {
+    let destination = source_for(target.source_type).ok_or(StartupError::NotSupported)?;
+    let (source_location, entry_name) = manager::update_entry(system_registry(), item, target)?;
+    find_created(destination, &source_location, &entry_name)
+}
+
+pub struct RegistrySource;
+
+impl StartupSource for RegistrySource {
+    fn source_type(&self) -> SourceType {
+        SourceType::Registry
+    }
+
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities { can_toggle: true, can_delete: true, can_create: true, can_edit: true }
+    }
+
+    fn scan(&self, ctx: &ScanContext) -> Vec<StartupItem> {
+        scanner::scan_registry_items(ctx)
+    }
+
+    fn find(&self, ctx: &ScanContext, source_location: &str, entry_name: &str) -> Option<StartupItem> {
+        scanner::scan_registry_entry(ctx, source_location, entry_name)
+    }
+
+    fn toggle(&self, item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+        manager::toggle_registry_item(system_registry(), item, enable)
+    }
+
+    fn delete(&self, item: &StartupItem) -> Result<(), StartupError> {
+        manager::delete_registry_item(system_registry(), item)
+    }
+
+    fn create(&self, new_item: &NewStartupItem) -> Result<StartupItem, StartupError> {
+        let (source_location, entry_name) = manager::create_registry_item(system_registry(), new_item)?;
+        find_created(self, &source_location, &entry_name)
+    }
+
+    fn update(&self, item: &StartupItem, target: &NewStartupItem) -> Result<StartupItem, StartupError> {
+        update_entry(item, target)
+    }
+}
+
+pub struct StartupFolderSource;
+
+impl StartupSource for StartupFolderSource {
+    fn source_type(&self) -> SourceType {
+        SourceType::Folder
+    }
+
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities { can_toggle: true, can_delete: true, can_create: true, can_edit: true }
+    }
+
+    fn scan(&self, ctx: &ScanContext) -> Vec<StartupItem> {
+        scanner::scan_startup_folder_items(ctx)
+    }
+
+    fn find(&self, ctx: &ScanContext, source_location: &str, entry_name: &str) -> Option<StartupItem> {
+        scanner::scan_startup_folder_file(ctx, Path::new(source_location), entry_name)
+    }
+
+    fn toggle(&self, item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+        manager::toggle_folder_item(system_registry(), item, enable)
+    }
+
+    fn delete(&self, item: &StartupItem) -> Result<(), StartupError> {
+        manager::delete_folder_item(system_registry(), item)
+    }
+
+    fn create(&self, new_item: &NewStartupItem) -> Result<StartupItem, StartupError> {
+        let (source_location, entry_name) = manager::create_folder_item(system_registry(), new_item)?;
+        find_created(self, &source_location, &entry_name)
+    }
+
+    fn update(&self, item: &StartupItem, target: &NewStartupItem) -> Result<StartupItem, StartupError> {
+        update_entry(item, target)
+    }
+}
+
+pub struct ScheduledTaskSource;
+
+impl StartupSource for ScheduledTaskSource {
+    fn source_type(&self) -> SourceType {
+        SourceType::ScheduledTask
+    }
+
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities { can_toggle: true, can_delete: true, can_create: false, can_edit: false }
+    }
+
+    fn scan(&self, ctx: &ScanContext) -> Vec<StartupItem> {
+        scanner::scan_scheduled_task_items(ctx)
+    }
+
+    fn toggle(&self, item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+        manager::toggle_task_item(item, enable)
+    }
+
+    fn delete(&self, item: &StartupItem) -> Result<(), StartupError> {
+        manager::delete_task_item(item)
+    }
+}
+
+pub struct ServiceSource;
+
+impl StartupSource for ServiceSource {
+    fn source_type(&self) -> SourceType {
+        SourceType::Service
+    }
+
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities { can_toggle: true, can_delete: false, can_create: false, can_edit: false }
+    }
+
+    fn scan(&self, ctx: &ScanContext) -> Vec<StartupItem> {
+        scanner::scan_service_items(ctx)
+    }
+
+    fn toggle(&self, item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+        manager::toggle_service_item(system_registry(), item, enable)?;
+        // Keep listing the service after it stops being Automatic
+        settings::set_service_switched_to_manual(&item.entry_name, !enable).map_err(StartupError::IoError)
+    }
+
+    fn delete(&self, _item: &StartupItem) -> Result<(), StartupError> {
+        // Removing a service key leaves a broken service behind; that is a job for sc.exe
+        Err(StartupError::NotSupported)
+    }
+}
+
+pub struct XdgAutostartSource;
+
+impl StartupSource for XdgAutostartSource {
+    fn source_type(&self) -> SourceType {
+        SourceType::XdgAutostart
+    }
+
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities { can_toggle: true, can_delete: true, can_create: false, can_edit: false }
+    }
+
+    fn scan(&self, ctx: &ScanContext) -> Vec<StartupItem> {
+        scanner::scan_xdg_autostart_items(ctx)
+    }
+
+    fn toggle(&self, item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+        manager::toggle_xdg_item(item, enable)
+    }
+
+    fn delete(&self, item: &StartupItem) -> Result<(), StartupError> {
+        manager::delete_xdg_item(item)
+    }
+}
+
+pub struct SystemdUserSource;
+
+impl StartupSource for SystemdUserSource {
+    fn source_type(&self) -> SourceType {
+        SourceType::SystemdUser
+    }
+
+    fn capabilities(&self) -> SourceCapabilities {
+        SourceCapabilities { can_toggle: true, can_delete: true, can_create: false, can_edit: false }
+    }
+
+    fn scan(&self, ctx: &ScanContext) -> Vec<StartupItem> {
+        scanner::scan_systemd_user_items(ctx)
+    }
+
+    fn toggle(&self, item: &StartupItem, enable: bool) -> Result<(), StartupError> {
+        manager::toggle_systemd_item(&systemd::user_unit_paths(), item, enable)
+    }
+
+    fn delete(&self, item: &StartupItem) -> Result<(), StartupError> {
+        manager::delete_systemd_item(&systemd::user_unit_paths(), item)
+    }
+}
+
+/// Every known source, in the order their items are collected
+pub static SOURCES: &[&dyn StartupSource] = &[
+    &RegistrySource,
+    &StartupFolderSource,
+    &ScheduledTaskSource,
+    &ServiceSource,
+    &XdgAutostartSource,
+    &SystemdUserSource,
+];
+
+pub fn source_for(source_type: SourceType) -> Option<&'static dyn StartupSource> {
+    SOURCES.iter().copied().find(|source| source.source_type() == source_type)
+}
+
+/// A source and what it can do, as reported to the UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceInfo {
+    pub source_type: SourceType,
+    pub capabilities: SourceCapabilities,
+}
+
+pub fn describe_sources() -> Vec<SourceInfo> {
+    SOURCES
+        .iter()
+        .map(|source| SourceInfo {
+            source_type: source.source_type(),
+            capabilities: source.capabilities(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::registry::{MemoryRegistry, RegValueType, RegistryBackend, RegistryHive, RegistryValue};
+    use crate::startup::testdir::TestDir;
+
+    const ALL: [SourceType; 6] = [
+        SourceType::Registry,
+        SourceType::Folder,
+        SourceType::ScheduledTask,
+        SourceType::Service,
+        SourceType::XdgAutostart,
+        SourceType::SystemdUser,
+    ];
+
+    #[test]
+    fn every_source_type_has_one_source() {
+        for source_type in ALL {
+            assert_eq!(source_for(source_type).unwrap().source_type(), source_type);
+            assert_eq!(SOURCES.iter().filter(|source| source.source_type() == source_type).count(), 1);
+        }
+        assert_eq!(SOURCES.len(), ALL.len());
+
+        let described: Vec<SourceType> = describe_sources().iter().map(|info| info.source_type).collect();
+        assert_eq!(described, ALL);
+    }
+
+    #[test]
+    fn capabilities_per_source() {
+        let capabilities = |source_type| {
+            let c = source_for(source_type).unwrap().capabilities();
+            (c.can_toggle, c.can_delete, c.can_create, c.can_edit)
+        };
+        assert_eq!(capabilities(SourceType::Registry), (true, true, true, true));
+        assert_eq!(capabilities(SourceType::Folder), (true, true, true, true));
+        assert_eq!(capabilities(SourceType::ScheduledTask), (true, true, false, false));
+        // Removing a service key would leave a broken service behind
+        assert_eq!(capabilities(SourceType::Service), (true, false, false, false));
+        assert_eq!(capabilities(SourceType::XdgAutostart), (true, true, false, false));
+        assert_eq!(capabilities(SourceType::SystemdUser), (true, true, false, false));
+
+        // Sources that cannot create or edit say so
+        let new_item = NewStartupItem {
+            source_type: SourceType::Service,
+            scope: crate::startup::Scope::CurrentUser,
+            name: "App".to_string(),
+            path: "app.exe".to_string(),
+            arguments: None,
+            working_directory: None,
+        };
+        let item = StartupItem::for_test(SourceType::Service, "", "App", "app.exe", true);
+        let service = source_for(SourceType::Service).unwrap();
+        assert!(matches!(service.create(&new_item), Err(StartupError::NotSupported)));
+        assert!(matches!(service.update(&item, &new_item), Err(StartupError::NotSupported)));
+    }
+
+    #[test]
+    fn finds_single_entries() {
+        let run = r"Software\Microsoft\Windows\CurrentVersion\Run";
+        let registry = MemoryRegistry::new();
+        registry.create_subkey(RegistryHive::CurrentUser, run).unwrap();
+        for name in ["App", "Other"] {
+            let command = RegistryValue::from_string(&format!(r"C:\{0}\{0}.exe", name), RegValueType::Sz);
+            registry.set_raw_value(RegistryHive::CurrentUser, run, name, &command).unwrap();
+        }
+        let dir = TestDir::new("sources");
+        dir.write("App.bat", "@echo off\r\n");
+        dir.write("notes.txt", "");
+        let mut ctx = ScanContext::with_registry(&registry);
+        ctx.user_startup_folder = Some(dir.path().to_path_buf());
+
+        let location = format!(r"HKEY_CURRENT_USER\{}", run);
+        let item = RegistrySource.find(&ctx, &location, "app").unwrap();
+        assert_eq!((item.entry_name.as_str(), item.command.as_str()), ("App", r"C:\App\App.exe"));
+        assert_eq!(item.id, item_id(SourceType::Registry, &location, "App"));
+        assert!(RegistrySource.find(&ctx, &location, "Missing").is_none());
+        assert!(RegistrySource.find(&ctx, r"HKEY_CURRENT_USER\Software\Elsewhere", "App").is_none());
+
+        let folder = dir.path().to_string_lossy().to_string();
+        let item = StartupFolderSource.find(&ctx, &folder, "App.bat").unwrap();
+        assert_eq!(item.id, item_id(SourceType::Folder, &folder, "App.bat"));
+        assert!(StartupFolderSource.find(&ctx, &folder, "notes.txt").is_none());
+        assert!(StartupFolderSource.find(&ctx, &folder, "Missing.bat").is_none());
+        assert!(StartupFolderSource.find(&ctx, &dir.join("other").to_string_lossy(), "App.bat").is_none());
+    }
+
+    #[test]
+    fn other_sources_find_items_by_id() {
+        let registry = MemoryRegistry::new();
+        let dir = TestDir::new("sources-xdg");
+        dir.write("App.desktop", "[Desktop Entry]\nType=Application\nName=App\nExec=app\n");
+        let mut ctx = ScanContext::with_registry(&registry);
+        ctx.xdg_user_autostart = Some(dir.path().to_path_buf());
+
+        let scanned = XdgAutostartSource.scan(&ctx);
+        assert_eq!(scanned.len(), 1);
+        let item = XdgAutostartSource.find(&ctx, &scanned[0].source_location, "App.desktop").unwrap();
+        assert_eq!(item.id, scanned[0].id);
+        // XDG file names keep their case
+        assert!(XdgAutostartSource.find(&ctx, &scanned[0].source_location, "app.desktop").is_none());
+    }
+}
@@ -0,0 +1,531 @@
+//! `systemd --user` services started with the login session.
+//!
+//! A unit is enabled by a symlink in `<target>.wants/` (or `.requires/`) of one
+//! of the user unit directories, pointing at the unit file. Enabling and
+//! disabling create and remove those links the way `systemctl --user enable`
+//! does; the change takes effect at the next login.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Targets reached when a user session starts
+pub const LOGIN_TARGETS: &[&str] = &["default.target", "graphical-session.target"];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitFile {
+    pub description: Option<String>,
+    /// First `ExecStart=` of `[Service]`, without the `-@+!:` prefixes
+    pub exec_start: Option<String>,
+    /// Targets listed by `WantedBy=`
+    pub wanted_by: Vec<String>,
+    /// Targets listed by `RequiredBy=`
+    pub required_by: Vec<String>,
+    /// Other names the unit is linked under when enabled
+    pub alias: Vec<String>,
+}
+
+impl UnitFile {
+    pub fn from_file(path: &Path) -> io::Result<UnitFile> {
+        let data = std::fs::read(path)?;
+        Ok(UnitFile::parse(&String::from_utf8_lossy(&data)))
+    }
+
+    pub fn parse(text: &str) -> UnitFile {
+        let mut unit = UnitFile::default();
+        let mut section = String::new();
+
+        for line in logical_lines(text) {
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].to_string();
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            match (section.as_str(), key) {
+                ("Unit", "Description") => unit.description = Some(value.to_string()),
+                ("Service", "ExecStart") if unit.exec_start.is_none() && !value.is_empty() => {
+                    unit.exec_start = Some(value.trim_start_matches(['-', '@', '+', '!', ':']).to_string());
+                }
+                // An empty assignment resets the list, as in drop-ins
+                ("Install", "WantedBy") => extend_list(&mut unit.wanted_by, value),
+                ("Install", "RequiredBy") => extend_list(&mut unit.required_by, value),
+                ("Install", "Alias") => extend_list(&mut unit.alias, value),
+                _ => {}
+            }
+        }
+
+        unit
+    }
+
+    /// Whether enabling the unit hooks it into a login target
+    pub fn starts_at_login(&self) -> bool {
+        self.wanted_by
+            .iter()
+            .chain(self.required_by.iter())
+            .any(|target| LOGIN_TARGETS.contains(&target.as_str()))
+    }
+
+    /// The program of `ExecStart`, with `%h` expanded and searched in `PATH` when it has no directory
+    pub fn program(&self) -> Option<String> {
+        let exec = self.exec_start.as_deref()?;
+        let program = exec.split_whitespace().next()?.trim_matches('"');
+        let program = expand_home_specifier(program);
+        if program.contains('/') {
+            return Some(program);
+        }
+
+        let found = std::env::var_os("PATH").and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(&program))
+                .find(|candidate| candidate.is_file())
+        });
+        Some(found.map(|p| p.to_string_lossy().to_string()).unwrap_or(program))
+    }
+
+    pub fn command_line(&self) -> Option<String> {
+        self.exec_start.as_deref().map(expand_home_specifier)
+    }
+}
+
+/// Lines with continuations joined and comments dropped
+fn logical_lines(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if current.is_empty() && (line.is_empty() || line.starts_with('#') || line.starts_with(';')) {
+            continue;
+        }
+        match line.strip_suffix('\\') {
+            Some(start) => {
+                current.push_str(start.trim_end());
+                current.push(' ');
+            }
+            None => {
+                current.push_str(line);
+                lines.push(std::mem::take(&mut current));
+            }
+        }
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+fn extend_list(list: &mut Vec<String>, value: &str) {
+    if value.is_empty() {
+        list.clear();
+    } else {
+        list.extend(value.split_whitespace().map(str::to_string));
+    }
+}
+
+fn expand_home_specifier(value: &str) -> String {
+    match std::env::var("HOME") {
+        Ok(home) => value.replace("%h", &home),
+        Err(_) => value.to_string(),
+    }
+}
+
+/// `foo@bar.service` -> `foo@.service`, the file instances are created from
+fn template_name(unit_name: &str) -> Option<String> {
+    let (prefix, rest) = unit_name.split_once('@')?;
+    let suffix = rest.rsplit_once('.').map(|(_, suffix)| suffix)?;
+    (!rest.starts_with('.')).then(|| format!("{}@.{}", prefix, suffix))
+}
+
+/// User unit directories in order of priority. The first one is the user's
+/// own configuration, where `systemctl --user enable` puts its links.
+pub fn user_unit_paths() -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let env_dir = |name: &str, fallback: Option<PathBuf>| {
+        std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or(fallback)
+    };
+
+    let mut paths = Vec::new();
+    if let Some(config_home) = env_dir("XDG_CONFIG_HOME", home.as_ref().map(|h| h.join(".config"))) {
+        paths.push(config_home.join("systemd/user"));
+    }
+    paths.push(PathBuf::from("/etc/systemd/user"));
+    if let Some(data_home) = env_dir("XDG_DATA_HOME", home.as_ref().map(|h| h.join(".local/share"))) {
+        paths.push(data_home.join("systemd/user"));
+    }
+
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    paths.extend(
+        data_dirs
+            .split(':')
+            .filter(|d| !d.is_empty())
+            .map(|d| PathBuf::from(d).join("systemd/user")),
+    );
+
+    paths.push(PathBuf::from("/usr/local/lib/systemd/user"));
+    paths.push(PathBuf::from("/usr/lib/systemd/user"));
+    paths.dedup();
+    paths
+}
+
+/// A masked unit is linked to `/dev/null`
+fn is_masked(path: &Path) -> bool {
+    std::fs::read_link(path).map(|target| target == Path::new("/dev/null")).unwrap_or(false)
+}
+
+/// The file a unit name loads from: the first search path that has it, falling
+/// back to the template for instances
+pub fn find_unit_file(paths: &[PathBuf], unit_name: &str) -> Option<PathBuf> {
+    let lookup = |name: &str| {
+        paths
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.exists() || is_masked(candidate))
+    };
+
+    let found = lookup(unit_name).or_else(|| template_name(unit_name).and_then(|t| lookup(&t)))?;
+    (!is_masked(&found)).then_some(found)
+}
+
+/// Names of the units linked into a target's `.wants/` or `.requires/` directory in any search path
+pub fn units_wanted_by(paths: &[PathBuf], target: &str) -> Vec<String> {
+    let mut names = Vec::new();
+
+    for dir in paths {
+        for suffix in ["wants", "requires"] {
+            let Ok(entries) = std::fs::read_dir(dir.join(format!("{}.{}", target, suffix))) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+
+    names
+}
+
+/// Service units that start at login: those linked into a login target, plus
+/// those whose `[Install]` section would link them there, so they can be enabled.
+/// Returns (unit name, unit file, enabled).
+pub fn find_login_units(paths: &[PathBuf]) -> Vec<(String, PathBuf, bool)> {
+    let mut units: Vec<(String, PathBuf, bool)> = Vec::new();
+
+    for target in LOGIN_TARGETS {
+        for name in units_wanted_by(paths, target) {
+            if !name.ends_with(".service") || units.iter().any(|(n, _, _)| *n == name) {
+                continue;
+            }
+            if let Some(file) = find_unit_file(paths, &name) {
+                units.push((name, file, true));
+            }
+        }
+    }
+
+    for dir in paths {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        // Symlinks are aliases or masks, not units of their own
+        let mut names: Vec<String> = entries
+            .flatten()
+            .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".service") && !name.contains("@."))
+            .collect();
+        names.sort();
+
+        for name in names {
+            if units.iter().any(|(n, _, _)| *n == name) {
+                continue;
+            }
+            // Only the file that takes effect counts; a masked unit never starts
+            let Some(file) = find_unit_file(paths, &name) else {
+                continue;
+            };
+            if UnitFile::from_file(&file).map(|unit| unit.starts_at_login()).unwrap_or(false) {
+                units.push((name, file, false));
+            }
+        }
+    }
+
+    units
+}
+
+#[cfg(unix)]
+pub fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(not(unix))]
+pub fn symlink(_original: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links need a Unix system"))
+}
+
+/// Every path `enable_unit` or `disable_unit` may create or remove for a unit
+pub fn unit_link_paths(paths: &[PathBuf], unit_name: &str, unit_file: &Path) -> Vec<PathBuf> {
+    let unit = UnitFile::from_file(unit_file).unwrap_or_default();
+    let mut links: Vec<PathBuf> = Vec::new();
+
+    if let Some(config_dir) = paths.first() {
+        links.extend(unit.wanted_by.iter().map(|t| config_dir.join(format!("{}.wants", t)).join(unit_name)));
+        links.extend(unit.required_by.iter().map(|t| config_dir.join(format!("{}.requires", t)).join(unit_name)));
+    }
+
+    for dir in paths {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.ends_with(".wants") || name.ends_with(".requires") {
+                    links.push(entry.path().join(unit_name));
+                }
+            }
+        }
+        links.extend(unit.alias.iter().map(|alias| dir.join(alias)));
+    }
+
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// Link a unit into the targets of its `[Install]` section and under its aliases,
+/// in the user's configuration directory (`config_dir`)
+pub fn enable_unit(config_dir: &Path, unit_name: &str, unit_file: &Path) -> io::Result<()> {
+    let unit = UnitFile::from_file(unit_file)?;
+    let mut links: Vec<PathBuf> = Vec::new();
+
+    for target in &unit.wanted_by {
+        links.push(config_dir.join(format!("{}.wants", target)).join(unit_name));
+    }
+    for target in &unit.required_by {
+        links.push(config_dir.join(format!("{}.requires", target)).join(unit_name));
+    }
+    for alias in &unit.alias {
+        links.push(config_dir.join(alias));
+    }
+
+    if links.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unit has no [Install] section"));
+    }
+
+    for link in links {
+        if link.symlink_metadata().is_ok() {
+            continue;
+        }
+        if let Some(parent) = link.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        symlink(unit_file, &link)?;
+    }
+
+    Ok(())
+}
+
+/// Remove every `.wants/` and `.requires/` link of a unit and its aliases.
+///
+/// Links outside the user's configuration (`paths[0]`) need root and fail with
+/// `PermissionDenied`. They are tried first, and links already removed are put
+/// back on failure, so the unit is never left linked into only some targets.
+pub fn disable_unit(paths: &[PathBuf], unit_name: &str, unit_file: &Path) -> io::Result<()> {
+    let aliases = UnitFile::from_file(unit_file).map(|unit| unit.alias).unwrap_or_default();
+    let mut links: Vec<PathBuf> = Vec::new();
+
+    for dir in paths {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name.ends_with(".wants") || name.ends_with(".requires") {
+                let link = entry.path().join(unit_name);
+                if link.symlink_metadata().is_ok() {
+                    links.push(link);
+                }
+            }
+        }
+
+        for alias in &aliases {
+            let link = dir.join(alias);
+            if link.symlink_metadata().map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+                links.push(link);
+            }
+        }
+    }
+
+    let config_dir = paths.first();
+    links.sort_by_key(|link| config_dir.is_some_and(|dir| link.starts_with(dir)));
+
+    let mut removed: Vec<(PathBuf, PathBuf)> = Vec::new();
+    for link in links {
+        let target = std::fs::read_link(&link);
+        if let Err(e) = std::fs::remove_file(&link) {
+            for (link, target) in removed.iter().rev() {
+                let _ = symlink(target, link);
+            }
+            return Err(io::Error::new(e.kind(), format!("{}: {}", link.display(), e)));
+        }
+        if let Ok(target) = target {
+            removed.push((link, target));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::testdir::TestDir;
+
+    const SYNC: &str = "\
+[Unit]
+Description=File sync
+
+[Service]
+# Comments and blank lines are skipped
+ExecStart=-/usr/bin/sync-daemon \\
+    --tray \\
+    --verbose
+ExecStart=/usr/bin/ignored
+
+[Install]
+WantedBy=multi-user.target
+WantedBy=
+WantedBy=default.target graphical-session.target
+RequiredBy=sync.target
+Alias=filesync.service
+";
+
+    const INSTALLABLE: &str = "[Service]\nExecStart=/usr/bin/app\n\n[Install]\nWantedBy=default.target\n";
+
+    #[test]
+    fn parses_unit_files() {
+        let unit = UnitFile::parse(SYNC);
+        assert_eq!(unit.description.as_deref(), Some("File sync"));
+        assert_eq!(unit.exec_start.as_deref(), Some("/usr/bin/sync-daemon --tray --verbose"));
+        // The empty assignment dropped multi-user.target
+        assert_eq!(unit.wanted_by, ["default.target", "graphical-session.target"]);
+        assert_eq!(unit.required_by, ["sync.target"]);
+        assert_eq!(unit.alias, ["filesync.service"]);
+        assert!(unit.starts_at_login());
+        assert_eq!(unit.program().as_deref(), Some("/usr/bin/sync-daemon"));
+    }
+
+    #[test]
+    fn strips_exec_start_prefixes() {
+        for prefix in ["", "-", "@", "+", "!", "!!", ":", "-+"] {
+            let unit = UnitFile::parse(&format!("[Service]\nExecStart={}/usr/bin/app --x\n", prefix));
+            assert_eq!(unit.exec_start.as_deref(), Some("/usr/bin/app --x"), "prefix {:?}", prefix);
+        }
+        // Keys outside their section do not count
+        let unit = UnitFile::parse("[Unit]\nExecStart=/usr/bin/app\nWantedBy=default.target\n");
+        assert_eq!(unit, UnitFile::default());
+    }
+
+    #[test]
+    fn maps_instances_to_templates() {
+        assert_eq!(template_name("app@work.service").as_deref(), Some("app@.service"));
+        assert_eq!(template_name("app@.service"), None);
+        assert_eq!(template_name("app.service"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn finds_enabled_and_installable_login_units() {
+        let dir = TestDir::new("systemd-login");
+        let (config, vendor) = (dir.join("config"), dir.join("vendor"));
+        let enabled = dir.write("vendor/enabled.service", "[Service]\nExecStart=/usr/bin/enabled\n");
+        let installable = dir.write("vendor/installable.service", INSTALLABLE);
+        dir.write("vendor/server.service", "[Service]\nExecStart=/usr/bin/server\n\n[Install]\nWantedBy=multi-user.target\n");
+        dir.write("vendor/masked.service", INSTALLABLE);
+        dir.write("vendor/instance@.service", INSTALLABLE);
+        std::fs::create_dir_all(config.join("default.target.wants")).unwrap();
+        symlink(&enabled, &config.join("default.target.wants/enabled.service")).unwrap();
+        symlink(Path::new("/dev/null"), &config.join("masked.service")).unwrap();
+
+        let paths = [config, vendor];
+        assert_eq!(find_unit_file(&paths, "masked.service"), None);
+        assert_eq!(find_unit_file(&paths, "instance@work.service"), Some(dir.join("vendor/instance@.service")));
+        assert_eq!(
+            find_login_units(&paths),
+            [
+                ("enabled.service".to_string(), enabled, true),
+                ("installable.service".to_string(), installable, false),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn enable_and_disable_unit_manage_links() {
+        let dir = TestDir::new("systemd-toggle");
+        let config = dir.join("config");
+        let unit_file = dir.write("vendor/sync.service", SYNC);
+        let paths = [config.clone(), dir.join("vendor")];
+
+        enable_unit(&config, "sync.service", &unit_file).unwrap();
+        let links = [
+            config.join("default.target.wants/sync.service"),
+            config.join("graphical-session.target.wants/sync.service"),
+            config.join("sync.target.requires/sync.service"),
+            config.join("filesync.service"),
+        ];
+        for link in &links {
+            assert_eq!(std::fs::read_link(link).unwrap(), unit_file);
+        }
+        assert_eq!(find_login_units(&paths), [("sync.service".to_string(), unit_file.clone(), true)]);
+        // Enabling again keeps the links as they are
+        enable_unit(&config, "sync.service", &unit_file).unwrap();
+
+        // A link shipped by the vendor is removed as well
+        std::fs::create_dir_all(dir.join("vendor/default.target.wants")).unwrap();
+        symlink(&unit_file, &dir.join("vendor/default.target.wants/sync.service")).unwrap();
+
+        disable_unit(&paths, "sync.service", &unit_file).unwrap();
+        for link in links.iter().chain([&dir.join("vendor/default.target.wants/sync.service")]) {
+            assert!(link.symlink_metadata().is_err(), "{} left behind", link.display());
+        }
+        assert_eq!(find_login_units(&paths), [("sync.service".to_string(), unit_file, false)]);
+    }
+
+    #[test]
+    fn enable_unit_needs_an_install_section() {
+        let dir = TestDir::new("systemd-no-install");
+        let unit_file = dir.write("vendor/plain.service", "[Service]\nExecStart=/usr/bin/plain\n");
+        let error = enable_unit(&dir.join("config"), "plain.service", &unit_file).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn disable_unit_keeps_every_link_when_one_cannot_be_removed() {
+        let dir = TestDir::new("systemd-partial");
+        let unit_file = dir.write("vendor/app.service", INSTALLABLE);
+        let user_link = dir.join("config/default.target.wants/app.service");
+        let etc_link = dir.join("etc/graphical-session.target.wants/app.service");
+        for link in [&user_link, &etc_link] {
+            std::fs::create_dir_all(link.parent().unwrap()).unwrap();
+            symlink(&unit_file, link).unwrap();
+        }
+        // Stands in for a link the user may not remove
+        std::fs::create_dir_all(dir.join("vendor/default.target.wants/app.service")).unwrap();
+
+        let paths = [dir.join("config"), dir.join("etc"), dir.join("vendor")];
+        assert!(disable_unit(&paths, "app.service", &unit_file).is_err());
+        assert_eq!(std::fs::read_link(&user_link).unwrap(), unit_file);
+        assert_eq!(std::fs::read_link(&etc_link).unwrap(), unit_file);
+    }
+}
@@ -0,0 +1,570 @@
+//! Task Scheduler definitions, as stored in the XML files under `System32\Tasks`.
+//!
+//! The files are usually UTF-16LE with a BOM. Parsing and editing work on the
+//! bytes alone, so neither needs the Task Scheduler service.
+
+use std::path::{Path, PathBuf};
+
+use quick_xml::Reader;
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::Event;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskError {
+    Io(String),
+    InvalidEncoding,
+    Xml(String),
+}
+
+impl std::fmt::Display for TaskError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskError::Io(msg) => write!(f, "读取计划任务失败: {}", msg),
+            TaskError::InvalidEncoding => write!(f, "计划任务文件编码无效"),
+            TaskError::Xml(msg) => write!(f, "计划任务XML无效: {}", msg),
+        }
+    }
+}
+
+/// Encoding of a task file, kept so edits are written back the same way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextEncoding {
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+fn decode_text(data: &[u8]) -> Result<(String, TextEncoding), TaskError> {
+    let utf16 = |bytes: &[u8], big_endian: bool| -> Result<String, TaskError> {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| if big_endian { u16::from_be_bytes([c[0], c[1]]) } else { u16::from_le_bytes([c[0], c[1]]) })
+            .collect();
+        String::from_utf16(&units).map_err(|_| TaskError::InvalidEncoding)
+    };
+
+    if let Some(rest) = data.strip_prefix(&[0xFF, 0xFE]) {
+        Ok((utf16(rest, false)?, TextEncoding::Utf16Le))
+    } else if let Some(rest) = data.strip_prefix(&[0xFE, 0xFF]) {
+        Ok((utf16(rest, true)?, TextEncoding::Utf16Be))
+    } else if let Some(rest) = data.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        let text = String::from_utf8(rest.to_vec()).map_err(|_| TaskError::InvalidEncoding)?;
+        Ok((text, TextEncoding::Utf8Bom))
+    } else if data.len() >= 2 && data[0] == b'<' && data[1] == 0 {
+        // UTF-16LE without BOM
+        Ok((utf16(data, false)?, TextEncoding::Utf16Le))
+    } else {
+        let text = String::from_utf8(data.to_vec()).map_err(|_| TaskError::InvalidEncoding)?;
+        Ok((text, TextEncoding::Utf8))
+    }
+}
+
+fn encode_text(text: &str, encoding: TextEncoding) -> Vec<u8> {
+    match encoding {
+        TextEncoding::Utf8 => text.as_bytes().to_vec(),
+        TextEncoding::Utf8Bom => [&[0xEF, 0xBB, 0xBF], text.as_bytes()].concat(),
+        TextEncoding::Utf16Le => {
+            let mut data = vec![0xFF, 0xFE];
+            data.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            data
+        }
+        TextEncoding::Utf16Be => {
+            let mut data = vec![0xFE, 0xFF];
+            data.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+            data
+        }
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn xml_error(e: impl std::fmt::Display) -> TaskError {
+    TaskError::Xml(e.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriggerKind {
+    Logon,
+    Boot,
+    /// Any other trigger element, e.g. `TimeTrigger` or `EventTrigger`
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskTrigger {
+    pub kind: TriggerKind,
+    pub enabled: bool,
+    /// For logon triggers: the user whose logon starts the task (any user if absent)
+    pub user_id: Option<String>,
+    /// ISO 8601 duration, e.g. `PT30S`
+    pub delay: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskAction {
+    Exec {
+        command: String,
+        arguments: Option<String>,
+        working_directory: Option<String>,
+    },
+    ComHandler {
+        class_id: String,
+        data: Option<String>,
+    },
+    /// Deprecated actions such as `SendEmail` and `ShowMessage`
+    Other(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskPrincipal {
+    pub user_id: Option<String>,
+    pub group_id: Option<String>,
+    pub logon_type: Option<String>,
+    /// `LeastPrivilege` or `HighestAvailable`
+    pub run_level: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskDefinition {
+    pub uri: Option<String>,
+    pub author: Option<String>,
+    pub description: Option<String>,
+    /// `Settings/Enabled`, true when absent
+    pub enabled: bool,
+    pub hidden: bool,
+    pub triggers: Vec<TaskTrigger>,
+    pub actions: Vec<TaskAction>,
+    /// The first principal; tasks practically always have exactly one
+    pub principal: TaskPrincipal,
+}
+
+impl TaskDefinition {
+    pub fn from_file(path: &Path) -> Result<TaskDefinition, TaskError> {
+        let data = std::fs::read(path).map_err(|e| TaskError::Io(e.to_string()))?;
+        TaskDefinition::parse(&data)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<TaskDefinition, TaskError> {
+        let (text, _) = decode_text(data)?;
+        let mut reader = Reader::from_str(&text);
+
+        let mut task = TaskDefinition {
+            uri: None,
+            author: None,
+            description: None,
+            enabled: true,
+            hidden: false,
+            triggers: Vec::new(),
+            actions: Vec::new(),
+            principal: TaskPrincipal::default(),
+        };
+        let mut principals_seen = 0;
+        let mut stack: Vec<String> = Vec::new();
+        let mut text_buf = String::new();
+        let mut saw_root = false;
+
+        loop {
+            match reader.read_event().map_err(xml_error)? {
+                Event::Start(e) => {
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                    if stack.is_empty() {
+                        if name != "Task" {
+                            return Err(TaskError::Xml(format!("unexpected root element <{}>", name)));
+                        }
+                        saw_root = true;
+                    }
+                    task.start_element(&stack, &name, &mut principals_seen);
+                    stack.push(name);
+                    text_buf.clear();
+                }
+                Event::Empty(e) => {
+                    let name = String::from_utf8_lossy(e.local_name().as_ref()).to_string();
+                    if stack.is_empty() {
+                        return Err(TaskError::Xml(format!("unexpected root element <{}>", name)));
+                    }
+                    task.start_element(&stack, &name, &mut principals_seen);
+                }
+                Event::Text(e) => text_buf.push_str(&e.xml_content().map_err(xml_error)?),
+                Event::CData(e) => text_buf.push_str(&e.decode().map_err(xml_error)?),
+                Event::GeneralRef(e) => {
+                    if let Some(ch) = e.resolve_char_ref().map_err(xml_error)? {
+                        text_buf.push(ch);
+                    } else {
+                        let name = e.decode().map_err(xml_error)?;
+                        text_buf.push_str(resolve_predefined_entity(&name).unwrap_or_default());
+                    }
+                }
+                Event::End(_) => {
+                    let value = text_buf.trim().to_string();
+                    text_buf.clear();
+                    let extra_principal = principals_seen > 1 && stack.get(1).map(String::as_str) == Some("Principals");
+                    if !value.is_empty() && !extra_principal {
+                        task.end_element(&stack, value);
+                    }
+                    stack.pop();
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        if !saw_root {
+            return Err(TaskError::Xml("missing <Task> element".to_string()));
+        }
+
+        Ok(task)
+    }
+
+    /// Called for every opening (or empty) element; `parents` excludes the element itself
+    fn start_element(&mut self, parents: &[String], name: &str, principals_seen: &mut usize) {
+        let parents: Vec<&str> = parents.iter().map(String::as_str).collect();
+        match parents.as_slice() {
+            ["Task", "Triggers"] => self.triggers.push(TaskTrigger {
+                kind: match name {
+                    "LogonTrigger" => TriggerKind::Logon,
+                    "BootTrigger" => TriggerKind::Boot,
+                    other => TriggerKind::Other(other.to_string()),
+                },
+                enabled: true,
+                user_id: None,
+                delay: None,
+            }),
+            ["Task", "Actions"] => self.actions.push(match name {
+                "Exec" => TaskAction::Exec {
+                    command: String::new(),
+                    arguments: None,
+                    working_directory: None,
+                },
+                "ComHandler" => TaskAction::ComHandler {
+                    class_id: String::new(),
+                    data: None,
+                },
+                other => TaskAction::Other(other.to_string()),
+            }),
+            ["Task", "Principals"] if name == "Principal" => *principals_seen += 1,
+            _ => {}
+        }
+    }
+
+    /// Called with the text content of every closing element; `path` includes the element
+    fn end_element(&mut self, path: &[String], value: String) {
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        match path.as_slice() {
+            ["Task", "RegistrationInfo", "URI"] => self.uri = Some(value),
+            ["Task", "RegistrationInfo", "Author"] => self.author = Some(value),
+            ["Task", "RegistrationInfo", "Description"] => self.description = Some(value),
+            ["Task", "Settings", "Enabled"] => self.enabled = parse_bool(&value).unwrap_or(true),
+            ["Task", "Settings", "Hidden"] => self.hidden = parse_bool(&value).unwrap_or(false),
+            ["Task", "Triggers", _, field] => {
+                if let Some(trigger) = self.triggers.last_mut() {
+                    match *field {
+                        "Enabled" => trigger.enabled = parse_bool(&value).unwrap_or(true),
+                        "UserId" => trigger.user_id = Some(value),
+                        "Delay" => trigger.delay = Some(value),
+                        _ => {}
+                    }
+                }
+            }
+            ["Task", "Actions", _, field] => match (self.actions.last_mut(), *field) {
+                (Some(TaskAction::Exec { command, .. }), "Command") => *command = value,
+                (Some(TaskAction::Exec { arguments, .. }), "Arguments") => *arguments = Some(value),
+                (Some(TaskAction::Exec { working_directory, .. }), "WorkingDirectory") => {
+                    *working_directory = Some(value)
+                }
+                (Some(TaskAction::ComHandler { class_id, .. }), "ClassId") => *class_id = value,
+                (Some(TaskAction::ComHandler { data, .. }), "Data") => *data = Some(value),
+                _ => {}
+            },
+            ["Task", "Principals", "Principal", field] => match *field {
+                "UserId" => self.principal.user_id = Some(value),
+                "GroupId" => self.principal.group_id = Some(value),
+                "LogonType" => self.principal.logon_type = Some(value),
+                "RunLevel" => self.principal.run_level = Some(value),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Whether an enabled logon or boot trigger starts this task
+    pub fn runs_at_startup(&self) -> bool {
+        self.triggers
+            .iter()
+            .any(|t| t.enabled && matches!(t.kind, TriggerKind::Logon | TriggerKind::Boot))
+    }
+
+    /// The first program the task runs, with its arguments
+    pub fn exec_action(&self) -> Option<(&str, Option<&str>)> {
+        self.actions.iter().find_map(|action| match action {
+            TaskAction::Exec { command, arguments, .. } if !command.is_empty() => {
+                Some((command.as_str(), arguments.as_deref()))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Set `Settings/Enabled` in a task file, keeping its encoding and all other content
+pub fn set_task_enabled(data: &[u8], enabled: bool) -> Result<Vec<u8>, TaskError> {
+    let (text, encoding) = decode_text(data)?;
+    let value = if enabled { "true" } else { "false" };
+    let mut reader = Reader::from_str(&text);
+
+    let mut depth = 0usize;
+    let mut in_settings = false;
+    let mut settings_content_start = None;
+    let mut enabled_start = None;
+    let mut edit: Option<(usize, usize, String)> = None;
+    let mut task_end = None;
+
+    loop {
+        let before = reader.buffer_position() as usize;
+        let event = reader.read_event().map_err(xml_error)?;
+        let after = reader.buffer_position() as usize;
+
+        match event {
+            Event::Start(e) => {
+                let name = e.local_name();
+                if depth == 1 && name.as_ref() == b"Settings" {
+                    in_settings = true;
+                    settings_content_start = Some(after);
+                } else if depth == 2 && in_settings && name.as_ref() == b"Enabled" {
+                    enabled_start = Some(after);
+                }
+                depth += 1;
+            }
+            Event::Empty(e) => {
+                let name = e.local_name();
+                if depth == 1 && name.as_ref() == b"Settings" {
+                    edit = Some((before, after, format!("<Settings><Enabled>{}</Enabled></Settings>", value)));
+                } else if depth == 2 && in_settings && name.as_ref() == b"Enabled" {
+                    edit = Some((before, after, format!("<Enabled>{}</Enabled>", value)));
+                }
+            }
+            Event::End(e) => {
+                depth = depth.saturating_sub(1);
+                let name = e.local_name();
+                if depth == 2 && in_settings && name.as_ref() == b"Enabled" {
+                    if let Some(start) = enabled_start.take() {
+                        edit = Some((start, before, value.to_string()));
+                    }
+                } else if depth == 1 && name.as_ref() == b"Settings" {
+                    in_settings = false;
+                } else if depth == 0 && name.as_ref() == b"Task" {
+                    task_end = Some(before);
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    let (start, end, replacement) = match (edit, settings_content_start, task_end) {
+        (Some(edit), _, _) => edit,
+        // <Settings> without <Enabled>
+        (None, Some(start), _) => (start, start, format!("<Enabled>{}</Enabled>", value)),
+        // No <Settings> at all
+        (None, None, Some(end)) => (end, end, format!("<Settings><Enabled>{}</Enabled></Settings>", value)),
+        (None, None, None) => return Err(TaskError::Xml("missing <Task> element".to_string())),
+    };
+
+    let mut updated = String::with_capacity(text.len() + replacement.len());
+    updated.push_str(&text[..start]);
+    updated.push_str(&replacement);
+    updated.push_str(&text[end..]);
+    Ok(encode_text(&updated, encoding))
+}
+
+/// `%SystemRoot%\System32\Tasks` of the running system
+pub fn system_tasks_folder() -> Option<PathBuf> {
+    std::env::var("SystemRoot")
+        .or_else(|_| std::env::var("windir"))
+        .ok()
+        .map(|root| PathBuf::from(root).join("System32").join("Tasks"))
+}
+
+/// All task files below a tasks folder, with their scheduler paths such as `\Vendor\Updater`.
+/// Folders that cannot be read (most of `\Microsoft` for non-admins) are skipped.
+pub fn find_task_files(root: &Path) -> Vec<(String, PathBuf)> {
+    fn walk(dir: &Path, prefix: &str, depth: u32, tasks: &mut Vec<(String, PathBuf)>) {
+        if depth > 16 {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let task_path = format!("{}\\{}", prefix, name);
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => walk(&entry.path(), &task_path, depth + 1, tasks),
+                Ok(t) if t.is_file() => tasks.push((task_path, entry.path())),
+                _ => {}
+            }
+        }
+    }
+
+    let mut tasks = Vec::new();
+    walk(root, "", 0, &mut tasks);
+    tasks
+}
+
+/// Split a scheduler path into its folder (`\` for the root) and task name
+pub fn split_task_path(task_path: &str) -> (String, String) {
+    match task_path.rfind('\\') {
+        Some(0) | None => ("\\".to_string(), task_path.trim_start_matches('\\').to_string()),
+        Some(pos) => (task_path[..pos].to_string(), task_path[pos + 1..].to_string()),
+    }
+}
+
+/// Join a scheduler folder and task name back into a full task path
+pub fn join_task_path(folder: &str, name: &str) -> String {
+    format!("{}\\{}", folder.trim_end_matches('\\'), name)
+}
+
+/// Location of a task's file below a tasks folder
+pub fn task_file_path(tasks_folder: &Path, task_path: &str) -> PathBuf {
+    task_path
+        .split('\\')
+        .filter(|segment| !segment.is_empty())
+        .fold(tasks_folder.to_path_buf(), |path, segment| path.join(segment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// UTF-16LE with a BOM and CRLF line ends, as the scheduler writes them
+    const UPDATER: &[u8] = include_bytes!("../../tests/fixtures/tasks/updater.xml");
+    const NO_SETTINGS: &[u8] = include_bytes!("../../tests/fixtures/tasks/no_settings.xml");
+    /// UTF-8 with a BOM
+    const WITHOUT_ENABLED: &[u8] = include_bytes!("../../tests/fixtures/tasks/settings_without_enabled.xml");
+
+    #[test]
+    fn parses_registration_info_settings_and_principal() {
+        let task = TaskDefinition::parse(UPDATER).unwrap();
+        assert_eq!(task.uri.as_deref(), Some(r"\Contoso\Updater"));
+        assert_eq!(task.author.as_deref(), Some("Contoso & Co"));
+        assert_eq!(task.description.as_deref(), Some("Keeps Contoso up to date"));
+        assert!(!task.enabled);
+        assert!(task.hidden);
+        assert_eq!(task.principal.user_id.as_deref(), Some("S-1-5-21-1000"));
+        assert_eq!(task.principal.logon_type.as_deref(), Some("InteractiveToken"));
+        assert_eq!(task.principal.run_level.as_deref(), Some("HighestAvailable"));
+    }
+
+    #[test]
+    fn parses_logon_and_boot_triggers() {
+        let task = TaskDefinition::parse(UPDATER).unwrap();
+        assert_eq!(
+            task.triggers,
+            [
+                TaskTrigger {
+                    kind: TriggerKind::Logon,
+                    enabled: true,
+                    user_id: Some(r"DESKTOP\me".to_string()),
+                    delay: Some("PT30S".to_string()),
+                },
+                TaskTrigger { kind: TriggerKind::Boot, enabled: false, user_id: None, delay: None },
+                TaskTrigger {
+                    kind: TriggerKind::Other("TimeTrigger".to_string()),
+                    enabled: true,
+                    user_id: None,
+                    delay: None,
+                },
+            ]
+        );
+        assert!(task.runs_at_startup());
+
+        // An empty <BootTrigger /> counts, an event trigger does not
+        assert!(TaskDefinition::parse(NO_SETTINGS).unwrap().runs_at_startup());
+        assert!(!TaskDefinition::parse(WITHOUT_ENABLED).unwrap().runs_at_startup());
+    }
+
+    #[test]
+    fn parses_exec_and_com_handler_actions() {
+        let task = TaskDefinition::parse(UPDATER).unwrap();
+        assert_eq!(
+            task.actions,
+            [
+                TaskAction::ComHandler {
+                    class_id: "{A6BA00FE-40E8-477C-B713-C64A14F18ADB}".to_string(),
+                    data: Some("--check".to_string()),
+                },
+                TaskAction::Exec {
+                    command: r#""C:\Program Files\Contoso\updater.exe""#.to_string(),
+                    arguments: Some("/silent /source=logon".to_string()),
+                    working_directory: Some(r"C:\Program Files\Contoso".to_string()),
+                },
+            ]
+        );
+        assert_eq!(
+            task.exec_action(),
+            Some((r#""C:\Program Files\Contoso\updater.exe""#, Some("/silent /source=logon")))
+        );
+
+        let task = TaskDefinition::parse(NO_SETTINGS).unwrap();
+        assert!(task.enabled);
+        assert_eq!(task.exec_action(), Some((r"%SystemRoot%\System32\cleanup.exe", None)));
+    }
+
+    #[test]
+    fn set_task_enabled_round_trips() {
+        for fixture in [UPDATER, NO_SETTINGS, WITHOUT_ENABLED] {
+            let original = TaskDefinition::parse(fixture).unwrap();
+            for enabled in [true, false] {
+                let data = set_task_enabled(fixture, enabled).unwrap();
+                // Same BOM, so the same encoding
+                assert_eq!(data[..2], fixture[..2]);
+
+                let task = TaskDefinition::parse(&data).unwrap();
+                assert_eq!(task.enabled, enabled);
+                assert_eq!(task, TaskDefinition { enabled, ..original.clone() });
+
+                // Setting the same value again changes nothing
+                assert_eq!(set_task_enabled(&data, enabled).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn set_task_enabled_only_touches_settings() {
+        let data = set_task_enabled(UPDATER, true).unwrap();
+        let (before, _) = decode_text(UPDATER).unwrap();
+        let (after, encoding) = decode_text(&data).unwrap();
+        assert_eq!(encoding, TextEncoding::Utf16Le);
+        assert_eq!(after, before.replace("<Enabled>false</Enabled>\r\n  </Settings>", "<Enabled>true</Enabled>\r\n  </Settings>"));
+
+        // The trigger's own <Enabled> is left alone
+        let data = set_task_enabled(NO_SETTINGS, false).unwrap();
+        let text = String::from_utf8(data).unwrap();
+        assert!(text.ends_with("<Settings><Enabled>false</Enabled></Settings></Task>\n"));
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(matches!(TaskDefinition::parse(b"<Tasks />"), Err(TaskError::Xml(_))));
+        assert!(matches!(TaskDefinition::parse(b""), Err(TaskError::Xml(_))));
+        assert_eq!(TaskDefinition::parse(&[0xFF, 0xFE, 0x00, 0xD8]), Err(TaskError::InvalidEncoding));
+        assert!(matches!(set_task_enabled(b"<?xml version=\"1.0\"?>", true), Err(TaskError::Xml(_))));
+    }
+
+    #[test]
+    fn maps_task_paths_to_files() {
+        assert_eq!(split_task_path(r"\Updater"), ("\\".to_string(), "Updater".to_string()));
+        assert_eq!(split_task_path(r"\Contoso\Updater"), (r"\Contoso".to_string(), "Updater".to_string()));
+        assert_eq!(join_task_path("\\", "Updater"), r"\Updater");
+        assert_eq!(join_task_path(r"\Contoso", "Updater"), r"\Contoso\Updater");
+        assert_eq!(
+            task_file_path(Path::new("Tasks"), r"\Contoso\Updater"),
+            Path::new("Tasks").join("Contoso").join("Updater")
+        );
+    }
+}
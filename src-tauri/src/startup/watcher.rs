@@ -0,0 +1,502 @@
+//! Watches the registry keys and folders startup items live in.
+//!
+//! The watcher asks the system to signal changes to the scanned registry keys
+//! and startup directories, then rescans those sources and reports the items
+//! that were added, removed or changed since the previous scan. Changes made
+//! through this app are reported too, but only changes from elsewhere are
+//! acted on: by the allowlist policy when one is enforced, otherwise by the
+//! watch policy for additions that are not in the baseline. What either does
+//! goes through the manager, so it shows up in the history and can be undone.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::approved::ApprovalKey;
+use super::baseline::{self, ChangeKind, ItemChange};
+use super::locations::REGISTRY_LOCATIONS;
+use super::manager;
+use super::policy::{self, BlockAction};
+use super::registry::RegistryHive;
+use super::scanner::ScanContext;
+use super::settings::{self, WatchPolicy};
+use super::sources::SOURCES;
+use super::{systemd, SourceType, StartupItem};
+
+/// Sources backed by the watched keys and directories
+const WATCHED_SOURCES: &[SourceType] = &[
+    SourceType::Registry,
+    SourceType::Folder,
+    SourceType::XdgAutostart,
+    SourceType::SystemdUser,
+];
+
+/// How long a wait for changes lasts before checking whether to stop
+const POLL_INTERVAL: Duration = Duration::from_millis(1000);
+/// Time for a burst of writes (an installer, a rename plus an approval record) to finish
+const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+static WATCHER_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Bumped on every start and stop, so a thread left over from an earlier start ends
+static WATCHER_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAction {
+    Disabled,
+    Reverted,
+    Quarantined,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchEvent {
+    pub change: ItemChange,
+    /// What the watch policy did with an unapproved addition
+    pub action: Option<PolicyAction>,
+    /// Why the policy could not be applied
+    pub error: Option<String>,
+}
+
+/// Registry keys holding startup entries or their StartupApproved records
+fn watched_keys() -> Vec<(RegistryHive, String)> {
+    let mut keys: Vec<(RegistryHive, String)> = Vec::new();
+    let approval_keys = [ApprovalKey::Run, ApprovalKey::Run32, ApprovalKey::StartupFolder];
+    let locations = REGISTRY_LOCATIONS
+        .iter()
+        .map(|location| (location.hive, location.path.to_string()))
+        .chain(
+            [RegistryHive::CurrentUser, RegistryHive::LocalMachine]
+                .into_iter()
+                .flat_map(|hive| approval_keys.iter().map(move |key| (hive, key.path()))),
+        );
+    for key in locations {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+/// Startup folders, autostart directories and the systemd unit directories with their `.wants` links
+fn watched_folders(ctx: &ScanContext) -> Vec<PathBuf> {
+    let mut folders: Vec<PathBuf> = ctx
+        .user_startup_folder
+        .iter()
+        .chain(ctx.common_startup_folder.iter())
+        .chain(ctx.xdg_user_autostart.iter())
+        .chain(ctx.xdg_system_autostart.iter())
+        .cloned()
+        .collect();
+    for path in &ctx.systemd_user_paths {
+        folders.push(path.clone());
+        folders.extend(
+            systemd::LOGIN_TARGETS
+                .iter()
+                .map(|target| path.join(format!("{}.wants", target))),
+        );
+    }
+    folders.retain(|folder| folder.is_dir());
+    folders
+}
+
+fn scan_watched() -> Vec<StartupItem> {
+    let ctx = ScanContext::system();
+    SOURCES
+        .iter()
+        .filter(|source| WATCHED_SOURCES.contains(&source.source_type()))
+        .flat_map(|source| source.scan(&ctx))
+        .collect()
+}
+
+/// Hold a changed item to the allowlist policy when one is enforced
+fn apply_allowlist(item: &StartupItem) -> Option<Result<PolicyAction, String>> {
+    let decision = match policy::enforce_items(std::slice::from_ref(item)) {
+        Ok(decisions) => decisions.into_iter().next()?,
+        Err(e) => return Some(Err(e)),
+    };
+    if let Some(error) = decision.error {
+        return Some(Err(error));
+    }
+    match decision.action? {
+        BlockAction::Disable => Some(Ok(PolicyAction::Disabled)),
+        BlockAction::Quarantine => Some(Ok(PolicyAction::Quarantined)),
+        BlockAction::Notify => None,
+    }
+}
+
+/// Act on a change made outside this app: through the allowlist when it is enforced,
+/// otherwise by disabling or removing an addition that is not in the baseline
+fn apply_policy(policy: WatchPolicy, change: &ItemChange, current: &[StartupItem]) -> Option<Result<PolicyAction, String>> {
+    if change.kind == ChangeKind::Removed {
+        return None;
+    }
+    let item = current.iter().find(|item| {
+        item.source_type == change.source_type
+            && item.source_location == change.source_location
+            && item.entry_name == change.entry_name
+    })?;
+    if policy::is_enforced() {
+        return apply_allowlist(item);
+    }
+    if policy == WatchPolicy::Notify || change.kind != ChangeKind::Added || baseline::is_known(item) {
+        return None;
+    }
+
+    let result = match policy {
+        WatchPolicy::Disable if !item.enabled => return None,
+        WatchPolicy::Disable => manager::toggle_startup_item(item, false).map(|_| PolicyAction::Disabled),
+        WatchPolicy::Revert => manager::delete_startup_item(item).map(|_| PolicyAction::Reverted),
+        WatchPolicy::Notify => return None,
+    };
+    Some(result.map_err(|e| e.to_string()))
+}
+
+/// Start watching in the background; `on_change` receives the changes found by each rescan
+pub fn start_watcher(on_change: impl Fn(Vec<WatchEvent>) + Send + 'static) {
+    if WATCHER_RUNNING.swap(true, Ordering::SeqCst) {
+        return; // Already running
+    }
+    let generation = WATCHER_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let current_generation = move || WATCHER_GENERATION.load(Ordering::SeqCst) == generation;
+
+    thread::spawn(move || {
+        let ctx = ScanContext::system();
+        let Ok(mut notifier) = Notifier::new(&watched_keys(), &watched_folders(&ctx)) else {
+            if current_generation() {
+                WATCHER_RUNNING.store(false, Ordering::SeqCst);
+            }
+            return;
+        };
+
+        let mut known = scan_watched();
+        let mut operations = manager::operations_finished();
+
+        while current_generation() {
+            if !notifier.wait(POLL_INTERVAL) {
+                continue;
+            }
+
+            // Wait for the writes to settle and for operations of this app to finish
+            thread::sleep(SETTLE_DELAY);
+            while manager::operations_running() {
+                thread::sleep(SETTLE_DELAY);
+            }
+            notifier.drain();
+
+            // Changes that overlap an operation of this app are only reported,
+            // so a policy never undoes what the user just did
+            let own_changes = manager::operations_finished() != operations;
+            let policy = settings::get_watch_policy();
+
+            let current = scan_watched();
+            let changes = baseline::diff_items(&known, &current);
+            let mut acted = false;
+            let events: Vec<WatchEvent> = changes
+                .into_iter()
+                .map(|change| {
+                    let outcome = if own_changes { None } else { apply_policy(policy, &change, &current) };
+                    acted |= outcome.is_some();
+                    let (action, error) = match outcome {
+                        Some(Ok(action)) => (Some(action), None),
+                        Some(Err(e)) => (None, Some(e)),
+                        None => (None, None),
+                    };
+                    WatchEvent { change, action, error }
+                })
+                .collect();
+
+            known = if acted { scan_watched() } else { current };
+            operations = manager::operations_finished();
+            if !events.is_empty() {
+                on_change(events);
+            }
+        }
+    });
+}
+
+pub fn stop_watcher() {
+    WATCHER_GENERATION.fetch_add(1, Ordering::SeqCst);
+    WATCHER_RUNNING.store(false, Ordering::SeqCst);
+}
+
+pub fn is_watcher_running() -> bool {
+    WATCHER_RUNNING.load(Ordering::SeqCst)
+}
+
+#[cfg(windows)]
+use windows_notifier::Notifier;
+
+/// Change notifications for registry keys and directories through their Win32 wait handles
+#[cfg(windows)]
+mod windows_notifier {
+    use std::ffi::c_void;
+    use std::io;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::{CloseHandle, HANDLE, WAIT_OBJECT_0};
+    use windows::Win32::Storage::FileSystem::{
+        FindCloseChangeNotification, FindFirstChangeNotificationW, FindNextChangeNotification,
+        FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE,
+    };
+    use windows::Win32::System::Registry::{
+        RegNotifyChangeKeyValue, HKEY, REG_NOTIFY_CHANGE_LAST_SET, REG_NOTIFY_CHANGE_NAME,
+    };
+    use windows::Win32::System::Threading::{CreateEventW, WaitForMultipleObjects};
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, KEY_NOTIFY};
+    use winreg::RegKey;
+
+    use crate::startup::registry::RegistryHive;
+
+    enum Watch {
+        /// An open key and the event its change notification signals
+        Key { key: RegKey, event: HANDLE },
+        Folder(HANDLE),
+    }
+
+    pub struct Notifier {
+        watches: Vec<Watch>,
+    }
+
+    /// Ask for the key's event to be signalled on its next change; a notification fires only once
+    fn arm_key(key: &RegKey, event: HANDLE) -> bool {
+        let hkey = HKEY(key.raw_handle() as *mut c_void);
+        unsafe {
+            RegNotifyChangeKeyValue(hkey, true, REG_NOTIFY_CHANGE_NAME | REG_NOTIFY_CHANGE_LAST_SET, event, true).is_ok()
+        }
+    }
+
+    impl Notifier {
+        /// Keys and folders that do not exist are skipped
+        pub fn new(keys: &[(RegistryHive, String)], folders: &[PathBuf]) -> io::Result<Notifier> {
+            let mut watches = Vec::new();
+
+            for (hive, path) in keys {
+                let root = match hive {
+                    RegistryHive::CurrentUser => RegKey::predef(HKEY_CURRENT_USER),
+                    RegistryHive::LocalMachine => RegKey::predef(HKEY_LOCAL_MACHINE),
+                };
+                let Ok(key) = root.open_subkey_with_flags(path, KEY_NOTIFY) else {
+                    continue;
+                };
+                let event = unsafe { CreateEventW(None, false, false, PCWSTR::null()) }.map_err(io::Error::other)?;
+                if arm_key(&key, event) {
+                    watches.push(Watch::Key { key, event });
+                } else {
+                    let _ = unsafe { CloseHandle(event) };
+                }
+            }
+
+            for folder in folders {
+                let path = HSTRING::from(folder.as_os_str());
+                let handle = unsafe {
+                    FindFirstChangeNotificationW(&path, false, FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_LAST_WRITE)
+                };
+                if let Ok(handle) = handle {
+                    watches.push(Watch::Folder(handle));
+                }
+            }
+
+            Ok(Notifier { watches })
+        }
+
+        fn handles(&self) -> Vec<HANDLE> {
+            self.watches
+                .iter()
+                .map(|watch| match watch {
+                    Watch::Key { event, .. } => *event,
+                    Watch::Folder(handle) => *handle,
+                })
+                .collect()
+        }
+
+        /// Wait until a watched key or folder changes, `false` on timeout
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            let handles = self.handles();
+            if handles.is_empty() {
+                std::thread::sleep(timeout);
+                return false;
+            }
+
+            let result = unsafe { WaitForMultipleObjects(&handles, false, timeout.as_millis() as u32) };
+            let index = result.0.wrapping_sub(WAIT_OBJECT_0.0) as usize;
+            let Some(watch) = self.watches.get(index) else {
+                return false;
+            };
+
+            // Re-arm the signalled watch for the next change
+            match watch {
+                Watch::Key { key, event } => {
+                    arm_key(key, *event);
+                }
+                Watch::Folder(handle) => {
+                    let _ = unsafe { FindNextChangeNotification(*handle) };
+                }
+            }
+            true
+        }
+
+        /// Consume notifications that are already pending
+        pub fn drain(&mut self) {
+            while self.wait(Duration::ZERO) {}
+        }
+    }
+
+    impl Drop for Notifier {
+        fn drop(&mut self) {
+            for watch in &self.watches {
+                match watch {
+                    Watch::Key { event, .. } => {
+                        let _ = unsafe { CloseHandle(*event) };
+                    }
+                    Watch::Folder(handle) => {
+                        let _ = unsafe { FindCloseChangeNotification(*handle) };
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+use inotify_notifier::Notifier;
+
+/// Directory change notifications through inotify; there is no live registry to watch here
+#[cfg(target_os = "linux")]
+mod inotify_notifier {
+    use std::ffi::{c_char, c_int, c_short, c_ulong, CString};
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::startup::registry::RegistryHive;
+
+    const IN_NONBLOCK: c_int = 0o4000;
+    const IN_CLOEXEC: c_int = 0o2000000;
+
+    const IN_MODIFY: u32 = 0x0000_0002;
+    const IN_ATTRIB: u32 = 0x0000_0004;
+    const IN_MOVED_FROM: u32 = 0x0000_0040;
+    const IN_MOVED_TO: u32 = 0x0000_0080;
+    const IN_CREATE: u32 = 0x0000_0100;
+    const IN_DELETE: u32 = 0x0000_0200;
+    const WATCH_MASK: u32 = IN_MODIFY | IN_ATTRIB | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE;
+
+    const POLLIN: c_short = 0x0001;
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    unsafe extern "C" {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int;
+        fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+    }
+
+    pub struct Notifier {
+        inotify: File,
+    }
+
+    impl Notifier {
+        /// Folders that do not exist are skipped
+        pub fn new(_keys: &[(RegistryHive, String)], folders: &[PathBuf]) -> io::Result<Notifier> {
+            let fd = unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // The file owns the descriptor and closes it on drop
+            let inotify = unsafe { File::from_raw_fd(fd) };
+
+            for folder in folders {
+                let Ok(path) = CString::new(folder.as_os_str().as_bytes()) else {
+                    continue;
+                };
+                if unsafe { inotify_add_watch(fd, path.as_ptr(), WATCH_MASK) } < 0 {
+                    let error = io::Error::last_os_error();
+                    if !matches!(error.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory) {
+                        return Err(error);
+                    }
+                }
+            }
+
+            Ok(Notifier { inotify })
+        }
+
+        /// Wait until a watched folder changes, `false` on timeout
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            let mut pollfd = PollFd { fd: self.inotify.as_raw_fd(), events: POLLIN, revents: 0 };
+            let ready = unsafe { poll(&mut pollfd, 1, timeout.as_millis() as c_int) };
+            if ready <= 0 {
+                return false;
+            }
+            self.drain();
+            true
+        }
+
+        /// Consume notifications that are already pending
+        pub fn drain(&mut self) {
+            let mut buffer = [0u8; 4096];
+            while matches!(self.inotify.read(&mut buffer), Ok(n) if n > 0) {}
+        }
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+use polling_notifier::Notifier;
+
+/// No change notifications on this platform; nothing is ever reported
+#[cfg(not(any(windows, target_os = "linux")))]
+mod polling_notifier {
+    use std::io;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use crate::startup::registry::RegistryHive;
+
+    pub struct Notifier;
+
+    impl Notifier {
+        pub fn new(_keys: &[(RegistryHive, String)], _folders: &[PathBuf]) -> io::Result<Notifier> {
+            Err(io::Error::new(io::ErrorKind::Unsupported, "change notifications are not available"))
+        }
+
+        pub fn wait(&mut self, timeout: Duration) -> bool {
+            std::thread::sleep(timeout);
+            false
+        }
+
+        pub fn drain(&mut self) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn notices_a_file_created_in_a_watched_folder() {
+        use std::time::Duration;
+
+        use super::Notifier;
+        use crate::startup::testdir::TestDir;
+
+        let dir = TestDir::new("watcher");
+        let missing = dir.join("missing");
+        let mut notifier = Notifier::new(&[], &[dir.path().to_path_buf(), missing]).unwrap();
+        assert!(!notifier.wait(Duration::from_millis(10)));
+
+        dir.write("app.desktop", "[Desktop Entry]\n");
+        assert!(notifier.wait(Duration::from_secs(5)));
+        assert!(!notifier.wait(Duration::from_millis(10)));
+    }
+}
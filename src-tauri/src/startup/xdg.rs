@@ -0,0 +1,463 @@
+//! XDG autostart entries: `.desktop` files in `~/.config/autostart` and `/etc/xdg/autostart`.
+//!
+//! A file in the user directory replaces a system file of the same name. Desktop
+//! environments disable an entry by writing such a copy with `Hidden=true`, so
+//! the system file itself is never touched.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+const DESKTOP_ENTRY_GROUP: &str = "[Desktop Entry]";
+
+/// The keys of a `[Desktop Entry]` group that matter for autostart
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DesktopEntry {
+    pub entry_type: Option<String>,
+    pub name: Option<String>,
+    pub comment: Option<String>,
+    pub exec: Option<String>,
+    pub icon: Option<String>,
+    /// `Hidden=true`: the entry is switched off (or deleted, in the words of the spec)
+    pub hidden: bool,
+    /// GNOME's own switch, `X-GNOME-Autostart-enabled=false` keeps the entry from starting
+    pub gnome_autostart_enabled: Option<bool>,
+    pub only_show_in: Vec<String>,
+    pub not_show_in: Vec<String>,
+}
+
+impl DesktopEntry {
+    pub fn from_file(path: &Path) -> io::Result<DesktopEntry> {
+        let data = std::fs::read(path)?;
+        Ok(DesktopEntry::parse(&String::from_utf8_lossy(&data)))
+    }
+
+    /// Read the `[Desktop Entry]` group. Localized keys (`Name[de]`) and other groups are ignored.
+    pub fn parse(text: &str) -> DesktopEntry {
+        let mut entry = DesktopEntry::default();
+
+        for (key, value) in entry_group_pairs(text) {
+            match key {
+                "Type" => entry.entry_type = Some(unescape(value)),
+                "Name" => entry.name = Some(unescape(value)),
+                "Comment" => entry.comment = Some(unescape(value)),
+                "Exec" => entry.exec = Some(unescape(value)),
+                "Icon" => entry.icon = Some(unescape(value)),
+                "Hidden" => entry.hidden = value == "true",
+                "X-GNOME-Autostart-enabled" => entry.gnome_autostart_enabled = Some(value != "false"),
+                "OnlyShowIn" => entry.only_show_in = split_list(value),
+                "NotShowIn" => entry.not_show_in = split_list(value),
+                _ => {}
+            }
+        }
+
+        entry
+    }
+
+    pub fn is_application(&self) -> bool {
+        self.entry_type.as_deref().is_none_or(|t| t == "Application")
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.hidden && self.gnome_autostart_enabled != Some(false)
+    }
+
+    /// Whether the entry applies to a session running the given desktops
+    /// (`XDG_CURRENT_DESKTOP`). An unknown desktop sees every entry.
+    pub fn shown_in(&self, desktops: &[String]) -> bool {
+        if desktops.is_empty() {
+            return true;
+        }
+        let listed = |list: &[String]| list.iter().any(|d| desktops.iter().any(|c| c.eq_ignore_ascii_case(d)));
+
+        if !self.only_show_in.is_empty() && !listed(&self.only_show_in) {
+            return false;
+        }
+        !listed(&self.not_show_in)
+    }
+
+    /// The program of `Exec`, searched in `PATH` when it has no directory
+    pub fn program(&self) -> Option<String> {
+        let program = exec_arguments(self.exec.as_deref()?).into_iter().next()?;
+        if program.contains('/') {
+            return Some(program);
+        }
+
+        let found = std::env::var_os("PATH").and_then(|paths| {
+            std::env::split_paths(&paths)
+                .map(|dir| dir.join(&program))
+                .find(|candidate| candidate.is_file())
+        });
+        Some(found.map(|p| p.to_string_lossy().to_string()).unwrap_or(program))
+    }
+
+    /// `Exec` without the field codes (`%f`, `%U`, ...) a launcher would fill in
+    pub fn command_line(&self) -> Option<String> {
+        let exec = self.exec.as_deref()?;
+        let command = exec
+            .split(' ')
+            .filter(|arg| !is_field_code(arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+            .replace("%%", "%");
+        Some(command.trim().to_string())
+    }
+}
+
+/// Key-value pairs of the `[Desktop Entry]` group, in file order
+fn entry_group_pairs(text: &str) -> impl Iterator<Item = (&str, &str)> {
+    let mut in_group = false;
+    text.lines().filter_map(move |line| {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_group = line == DESKTOP_ENTRY_GROUP;
+            return None;
+        }
+        if !in_group || line.starts_with('#') {
+            return None;
+        }
+        let (key, value) = line.split_once('=')?;
+        Some((key.trim(), value.trim()))
+    })
+}
+
+/// Undo the escapes of string values (`\s`, `\n`, `\t`, `\r`, `\\`)
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// `GNOME;KDE;` -> ["GNOME", "KDE"]
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn is_field_code(arg: &str) -> bool {
+    arg.len() == 2 && arg.starts_with('%') && arg != "%%"
+}
+
+/// Split `Exec` into arguments, honoring double quotes
+fn exec_arguments(exec: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' if in_quotes => {
+                if let Some(next) = chars.next() {
+                    current.push(next);
+                }
+            }
+            ' ' | '\t' if !in_quotes => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+
+    args.retain(|arg| !is_field_code(arg));
+    args
+}
+
+/// Set keys of the `[Desktop Entry]` group, keeping every other line as it is.
+/// Missing keys are added at the end of the group; a missing group is created.
+pub fn set_entry_keys(text: &str, keys: &[(&str, &str)]) -> String {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let mut pending: Vec<(&str, &str)> = keys.to_vec();
+
+    let Some(group_start) = lines.iter().position(|l| l.trim() == DESKTOP_ENTRY_GROUP) else {
+        let mut result = String::from(DESKTOP_ENTRY_GROUP);
+        result.push('\n');
+        for (key, value) in keys {
+            result.push_str(&format!("{}={}\n", key, value));
+        }
+        if !text.trim().is_empty() {
+            result.push('\n');
+            result.push_str(text);
+        }
+        return result;
+    };
+
+    let mut group_end = lines.len();
+    for (i, line) in lines.iter_mut().enumerate().skip(group_start + 1) {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            group_end = i;
+            break;
+        }
+        let Some((key, _)) = trimmed.split_once('=') else {
+            continue;
+        };
+        if let Some(pos) = pending.iter().position(|(k, _)| *k == key.trim()) {
+            let (key, value) = pending.remove(pos);
+            *line = format!("{}={}", key, value);
+        }
+    }
+
+    // Append after the last non-blank line of the group
+    let mut insert_at = group_end;
+    while insert_at > group_start + 1 && lines[insert_at - 1].trim().is_empty() {
+        insert_at -= 1;
+    }
+    for (offset, (key, value)) in pending.iter().enumerate() {
+        lines.insert(insert_at + offset, format!("{}={}", key, value));
+    }
+
+    let mut result = lines.join("\n");
+    result.push('\n');
+    result
+}
+
+/// `$XDG_CONFIG_HOME/autostart`, by default `~/.config/autostart`
+pub fn user_autostart_dir() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("autostart"))
+}
+
+/// `autostart` below every `$XDG_CONFIG_DIRS` entry, by default `/etc/xdg/autostart`
+pub fn system_autostart_dirs() -> Vec<PathBuf> {
+    let config_dirs = std::env::var("XDG_CONFIG_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".to_string());
+
+    config_dirs
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| PathBuf::from(dir).join("autostart"))
+        .collect()
+}
+
+/// Desktops of the running session, from `XDG_CURRENT_DESKTOP` (`ubuntu:GNOME`)
+pub fn current_desktops() -> Vec<String> {
+    std::env::var("XDG_CURRENT_DESKTOP")
+        .map(|value| value.split(':').filter(|d| !d.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// The `.desktop` files that take effect, as (file name, path). Earlier
+/// directories win over later ones with a file of the same name.
+pub fn find_autostart_files(dirs: &[PathBuf]) -> Vec<(String, PathBuf)> {
+    let mut files: Vec<(String, PathBuf)> = Vec::new();
+
+    for dir in dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut names: Vec<(String, PathBuf)> = entries
+            .flatten()
+            .map(|e| (e.file_name().to_string_lossy().to_string(), e.path()))
+            .filter(|(name, path)| name.ends_with(".desktop") && path.is_file())
+            .collect();
+        names.sort();
+
+        for (name, path) in names {
+            if !files.iter().any(|(n, _)| *n == name) {
+                files.push((name, path));
+            }
+        }
+    }
+
+    files
+}
+
+/// Switch an entry on or off through its copy in the user directory, creating
+/// the copy from `source` when the entry only exists system-wide
+pub fn set_autostart_enabled(user_dir: &Path, source: &Path, enabled: bool) -> io::Result<()> {
+    let file_name = source
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let text = String::from_utf8_lossy(&std::fs::read(source)?).to_string();
+
+    let updated = if enabled {
+        let mut keys = vec![("Hidden", "false")];
+        // An entry shipped switched off for GNOME has to be switched on there as well
+        if DesktopEntry::parse(&text).gnome_autostart_enabled == Some(false) {
+            keys.push(("X-GNOME-Autostart-enabled", "true"));
+        }
+        set_entry_keys(&text, &keys)
+    } else {
+        set_entry_keys(&text, &[("Hidden", "true")])
+    };
+
+    std::fs::create_dir_all(user_dir)?;
+    std::fs::write(user_dir.join(file_name), updated)
+}
+
+/// Find the file for an `Icon` value: an absolute path, or a name looked up as a
+/// PNG in the hicolor theme and `pixmaps` of every `$XDG_DATA_DIRS` entry
+pub fn find_icon_file(icon: &str) -> Option<PathBuf> {
+    if icon.starts_with('/') {
+        return Some(PathBuf::from(icon)).filter(|p| p.is_file());
+    }
+
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    let file_name = format!("{}.png", icon);
+
+    for dir in data_dirs.split(':').filter(|d| !d.is_empty()).map(Path::new) {
+        for size in ["256x256", "128x128", "64x64", "48x48", "32x32"] {
+            let candidate = dir.join("icons/hicolor").join(size).join("apps").join(&file_name);
+            if candidate.is_file() {
+                return Some(candidate);
+            }
+        }
+        let candidate = dir.join("pixmaps").join(&file_name);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::startup::testdir::TestDir;
+
+    const SYNC: &str = "\
+# Shipped by the vendor
+[Desktop Entry]
+Type=Application
+Name=Sync
+Name[de]=Abgleich
+Comment=Keeps\\sfiles\\tin sync
+Exec=\"/opt/sync/bin/sync daemon\" --tray %U
+Icon=sync
+OnlyShowIn=GNOME;KDE;
+
+[Desktop Action Quit]
+Name=Quit
+Hidden=true
+";
+
+    fn desktops(list: &[&str]) -> Vec<String> {
+        list.iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_the_desktop_entry_group() {
+        let entry = DesktopEntry::parse(SYNC);
+        assert_eq!(entry.name.as_deref(), Some("Sync"));
+        assert_eq!(entry.comment.as_deref(), Some("Keeps files\tin sync"));
+        assert_eq!(entry.only_show_in, ["GNOME", "KDE"]);
+        assert!(entry.is_application());
+        // `Hidden` of another group does not count
+        assert!(entry.is_enabled());
+        assert_eq!(entry.program().as_deref(), Some("/opt/sync/bin/sync daemon"));
+        assert_eq!(entry.command_line().as_deref(), Some("\"/opt/sync/bin/sync daemon\" --tray"));
+    }
+
+    #[test]
+    fn hidden_and_gnome_switches_disable_entries() {
+        assert!(!DesktopEntry::parse("[Desktop Entry]\nHidden=true\n").is_enabled());
+        assert!(DesktopEntry::parse("[Desktop Entry]\nHidden=false\n").is_enabled());
+        assert!(!DesktopEntry::parse("[Desktop Entry]\nX-GNOME-Autostart-enabled=false\n").is_enabled());
+        assert!(DesktopEntry::parse("[Desktop Entry]\nX-GNOME-Autostart-enabled=true\n").is_enabled());
+        assert!(!DesktopEntry::parse("[Desktop Entry]\nType=Link\n").is_application());
+    }
+
+    #[test]
+    fn only_show_in_and_not_show_in() {
+        let only = DesktopEntry::parse(SYNC);
+        assert!(only.shown_in(&desktops(&["ubuntu", "gnome"])));
+        assert!(!only.shown_in(&desktops(&["XFCE"])));
+        assert!(only.shown_in(&[]));
+
+        let not = DesktopEntry::parse("[Desktop Entry]\nNotShowIn=KDE;\n");
+        assert!(!not.shown_in(&desktops(&["KDE"])));
+        assert!(not.shown_in(&desktops(&["GNOME"])));
+    }
+
+    #[test]
+    fn user_files_shadow_system_files() {
+        let dir = TestDir::new("xdg-shadow");
+        let user = dir.write("user/sync.desktop", "[Desktop Entry]\nHidden=true\n");
+        dir.write("system/sync.desktop", SYNC);
+        let backup = dir.write("system/backup.desktop", "[Desktop Entry]\nName=Backup\n");
+        dir.write("vendor/backup.desktop", "[Desktop Entry]\nName=Vendor backup\n");
+        let notes = dir.write("vendor/notes.desktop", "[Desktop Entry]\nName=Notes\n");
+        dir.write("vendor/readme.txt", "not an entry");
+
+        let files = find_autostart_files(&[dir.join("user"), dir.join("missing"), dir.join("system"), dir.join("vendor")]);
+        assert_eq!(
+            files,
+            [
+                ("sync.desktop".to_string(), user),
+                ("backup.desktop".to_string(), backup),
+                ("notes.desktop".to_string(), notes),
+            ]
+        );
+    }
+
+    #[test]
+    fn toggling_writes_an_override_and_leaves_the_system_file() {
+        let dir = TestDir::new("xdg-toggle");
+        let system = dir.write("system/sync.desktop", SYNC);
+        let user_dir = dir.join("user");
+        let copy = user_dir.join("sync.desktop");
+
+        set_autostart_enabled(&user_dir, &system, false).unwrap();
+        assert_eq!(std::fs::read_to_string(&system).unwrap(), SYNC);
+        let text = std::fs::read_to_string(&copy).unwrap();
+        assert!(!DesktopEntry::parse(&text).is_enabled());
+        assert_eq!(DesktopEntry::parse(&text).exec, DesktopEntry::parse(SYNC).exec);
+        // The new key goes at the end of its group, not of the file
+        assert!(text.contains("OnlyShowIn=GNOME;KDE;\nHidden=true\n\n[Desktop Action Quit]"));
+
+        set_autostart_enabled(&user_dir, &copy, true).unwrap();
+        assert!(DesktopEntry::parse(&std::fs::read_to_string(&copy).unwrap()).is_enabled());
+    }
+
+    #[test]
+    fn enabling_switches_gnome_entries_back_on() {
+        let dir = TestDir::new("xdg-gnome");
+        let system = dir.write("system/tracker.desktop", "[Desktop Entry]\nExec=tracker\nX-GNOME-Autostart-enabled=false\n");
+        let user_dir = dir.join("user");
+
+        set_autostart_enabled(&user_dir, &system, true).unwrap();
+        let text = std::fs::read_to_string(user_dir.join("tracker.desktop")).unwrap();
+        assert_eq!(text, "[Desktop Entry]\nExec=tracker\nX-GNOME-Autostart-enabled=true\nHidden=false\n");
+    }
+
+    #[test]
+    fn set_entry_keys_creates_a_missing_group() {
+        assert_eq!(
+            set_entry_keys("[Other]\nKey=1\n", &[("Hidden", "true")]),
+            "[Desktop Entry]\nHidden=true\n\n[Other]\nKey=1\n"
+        );
+    }
+}
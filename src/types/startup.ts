@@ -1,13 +1,216 @@
+export interface CommandLine {
+  executable: string;
+  arguments: string;
+}
+
+export interface Launcher {
+  kind:
+    | "rundll32"
+    | "regsvr32"
+    | "script_host"
+    | "power_shell"
+    | "cmd"
+    | "msiexec"
+    | "mshta";
+  host: string;
+  target: string;
+  target_kind: "file" | "code" | "url" | "product";
+  entry_point: string | null;
+  arguments: string;
+}
+
+export interface ClickOnceReference {
+  deployment_url: string;
+  name: string;
+  culture: string | null;
+  public_key_token: string | null;
+  processor_architecture: string | null;
+  display_name: string | null;
+  publisher: string | null;
+}
+
+export interface FolderFile {
+  file_type:
+    | "program"
+    | "batch"
+    | "windows_script"
+    | "power_shell"
+    | "html_application"
+    | "internet_shortcut"
+    | "click_once"
+    | "pif";
+  interpreter: string | null;
+  target: string | null;
+  click_once: ClickOnceReference | null;
+  icon: [string, number] | null;
+}
+
 export interface StartupItem {
   id: string;
   name: string;
+  entry_name: string;
   description: string | null;
+  company_name: string | null;
+  product_name: string | null;
+  file_version: string | null;
+  original_filename: string | null;
+  legal_copyright: string | null;
   path: string;
   command: string;
+  command_line: CommandLine;
+  launcher: Launcher | null;
+  folder_file: FolderFile | null;
   icon: string | null;
   source: string;
-  source_type: "registry" | "folder";
+  source_type:
+    | "registry"
+    | "folder"
+    | "scheduled_task"
+    | "service"
+    | "xdg_autostart"
+    | "systemd_user";
   source_location: string;
   enabled: boolean;
+  disabled_at: number | null;
   valid: boolean;
 }
+
+export interface SourceCapabilities {
+  can_toggle: boolean;
+  can_delete: boolean;
+  can_create: boolean;
+  can_edit: boolean;
+}
+
+export interface SourceInfo {
+  source_type: StartupItem["source_type"];
+  capabilities: SourceCapabilities;
+}
+
+export interface NewStartupItem {
+  source_type: "registry" | "folder";
+  scope: "current_user" | "all_users";
+  name: string;
+  path: string;
+  arguments: string | null;
+  working_directory: string | null;
+}
+
+export interface HistoryEntry {
+  id: string;
+  timestamp: number;
+  operation: "enable" | "disable" | "delete" | "create" | "update";
+  item_name: string;
+  source: string;
+  source_type: StartupItem["source_type"];
+  undone: boolean;
+}
+
+export interface SnapshotChange {
+  kind: "add" | "modify" | "enable" | "disable" | "remove" | "extra";
+  source_type: StartupItem["source_type"];
+  name: string;
+  source: string;
+  entry_name: string;
+  supported: boolean;
+  applied: boolean;
+}
+
+export interface ImportReport {
+  dry_run: boolean;
+  changes: SnapshotChange[];
+  settings_changed: boolean;
+  errors: string[];
+}
+
+export interface ItemChange {
+  kind: "added" | "removed" | "command_changed" | "enabled_changed";
+  source_type: StartupItem["source_type"];
+  name: string;
+  source: string;
+  source_location: string;
+  entry_name: string;
+  previous_command: string | null;
+  command: string | null;
+  previous_enabled: boolean | null;
+  enabled: boolean | null;
+}
+
+export interface BaselineDiff {
+  baseline_created_at: number;
+  changes: ItemChange[];
+}
+
+export type WatchPolicy = "notify" | "disable" | "revert";
+
+export interface WatchEvent {
+  change: ItemChange;
+  action: "disabled" | "reverted" | "quarantined" | null;
+  error: string | null;
+}
+
+export interface AllowRule {
+  name: string | null;
+  path: string | null;
+  sha256: string | null;
+  publisher: string | null;
+}
+
+export type BlockAction = "notify" | "disable" | "quarantine";
+
+export interface StartupPolicy {
+  enabled: boolean;
+  action: BlockAction;
+  rules: AllowRule[];
+}
+
+export interface PolicyDecision {
+  timestamp: number;
+  item_name: string;
+  entry_name: string;
+  source: string;
+  source_type: StartupItem["source_type"];
+  path: string;
+  allowed: boolean;
+  rule: number | null;
+  action: BlockAction | null;
+  error: string | null;
+}
+
+export interface QuarantinedItem {
+  id: string;
+  quarantined_at: number;
+  path: string;
+  item: {
+    name: string;
+    source: string;
+    entry_name: string;
+    command: string;
+  };
+}
+
+export interface KnownItem {
+  name: string;
+  entry_name: string;
+  source: string;
+  source_location: string;
+  path: string;
+}
+
+export interface RelinkSuggestion {
+  item_id: string;
+  name: string;
+  source: string;
+  path: string;
+  reason: "program" | "process" | "name";
+}
+
+export interface OrphanedSettings {
+  item_id: string;
+  last_seen: KnownItem | null;
+  auto_minimize: boolean;
+  process_name: string | null;
+  minimize_behavior: string | null;
+  minimize_delay: number | null;
+  suggestions: RelinkSuggestion[];
+}